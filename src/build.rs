@@ -1,33 +1,75 @@
-use clap::{Args, ValueEnum};
-use std::{collections::HashMap, fs, path::PathBuf, process::Command};
-
-use crate::{context::*, swift, Ctx};
+use clap::Args;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+#[cfg(feature = "swift-bridge")]
+use crate::swift;
+use crate::{
+    build_manifest::{BuildManifest, TargetManifest},
+    builds,
+    context::*,
+    error::{CompilerOutput, Error},
+    runner::{run_checked, SystemRunner, ToolCall, ToolRunner},
+    sign::{
+        self, entitlements,
+        identity::Identity,
+        macho::{self, MachO},
+        profile::{ProvisioningProfile, EMBEDDED_PROFILE_IOS, EMBEDDED_PROFILE_MACOS},
+        resources, SigningOptions,
+    },
+    symbolicate::debug_info,
+    Ctx,
+};
 
 #[derive(Args)]
 pub struct BuildArgs {
     /// Compile the provided library example into an IPA.
     /// If blank, will compile the Rust binary.
     #[arg(short, long)]
-    example: Option<String>,
-    /// Compile in release mode
+    pub example: Option<String>,
+    /// Compile in release mode. Release builds also save the binary's debug info in a dSYM.
     #[arg(short, long)]
-    release: bool,
+    pub release: bool,
     /// The app's name. If left unprovided, cargo-ipa will search
     /// for it in Cargo.toml. If it can't find it there, it will
     /// error.
     #[arg(short, long)]
-    name: Option<String>,
+    pub name: Option<String>,
     /// Only compile for 1 platform instead of both
     #[arg(short, long, value_enum)]
-    platform: Option<Platform>,
+    pub platform: Option<Platform>,
     /// Only compile for 1 architecture instead of both
     #[arg(short, long, value_enum)]
-    architecture: Option<Architecture>,
+    pub architecture: Option<Architecture>,
+    /// Strip the debug info and local symbols from the app's binary. They're still kept in
+    /// its dSYM, for symbolicating crash reports.
+    #[arg(long)]
+    pub strip: bool,
+    /// Also make an .xcarchive of each app and its dSYM, like Xcode's Product > Archive
+    #[arg(long)]
+    pub archive: bool,
+    /// How the app will be distributed. This picks the identity & profile from its
+    /// `signing.<method>` settings, sets `get-task-allow` and `aps-environment` to match, and
+    /// skips Simulator builds.
+    #[arg(long, value_enum)]
+    pub export_method: Option<ExportMethod>,
+    /// Build aarch64 iOS apps for the Simulator on Apple silicon Macs (`aarch64-apple-ios-sim`)
+    /// instead of for devices. x86_64 iOS apps are always for the Simulator.
+    #[arg(long, conflicts_with = "export_method")]
+    pub simulator: bool,
 }
 
-pub fn build(args: BuildArgs) -> Result<(), String> {
-    let ctx = &mut Ctx::new(&args.name).unwrap();
+pub fn build(args: BuildArgs) -> Result<(), Error> {
+    let ctx = &mut Ctx::new(&args.name)?;
+    build_with(ctx, &args, &SystemRunner)
+}
 
+/// Builds the project described by `ctx`, running every external tool through `runner`
+pub fn build_with(ctx: &mut Ctx, args: &BuildArgs, runner: &dyn ToolRunner) -> Result<(), Error> {
     // ========== SETUP ==========
     println!("Setting up...");
     // These arguments to Cargo will never change, since they don't rely on target triples
@@ -42,7 +84,7 @@ pub fn build(args: BuildArgs) -> Result<(), String> {
     #[cfg(feature = "swift-bridge")]
     let static_swift_args =
         if let Some((swift_args, cargo_args)) = swift::static_args(ctx, args.release) {
-            static_cargo_args.extend(cargo_args.into_iter());
+            static_cargo_args.extend(cargo_args);
             Some(swift_args)
         } else {
             None
@@ -53,42 +95,76 @@ pub fn build(args: BuildArgs) -> Result<(), String> {
         ctx.project_id.to_string()
     };
     // Find XCode Toolchain
-    let xcode_toolchain = detect_xcode();
+    let xcode_toolchain = detect_xcode(runner);
+    // Export methods can have their own identity & profile
+    let mut targets_list = gen_targets_list(args.platform, args.architecture);
+    if let Some(method) = args.export_method {
+        if args.simulator {
+            return Err(Error::Config(
+                "Simulator builds can't be exported. Leave out `--simulator` or `--export-method`."
+                    .into(),
+            ));
+        }
+        println!("|- Exporting for {}", method.name());
+        ctx.cfg.signing.use_export_method(method);
+        // Only the Simulator runs x86_64 iOS apps, so they can't be distributed
+        targets_list.retain(|target| *target != (Platform::iOS, Architecture::x86_64));
+        if targets_list.is_empty() {
+            return Err(Error::Config(
+                "x86_64 iOS apps only run on the Simulator, so they can't be exported. Build for aarch64, or leave out `--export-method`.".into(),
+            ));
+        }
+    }
+    // Load the signing identity now, so a bad password fails before anything compiles
+    let identity = sign::load_identity(ctx)?;
+    match identity.as_ref().and_then(Identity::common_name) {
+        Some(name) => println!("|- Signing as `{name}`"),
+        None if identity.is_some() => println!("|- Signing with the configured identity"),
+        None => println!("|- No signing identity is set up; apps will be ad-hoc signed"),
+    }
+    let profile = sign::load_profile(ctx, identity.as_ref())?;
+    if let Some(ref profile) = profile {
+        println!("|- Using the provisioning profile `{}`", profile.name);
+    }
+    if let Some(method) = args.export_method {
+        check_export_method(method, profile.as_ref())?;
+    }
 
     // ========== GENERATE INFO.PLIST ==========
     println!("Generating `Info.plist`...");
     // A map of the Info.plist values, and some default necessary values
-    let mut map = HashMap::<String, String>::new();
-    map.insert("CFBundleExecutable".into(), binary_name);
-    map.insert(
-        "CFBundleIdentifier".into(),
-        "com.".to_owned() + ctx.project_id.as_str(),
-    );
-    map.insert("CFBundleName".into(), ctx.project_name.clone());
-    map.insert("CFBundleVersion".into(), ctx.project_version.clone());
+    let mut map = BTreeMap::<String, plist::Value>::new();
+    map.insert("CFBundleExecutable".into(), binary_name.into());
+    map.insert("CFBundleIdentifier".into(), ctx.bundle_id.clone().into());
+    map.insert("CFBundleName".into(), ctx.project_name.clone().into());
+    map.insert("CFBundleVersion".into(), ctx.project_version.clone().into());
     map.insert(
         "CFBundleShortVersionString".into(),
-        ctx.project_version.clone(),
+        ctx.project_version.clone().into(),
     );
-    map.insert("CFBundlePackageType".to_string(), "APPL".to_string());
-    // Check for Info.plist overrides in Cargo.toml
-    if let Some(cfg) = &ctx.cfg {
-        if let Some(toml::Value::Table(properties)) = cfg.get("properties") {
-            for (key, value) in properties.into_iter() {
-                map.insert(key.to_owned(), value.to_string());
-            }
-        }
+    map.insert("CFBundlePackageType".to_string(), "APPL".into());
+    // Apply the Info.plist overrides from Cargo.toml
+    for (key, value) in &ctx.cfg.properties {
+        map.insert(key.to_owned(), value.clone());
     }
     // Write everything to Info.plist & make sure it succeeds
-    if let Err(e) = fs::write(ctx.cargo_ipa_dir.join("Info.plist"), gen_info_plist(map)) {
-        return Err(format!(
-            "Failed to write to Info.plist! The error was: {e}\nAborting."
-        ));
+    if let Err(e) = gen_info_plist(map, &ctx.cargo_ipa_dir.join("Info.plist")) {
+        return Err(Error::Packaging {
+            message: "Failed to write to Info.plist! Aborting.".into(),
+            source: Some(Box::new(e)),
+        });
     }
 
     // ========== COMPILATION ==========
-    for (platform, architecture) in gen_targets_list(&args) {
-        let target_triple = architecture.to_string() + "-apple-" + &platform.to_string();
+    let mut targets = Vec::new();
+    for (platform, architecture) in targets_list {
+        // aarch64 iOS has a separate target for the Simulator
+        let simulator =
+            args.simulator && (platform, architecture) == (Platform::iOS, Architecture::aarch64);
+        let target_triple = match simulator {
+            true => get_target_triple(platform, architecture) + "-sim",
+            false => get_target_triple(platform, architecture),
+        };
         println!("Compiling for {target_triple}...");
 
         if ctx.force_cargo_recompile {
@@ -98,27 +174,36 @@ pub fn build(args: BuildArgs) -> Result<(), String> {
                 cargo_args.push("-r");
             }
 
-            let clean_result = Command::new("cargo").args(cargo_args).status();
-            if clean_result.is_err() || !clean_result.unwrap().success() {
-                return Err("Failed to clean old build files.".to_string());
-            }
+            let clean_call = ToolCall::new("cargo", &cargo_args)
+                .current_dir(&ctx.root_dir)
+                .echo();
+            run_checked(runner, &clean_call, |output| Error::CompileFailed {
+                tool: "Cargo".into(),
+                stderr: CompilerOutput(output.stderr),
+            })?;
         }
 
         // Compile Swift
         #[cfg(feature = "swift-bridge")]
         if let Some(ref static_swift_args) = static_swift_args {
-            let target = swift::get_target_triple(platform, architecture);
-            let sdk = swift::get_sdk(platform);
+            let mut target = swift::get_target_triple(platform, architecture);
+            if simulator {
+                target.push_str("-simulator");
+            }
+            let sdk = swift::get_sdk(runner, platform, simulator)?;
             let mut swift_args = vec![
                 "build", "-Xswiftc", "-target", "-Xswiftc", &target, "--sdk", &sdk,
             ];
             swift_args.extend(static_swift_args.iter().map(|item| item.as_str()));
 
             println!("|- Compiling Swift code...");
-            let build_status = Command::new("swift").args(swift_args).status();
-            if build_status.is_err() || !build_status.unwrap().success() {
-                return Err("Swift failed to compile the project! Aborting.".into());
-            }
+            let build_call = ToolCall::new("swift", &swift_args)
+                .current_dir(&ctx.root_dir)
+                .echo();
+            run_checked(runner, &build_call, |output| Error::CompileFailed {
+                tool: "Swift".into(),
+                stderr: CompilerOutput(output.stderr),
+            })?;
         }
 
         // Compile Rust
@@ -130,58 +215,127 @@ pub fn build(args: BuildArgs) -> Result<(), String> {
         cargo_args.push("-L");
         let platform_toolchain = xcode_toolchain.join(match platform {
             Platform::macOS => "macosx",
+            Platform::iOS if simulator => "iphonesimulator",
             Platform::iOS => "iphoneos",
         });
         cargo_args.push(platform_toolchain.to_str().unwrap());
 
         // Make sure building succeeded
-        let build_status = Command::new("cargo").args(cargo_args).status();
-        if build_status.is_err() || !build_status.unwrap().success() {
-            return Err("Cargo failed to compile the project! Aborting.".into());
+        let build_call = ToolCall::new("cargo", &cargo_args)
+            .current_dir(&ctx.root_dir)
+            .echo();
+        run_checked(runner, &build_call, |output| Error::CompileFailed {
+            tool: "Cargo".into(),
+            stderr: CompilerOutput(output.stderr),
+        })?;
+
+        // Profiles are for one platform; the others are signed without it
+        let target_profile = profile
+            .as_ref()
+            .filter(|profile| profile.supports(platform));
+        if let (Some(profile), None) = (&profile, target_profile) {
+            println!(
+                "|- The provisioning profile `{}` isn't for {target_triple}, so it won't be embedded",
+                profile.name
+            );
         }
 
         // Make the .ipa or .app file, as appropriate
-        match platform {
-            Platform::macOS => gen_app(ctx, &target_triple, &args, true)?,
-            Platform::iOS => gen_ipa(ctx, &target_triple, &args)?,
+        let artifact = match platform {
+            Platform::macOS => gen_app(
+                ctx,
+                &target_triple,
+                args,
+                runner,
+                identity.as_ref(),
+                target_profile,
+                true,
+            )?,
+            Platform::iOS => gen_ipa(
+                ctx,
+                &target_triple,
+                args,
+                runner,
+                identity.as_ref(),
+                target_profile,
+            )?,
+        };
+        let app_name = ctx.project_name.clone() + "." + &target_triple + ".app";
+        let archive = match args.archive {
+            // IPAs' apps are left in the Payload folder they were zipped from
+            true => Some(gen_archive(
+                ctx,
+                &target_triple,
+                &match platform {
+                    Platform::macOS => ctx.cargo_ipa_dir.join(&app_name),
+                    Platform::iOS => ctx.cargo_ipa_dir.join("Payload").join(&app_name),
+                },
+                architecture,
+                identity.as_ref(),
+            )?),
+            false => None,
         };
+        targets.push(TargetManifest {
+            artifact: Path::new(&artifact)
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .into_owned(),
+            dsym: saves_dsym(args).then(|| dsym_name(ctx, &target_triple)),
+            archive,
+            uuid: debug_info::read_uuid(&binary_path(ctx, &target_triple, args)),
+            target: target_triple,
+            stripped: args.strip,
+        });
     }
 
     // ========== CLEANUP ==========
     println!("Cleaning up...");
+    let (git_commit, git_tags) = builds::git_info(ctx, runner);
+    let manifest = BuildManifest {
+        name: ctx.project_name.clone(),
+        bundle_id: ctx.bundle_id.clone(),
+        version: ctx.project_version.clone(),
+        release: args.release,
+        example: args.example.clone(),
+        export_method: args.export_method,
+        created: builds::created_now(),
+        git_commit,
+        git_tags,
+        targets,
+    };
+    manifest.save(&ctx.cargo_ipa_dir)?;
+    // Keep a copy, since the next build replaces everything in `target/cargo-ipa`
+    let build_dir = builds::archive(ctx, &manifest)?;
 
     println!(
-        "Done! Your build files are at `{}`",
-        ctx.cargo_ipa_dir.to_str().unwrap()
+        "Done! Your build files are at `{}`, and archived at `{}`",
+        ctx.cargo_ipa_dir.to_str().unwrap(),
+        build_dir.to_str().unwrap()
     );
     Ok(())
 }
 
 /// Generate the Info.plist file
-fn gen_info_plist(map: HashMap<String, String>) -> String {
-    let mut buffer = String::new();
-    buffer += PLIST_OPENING;
-
-    for (key, value) in map.iter() {
-        buffer += &format!("<key>{key}</key>\n");
-        buffer += &format!("<string>{value}</string>\n");
-    }
-
-    buffer += PLIST_CLOSING;
-    buffer
+fn gen_info_plist(map: BTreeMap<String, plist::Value>, path: &PathBuf) -> Result<(), plist::Error> {
+    let dict: plist::Dictionary = map.into_iter().collect();
+    plist::Value::Dictionary(dict).to_file_xml(path)
 }
 
 /// Generate a list of targets to compile for
-fn gen_targets_list(args: &BuildArgs) -> Vec<(Platform, Architecture)> {
+pub fn gen_targets_list(
+    platform: Option<Platform>,
+    architecture: Option<Architecture>,
+) -> Vec<(Platform, Architecture)> {
     // Cache the architectures being used
-    let architectures = if let Some(architecture) = args.architecture {
+    let architectures = if let Some(architecture) = architecture {
         vec![architecture]
     } else {
         vec![Architecture::x86_64, Architecture::aarch64]
     };
 
     // Cache the platforms being used
-    let platforms = if let Some(platform) = args.platform {
+    let platforms = if let Some(platform) = platform {
         vec![platform]
     } else {
         vec![Platform::iOS, Platform::macOS]
@@ -199,19 +353,337 @@ fn gen_targets_list(args: &BuildArgs) -> Vec<(Platform, Architecture)> {
     result
 }
 
+/// Get Rust's target-triple for a platform & architecture
+pub fn get_target_triple(platform: Platform, architecture: Architecture) -> String {
+    architecture.to_string() + "-apple-" + &platform.to_string()
+}
+
+/// Makes sure the provisioning profile is the right kind for the export method. Development
+/// builds can be ad-hoc signed for the Simulator, but the others all need a profile.
+fn check_export_method(
+    method: ExportMethod,
+    profile: Option<&ProvisioningProfile>,
+) -> Result<(), Error> {
+    let method_name = method.name();
+    match profile {
+        Some(profile) if profile.export_method() != method => Err(Error::Signing(format!(
+            "The provisioning profile `{}` is for {} builds, not {method_name} ones. Set `signing.{method_name}.provisioning-profile` to a {method_name} profile.",
+            profile.name,
+            profile.export_method().name()
+        ))),
+        None if method != ExportMethod::Development => Err(Error::Signing(format!(
+            "`--export-method {method_name}` needs a provisioning profile. Set `signing.{method_name}.provisioning-profile`, and `signing.{method_name}.identity` if it's for a different certificate."
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Copies the Swift libraries an iOS app's binary loads from its rpath (rather than from the
+/// OS) out of Xcode's `toolchain` and into the app's Frameworks folder, then signs them. Apps
+/// that use swift-bridge need these when they target iOS versions without Swift built in.
+/// Returns the libraries' names.
+pub fn embed_swift_libraries(
+    app_path: &Path,
+    binary: &Path,
+    toolchain: &Path,
+    identity: Option<&Identity>,
+) -> Result<Vec<String>, Error> {
+    let data = match fs::read(binary) {
+        Ok(data) => data,
+        Err(e) => return Err(Error::io("Failed to read the app's binary", binary, e)),
+    };
+    let slices = match macho::parse_fat(&data)? {
+        Some(arches) => arches
+            .iter()
+            .map(|arch| &data[arch.offset as usize..(arch.offset + arch.size) as usize])
+            .collect(),
+        None => vec![&data[..]],
+    };
+    let mut libraries = Vec::new();
+    for slice in slices {
+        for dylib in MachO::parse(slice)?.dylibs {
+            if let Some(library) = dylib.strip_prefix("@rpath/") {
+                if library.starts_with("libswift") && !libraries.iter().any(|l| l == library) {
+                    libraries.push(library.to_string());
+                }
+            }
+        }
+    }
+    libraries.sort();
+    if libraries.is_empty() {
+        return Ok(libraries);
+    }
+
+    println!("   |- Embedding Swift's libraries...");
+    let frameworks = app_path.join("Frameworks");
+    if let Err(e) = fs::create_dir_all(&frameworks) {
+        return Err(Error::io(
+            "Failed to create the app's Frameworks folder",
+            frameworks,
+            e,
+        ));
+    }
+    for library in &libraries {
+        let original = toolchain.join(library);
+        let embedded = frameworks.join(library);
+        if let Err(e) = fs::copy(&original, &embedded) {
+            return Err(Error::io(
+                "Failed to copy a Swift library from Xcode",
+                original,
+                e,
+            ));
+        }
+        // Like codesign, libraries without an Info.plist are identified by their file name
+        let signing_options = SigningOptions {
+            identifier: library.trim_end_matches(".dylib"),
+            identity,
+            ..Default::default()
+        };
+        sign::sign_file(&embedded, &signing_options)?;
+    }
+    Ok(libraries)
+}
+
+/// Copies the originals of the Swift libraries an app embeds from Xcode's `toolchain` (eg
+/// `.../usr/lib/swift/iphoneos`) into `swift_support/iphoneos`, which the App Store checks
+/// the app's copies against. Returns the libraries' names.
+pub fn gen_swift_support(
+    app_path: &Path,
+    toolchain: &Path,
+    swift_support: &Path,
+) -> Result<Vec<String>, Error> {
+    let mut libraries: Vec<String> = match fs::read_dir(app_path.join("Frameworks")) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with("libswift") && name.ends_with(".dylib"))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    libraries.sort();
+    if libraries.is_empty() {
+        return Ok(libraries);
+    }
+
+    println!("|- Adding SwiftSupport...");
+    let out_dir = swift_support.join("iphoneos");
+    if let Err(e) = fs::create_dir_all(&out_dir) {
+        return Err(Error::io("Failed to create SwiftSupport", out_dir, e));
+    }
+    for library in &libraries {
+        let original = toolchain.join(library);
+        if let Err(e) = fs::copy(&original, out_dir.join(library)) {
+            return Err(Error::io(
+                "Failed to copy a Swift library from Xcode",
+                original,
+                e,
+            ));
+        }
+    }
+    Ok(libraries)
+}
+
+/// Where Cargo puts the binary for a target
+fn binary_path(ctx: &Ctx, target_triple: &str, args: &BuildArgs) -> PathBuf {
+    let bin_path =
+        ctx.target_dir
+            .join(target_triple)
+            .join(if args.release { "release" } else { "debug" });
+    match args.example {
+        Some(ref example_name) => bin_path.join("examples").join(example_name),
+        None => bin_path.join(&ctx.project_id),
+    }
+}
+
+/// The name of a target's dSYM, in `target/cargo-ipa`
+fn dsym_name(ctx: &Ctx, target_triple: &str) -> String {
+    ctx.project_name.clone() + "." + target_triple + ".app.dSYM"
+}
+
+/// Whether a build makes dSYMs. dsymutil is slow, so debug builds (which aren't shipped, and
+/// keep their debug info in the binary) skip it, unless a stripped binary or archive needs it.
+fn saves_dsym(args: &BuildArgs) -> bool {
+    args.release || args.strip || args.archive
+}
+
+/// Saves the binary's debug info in a dSYM next to the app, so crash reports from it can be
+/// symbolicated after it's shipped
+fn gen_dsym(
+    ctx: &Ctx,
+    target_triple: &str,
+    bin_path: &Path,
+    runner: &dyn ToolRunner,
+) -> Result<(), Error> {
+    println!("   |- Saving debug info to a dSYM...");
+    let dsym_path = ctx.cargo_ipa_dir.join(dsym_name(ctx, target_triple));
+    if dsym_path.exists() {
+        if let Err(e) = fs::remove_dir_all(&dsym_path) {
+            return Err(Error::io(
+                "dSYM already exists, and can't be removed",
+                dsym_path,
+                e,
+            ));
+        }
+    }
+
+    // dsymutil collects the DWARF from the object files the binary was linked from
+    let dsymutil_call = ToolCall::new(
+        "dsymutil",
+        &[
+            bin_path.to_str().unwrap(),
+            "-o",
+            dsym_path.to_str().unwrap(),
+        ],
+    );
+    run_checked(runner, &dsymutil_call, |output| Error::Packaging {
+        message: "Failed to save the app's debug info to a dSYM".into(),
+        source: Some(output.stderr.into()),
+    })?;
+    let dwarf_path = dsym_path
+        .join("Contents/Resources/DWARF")
+        .join(bin_path.file_name().unwrap());
+    if !dwarf_path.is_file() {
+        return Err(Error::packaging(format!(
+            "dsymutil didn't save the debug info to `{}`",
+            dwarf_path.display()
+        )));
+    }
+
+    // dsymutil's Info.plist doesn't know which app it's for
+    let mut map = BTreeMap::<String, plist::Value>::new();
+    map.insert("CFBundleDevelopmentRegion".into(), "English".into());
+    map.insert(
+        "CFBundleIdentifier".into(),
+        format!("com.apple.xcode.dsym.{}", ctx.bundle_id).into(),
+    );
+    map.insert("CFBundleInfoDictionaryVersion".into(), "6.0".into());
+    map.insert("CFBundlePackageType".into(), "dSYM".into());
+    map.insert("CFBundleSignature".into(), "????".into());
+    map.insert(
+        "CFBundleShortVersionString".into(),
+        ctx.project_version.clone().into(),
+    );
+    map.insert("CFBundleVersion".into(), ctx.project_version.clone().into());
+    let info_plist_path = dsym_path.join("Contents/Info.plist");
+    if let Err(e) = gen_info_plist(map, &info_plist_path) {
+        return Err(Error::Packaging {
+            message: "Failed to write the dSYM's Info.plist".into(),
+            source: Some(Box::new(e)),
+        });
+    }
+    Ok(())
+}
+
+/// Makes an .xcarchive from an app that's been built & signed, and its dSYM, for tools that
+/// only take Xcode's archives. Returns the archive's name.
+fn gen_archive(
+    ctx: &Ctx,
+    target_triple: &str,
+    app_path: &Path,
+    architecture: Architecture,
+    identity: Option<&Identity>,
+) -> Result<String, Error> {
+    println!("|- Archiving the app...");
+    let archive_name = ctx.project_name.clone() + "." + target_triple + ".xcarchive";
+    let archive_path = ctx.cargo_ipa_dir.join(&archive_name);
+    if archive_path.exists() {
+        if let Err(e) = fs::remove_dir_all(&archive_path) {
+            return Err(Error::io(
+                "Archive already exists, and can't be removed",
+                archive_path,
+                e,
+            ));
+        }
+    }
+
+    // Xcode names the app & dSYM after the product, without the target
+    let app_name = ctx.project_name.clone() + ".app";
+    let copies = [
+        (
+            app_path.to_owned(),
+            archive_path.join("Products/Applications").join(&app_name),
+        ),
+        (
+            ctx.cargo_ipa_dir.join(dsym_name(ctx, target_triple)),
+            archive_path.join("dSYMs").join(app_name.clone() + ".dSYM"),
+        ),
+    ];
+    for (from, to) in copies {
+        if let Err(e) = builds::copy_all(&from, &to) {
+            return Err(Error::io("Failed to copy into the archive", from, e));
+        }
+    }
+
+    let mut properties = plist::Dictionary::new();
+    properties.insert(
+        "ApplicationPath".into(),
+        format!("Applications/{app_name}").into(),
+    );
+    properties.insert(
+        "Architectures".into(),
+        plist::Value::Array(vec![match architecture {
+            Architecture::aarch64 => "arm64".into(),
+            Architecture::x86_64 => "x86_64".into(),
+        }]),
+    );
+    properties.insert("CFBundleIdentifier".into(), ctx.bundle_id.clone().into());
+    properties.insert(
+        "CFBundleShortVersionString".into(),
+        ctx.project_version.clone().into(),
+    );
+    properties.insert("CFBundleVersion".into(), ctx.project_version.clone().into());
+    // `-` is what codesign calls ad-hoc signing
+    let signing_identity = identity.and_then(Identity::common_name);
+    properties.insert(
+        "SigningIdentity".into(),
+        signing_identity.unwrap_or_else(|| "-".into()).into(),
+    );
+    if let Some(team) = identity.and_then(Identity::team_id) {
+        properties.insert("Team".into(), team.into());
+    }
+
+    let mut map = BTreeMap::<String, plist::Value>::new();
+    map.insert(
+        "ApplicationProperties".into(),
+        plist::Value::Dictionary(properties),
+    );
+    map.insert("ArchiveVersion".into(), 2.into());
+    map.insert(
+        "CreationDate".into(),
+        plist::Date::from(SystemTime::now()).into(),
+    );
+    map.insert("Name".into(), ctx.project_name.clone().into());
+    map.insert("SchemeName".into(), ctx.project_name.clone().into());
+    let info_plist_path = archive_path.join("Info.plist");
+    if let Err(e) = gen_info_plist(map, &info_plist_path) {
+        return Err(Error::Packaging {
+            message: "Failed to write the archive's Info.plist".into(),
+            source: Some(Box::new(e)),
+        });
+    }
+    Ok(archive_name)
+}
+
 /// Compress everything into an IPA file
-fn gen_ipa(ctx: &Ctx, target_triple: &str, args: &BuildArgs) -> Result<String, String> {
+fn gen_ipa(
+    ctx: &Ctx,
+    target_triple: &str,
+    args: &BuildArgs,
+    runner: &dyn ToolRunner,
+    identity: Option<&Identity>,
+    profile: Option<&ProvisioningProfile>,
+) -> Result<String, Error> {
     // Make sure the IPA file doesn't already exist;
     // otherwise, the zip command will add to it instead of making a new one
-    let ipa_file = ctx
-        .cargo_ipa_dir
-        .join(ctx.project_name.clone() + target_triple + ".ipa");
+    let ipa_name = ctx.project_name.clone() + "." + target_triple + ".ipa";
+    let ipa_file = ctx.cargo_ipa_dir.join(&ipa_name);
     if ipa_file.exists() {
         if let Err(e) = fs::remove_file(&ipa_file) {
-            return Err(
-                "Error: IPA file already exists, and can't be removed: ".to_owned()
-                    + &e.to_string(),
-            );
+            return Err(Error::io(
+                "IPA file already exists, and can't be removed",
+                ipa_file,
+                e,
+            ));
         }
     }
 
@@ -219,17 +691,22 @@ fn gen_ipa(ctx: &Ctx, target_triple: &str, args: &BuildArgs) -> Result<String, S
     let payload_folder = ctx.cargo_ipa_dir.join("Payload");
     if payload_folder.exists() {
         if let Err(e) = fs::remove_dir_all(&payload_folder) {
-            return Err(
-                "Error: Build files already exist, and can't be removed: ".to_owned()
-                    + &e.to_string(),
-            );
+            return Err(Error::io(
+                "Build files already exist, and can't be removed",
+                payload_folder,
+                e,
+            ));
         }
     }
     if let Err(e) = fs::create_dir(&payload_folder) {
-        return Err("Error: Failed to create build directory: ".to_owned() + &e.to_string());
+        return Err(Error::io(
+            "Failed to create build directory",
+            payload_folder,
+            e,
+        ));
     }
 
-    let app_name = gen_app(ctx, target_triple, args, false)?;
+    let app_name = gen_app(ctx, target_triple, args, runner, identity, profile, false)?;
     println!("|- Compressing the app into an IPA...");
     println!(
         "Moving {} from {} to {}",
@@ -241,50 +718,87 @@ fn gen_ipa(ctx: &Ctx, target_triple: &str, args: &BuildArgs) -> Result<String, S
         ctx.cargo_ipa_dir.join(&app_name),
         payload_folder.join(&app_name),
     ) {
-        return Err(
-            "Error: Failed to copy .app file for compression: ".to_string() + &e.to_string(),
-        );
+        return Err(Error::io(
+            "Failed to copy .app file for compression",
+            ctx.cargo_ipa_dir.join(&app_name),
+            e,
+        ));
     }
 
-    // Need to go to relative path above Payload - otherwise the path is weird in the zip file
-    // (eg /full/path/to/Payload instead of Payload)
-    std::env::set_current_dir(&ctx.cargo_ipa_dir).expect("Failed to go to build directory");
-
-    // Zip the Payload folder into our ipa file
-    let zip_cmd = Command::new("zip")
-        .arg("-r")
-        .arg(ctx.project_name.clone() + "." + target_triple + ".ipa")
-        .arg("Payload")
-        .status();
-
-    if zip_cmd.is_err() || !zip_cmd.unwrap().success() {
-        return Err("Error: Failed to compress the app into an IPA! Aborting.".into());
+    // Only App Store builds have SwiftSupport, and only if the app embeds Swift's libraries
+    let swift_support = ctx.cargo_ipa_dir.join("SwiftSupport");
+    if swift_support.exists() {
+        if let Err(e) = fs::remove_dir_all(&swift_support) {
+            return Err(Error::io(
+                "Build files already exist, and can't be removed",
+                swift_support,
+                e,
+            ));
+        }
+    }
+    if args.export_method == Some(ExportMethod::AppStore) {
+        gen_swift_support(
+            &payload_folder.join(&app_name),
+            &detect_xcode(runner).join("iphoneos"),
+            &swift_support,
+        )?;
     }
 
+    let extra: &[&str] = if swift_support.exists() {
+        &["SwiftSupport"]
+    } else {
+        &[]
+    };
+    zip_payload(runner, &ctx.cargo_ipa_dir, &ipa_name, extra)?;
+
     Ok(ipa_file.to_str().unwrap().to_string())
 }
 
+/// Zips the Payload folder in `dir` (and the `extra` files & folders next to it, like
+/// SwiftSupport) into an IPA called `ipa_name`, also in `dir`
+pub(crate) fn zip_payload(
+    runner: &dyn ToolRunner,
+    dir: &Path,
+    ipa_name: &str,
+    extra: &[&str],
+) -> Result<(), Error> {
+    // Need to run zip from the folder above Payload - otherwise the path is weird in the zip file
+    // (eg /full/path/to/Payload instead of Payload)
+    let mut zip_args = vec!["-r", ipa_name, "Payload"];
+    zip_args.extend(extra);
+    let zip_call = ToolCall::new("zip", &zip_args).current_dir(dir).echo();
+    run_checked(runner, &zip_call, |output| Error::Packaging {
+        message: "Failed to compress the app into an IPA! Aborting.".into(),
+        source: Some(output.stderr.into()),
+    })?;
+    Ok(())
+}
+
 /// Compress everything into an .app file
 fn gen_app(
     ctx: &Ctx,
     target_triple: &str,
     args: &BuildArgs,
+    runner: &dyn ToolRunner,
+    identity: Option<&Identity>,
+    profile: Option<&ProvisioningProfile>,
     macos: bool,
-) -> Result<String, String> {
+) -> Result<String, Error> {
     println!("|- Generating .app file...");
     // Where the .app folder will be placed
     let app_name = ctx.project_name.clone() + "." + target_triple + ".app";
     let app_path = ctx.cargo_ipa_dir.join(&app_name);
     if app_path.exists() {
         if let Err(e) = fs::remove_dir_all(&app_path) {
-            return Err(
-                "Error: App file already exists, and can't be removed: ".to_owned()
-                    + &e.to_string(),
-            );
+            return Err(Error::io(
+                "App file already exists, and can't be removed",
+                app_path,
+                e,
+            ));
         }
     }
     if let Err(e) = fs::create_dir(&app_path) {
-        return Err("Error: Failed to create .app directory: ".to_owned() + &e.to_string());
+        return Err(Error::io("Failed to create .app directory", app_path, e));
     }
 
     // Find the binary
@@ -293,14 +807,7 @@ fn gen_app(
     } else {
         &ctx.project_id
     };
-    let mut bin_path =
-        ctx.target_dir
-            .join(target_triple)
-            .join(if args.release { "release" } else { "debug" });
-    if args.example.is_some() {
-        bin_path.push("examples");
-    }
-    bin_path.push(bin_name);
+    let bin_path = binary_path(ctx, target_triple, args);
 
     // Find Info.plist
     let info_plist_path = ctx.cargo_ipa_dir.join("Info.plist");
@@ -311,18 +818,21 @@ fn gen_app(
         // The Contents folder inside the app
         let contents_path = app_path.join("Contents");
         if let Err(e) = fs::create_dir(&contents_path) {
-            return Err(
-                "Error: Failed to create Contents directory in the app: ".to_owned()
-                    + &e.to_string(),
-            );
+            return Err(Error::io(
+                "Failed to create Contents directory in the app",
+                contents_path,
+                e,
+            ));
         }
 
         // The MacOS folder inside the Contents path
         let macos_path = contents_path.join("MacOS");
         if let Err(e) = fs::create_dir(&macos_path) {
-            return Err(
-                "Error: Failed to create MacOS directory in the app: ".to_owned() + &e.to_string(),
-            );
+            return Err(Error::io(
+                "Failed to create MacOS directory in the app",
+                macos_path,
+                e,
+            ));
         }
 
         (contents_path.join("Info.plist"), macos_path.join(bin_name))
@@ -331,22 +841,136 @@ fn gen_app(
     };
 
     println!("   |- Copying Info.plist...");
-    let info_plist = fs::read(info_plist_path);
-    if info_plist.is_err() || fs::write(new_info_plist_path, info_plist.unwrap()).is_err() {
-        return Err("Error: Failed to copy Info.plist to the new app".into());
+    if let Err(e) = fs::copy(&info_plist_path, &new_info_plist_path) {
+        return Err(Error::io(
+            "Failed to copy Info.plist to the new app",
+            info_plist_path,
+            e,
+        ));
     }
     println!("   |- Copying the binary...");
-    let binary = fs::read(bin_path);
-    if binary.is_err() || fs::write(&new_bin_path, binary.unwrap()).is_err() {
-        return Err("Error: Failed to copy the binary to the new app".into());
+    if let Err(e) = fs::copy(&bin_path, &new_bin_path) {
+        return Err(Error::io(
+            "Failed to copy the binary to the new app",
+            bin_path,
+            e,
+        ));
+    }
+    // Cargo's copy of the binary keeps its debug info, even if the app's is stripped
+    if saves_dsym(args) {
+        gen_dsym(ctx, target_triple, &bin_path, runner)?;
     }
-    let executable_command = Command::new("chmod")
-        .arg("+x")
-        .arg(new_bin_path.to_str().unwrap())
-        .status();
-    if executable_command.is_err() || !executable_command.unwrap().success() {
-        return Err("Error: Failed to make the app's binary executable".to_string());
+    if args.strip {
+        // Signing comes after, since stripping changes the binary
+        println!("   |- Stripping the binary...");
+        let strip_call = ToolCall::new("strip", &["-S", "-x", new_bin_path.to_str().unwrap()]);
+        run_checked(runner, &strip_call, |output| Error::Packaging {
+            message: "Failed to strip the app's binary".into(),
+            source: Some(output.stderr.into()),
+        })?;
     }
+    let chmod_call = ToolCall::new("chmod", &["+x", new_bin_path.to_str().unwrap()]);
+    run_checked(runner, &chmod_call, |output| Error::Packaging {
+        message: "Failed to make the app's binary executable".into(),
+        source: Some(output.stderr.into()),
+    })?;
+
+    // macOS has always had Swift built in, but older iOS versions need the app to bring its own
+    if !macos {
+        let toolchain = match target_triple.ends_with("-sim") {
+            true => "iphonesimulator",
+            false => "iphoneos",
+        };
+        embed_swift_libraries(
+            &app_path,
+            &new_bin_path,
+            &detect_xcode(runner).join(toolchain),
+            identity,
+        )?;
+    }
+
+    // On macOS, everything except the binary goes in the Contents folder
+    let bundle_root = if macos {
+        app_path.join("Contents")
+    } else {
+        app_path.clone()
+    };
+    if let Some(profile) = profile {
+        println!("   |- Embedding the provisioning profile...");
+        let profile_path = bundle_root.join(if macos {
+            EMBEDDED_PROFILE_MACOS
+        } else {
+            EMBEDDED_PROFILE_IOS
+        });
+        if let Err(e) = fs::write(&profile_path, &profile.data) {
+            return Err(Error::io(
+                "Failed to embed the provisioning profile in the app",
+                profile_path,
+                e,
+            ));
+        }
+    }
+
+    println!("   |- Sealing the app's resources...");
+    let code_resources = resources::seal(
+        &bundle_root,
+        new_bin_path.strip_prefix(&bundle_root).unwrap(),
+        macos,
+    )?;
+
+    println!("   |- Signing the binary...");
+    let info_plist = match fs::read(&new_info_plist_path) {
+        Ok(info_plist) => info_plist,
+        Err(e) => {
+            return Err(Error::io(
+                "Failed to read the app's Info.plist",
+                new_info_plist_path,
+                e,
+            ))
+        }
+    };
+    let mut entitlements =
+        entitlements::generate(&ctx.cfg, &ctx.bundle_id, identity, profile, macos)?;
+    if let Some(method) = args.export_method {
+        entitlements::apply_export_method(&mut entitlements, method, profile, macos);
+    }
+    if let Some(profile) = profile {
+        entitlements::check(&entitlements, profile)?;
+    }
+    let entitlements = if entitlements.is_empty() {
+        None
+    } else {
+        // Keep a copy next to the app, so it's easy to see what the app was signed with
+        let xml = entitlements::to_xml(&entitlements)?;
+        let xcent_path = ctx
+            .cargo_ipa_dir
+            .join(ctx.project_name.clone() + "." + target_triple + ".xcent");
+        if let Err(e) = fs::write(&xcent_path, &xml) {
+            return Err(Error::io(
+                "Failed to write the app's entitlements",
+                xcent_path,
+                e,
+            ));
+        }
+        Some(xml)
+    };
+    // The hardened runtime & secure timestamps are only for notarizing macOS apps
+    let signing = &ctx.cfg.signing;
+    let timestamp_url = signing.timestamp_url.as_deref().filter(|_| macos);
+    if timestamp_url.is_some() && identity.is_some() {
+        println!("   |- Timestamping the signature...");
+    }
+    let signing_options = SigningOptions {
+        identifier: &ctx.bundle_id,
+        info_plist: Some(&info_plist),
+        code_resources: Some(&code_resources),
+        entitlements: entitlements.as_deref(),
+        identity,
+        hardened_runtime: macos && signing.hardened_runtime,
+        timestamp_url,
+        ..Default::default()
+    };
+    sign::sign_file(&new_bin_path, &signing_options)?;
 
     Ok(app_name)
 }
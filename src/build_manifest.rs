@@ -0,0 +1,82 @@
+//! The build manifest: what `cargo ipa build` made, saved as JSON next to it. It records each
+//! target's app and dSYM, and the binary's UUID, which is how crash reports say which build
+//! they came from.
+
+use {
+    crate::{context::ExportMethod, error::Error},
+    serde::{Deserialize, Serialize},
+    std::{fs, io, path::Path},
+};
+
+/// The manifest's file name, in `target/cargo-ipa`
+pub const BUILD_MANIFEST: &str = "build-manifest.json";
+
+#[derive(Serialize, Deserialize)]
+pub struct BuildManifest {
+    /// The app's human-readable name
+    pub name: String,
+    pub bundle_id: String,
+    pub version: String,
+    /// Whether the app was built in release mode
+    pub release: bool,
+    /// The example that was built, if it wasn't the package's binary
+    pub example: Option<String>,
+    /// How the app was exported, if it was
+    #[serde(default)]
+    pub export_method: Option<ExportMethod>,
+    /// When it was built, like `2026-10-18T10:10:10Z`
+    pub created: String,
+    /// The git commit it was built from, if the project's in a git repo
+    pub git_commit: Option<String>,
+    /// The git tags pointing at that commit
+    #[serde(default)]
+    pub git_tags: Vec<String>,
+    pub targets: Vec<TargetManifest>,
+}
+
+/// What was made for one target triple. Paths are relative to the manifest's folder.
+#[derive(Serialize, Deserialize)]
+pub struct TargetManifest {
+    /// The target triple, like `aarch64-apple-ios`
+    pub target: String,
+    /// The IPA or .app
+    pub artifact: String,
+    /// The dSYM with the binary's debug info, if one was made
+    #[serde(default)]
+    pub dsym: Option<String>,
+    /// The .xcarchive, if one was made
+    #[serde(default)]
+    pub archive: Option<String>,
+    /// The binary's LC_UUID, which its dSYM shares
+    pub uuid: Option<String>,
+    /// Whether the binary in the app was stripped of its symbols
+    pub stripped: bool,
+}
+
+impl BuildManifest {
+    /// Loads the manifest in `dir`
+    pub fn load(dir: &Path) -> Result<Self, Error> {
+        let path = dir.join(BUILD_MANIFEST);
+        let json = match fs::read(&path) {
+            Ok(json) => json,
+            Err(e) => return Err(Error::io("Failed to read the build manifest", path, e)),
+        };
+        serde_json::from_slice(&json).map_err(|e| {
+            Error::io(
+                "Failed to read the build manifest",
+                path,
+                io::Error::new(io::ErrorKind::InvalidData, e),
+            )
+        })
+    }
+
+    /// Saves the manifest in `dir`
+    pub fn save(&self, dir: &Path) -> Result<(), Error> {
+        let path = dir.join(BUILD_MANIFEST);
+        let json = serde_json::to_vec_pretty(self).unwrap();
+        if let Err(e) = fs::write(&path, json) {
+            return Err(Error::io("Failed to write the build manifest", path, e));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,288 @@
+//! The build archive. Every `cargo ipa build` is copied into
+//! `target/cargo-ipa/builds/<version>-<timestamp>-<commit>/`, with its apps, dSYMs, Info.plist,
+//! and manifest, so older builds can still be symbolicated or sent out after newer ones
+//! replace them in `target/cargo-ipa`.
+
+use clap::{Args, Subcommand};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    build_manifest::{BuildManifest, BUILD_MANIFEST},
+    error::Error,
+    runner::{ToolCall, ToolRunner},
+    sign::verify::format_time,
+    Ctx,
+};
+
+#[derive(Args)]
+pub struct BuildsArgs {
+    #[command(subcommand)]
+    pub command: BuildsCommand,
+    /// The app's name. If left unprovided, cargo-ipa will search for it in Cargo.toml.
+    #[arg(short, long, global = true)]
+    pub name: Option<String>,
+}
+
+#[derive(Subcommand)]
+pub enum BuildsCommand {
+    /// List the archived builds, newest first.
+    List,
+    /// Show an archived build's apps, dSYMs, and UUIDs.
+    Show {
+        /// The build's ID, or the start of it. Defaults to the newest build.
+        id: Option<String>,
+    },
+    /// Delete old builds.
+    Prune(PruneArgs),
+}
+
+#[derive(Args)]
+pub struct PruneArgs {
+    /// How many of the newest builds to keep
+    #[arg(long, default_value_t = 10)]
+    pub keep: usize,
+    /// Also keep builds of commits with a git tag, however old they are
+    #[arg(long)]
+    pub keep_tagged: bool,
+    /// Only list the builds that would be deleted
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+/// A build in the archive
+pub struct ArchivedBuild {
+    /// The build's folder name, like `1.2.0-20261018T101010Z-3f2a9c1`
+    pub id: String,
+    pub path: PathBuf,
+    pub manifest: BuildManifest,
+}
+
+pub fn builds(args: BuildsArgs) -> Result<(), Error> {
+    let ctx = Ctx::new(&args.name)?;
+    match &args.command {
+        BuildsCommand::List => {
+            let builds = list_with(&ctx)?;
+            if builds.is_empty() {
+                println!("|- There aren't any builds yet. `cargo ipa build` makes them.");
+            }
+            for build in builds {
+                let manifest = &build.manifest;
+                let mut details =
+                    vec![if manifest.release { "release" } else { "debug" }.to_string()];
+                if let Some(example) = &manifest.example {
+                    details.push(format!("example `{example}`"));
+                }
+                if !manifest.git_tags.is_empty() {
+                    details.push(format!("tagged {}", manifest.git_tags.join(", ")));
+                }
+                println!("|- {} ({})", build.id, details.join(", "));
+            }
+        }
+        BuildsCommand::Show { id } => {
+            let build = show_with(&ctx, id.as_deref())?;
+            let manifest = &build.manifest;
+            println!("|- {} {}", manifest.name, manifest.version);
+            println!("   |- Path: {}", build.path.display());
+            println!("   |- Built: {}", manifest.created);
+            if let Some(commit) = &manifest.git_commit {
+                println!("   |- Commit: {commit}");
+            }
+            if !manifest.git_tags.is_empty() {
+                println!("   |- Tags: {}", manifest.git_tags.join(", "));
+            }
+            for target in &manifest.targets {
+                println!("   |- {}", target.target);
+                println!("      |- App: {}", target.artifact);
+                if let Some(dsym) = &target.dsym {
+                    println!("      |- dSYM: {dsym}");
+                }
+                if let Some(uuid) = &target.uuid {
+                    println!("      |- UUID: {uuid}");
+                }
+            }
+        }
+        BuildsCommand::Prune(prune_args) => {
+            let removed = prune_with(&ctx, prune_args)?;
+            let verb = if prune_args.dry_run {
+                "Would delete"
+            } else {
+                "Deleted"
+            };
+            for id in &removed {
+                println!("|- {verb} `{id}`");
+            }
+            println!("{verb} {} build(s)", removed.len());
+        }
+    }
+    Ok(())
+}
+
+/// Where archived builds are kept
+pub fn builds_dir(ctx: &Ctx) -> PathBuf {
+    ctx.cargo_ipa_dir.join("builds")
+}
+
+/// The commit the project is at, and the tags pointing at it. Projects that aren't in a git
+/// repo (or machines without git) don't have one.
+pub fn git_info(ctx: &Ctx, runner: &dyn ToolRunner) -> (Option<String>, Vec<String>) {
+    let git = |args: &[&str]| {
+        let call = ToolCall::new("git", args).current_dir(&ctx.root_dir);
+        match runner.execute(&call) {
+            Ok(output) if output.success => Some(output.stdout),
+            _ => None,
+        }
+    };
+    let Some(commit) = git(&["rev-parse", "--short", "HEAD"]) else {
+        return (None, Vec::new());
+    };
+    let tags = git(&["tag", "--points-at", "HEAD"]).unwrap_or_default();
+    (
+        Some(commit.trim().to_owned()),
+        tags.lines().map(str::to_owned).collect(),
+    )
+}
+
+/// The time a build was made, like `2026-10-18T10:10:10Z`
+pub fn created_now() -> String {
+    // Without the fractions of a second, which would make IDs longer than they need to be
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    format_time(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+/// Copies a finished build's apps, dSYMs, Info.plist, and manifest from `target/cargo-ipa`
+/// into the archive. Returns its folder.
+pub fn archive(ctx: &Ctx, manifest: &BuildManifest) -> Result<PathBuf, Error> {
+    // `2026-10-18T10:10:10Z` becomes `20261018T101010Z`, so IDs sort by when they were built
+    let mut id = format!(
+        "{}-{}",
+        manifest.version,
+        manifest.created.replace(['-', ':'], "")
+    );
+    if let Some(commit) = &manifest.git_commit {
+        id = id + "-" + commit;
+    }
+    let build_dir = builds_dir(ctx).join(id);
+    // The same commit, built twice in a second, is the same build
+    if build_dir.exists() {
+        if let Err(e) = fs::remove_dir_all(&build_dir) {
+            return Err(Error::io(
+                "Build already exists in the archive, and can't be removed",
+                build_dir,
+                e,
+            ));
+        }
+    }
+
+    let mut files = vec!["Info.plist", BUILD_MANIFEST];
+    for target in &manifest.targets {
+        files.push(&target.artifact);
+        files.extend(target.dsym.as_deref());
+        files.extend(target.archive.as_deref());
+    }
+    for file in files {
+        let from = ctx.cargo_ipa_dir.join(file);
+        let to = build_dir.join(file);
+        if let Err(e) = copy_all(&from, &to) {
+            return Err(Error::io("Failed to archive the build", from, e));
+        }
+    }
+    Ok(build_dir)
+}
+
+/// Copies a file, or a folder and everything in it
+pub(crate) fn copy_all(from: &Path, to: &Path) -> io::Result<()> {
+    if from.is_dir() {
+        fs::create_dir_all(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_all(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else {
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+/// Every archived build, newest first. Folders without a manifest are skipped.
+pub fn list_with(ctx: &Ctx) -> Result<Vec<ArchivedBuild>, Error> {
+    let dir = builds_dir(ctx);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(Error::io("Failed to read the build archive", dir, e)),
+    };
+    let mut builds = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !path.join(BUILD_MANIFEST).is_file() {
+            continue;
+        }
+        builds.push(ArchivedBuild {
+            id: entry.file_name().to_string_lossy().into_owned(),
+            manifest: BuildManifest::load(&path)?,
+            path,
+        });
+    }
+    builds.sort_by(|a, b| (&b.manifest.created, &b.id).cmp(&(&a.manifest.created, &a.id)));
+    Ok(builds)
+}
+
+/// Finds the archived build with `id`, or the only one starting with it. Without an ID, it's
+/// the newest build.
+pub fn show_with(ctx: &Ctx, id: Option<&str>) -> Result<ArchivedBuild, Error> {
+    let builds = list_with(ctx)?;
+    let Some(id) = id else {
+        return builds.into_iter().next().ok_or_else(|| {
+            Error::Config("There aren't any builds yet. `cargo ipa build` makes them.".into())
+        });
+    };
+    if let Some(i) = builds.iter().position(|build| build.id == id) {
+        return Ok(builds.into_iter().nth(i).unwrap());
+    }
+    let mut matches: Vec<_> = builds
+        .into_iter()
+        .filter(|build| build.id.starts_with(id))
+        .collect();
+    match matches.len() {
+        1 => Ok(matches.remove(0)),
+        0 => Err(Error::Config(format!(
+            "There's no build `{id}`. `cargo ipa builds list` lists them."
+        ))),
+        _ => Err(Error::Config(format!(
+            "More than one build starts with `{id}`: {}",
+            matches
+                .iter()
+                .map(|build| format!("`{}`", build.id))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+    }
+}
+
+/// Deletes every archived build but the newest `args.keep` (and tagged ones, with
+/// `args.keep_tagged`). Returns the IDs of the deleted builds, oldest last.
+pub fn prune_with(ctx: &Ctx, args: &PruneArgs) -> Result<Vec<String>, Error> {
+    let mut removed = Vec::new();
+    for build in list_with(ctx)?.into_iter().skip(args.keep) {
+        if args.keep_tagged && !build.manifest.git_tags.is_empty() {
+            continue;
+        }
+        if !args.dry_run {
+            if let Err(e) = fs::remove_dir_all(&build.path) {
+                return Err(Error::io("Failed to delete the build", build.path, e));
+            }
+        }
+        removed.push(build.id);
+    }
+    Ok(removed)
+}
@@ -0,0 +1,380 @@
+use crate::{context::ExportMethod, error::Error};
+use serde::{
+    de::{self, IgnoredAny, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+use toml::Spanned;
+
+/// Every setting that can go in the [package.metadata.cargo-ipa] section.
+/// Keys that aren't in this list get a warning when Cargo.toml is loaded.
+const KEYS: &[&str] = &[
+    "app-store-connect",
+    "bundle-id",
+    "capabilities",
+    "entitlements",
+    "name",
+    "properties",
+    "signing",
+    "swift-bridges",
+    "swift-library",
+];
+/// Every setting that can go in the [package.metadata.cargo-ipa.signing] section
+const SIGNING_KEYS: &[&str] = &[
+    "ad-hoc",
+    "app-store",
+    "certificate-chain",
+    "development",
+    "devices",
+    "enterprise",
+    "hardened-runtime",
+    "identity",
+    "password-file",
+    "provisioning-profile",
+    "runtime-exceptions",
+    "timestamp-url",
+];
+/// Every setting that can go in a [package.metadata.cargo-ipa.signing.<export method>] section
+const EXPORT_SIGNING_KEYS: &[&str] = &[
+    "certificate-chain",
+    "identity",
+    "password-file",
+    "provisioning-profile",
+];
+/// Every setting that can go in the [package.metadata.cargo-ipa.app-store-connect] section
+const APP_STORE_CONNECT_KEYS: &[&str] = &["api-issuer", "api-key", "api-key-id", "app-id"];
+
+/// The settings in the [package.metadata.cargo-ipa] section of Cargo.toml
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", expecting = "a table of cargo-ipa settings")]
+pub struct Config {
+    /// The app's human-readable name (CFBundleName)
+    pub name: Option<String>,
+    /// The app's reverse-DNS bundle ID (CFBundleIdentifier)
+    pub bundle_id: Option<String>,
+    /// Extra values to set (or override) in the Info.plist
+    #[serde(default)]
+    pub properties: BTreeMap<String, plist::Value>,
+    /// Entitlements to give the app, on top of the ones from `capabilities`
+    #[serde(default)]
+    pub entitlements: BTreeMap<String, plist::Value>,
+    /// Shorthands for common entitlements, like `push` or `app-groups:group.com.example.app`
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    /// How to sign the app. If blank, the app is ad-hoc signed.
+    #[serde(default)]
+    pub signing: SigningConfig,
+    /// Rust files that use swift-bridge's FFI, relative to the project root
+    pub swift_bridges: Option<Vec<PathBuf>>,
+    /// The Swift package to compile, relative to the project root
+    pub swift_library: Option<PathBuf>,
+    /// How to reach App Store Connect, for uploading builds
+    #[serde(default)]
+    pub app_store_connect: AppStoreConnectConfig,
+}
+
+/// The settings in the [package.metadata.cargo-ipa.signing] section of Cargo.toml
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", expecting = "a table of signing settings")]
+pub struct SigningConfig {
+    /// The .p12 file with the certificate & private key to sign with, relative to the project root
+    pub identity: Option<PathBuf>,
+    /// A file containing the .p12 file's password, relative to the project root
+    pub password_file: Option<PathBuf>,
+    /// Certificates that issued the identity's certificate (like Apple's WWDR certificate),
+    /// relative to the project root. These are included in the signature.
+    #[serde(default)]
+    pub certificate_chain: Vec<PathBuf>,
+    /// The provisioning profile to embed in the app, relative to the project root
+    pub provisioning_profile: Option<PathBuf>,
+    /// UDIDs of devices the app will be installed on, which have to be in the profile
+    #[serde(default)]
+    pub devices: Vec<String>,
+    /// Turns on the hardened runtime for macOS apps, which notarization requires
+    #[serde(default)]
+    pub hardened_runtime: bool,
+    /// Things the hardened runtime normally blocks that the app needs, like `allow-jit`
+    #[serde(default)]
+    pub runtime_exceptions: Vec<String>,
+    /// An RFC 3161 timestamp authority to timestamp macOS apps' signatures with, like
+    /// `http://timestamp.apple.com/ts01`
+    pub timestamp_url: Option<String>,
+    /// Settings that replace the ones above for `--export-method development`
+    pub development: Option<ExportSigningConfig>,
+    /// Settings that replace the ones above for `--export-method ad-hoc`
+    pub ad_hoc: Option<ExportSigningConfig>,
+    /// Settings that replace the ones above for `--export-method enterprise`
+    pub enterprise: Option<ExportSigningConfig>,
+    /// Settings that replace the ones above for `--export-method app-store`
+    pub app_store: Option<ExportSigningConfig>,
+}
+impl SigningConfig {
+    /// Replaces the identity & profile with the ones set up for an export method, if any
+    pub fn use_export_method(&mut self, method: ExportMethod) {
+        let overrides = match method {
+            ExportMethod::Development => self.development.take(),
+            ExportMethod::AdHoc => self.ad_hoc.take(),
+            ExportMethod::Enterprise => self.enterprise.take(),
+            ExportMethod::AppStore => self.app_store.take(),
+        };
+        let Some(overrides) = overrides else {
+            return;
+        };
+        if overrides.identity.is_some() {
+            self.identity = overrides.identity;
+            self.password_file = overrides.password_file;
+        }
+        if let Some(chain) = overrides.certificate_chain {
+            self.certificate_chain = chain;
+        }
+        if overrides.provisioning_profile.is_some() {
+            self.provisioning_profile = overrides.provisioning_profile;
+        }
+    }
+}
+
+/// The settings in a [package.metadata.cargo-ipa.signing.<export method>] section of
+/// Cargo.toml. They're all relative to the project root.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", expecting = "a table of signing settings")]
+pub struct ExportSigningConfig {
+    pub identity: Option<PathBuf>,
+    pub password_file: Option<PathBuf>,
+    pub certificate_chain: Option<Vec<PathBuf>>,
+    pub provisioning_profile: Option<PathBuf>,
+}
+
+/// The settings in the [package.metadata.cargo-ipa.app-store-connect] section of Cargo.toml.
+/// The `CARGO_IPA_API_*` environment variables override these, so CI can keep the key secret.
+#[derive(Deserialize, Default)]
+#[serde(
+    rename_all = "kebab-case",
+    expecting = "a table of App Store Connect settings"
+)]
+pub struct AppStoreConnectConfig {
+    /// The App Store Connect API key (`AuthKey_<key ID>.p8`), relative to the project root
+    pub api_key: Option<PathBuf>,
+    /// The API key's ID. Defaults to the one in the key's file name.
+    pub api_key_id: Option<String>,
+    /// The API key's issuer ID, from the Keys tab in App Store Connect
+    pub api_issuer: Option<String>,
+    /// The app's Apple ID in App Store Connect. If blank, it's looked up by bundle ID.
+    pub app_id: Option<String>,
+}
+
+/// The parts of Cargo.toml that cargo-ipa cares about
+pub struct Manifest {
+    /// The package's name
+    pub name: String,
+    /// The package's version
+    pub version: String,
+    /// The cargo-ipa settings, or the defaults if there's no cargo-ipa section
+    pub config: Config,
+    /// Problems that don't stop the build, like unknown settings
+    pub warnings: Vec<String>,
+}
+
+// The layout of Cargo.toml, minus everything cargo-ipa doesn't use
+#[derive(Deserialize)]
+struct RawManifest {
+    package: RawPackage,
+}
+#[derive(Deserialize)]
+struct RawPackage {
+    name: String,
+    version: String,
+    #[serde(default)]
+    metadata: RawMetadata,
+}
+#[derive(Deserialize, Default)]
+struct RawMetadata {
+    #[serde(rename = "cargo-ipa")]
+    cargo_ipa: Option<Config>,
+}
+
+// The same layout again, but only keeping the cargo-ipa keys and where they are
+#[derive(Deserialize)]
+struct KeysManifest {
+    package: KeysPackage,
+}
+#[derive(Deserialize)]
+struct KeysPackage {
+    #[serde(default)]
+    metadata: KeysMetadata,
+}
+#[derive(Deserialize, Default)]
+struct KeysMetadata {
+    #[serde(rename = "cargo-ipa", default)]
+    cargo_ipa: Keys,
+}
+
+/// The keys in a table and where they are, with the keys of any tables inside it
+#[derive(Default)]
+struct Keys(Vec<(Spanned<String>, Option<Keys>)>);
+impl<'de> Deserialize<'de> for Keys {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(deserializer
+            .deserialize_any(KeysVisitor)?
+            .unwrap_or_default())
+    }
+}
+
+/// Visits any value, keeping the keys of tables and skipping everything else
+struct KeysVisitor;
+impl<'de> Visitor<'de> for KeysVisitor {
+    type Value = Option<Keys>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("any value")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut keys = Vec::new();
+        while let Some(key) = map.next_key()? {
+            keys.push((key, map.next_value::<KeysValue>()?.0));
+        }
+        Ok(Some(Keys(keys)))
+    }
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        while seq.next_element::<IgnoredAny>()?.is_some() {}
+        Ok(None)
+    }
+    fn visit_bool<E: de::Error>(self, _: bool) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+    fn visit_i64<E: de::Error>(self, _: i64) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+    fn visit_u64<E: de::Error>(self, _: u64) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+    fn visit_f64<E: de::Error>(self, _: f64) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+    fn visit_str<E: de::Error>(self, _: &str) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+}
+/// A value in a [`Keys`] table: its own keys, if it's a table
+struct KeysValue(Option<Keys>);
+impl<'de> Deserialize<'de> for KeysValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(KeysVisitor).map(KeysValue)
+    }
+}
+
+/// Reads and validates Cargo.toml
+pub fn load(cargo_toml: &Path) -> Result<Manifest, Error> {
+    let source = match fs::read_to_string(cargo_toml) {
+        Ok(source) => source,
+        Err(e) => return Err(Error::io("Failed to read Cargo.toml", cargo_toml, e)),
+    };
+    parse(&source)
+}
+
+/// Parses and validates the contents of a Cargo.toml file
+pub fn parse(source: &str) -> Result<Manifest, Error> {
+    let raw: RawManifest = match serde_path_to_error::deserialize(toml::Deserializer::new(source)) {
+        Ok(raw) => raw,
+        Err(e) => {
+            let path = e.path().to_string();
+            let e = e.into_inner();
+            let line = e.span().map(|span| line_of(source, span));
+            return Err(Error::Config(describe_error(&path, line, e.message())));
+        }
+    };
+
+    // The first pass already made sure this parses, so this won't fail
+    let keys: KeysManifest = toml::from_str(source).map_err(|e| Error::Config(e.to_string()))?;
+    let mut warnings = Vec::new();
+    check_keys(
+        &keys.package.metadata.cargo_ipa,
+        "package.metadata.cargo-ipa",
+        source,
+        &mut warnings,
+    );
+
+    Ok(Manifest {
+        name: raw.package.name,
+        version: raw.package.version,
+        config: raw.package.metadata.cargo_ipa.unwrap_or_default(),
+        warnings,
+    })
+}
+
+/// Builds the error message for a setting that failed to deserialize
+fn describe_error(path: &str, line: Option<usize>, message: &str) -> String {
+    let mut buffer = String::from("Invalid Cargo.toml detected!");
+    // serde_path_to_error uses "." when the error isn't in a specific key
+    if path != "." {
+        buffer += &format!(" At `{path}`");
+        if let Some(line) = line {
+            buffer += &format!(" (line {line})");
+        }
+        buffer += ":";
+    } else if let Some(line) = line {
+        buffer += &format!(" At line {line}:");
+    }
+    buffer += " ";
+    buffer += message.trim_end();
+    buffer
+}
+
+/// Warns about every key in the `table` at `path` that isn't a setting, then does the same for
+/// the tables of settings inside it. Free-form tables, like `properties`, aren't checked.
+fn check_keys(table: &Keys, path: &str, source: &str, warnings: &mut Vec<String>) {
+    let known = match path {
+        "package.metadata.cargo-ipa" => KEYS,
+        "package.metadata.cargo-ipa.signing" => SIGNING_KEYS,
+        "package.metadata.cargo-ipa.signing.development"
+        | "package.metadata.cargo-ipa.signing.ad-hoc"
+        | "package.metadata.cargo-ipa.signing.enterprise"
+        | "package.metadata.cargo-ipa.signing.app-store" => EXPORT_SIGNING_KEYS,
+        "package.metadata.cargo-ipa.app-store-connect" => APP_STORE_CONNECT_KEYS,
+        _ => return,
+    };
+    for (key, subtable) in &table.0 {
+        let name = key.get_ref();
+        if !known.contains(&name.as_str()) {
+            let line = line_of(source, key.span());
+            warnings.push(unknown_key_warning(path, name, line, known));
+        } else if let Some(subtable) = subtable {
+            check_keys(subtable, &format!("{path}.{name}"), source, warnings);
+        }
+    }
+}
+
+/// Builds the warning for an unknown key in the table at `path`, with a suggestion if there's
+/// a similarly named setting in `known`
+fn unknown_key_warning(path: &str, key: &str, line: usize, known: &[&str]) -> String {
+    let mut warning = format!("Unknown setting `{path}.{key}` (line {line}) will be ignored.");
+    if let Some(suggestion) = suggest(key, known) {
+        warning += &format!(" Did you mean `{suggestion}`?");
+    }
+    warning
+}
+
+/// Finds the name in `known` that's closest to `name`, if any are close enough. Used to
+/// suggest fixes for typos in settings, capabilities, and the like.
+pub(crate) fn suggest<'a>(name: &str, known: &[&'a str]) -> Option<&'a str> {
+    known
+        .iter()
+        .map(|known| (*known, strsim::jaro_winkler(name, known)))
+        .filter(|(_, similarity)| *similarity > 0.8)
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(known, _)| known)
+}
+
+/// Converts a byte span in `source` into a (1-indexed) line number
+fn line_of(source: &str, span: Range<usize>) -> usize {
+    source[..span.start.min(source.len())]
+        .bytes()
+        .filter(|byte| *byte == b'\n')
+        .count()
+        + 1
+}
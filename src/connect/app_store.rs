@@ -0,0 +1,251 @@
+//! A client for App Store Connect's build uploads, which is what Transporter and `altool` use
+//! to send builds to TestFlight. A build upload is created for the app's version, its IPA is
+//! uploaded in the chunks App Store Connect asks for, and then it's committed and processed.
+//! See: https://developer.apple.com/documentation/appstoreconnectapi/build-uploads
+
+use {
+    super::{send, ApiKey, Data, DataList},
+    crate::{error::Error, sign::verify::hex},
+    md5::{Digest, Md5},
+    serde::Deserialize,
+    serde_json::json,
+    std::time::SystemTime,
+};
+
+/// Where the App Store Connect API is
+pub const API_URL: &str = "https://api.appstoreconnect.apple.com";
+const SERVICE: &str = "App Store Connect";
+
+pub struct AppStoreClient<'a> {
+    key: &'a ApiKey,
+    /// The API's base URL, normally [`API_URL`]
+    pub api_url: String,
+}
+
+/// The version of an app that's being uploaded
+pub struct BuildVersion {
+    /// CFBundleShortVersionString, eg `1.2.0`
+    pub version: String,
+    /// CFBundleVersion, which has to be new for every upload of a version
+    pub build: String,
+    /// `IOS`, `MAC_OS`, `TV_OS`, or `VISION_OS`
+    pub platform: String,
+}
+
+/// A file that's been reserved in a build upload, and how to upload it
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildUploadFile {
+    #[serde(default)]
+    pub upload_operations: Vec<UploadOperation>,
+}
+
+/// One chunk of a file, and the request that uploads it
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadOperation {
+    pub method: String,
+    pub url: String,
+    pub offset: usize,
+    pub length: usize,
+    #[serde(default)]
+    pub request_headers: Vec<HttpHeader>,
+}
+#[derive(Deserialize, Debug)]
+pub struct HttpHeader {
+    pub name: String,
+    pub value: String,
+}
+
+/// How a build upload is going. `state` is `AWAITING_UPLOAD`, `PROCESSING`, `FAILED`, or
+/// `COMPLETE`.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildUploadState {
+    pub state: String,
+    #[serde(default)]
+    pub errors: Vec<StateDetail>,
+    #[serde(default)]
+    pub warnings: Vec<StateDetail>,
+}
+
+/// Something App Store Connect found while processing a build
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StateDetail {
+    pub code: Option<String>,
+    pub description: Option<String>,
+}
+impl StateDetail {
+    /// The detail as one line
+    pub fn describe(&self) -> String {
+        match (&self.code, &self.description) {
+            (Some(code), Some(description)) => format!("{description} ({code})"),
+            (None, Some(description)) => description.clone(),
+            (Some(code), None) => code.clone(),
+            (None, None) => "No details were given".into(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct App {
+    bundle_id: String,
+}
+#[derive(Deserialize)]
+struct BuildUpload {
+    state: BuildUploadState,
+}
+
+impl<'a> AppStoreClient<'a> {
+    pub fn new(key: &'a ApiKey) -> Self {
+        Self {
+            key,
+            api_url: API_URL.into(),
+        }
+    }
+
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        let token = self.key.token(SystemTime::now());
+        ureq::request(method, &format!("{}/v1{path}", self.api_url))
+            .set("Authorization", &format!("Bearer {token}"))
+    }
+
+    /// Looks up an app's Apple ID by its bundle ID
+    pub fn find_app(&self, bundle_id: &str) -> Result<String, Error> {
+        let request = self
+            .request("GET", "/apps")
+            .query("filter[bundleId]", bundle_id);
+        let response: DataList<App> = send(SERVICE, request, None)?;
+        // The filter also matches bundle IDs that start with this one
+        match response
+            .data
+            .into_iter()
+            .find(|app| app.attributes.bundle_id == bundle_id)
+        {
+            Some(app) => Ok(app.id),
+            None => Err(Error::Config(format!(
+                "There's no app with the bundle ID `{bundle_id}` in App Store Connect. Create it there first, or set `app-id` in the [package.metadata.cargo-ipa.app-store-connect] section of Cargo.toml."
+            ))),
+        }
+    }
+
+    /// Starts a build upload for a version of an app. Returns its ID.
+    pub fn create_build_upload(
+        &self,
+        app_id: &str,
+        version: &BuildVersion,
+    ) -> Result<String, Error> {
+        let body = json!({ "data": {
+            "type": "buildUploads",
+            "attributes": {
+                "cfBundleShortVersionString": version.version,
+                "cfBundleVersion": version.build,
+                "platform": version.platform,
+            },
+            "relationships": {
+                "app": { "data": { "type": "apps", "id": app_id } },
+            },
+        }});
+        let response: Data<serde_json::Value> =
+            send(SERVICE, self.request("POST", "/buildUploads"), Some(body))?;
+        Ok(response.data.id)
+    }
+
+    /// Reserves a file in a build upload. Returns its ID, and the chunks to upload it in.
+    pub fn reserve_file(
+        &self,
+        upload_id: &str,
+        file_name: &str,
+        file_size: usize,
+    ) -> Result<(String, BuildUploadFile), Error> {
+        let body = json!({ "data": {
+            "type": "buildUploadFiles",
+            "attributes": {
+                "assetType": "ASSET",
+                "fileName": file_name,
+                "fileSize": file_size,
+                "uti": "com.apple.ipa",
+            },
+            "relationships": {
+                "buildUpload": { "data": { "type": "buildUploads", "id": upload_id } },
+            },
+        }});
+        let response: Data<BuildUploadFile> = send(
+            SERVICE,
+            self.request("POST", "/buildUploadFiles"),
+            Some(body),
+        )?;
+        Ok((response.data.id, response.data.attributes))
+    }
+
+    /// Uploads one chunk of a file. The operation's URL is pre-signed, so it doesn't need the
+    /// token.
+    pub fn upload_part(&self, operation: &UploadOperation, data: &[u8]) -> Result<(), Error> {
+        let Some(part) = data.get(operation.offset..operation.offset + operation.length) else {
+            return Err(Error::Service(format!(
+                "{SERVICE} asked for bytes {}..{} of the file, but it's only {} bytes long",
+                operation.offset,
+                operation.offset + operation.length,
+                data.len()
+            )));
+        };
+        let mut request = ureq::request(&operation.method, &operation.url);
+        for header in &operation.request_headers {
+            request = request.set(&header.name, &header.value);
+        }
+        match request.send_bytes(part) {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(code, response)) => {
+                let body = response.into_string().unwrap_or_default();
+                Err(Error::Service(format!(
+                    "Uploading bytes {}..{} failed with HTTP {code}: {}",
+                    operation.offset,
+                    operation.offset + operation.length,
+                    body.trim()
+                )))
+            }
+            Err(e) => Err(Error::Service(format!(
+                "Couldn't upload the build to `{}`. Check your internet connection. ({e})",
+                operation.url
+            ))),
+        }
+    }
+
+    /// Marks a file as uploaded, with its checksum so App Store Connect can check it arrived
+    /// intact. This starts processing the build.
+    pub fn commit_file(&self, file_id: &str, md5: &str) -> Result<(), Error> {
+        let body = json!({ "data": {
+            "type": "buildUploadFiles",
+            "id": file_id,
+            "attributes": {
+                "uploaded": true,
+                "sourceFileChecksums": {
+                    "file": { "hash": md5, "algorithm": "MD5" },
+                },
+            },
+        }});
+        let _: Data<serde_json::Value> = send(
+            SERVICE,
+            self.request("PATCH", &format!("/buildUploadFiles/{file_id}")),
+            Some(body),
+        )?;
+        Ok(())
+    }
+
+    /// Checks on a build upload
+    pub fn state(&self, upload_id: &str) -> Result<BuildUploadState, Error> {
+        let response: Data<BuildUpload> = send(
+            SERVICE,
+            self.request("GET", &format!("/buildUploads/{upload_id}")),
+            None,
+        )?;
+        Ok(response.data.attributes.state)
+    }
+}
+
+/// The MD5 hash App Store Connect checks uploads against, in hex
+pub fn file_checksum(data: &[u8]) -> String {
+    hex(&Md5::digest(data))
+}
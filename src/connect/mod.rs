@@ -0,0 +1,197 @@
+//! Talking to Apple's services for distributing apps, like the notary service. Most of them
+//! authenticate with an App Store Connect API key.
+//! See: https://developer.apple.com/documentation/appstoreconnectapi/generating-tokens-for-api-requests
+
+pub mod app_store;
+pub mod notary;
+pub mod ticket;
+
+use {
+    crate::error::Error,
+    base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine},
+    p256::{
+        ecdsa::{signature::Signer, Signature, SigningKey},
+        pkcs8::DecodePrivateKey,
+    },
+    serde::{de::DeserializeOwned, Deserialize},
+    std::{
+        env, fs,
+        io::Read,
+        path::{Path, PathBuf},
+        time::{SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// Overrides the API key's path
+pub const API_KEY_ENV: &str = "CARGO_IPA_API_KEY";
+/// Overrides the API key's ID
+pub const API_KEY_ID_ENV: &str = "CARGO_IPA_API_KEY_ID";
+/// Overrides the API key's issuer ID
+pub const API_ISSUER_ENV: &str = "CARGO_IPA_API_ISSUER";
+/// How long tokens last. Apple doesn't accept tokens that last longer than 20 minutes.
+const TOKEN_LIFETIME: u64 = 20 * 60;
+
+/// An App Store Connect API key, downloaded from the Users and Access page as a
+/// `AuthKey_<key ID>.p8` file
+pub struct ApiKey {
+    pub key_id: String,
+    pub issuer_id: String,
+    key: SigningKey,
+}
+impl ApiKey {
+    /// Loads an API key, letting the `CARGO_IPA_API_*` environment variables override the
+    /// given settings. The key ID defaults to the one in the file's name.
+    pub fn load(
+        path: Option<PathBuf>,
+        key_id: Option<String>,
+        issuer_id: Option<String>,
+    ) -> Result<Self, Error> {
+        let Some(path) = env::var_os(API_KEY_ENV).map(PathBuf::from).or(path) else {
+            return Err(Error::Config(format!(
+                "No App Store Connect API key was given. Pass `--api-key`, or set `{API_KEY_ENV}`."
+            )));
+        };
+        let key_id = env::var(API_KEY_ID_ENV)
+            .ok()
+            .or(key_id)
+            .or_else(|| key_id_from_file_name(&path));
+        let Some(key_id) = key_id else {
+            return Err(Error::Config(format!(
+                "The API key's ID couldn't be worked out from its file name (`{}`). Pass `--api-key-id`, or set `{API_KEY_ID_ENV}`.",
+                path.display()
+            )));
+        };
+        let Some(issuer_id) = env::var(API_ISSUER_ENV).ok().or(issuer_id) else {
+            return Err(Error::Config(format!(
+                "The API key's issuer ID wasn't given. It's at the top of the Keys tab in App Store Connect; pass `--api-issuer`, or set `{API_ISSUER_ENV}`."
+            )));
+        };
+
+        let pem = match fs::read_to_string(&path) {
+            Ok(pem) => pem,
+            Err(e) => return Err(Error::io("Failed to read the API key", path, e)),
+        };
+        let key = match SigningKey::from_pkcs8_pem(&pem) {
+            Ok(key) => key,
+            Err(e) => {
+                return Err(Error::Config(format!(
+                    "`{}` isn't an App Store Connect API key ({e}). It should be the `.p8` file App Store Connect gave you.",
+                    path.display()
+                )))
+            }
+        };
+        Ok(Self {
+            key_id,
+            issuer_id,
+            key,
+        })
+    }
+
+    /// Makes a signed JSON Web Token for API requests, valid from `now`
+    pub fn token(&self, now: SystemTime) -> String {
+        let issued_at = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let header = serde_json::json!({
+            "alg": "ES256",
+            "kid": self.key_id,
+            "typ": "JWT",
+        });
+        let claims = serde_json::json!({
+            "iss": self.issuer_id,
+            "iat": issued_at,
+            "exp": issued_at + TOKEN_LIFETIME,
+            "aud": "appstoreconnect-v1",
+        });
+        let message = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header.to_string()),
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        );
+        // JWTs use the raw r & s values, not DER
+        let signature: Signature = self.key.sign(message.as_bytes());
+        format!("{message}.{}", URL_SAFE_NO_PAD.encode(signature.to_bytes()))
+    }
+}
+
+/// Gets the key ID out of an `AuthKey_<key ID>.p8` file name
+fn key_id_from_file_name(path: &Path) -> Option<String> {
+    let stem = path.file_stem()?.to_str()?;
+    stem.strip_prefix("AuthKey_").map(str::to_owned)
+}
+
+/// The errors App Store Connect services send back
+#[derive(Deserialize)]
+struct ErrorResponse {
+    errors: Vec<ErrorItem>,
+}
+#[derive(Deserialize)]
+struct ErrorItem {
+    title: Option<String>,
+    detail: Option<String>,
+}
+
+// App Store Connect services wrap everything in JSON:API `data` objects
+#[derive(Deserialize)]
+pub(crate) struct Data<T> {
+    pub data: Resource<T>,
+}
+#[derive(Deserialize)]
+pub(crate) struct DataList<T> {
+    pub data: Vec<Resource<T>>,
+}
+#[derive(Deserialize)]
+pub(crate) struct Resource<T> {
+    pub id: String,
+    pub attributes: T,
+}
+
+/// Sends a request to an App Store Connect service, and reads the JSON it sends back. `service`
+/// names the service in errors, eg "the notary service".
+pub(crate) fn send<T: DeserializeOwned>(
+    service: &str,
+    request: ureq::Request,
+    body: Option<serde_json::Value>,
+) -> Result<T, Error> {
+    let url = request.url().to_owned();
+    let response = match body {
+        Some(body) => request
+            .set("Content-Type", "application/json")
+            .send_bytes(body.to_string().as_bytes()),
+        None => request.call(),
+    };
+    let response = match response {
+        Ok(response) => response,
+        Err(ureq::Error::Status(code, response)) => {
+            let mut message = format!("{service} responded with HTTP {code}");
+            if let Ok(ErrorResponse { errors }) = serde_json::from_reader(response.into_reader()) {
+                for error in errors {
+                    let text = [error.title, error.detail]
+                        .into_iter()
+                        .flatten()
+                        .collect::<Vec<_>>()
+                        .join(": ");
+                    message += &format!("\n  - {text}");
+                }
+            }
+            if code == 401 {
+                message += "\nCheck the API key, its ID, and its issuer ID are right, and that the key hasn't been revoked.";
+            }
+            return Err(Error::Service(message));
+        }
+        Err(e) => {
+            return Err(Error::Service(format!(
+                "Couldn't reach {service} (`{url}`). Check your internet connection. ({e})"
+            )))
+        }
+    };
+    let mut body = Vec::new();
+    if let Err(e) = response.into_reader().take(16 << 20).read_to_end(&mut body) {
+        return Err(Error::Service(format!(
+            "Failed to read {service}'s response: {e}"
+        )));
+    }
+    serde_json::from_slice(&body).map_err(|e| {
+        Error::Service(format!(
+            "{service} sent a response cargo-ipa doesn't understand ({e})"
+        ))
+    })
+}
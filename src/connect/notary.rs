@@ -0,0 +1,267 @@
+//! A client for Apple's notary service, which is what `notarytool` uses under the hood.
+//! Submissions are uploaded to an S3 bucket with temporary credentials the service hands out.
+//! See: https://developer.apple.com/documentation/notaryapi
+
+use {
+    super::{send, ApiKey, Data},
+    crate::{
+        error::Error,
+        sign::verify::{format_time, hex},
+    },
+    hmac::{Hmac, Mac},
+    serde::Deserialize,
+    sha2::{Digest, Sha256},
+    std::time::SystemTime,
+};
+
+/// Where the notary service is
+pub const NOTARY_URL: &str = "https://appstoreconnect.apple.com/notary/v2";
+/// Where submissions are uploaded to. Buckets are addressed by path, so a mock server can
+/// stand in for S3.
+pub const UPLOAD_URL: &str = "https://s3.us-west-2.amazonaws.com";
+/// The region the notary service's bucket is in
+const UPLOAD_REGION: &str = "us-west-2";
+const SERVICE: &str = "The notary service";
+
+pub struct NotaryClient<'a> {
+    key: &'a ApiKey,
+    /// The notary API's base URL, normally [`NOTARY_URL`]
+    pub notary_url: String,
+    /// S3's base URL, normally [`UPLOAD_URL`]
+    pub upload_url: String,
+}
+
+/// A new submission, and the temporary credentials for uploading it
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NewSubmission {
+    pub aws_access_key_id: String,
+    pub aws_secret_access_key: String,
+    pub aws_session_token: String,
+    pub bucket: String,
+    pub object: String,
+}
+
+/// How a submission is going. `status` is `In Progress`, `Accepted`, `Invalid`, or `Rejected`.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SubmissionStatus {
+    pub status: String,
+    pub name: Option<String>,
+}
+
+/// The notary service's log for a submission
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NotaryLog {
+    pub status: Option<String>,
+    pub status_summary: Option<String>,
+    pub issues: Option<Vec<NotaryIssue>>,
+}
+
+/// Something the notary service found wrong (or worth a warning) in the submission
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NotaryIssue {
+    /// `error` or `warning`
+    pub severity: String,
+    /// Where the problem is, starting with the submission's name, eg
+    /// `My App.zip/My App.app/Contents/MacOS/my-app`
+    pub path: Option<String>,
+    pub message: String,
+    pub doc_url: Option<String>,
+    pub architecture: Option<String>,
+}
+impl NotaryIssue {
+    /// The issue as one line, with its path relative to the bundle that was submitted
+    pub fn describe(&self, submission_name: &str) -> String {
+        let mut text = match self.path {
+            Some(ref path) => {
+                // Paths start with the submission, then the app inside it
+                let path = path
+                    .strip_prefix(submission_name)
+                    .map(|path| path.trim_start_matches('/'))
+                    .unwrap_or(path);
+                let path = match path.split_once(".app/") {
+                    Some((_, inside)) => inside,
+                    None => path,
+                };
+                match self.architecture {
+                    Some(ref arch) => format!("`{path}` ({arch}): "),
+                    None => format!("`{path}`: "),
+                }
+            }
+            None => String::new(),
+        };
+        text += &self.message;
+        if let Some(ref doc_url) = self.doc_url {
+            text += &format!(" See {doc_url}");
+        }
+        text
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LogUrl {
+    developer_log_url: String,
+}
+
+impl<'a> NotaryClient<'a> {
+    pub fn new(key: &'a ApiKey) -> Self {
+        Self {
+            key,
+            notary_url: NOTARY_URL.into(),
+            upload_url: UPLOAD_URL.into(),
+        }
+    }
+
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        let token = self.key.token(SystemTime::now());
+        ureq::request(method, &format!("{}{path}", self.notary_url))
+            .set("Authorization", &format!("Bearer {token}"))
+    }
+
+    /// Starts a submission called `name` (the file's name), with the file's SHA-256 hash.
+    /// Returns its ID and where to upload it.
+    pub fn submit(&self, name: &str, sha256: &str) -> Result<(String, NewSubmission), Error> {
+        let body = serde_json::json!({
+            "submissionName": name,
+            "sha256": sha256,
+        });
+        let response: Data<NewSubmission> =
+            send(SERVICE, self.request("POST", "/submissions"), Some(body))?;
+        Ok((response.data.id, response.data.attributes))
+    }
+
+    /// Uploads the submission's file to S3
+    pub fn upload(&self, submission: &NewSubmission, data: &[u8]) -> Result<(), Error> {
+        let path = format!("/{}/{}", submission.bucket, uri_encode(&submission.object));
+        let url = format!("{}{path}", self.upload_url);
+        let host = url
+            .split_once("://")
+            .map_or(url.as_str(), |(_, rest)| rest)
+            .split('/')
+            .next()
+            .unwrap_or_default()
+            .to_owned();
+        let request = ureq::put(&url);
+        let request = sign_upload(request, &host, &path, submission, data, SystemTime::now());
+        match request.send_bytes(data) {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(code, response)) => {
+                let body = response.into_string().unwrap_or_default();
+                Err(Error::Service(format!(
+                    "Uploading the submission failed with HTTP {code}: {}",
+                    body.trim()
+                )))
+            }
+            Err(e) => Err(Error::Service(format!(
+                "Couldn't upload the submission to `{url}`. Check your internet connection. ({e})"
+            ))),
+        }
+    }
+
+    /// Checks on a submission
+    pub fn status(&self, id: &str) -> Result<SubmissionStatus, Error> {
+        let response: Data<SubmissionStatus> = send(
+            SERVICE,
+            self.request("GET", &format!("/submissions/{id}")),
+            None,
+        )?;
+        Ok(response.data.attributes)
+    }
+
+    /// Downloads a finished submission's log. Returns the raw JSON, so it can be saved as-is,
+    /// and the parsed log.
+    pub fn log(&self, id: &str) -> Result<(serde_json::Value, NotaryLog), Error> {
+        let response: Data<LogUrl> = send(
+            SERVICE,
+            self.request("GET", &format!("/submissions/{id}/logs")),
+            None,
+        )?;
+        // The log is on a pre-signed URL, so it doesn't need the token
+        let url = response.data.attributes.developer_log_url;
+        let raw: serde_json::Value = send("The notary log server", ureq::get(&url), None)?;
+        let log = serde_json::from_value(raw.clone()).map_err(|e| {
+            Error::Service(format!(
+                "The notary log is in a format cargo-ipa doesn't understand ({e})"
+            ))
+        })?;
+        Ok((raw, log))
+    }
+}
+
+/// Signs an S3 upload with AWS Signature Version 4, using the submission's temporary
+/// credentials. See: https://docs.aws.amazon.com/AmazonS3/latest/API/sig-v4-header-based-auth.html
+pub fn sign_upload(
+    request: ureq::Request,
+    host: &str,
+    path: &str,
+    submission: &NewSubmission,
+    data: &[u8],
+    now: SystemTime,
+) -> ureq::Request {
+    // `2024-01-02T03:04:05Z` to `20240102T030405Z`
+    let timestamp = format_time(now).replace(['-', ':'], "");
+    let date = &timestamp[..8];
+    let payload_hash = hex(&Sha256::digest(data));
+
+    let signed_headers = "host;x-amz-content-sha256;x-amz-date;x-amz-security-token";
+    let canonical_request = format!(
+        "PUT\n{path}\n\nhost:{host}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{timestamp}\nx-amz-security-token:{}\n\n{signed_headers}\n{payload_hash}",
+        submission.aws_session_token
+    );
+    let scope = format!("{date}/{UPLOAD_REGION}/s3/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{timestamp}\n{scope}\n{}",
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let mut key = hmac(
+        format!("AWS4{}", submission.aws_secret_access_key).as_bytes(),
+        date.as_bytes(),
+    );
+    for part in [UPLOAD_REGION, "s3", "aws4_request"] {
+        key = hmac(&key, part.as_bytes());
+    }
+    let signature = hex(&hmac(&key, string_to_sign.as_bytes()));
+
+    request
+        .set("x-amz-content-sha256", &payload_hash)
+        .set("x-amz-date", &timestamp)
+        .set("x-amz-security-token", &submission.aws_session_token)
+        .set(
+            "Authorization",
+            &format!(
+                "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+                submission.aws_access_key_id
+            ),
+        )
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encodes everything but unreserved characters and `/`
+fn uri_encode(text: &str) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' => encoded.push('/'),
+            _ => encoded += &format!("%{byte:02X}"),
+        }
+    }
+    encoded
+}
+
+/// The SHA-256 hash the notary service wants for a submission, as hex
+pub fn submission_hash(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
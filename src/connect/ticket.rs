@@ -0,0 +1,74 @@
+//! Notarization tickets, which the notary service publishes for every CDHash in an app it
+//! accepts. `stapler` looks them up in a public CloudKit database, by CDHash.
+
+use {
+    super::send,
+    crate::error::Error,
+    base64::{engine::general_purpose::STANDARD, Engine},
+    serde::Deserialize,
+};
+
+/// Where tickets are looked up
+pub const TICKET_URL: &str = "https://api.apple-cloudkit.com/database/1/com.apple.gk.ticket-delivery/production/public/records/lookup";
+const SERVICE: &str = "Apple's ticket service";
+
+#[derive(Deserialize)]
+struct Lookup {
+    records: Vec<Record>,
+}
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Record {
+    fields: Option<Fields>,
+    server_error_code: Option<String>,
+    reason: Option<String>,
+}
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Fields {
+    signed_ticket: Field,
+}
+#[derive(Deserialize)]
+struct Field {
+    value: String,
+}
+
+/// The name of the ticket's record: a version, the CodeDirectory's hash type, and the
+/// CDHash in hex
+pub fn record_name(cdhash_type: u8, cdhash: &str) -> String {
+    format!("2/{cdhash_type}/{cdhash}")
+}
+
+/// Downloads the ticket for a CDHash from the ticket service at `url`
+pub fn fetch(url: &str, cdhash_type: u8, cdhash: &str) -> Result<Vec<u8>, Error> {
+    let body = serde_json::json!({
+        "records": [{ "recordName": record_name(cdhash_type, cdhash) }],
+    });
+    let lookup: Lookup = send(SERVICE, ureq::post(url), Some(body))?;
+    let Some(record) = lookup.records.into_iter().next() else {
+        return Err(Error::Service(format!("{SERVICE} didn't send a ticket")));
+    };
+    match record {
+        Record {
+            fields: Some(fields),
+            ..
+        } => STANDARD.decode(fields.signed_ticket.value).map_err(|e| {
+            Error::Service(format!("{SERVICE} sent a ticket that isn't valid base64 ({e})"))
+        }),
+        Record {
+            server_error_code: Some(code),
+            ..
+        } if code == "NOT_FOUND" => Err(Error::Service(format!(
+            "There's no notarization ticket for the CDHash `{cdhash}`. Has the app been notarized? If it was just notarized, try again in a few minutes."
+        ))),
+        Record {
+            server_error_code,
+            reason,
+            ..
+        } => Err(Error::Service(format!(
+            "{SERVICE} couldn't find the ticket ({}): {}",
+            server_error_code.unwrap_or_default(),
+            reason.unwrap_or_default()
+        ))),
+    }
+}
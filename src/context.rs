@@ -1,33 +1,32 @@
-use std::{fs, path::PathBuf};
-use toml::{Table, Value};
+use crate::{
+    config::{self, Config},
+    error::Error,
+    runner::ToolRunner,
+};
+#[cfg(feature = "binary")]
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::{fmt, fs, path::PathBuf};
 
-/// This is the opening portion of every Info.plist
-pub const PLIST_OPENING: &str = r#"
-<?xml version="1.0" encoding="UTF-8"?>
-<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
-<plist version="1.0">
-<dict>
-"#;
-/// This is the closing portion of every Info.plist
-pub const PLIST_CLOSING: &str = r#"
-</dict>
-</plist>
-"#;
 /// Cargo.toml's name, for finding the project's root directory
 const CARGO_TOML: &str = "Cargo.toml";
 
 /// The app context
 pub struct Ctx {
-    /// Any configurations in the [package.metadata.cargo-ipa] section of Cargo.toml (if it exists)
-    pub cfg: Option<Table>,
+    /// The settings in the [package.metadata.cargo-ipa] section of Cargo.toml
+    /// (or the defaults, if it doesn't exist)
+    pub cfg: Config,
     /// The ID of the project, as listed in Cargo.toml
     pub project_id: String,
     /// The human-readable name of the project.
-    /// This can either come from a CFBundleName setting in the [cargo-ipa]
+    /// This can either come from the `name` setting in the [cargo-ipa]
     /// section of Cargo.toml, or can be set with the -n (or --name) argument
     pub project_name: String,
     /// The version of the project, as set in Cargo.toml
     pub project_version: String,
+    /// The app's bundle ID. This comes from the `bundle-id` setting, or defaults
+    /// to `com.<project id>`
+    pub bundle_id: String,
 
     /// Path to Cargo.toml
     pub cargo_toml: PathBuf,
@@ -41,112 +40,64 @@ pub struct Ctx {
     pub force_cargo_recompile: bool,
 }
 impl Ctx {
-    pub fn new(name_arg: &Option<String>) -> Result<Self, String> {
-        // Get all the project directories
-        // Locate Cargo.toml
-        let mut cargo_toml = None;
-        match std::env::current_dir() {
-            Ok(path) => {
-                for dir in path.ancestors() {
-                    let path = dir.join(CARGO_TOML);
-                    if path.exists() {
-                        cargo_toml = Some(path);
-                    }
-                }
-                if cargo_toml.is_none() {
-                    return Err("Failed to locate Cargo.toml".to_string());
-                }
-            }
-            Err(e) => return Err(format!("Failed to get current directory: {e}")),
-        };
-        let cargo_toml = cargo_toml.unwrap();
+    /// Loads the context for the project in the current directory
+    pub fn new(name_arg: &Option<String>) -> Result<Self, Error> {
+        Self::from_cargo_toml(find_cargo_toml()?, name_arg)
+    }
 
+    /// Loads the context for the project that `cargo_toml` belongs to
+    pub fn from_cargo_toml(cargo_toml: PathBuf, name_arg: &Option<String>) -> Result<Self, Error> {
         // Get the parent directory of Cargo.toml - the project's root directory
         let root_dir = match cargo_toml.parent() {
             Some(dir) => dir.to_owned(),
-            None => return Err("Failed to get project's root directory".to_owned()),
+            None => {
+                return Err(Error::Config(
+                    "Failed to get project's root directory".to_owned(),
+                ))
+            }
         };
 
         // Try to get or create the build directories
         let target_dir = root_dir.join("target");
         if !target_dir.is_dir() {
             if let Err(e) = fs::create_dir(&target_dir) {
-                return Err(format!(
-                    "Failed to find or create the target directory: {e}"
+                return Err(Error::io(
+                    "Failed to find or create the target directory",
+                    target_dir,
+                    e,
                 ));
             }
         }
         let cargo_ipa_dir = target_dir.join("cargo-ipa");
         if !cargo_ipa_dir.is_dir() {
             if let Err(e) = fs::create_dir(&cargo_ipa_dir) {
-                return Err(format!(
-                    "Failed to find or create the cargo-ipa directory: {e}"
+                return Err(Error::io(
+                    "Failed to find or create the cargo-ipa directory",
+                    cargo_ipa_dir,
+                    e,
                 ));
             }
         }
 
         // Try to parse Cargo.toml
-        let cfg_raw = match fs::read(&cargo_toml) {
-            Ok(buffer) => match std::str::from_utf8(&buffer) {
-                Ok(buffer_str) => match buffer_str.parse::<Table>() {
-                    Ok(cfg_raw) => cfg_raw,
-                    Err(e) => return Err(format!("Failed to parse Cargo.toml: {e}")),
-                },
-                Err(e) => return Err(format!("Failed to parse Cargo.toml: {e}")),
-            },
-            Err(e) => return Err(format!("Failed to read Cargo.toml: {e}")),
-        };
-
-        // These are normal Rust configurations, in the package section
-        let pkg_cfg = match cfg_raw.get("package") {
-            Some(pkg_cfg) => pkg_cfg,
-            None => {
-                return Err("Invalid Cargo.toml detected! Failed to get package name.".to_owned())
-            }
-        };
-        let project_id = match pkg_cfg.get("name") {
-            Some(Value::String(name)) => name.to_owned(),
-            _ => return Err("Invalid Cargo.toml detected! Failed to get package name.".to_owned()),
-        };
-        let project_version = match pkg_cfg.get("version") {
-            Some(Value::String(version)) => version.to_owned(),
-            _ => {
-                return Err(
-                    "Invalid Cargo.toml detected! Failed to get package version.".to_owned(),
-                )
-            }
-        };
-
-        // These are values in the cargo-ipa section, if it exists
-        let (project_name_cfg, cfg) = match pkg_cfg.get("metadata") {
-            None => (None, None),
-            Some(metadata_cfg) => match metadata_cfg.get("cargo-ipa") {
-                None => (None, None),
-                // If there is a cargo-ipa section, make sure it's valid, and also try to load the project name from it
-                Some(val) => {
-                    if let Value::Table(cfg) = val {
-                        match cfg.get("name") {
-                            Some(Value::String(name)) => {
-                                (Some(name.to_owned()), Some(cfg.to_owned()))
-                            }
-                            _ => (None, Some(cfg.to_owned())),
-                        }
-                    } else {
-                        println!(
-                        "WARNING: Invalid `cargo-ipa` configuration format detected. Resetting to no configuration."
-                    );
-                        (None, None)
-                    }
-                }
-            },
-        };
+        let manifest = config::load(&cargo_toml)?;
+        for warning in &manifest.warnings {
+            println!("WARNING: {warning}");
+        }
+        let cfg = manifest.config;
+        let project_id = manifest.name;
+        let project_version = manifest.version;
 
         let project_name = if let Some(name) = name_arg {
             name.to_string()
-        } else if let Some(name) = project_name_cfg {
-            name
+        } else if let Some(ref name) = cfg.name {
+            name.to_owned()
         } else {
-            return Err("No project name could be found!".into());
+            return Err(Error::Config("No project name could be found! Set the `name` setting in Cargo.toml (or run `cargo ipa init` to set it up), or pass the --name argument.".into()));
+        };
+        let bundle_id = match cfg.bundle_id {
+            Some(ref bundle_id) => bundle_id.to_owned(),
+            None => "com.".to_owned() + &project_id,
         };
 
         Ok(Self {
@@ -154,6 +105,7 @@ impl Ctx {
             project_id,
             project_version,
             project_name,
+            bundle_id,
             cargo_toml,
             target_dir,
             root_dir,
@@ -163,6 +115,24 @@ impl Ctx {
     }
 }
 
+/// Locates the project's Cargo.toml, searching upwards from the current directory
+pub fn find_cargo_toml() -> Result<PathBuf, Error> {
+    let mut cargo_toml = None;
+    match std::env::current_dir() {
+        Ok(path) => {
+            for dir in path.ancestors() {
+                let path = dir.join(CARGO_TOML);
+                if path.exists() {
+                    cargo_toml = Some(path);
+                }
+            }
+        }
+        Err(e) => return Err(Error::io("Failed to get current directory", ".", e)),
+    };
+
+    cargo_toml.ok_or_else(|| Error::Config("Failed to locate Cargo.toml".to_string()))
+}
+
 #[cfg(feature = "binary")]
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 #[allow(non_camel_case_types)]
@@ -179,12 +149,12 @@ pub enum Platform {
     macOS,
     iOS,
 }
-impl ToString for Platform {
-    fn to_string(&self) -> String {
-        match self {
-            Self::iOS => String::from("ios"),
-            Self::macOS => String::from("darwin"),
-        }
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::iOS => "ios",
+            Self::macOS => "darwin",
+        })
     }
 }
 
@@ -203,28 +173,55 @@ pub enum Architecture {
     x86_64,
     aarch64,
 }
-impl ToString for Architecture {
-    fn to_string(&self) -> String {
+impl fmt::Display for Architecture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::x86_64 => "x86_64",
+            Self::aarch64 => "aarch64",
+        })
+    }
+}
+
+/// How the app will be distributed, like the export methods in Xcode's Organizer. Each one
+/// needs its own kind of provisioning profile.
+#[cfg(feature = "binary")]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExportMethod {
+    /// For the team's own devices, with a debugger attached
+    Development,
+    /// For a list of testers' devices
+    AdHoc,
+    /// For any device in an organization, with an enterprise account
+    Enterprise,
+    /// For TestFlight and the App Store
+    AppStore,
+}
+#[cfg(not(feature = "binary"))]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExportMethod {
+    Development,
+    AdHoc,
+    Enterprise,
+    AppStore,
+}
+impl ExportMethod {
+    /// The method's name, as it's written in Cargo.toml and on the command line
+    pub fn name(self) -> &'static str {
         match self {
-            Self::x86_64 => String::from("x86_64"),
-            Self::aarch64 => String::from("aarch64"),
+            Self::Development => "development",
+            Self::AdHoc => "ad-hoc",
+            Self::Enterprise => "enterprise",
+            Self::AppStore => "app-store",
         }
     }
 }
 
-pub fn detect_xcode() -> PathBuf {
-    let xcode_toolchain = PathBuf::from(
-        if let Ok(output) = std::process::Command::new("xcode-select")
-            .arg("--print-path")
-            .output()
-        {
-            String::from_utf8(output.stdout.as_slice().into())
-                .unwrap()
-                .trim()
-                .to_string()
-        } else {
-            "/Applications/Xcode.app/Contents/Developer".to_string()
-        },
-    );
+pub fn detect_xcode(runner: &dyn ToolRunner) -> PathBuf {
+    let xcode_toolchain = PathBuf::from(match runner.run("xcode-select", &["--print-path"]) {
+        Ok(output) if output.success => output.stdout.trim().to_string(),
+        _ => "/Applications/Xcode.app/Contents/Developer".to_string(),
+    });
     xcode_toolchain.join("Toolchains/XcodeDefault.xctoolchain/usr/lib/swift")
 }
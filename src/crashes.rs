@@ -0,0 +1,119 @@
+use clap::Args;
+use std::{fs, path::PathBuf};
+
+use crate::{
+    context::{find_cargo_toml, Ctx},
+    device::{
+        afc::Afc,
+        crash_reports::{self, CRASH_REPORT_COPY_SERVICE, CRASH_REPORT_MOVER_SERVICE},
+        usbmux, Session,
+    },
+    error::Error,
+};
+
+#[derive(Args)]
+pub struct CrashesArgs {
+    /// The app's name, for finding its executable's name. If left unprovided, cargo-ipa will
+    /// search for it in Cargo.toml.
+    #[arg(short, long)]
+    pub name: Option<String>,
+    /// Only copy crash reports from this process. Defaults to the app's executable.
+    #[arg(long)]
+    pub process: Option<String>,
+    /// Copy every crash report on the device
+    #[arg(long, conflicts_with = "process")]
+    pub all: bool,
+    /// The folder to copy crash reports into. Defaults to `target/cargo-ipa/crashes`.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// The UDID of the device to copy crash reports from. Only needed if more than one is
+    /// plugged in.
+    #[arg(short, long)]
+    pub device: Option<String>,
+    /// usbmuxd's socket, eg to test against a fake one
+    #[arg(long, default_value = usbmux::USBMUXD_SOCKET)]
+    pub usbmux_socket: PathBuf,
+}
+
+pub fn crashes(args: CrashesArgs) -> Result<(), Error> {
+    // Cargo.toml's optional if the process and folder are given
+    let ctx = match find_cargo_toml() {
+        Ok(cargo_toml) => Some(Ctx::from_cargo_toml(cargo_toml, &args.name)?),
+        Err(_) => None,
+    };
+    crashes_with(&args, ctx.as_ref()).map(|_| ())
+}
+
+/// Copies the app's crash reports (`.ips` files) from a USB-connected device, through the
+/// usbmuxd at `args.usbmux_socket`. Returns the paths of the ones that hadn't been copied
+/// before.
+pub fn crashes_with(args: &CrashesArgs, ctx: Option<&Ctx>) -> Result<Vec<PathBuf>, Error> {
+    // ========== SETUP ==========
+    // The app's process is named after its executable, which is named after the package
+    let process = match (&args.process, ctx) {
+        _ if args.all => None,
+        (Some(process), _) => Some(process.as_str()),
+        (None, Some(ctx)) => Some(ctx.project_id.as_str()),
+        (None, None) => {
+            return Err(Error::Config(
+                "No process was given, and there's no project to find one in. Pass `--process`, or `--all`.".into(),
+            ))
+        }
+    };
+    let output = match (&args.output, ctx) {
+        (Some(output), _) => output.clone(),
+        (None, Some(ctx)) => ctx.cargo_ipa_dir.join("crashes"),
+        (None, None) => {
+            return Err(Error::Config(
+                "No folder was given to copy crash reports into, and there's no project to put them in. Pass `--output`.".into(),
+            ))
+        }
+    };
+    if let Err(e) = fs::create_dir_all(&output) {
+        return Err(Error::io(
+            "Failed to create the crash reports folder",
+            output,
+            e,
+        ));
+    }
+    let mut session = Session::connect(&args.usbmux_socket, args.device.as_deref())?;
+    session.announce()?;
+
+    // ========== COPY ==========
+    println!("|- Collecting crash reports...");
+    crash_reports::wait_for_mover(session.start_service(CRASH_REPORT_MOVER_SERVICE)?)?;
+    let mut afc = Afc::new(session.start_service(CRASH_REPORT_COPY_SERVICE)?);
+    let mut reports: Vec<_> = afc
+        .read_dir("/")?
+        .into_iter()
+        .filter(|name| match crash_reports::process_name(name) {
+            Some(name) => process.is_none_or(|process| name == process),
+            None => false,
+        })
+        .collect();
+    reports.sort();
+
+    let mut copied = Vec::new();
+    for report in &reports {
+        let path = output.join(report);
+        if path.exists() {
+            continue;
+        }
+        println!("   |- {report}");
+        let data = afc.download(&format!("/{report}"))?;
+        if let Err(e) = fs::write(&path, data) {
+            return Err(Error::io("Failed to save a crash report", path, e));
+        }
+        copied.push(path);
+    }
+
+    match (reports.len(), copied.len()) {
+        (0, _) => println!("|- No crash reports were found"),
+        (found, 0) => println!("|- All {found} crash reports had already been copied"),
+        (_, copied) => println!(
+            "|- Copied {copied} new crash reports to `{}`",
+            output.display()
+        ),
+    }
+    Ok(copied)
+}
@@ -0,0 +1,198 @@
+//! AFC (Apple File Conduit), for copying files to and from a device's media folder. Apps are
+//! uploaded to `PublicStaging` before installation_proxy installs them, and crash reports are
+//! copied from their own AFC service. Every packet has a 40-byte header, then its header data
+//! (like a path or file handle), then its payload.
+
+use {
+    super::{lost_connection, Connection},
+    crate::error::Error,
+    std::io::{self, Read, Write},
+};
+
+/// lockdownd's name for the AFC service
+pub const AFC_SERVICE: &str = "com.apple.afc";
+const SERVICE: &str = "AFC service";
+const MAGIC: &[u8; 8] = b"CFA6LPAA";
+const HEADER_LEN: u64 = 40;
+/// How much of a file is sent in each packet
+const CHUNK_LEN: usize = 64 * 1024;
+
+const OP_STATUS: u64 = 0x01;
+const OP_DATA: u64 = 0x02;
+const OP_READ_DIR: u64 = 0x03;
+const OP_MAKE_DIR: u64 = 0x09;
+const OP_FILE_OPEN: u64 = 0x0D;
+const OP_FILE_OPEN_RESULT: u64 = 0x0E;
+const OP_FILE_READ: u64 = 0x0F;
+const OP_FILE_WRITE: u64 = 0x10;
+const OP_FILE_CLOSE: u64 = 0x14;
+/// Opens a file for reading
+const MODE_READ_ONLY: u64 = 1;
+/// Opens a file for writing, creating or truncating it
+const MODE_WRITE_ONLY: u64 = 3;
+
+pub struct Afc {
+    conn: Connection,
+    packet_num: u64,
+}
+impl Afc {
+    pub fn new(conn: Connection) -> Self {
+        Self {
+            conn,
+            packet_num: 0,
+        }
+    }
+
+    fn send(&mut self, operation: u64, header_data: &[u8], payload: &[u8]) -> io::Result<()> {
+        let this_len = HEADER_LEN + header_data.len() as u64;
+        let mut packet = MAGIC.to_vec();
+        for field in [
+            this_len + payload.len() as u64,
+            this_len,
+            self.packet_num,
+            operation,
+        ] {
+            packet.extend_from_slice(&field.to_le_bytes());
+        }
+        packet.extend_from_slice(header_data);
+        packet.extend_from_slice(payload);
+        self.packet_num += 1;
+        self.conn.write_all(&packet)?;
+        self.conn.flush()
+    }
+
+    /// Reads a packet, returning its operation and everything after the header
+    fn receive(&mut self) -> io::Result<(u64, Vec<u8>)> {
+        let mut header = [0; HEADER_LEN as usize];
+        self.conn.read_exact(&mut header)?;
+        let field =
+            |i: usize| u64::from_le_bytes(header[8 + i * 8..16 + i * 8].try_into().unwrap());
+        let (entire_len, operation) = (field(0), field(3));
+        if &header[..8] != MAGIC || !(HEADER_LEN..16 << 20).contains(&entire_len) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the device sent a packet that isn't AFC",
+            ));
+        }
+        let mut data = vec![0; (entire_len - HEADER_LEN) as usize];
+        self.conn.read_exact(&mut data)?;
+        Ok((operation, data))
+    }
+
+    /// Sends a request, and checks it didn't fail. Returns the reply's operation and data.
+    fn request(
+        &mut self,
+        action: &str,
+        operation: u64,
+        header_data: &[u8],
+        payload: &[u8],
+    ) -> Result<(u64, Vec<u8>), Error> {
+        let (operation, data) = self
+            .send(operation, header_data, payload)
+            .and_then(|_| self.receive())
+            .map_err(|e| lost_connection(SERVICE, e))?;
+        if operation == OP_STATUS {
+            let code = data
+                .get(..8)
+                .map_or(0, |code| u64::from_le_bytes(code.try_into().unwrap()));
+            if code != 0 {
+                return Err(Error::Device(format!(
+                    "The device couldn't {action}: {}",
+                    describe_status(code)
+                )));
+            }
+        }
+        Ok((operation, data))
+    }
+
+    /// Makes a folder (and its parents), if it doesn't already exist
+    pub fn make_dir(&mut self, path: &str) -> Result<(), Error> {
+        let action = format!("make the folder `{path}`");
+        self.request(&action, OP_MAKE_DIR, &c_string(path), &[])?;
+        Ok(())
+    }
+
+    /// Lists the names of the files & folders in a folder
+    pub fn read_dir(&mut self, path: &str) -> Result<Vec<String>, Error> {
+        let action = format!("list the folder `{path}`");
+        let (operation, reply) = self.request(&action, OP_READ_DIR, &c_string(path), &[])?;
+        if operation != OP_DATA {
+            return Err(Error::Device(format!("The device didn't list `{path}`")));
+        }
+        Ok(reply
+            .split(|byte| *byte == 0)
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .filter(|name| !["", ".", ".."].contains(&name.as_str()))
+            .collect())
+    }
+
+    /// Opens a file, and returns its handle
+    fn open(&mut self, action: &str, path: &str, mode: u64) -> Result<Vec<u8>, Error> {
+        let mut open = mode.to_le_bytes().to_vec();
+        open.extend_from_slice(&c_string(path));
+        let (operation, reply) = self.request(action, OP_FILE_OPEN, &open, &[])?;
+        match reply.get(..8) {
+            Some(handle) if operation == OP_FILE_OPEN_RESULT => Ok(handle.to_vec()),
+            _ => Err(Error::Device(format!("The device didn't open `{path}`"))),
+        }
+    }
+
+    /// Reads a whole file
+    pub fn download(&mut self, path: &str) -> Result<Vec<u8>, Error> {
+        let action = format!("read `{path}`");
+        let handle = self.open(&action, path, MODE_READ_ONLY)?;
+        let mut read = handle.clone();
+        read.extend_from_slice(&(CHUNK_LEN as u64).to_le_bytes());
+
+        let mut data = Vec::new();
+        loop {
+            let (operation, chunk) = self.request(&action, OP_FILE_READ, &read, &[])?;
+            if operation != OP_DATA || chunk.is_empty() {
+                break;
+            }
+            data.extend_from_slice(&chunk);
+        }
+        self.request(&action, OP_FILE_CLOSE, &handle, &[])?;
+        Ok(data)
+    }
+
+    /// Writes a file, calling `progress` with how many bytes have been sent
+    pub fn upload(
+        &mut self,
+        path: &str,
+        data: &[u8],
+        mut progress: impl FnMut(usize),
+    ) -> Result<(), Error> {
+        let action = format!("write `{path}`");
+        let handle = self.open(&action, path, MODE_WRITE_ONLY)?;
+
+        let mut sent = 0;
+        for chunk in data.chunks(CHUNK_LEN) {
+            self.request(&action, OP_FILE_WRITE, &handle, chunk)?;
+            sent += chunk.len();
+            progress(sent);
+        }
+        self.request(&action, OP_FILE_CLOSE, &handle, &[])?;
+        Ok(())
+    }
+}
+
+/// Paths are sent NUL-terminated
+fn c_string(path: &str) -> Vec<u8> {
+    let mut data = path.as_bytes().to_vec();
+    data.push(0);
+    data
+}
+
+fn describe_status(code: u64) -> String {
+    let description = match code {
+        7 => "an argument was invalid",
+        8 => "it doesn't exist",
+        9 => "it's a folder",
+        10 => "permission was denied",
+        16 => "it already exists",
+        18 => "the device is out of space",
+        _ => return format!("AFC error {code}"),
+    };
+    format!("{description} (AFC error {code})")
+}
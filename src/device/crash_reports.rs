@@ -0,0 +1,45 @@
+//! Crash reports. crashreportmover moves new crash reports to where crashreportcopymobile, an AFC
+//! service, can copy them from.
+
+use {
+    super::{lost_connection, Connection},
+    crate::error::Error,
+    std::io::Read,
+};
+
+/// lockdownd's name for the service that gets crash reports ready to copy
+pub const CRASH_REPORT_MOVER_SERVICE: &str = "com.apple.crashreportmover";
+/// lockdownd's name for the AFC service crash reports are copied with
+pub const CRASH_REPORT_COPY_SERVICE: &str = "com.apple.crashreportcopymobile";
+
+/// Waits for crashreportmover to finish moving the device's crash reports, which it says by
+/// sending "ping"
+pub fn wait_for_mover(mut conn: Connection) -> Result<(), Error> {
+    let mut reply = [0; 4];
+    if let Err(e) = conn.read_exact(&mut reply) {
+        return Err(lost_connection("crash report mover", e));
+    }
+    match &reply {
+        b"ping" => Ok(()),
+        _ => Err(Error::Device(
+            "The device's crash report mover sent something other than `ping`".into(),
+        )),
+    }
+}
+
+/// Finds the name of the process a crash report is for, from its file name, like
+/// `my-app-2024-10-18-123456.ips`. Returns `None` for files that aren't crash reports.
+pub fn process_name(file_name: &str) -> Option<&str> {
+    let name = file_name.strip_suffix(".ips")?;
+    // The process's name can have dashes too, so the date's split off from the end
+    let mut parts = name.rsplitn(5, '-');
+    let date: Vec<_> = parts.by_ref().take(4).collect();
+    let is_date = date.len() == 4
+        && date
+            .iter()
+            .all(|part| !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_digit()));
+    match is_date {
+        true => parts.next().filter(|process| !process.is_empty()),
+        false => None,
+    }
+}
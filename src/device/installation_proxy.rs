@@ -0,0 +1,46 @@
+//! installation_proxy, which installs apps that have been uploaded to `PublicStaging` with AFC.
+//! It reports its progress as it goes, then `Complete` (or an error).
+
+use {
+    super::{lost_connection, read_plist, send_plist, string, Connection},
+    crate::error::Error,
+    plist::{Dictionary, Value},
+};
+
+/// lockdownd's name for the installation_proxy service
+pub const INSTALLATION_PROXY_SERVICE: &str = "com.apple.mobile.installation_proxy";
+const SERVICE: &str = "installation_proxy";
+
+/// Installs (or upgrades) the app at `package_path` in the device's media folder, calling
+/// `progress` with each status update and how far through it is, in percent
+pub fn install(
+    conn: &mut Connection,
+    package_path: &str,
+    mut progress: impl FnMut(&str, u64),
+) -> Result<(), Error> {
+    let mut message = Dictionary::new();
+    message.insert("Command".into(), "Install".into());
+    message.insert("PackagePath".into(), package_path.into());
+    message.insert("ClientOptions".into(), Dictionary::new().into());
+    send_plist(conn, message).map_err(|e| lost_connection(SERVICE, e))?;
+
+    loop {
+        let reply = read_plist(conn).map_err(|e| lost_connection(SERVICE, e))?;
+        if let Some(error) = string(&reply, "Error") {
+            let mut message = format!("The device couldn't install the app: {error}");
+            if let Some(description) = string(&reply, "ErrorDescription") {
+                message += &format!("\n  {description}");
+            }
+            return Err(Error::Device(message));
+        }
+        let status = string(&reply, "Status").unwrap_or_default();
+        if status == "Complete" {
+            return Ok(());
+        }
+        let percent = reply
+            .get("PercentComplete")
+            .and_then(Value::as_unsigned_integer)
+            .unwrap_or_default();
+        progress(&status, percent);
+    }
+}
@@ -0,0 +1,246 @@
+//! lockdownd, which every other service on a device is started through. Once a session's
+//! started with the pairing record's host ID, the connection switches to TLS, using the pairing
+//! record's certificates.
+
+use {
+    super::{
+        lost_connection, read_plist, send_plist, string,
+        usbmux::{Device, PairRecord, Usbmux},
+        Connection,
+    },
+    crate::error::Error,
+    plist::{Dictionary, Value},
+    rustls::{
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        crypto::{ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+        pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+        CertificateError, ClientConfig, ClientConnection, DigitallySignedStruct, SignatureScheme,
+        StreamOwned,
+    },
+    std::{os::unix::net::UnixStream, sync::Arc},
+};
+
+/// The port lockdownd listens on
+pub const LOCKDOWN_PORT: u16 = 62078;
+const SERVICE: &str = "lockdownd";
+const LABEL: &str = "cargo-ipa";
+
+/// A service lockdownd started
+pub struct StartedService {
+    pub port: u16,
+    /// Whether the service wants TLS, like lockdownd does
+    pub ssl: bool,
+}
+
+pub struct Lockdown {
+    conn: Connection,
+}
+impl Lockdown {
+    /// Connects to lockdownd on a device, and starts a session with its pairing record
+    pub fn connect(
+        usbmux: &mut Usbmux,
+        device: &Device,
+        pair_record: &PairRecord,
+    ) -> Result<Self, Error> {
+        let stream = usbmux.connect(device, LOCKDOWN_PORT)?;
+        let mut lockdown = Self {
+            conn: Connection::Plain(stream),
+        };
+        let reply = lockdown.request("QueryType", Dictionary::new())?;
+        if string(&reply, "Type").as_deref() != Some("com.apple.mobile.lockdown") {
+            return Err(Error::Device(format!(
+                "`{}` isn't running lockdownd on port {LOCKDOWN_PORT}",
+                device.udid
+            )));
+        }
+
+        let mut session = Dictionary::new();
+        session.insert("HostID".into(), pair_record.host_id.clone().into());
+        session.insert("SystemBUID".into(), pair_record.system_buid.clone().into());
+        let reply = lockdown.request("StartSession", session)?;
+        let ssl = reply.get("EnableSessionSSL").and_then(Value::as_boolean);
+        if ssl == Some(true) {
+            let Connection::Plain(stream) = lockdown.conn else {
+                unreachable!("the session only just started")
+            };
+            lockdown.conn = tls(stream, pair_record)?;
+        }
+        Ok(lockdown)
+    }
+
+    /// Sends a request, and checks it didn't fail
+    fn request(&mut self, request: &str, mut message: Dictionary) -> Result<Dictionary, Error> {
+        message.insert("Label".into(), LABEL.into());
+        message.insert("Request".into(), request.into());
+        send_plist(&mut self.conn, message).map_err(|e| lost_connection(SERVICE, e))?;
+        let reply = read_plist(&mut self.conn).map_err(|e| lost_connection(SERVICE, e))?;
+        let Some(error) = string(&reply, "Error") else {
+            return Ok(reply);
+        };
+        let hint = match error.as_str() {
+            "InvalidHostID" | "InvalidPairRecord" => {
+                " The device doesn't trust this computer anymore; unplug it, plug it back in, and tap Trust."
+            }
+            "PasswordProtected" => " Unlock the device, and try again.",
+            "UserDeniedPairing" => " The device doesn't trust this computer.",
+            _ => "",
+        };
+        Err(Error::Device(format!(
+            "lockdownd refused `{request}`: {error}.{hint}"
+        )))
+    }
+
+    /// Reads a value about the device, like `DeviceName` or `ProductVersion`
+    pub fn value(&mut self, key: &str) -> Result<Option<Value>, Error> {
+        let mut message = Dictionary::new();
+        message.insert("Key".into(), key.into());
+        let mut reply = self.request("GetValue", message)?;
+        Ok(reply.remove("Value"))
+    }
+
+    /// Starts a service, like `com.apple.afc`. Connect to it with usbmux on the port it's on.
+    pub fn start_service(&mut self, service: &str) -> Result<StartedService, Error> {
+        let mut message = Dictionary::new();
+        message.insert("Service".into(), service.into());
+        let reply = self.request("StartService", message)?;
+        let port = reply
+            .get("Port")
+            .and_then(Value::as_unsigned_integer)
+            .and_then(|port| u16::try_from(port).ok());
+        let Some(port) = port else {
+            return Err(Error::Device(format!(
+                "lockdownd started `{service}`, but didn't say which port it's on"
+            )));
+        };
+        Ok(StartedService {
+            port,
+            ssl: reply
+                .get("EnableServiceSSL")
+                .and_then(Value::as_boolean)
+                .unwrap_or(false),
+        })
+    }
+}
+
+/// Connects to a service lockdownd started
+pub fn connect_service(
+    usbmux: &mut Usbmux,
+    device: &Device,
+    pair_record: &PairRecord,
+    service: &StartedService,
+) -> Result<Connection, Error> {
+    let stream = usbmux.connect(device, service.port)?;
+    match service.ssl {
+        true => tls(stream, pair_record),
+        false => Ok(Connection::Plain(stream)),
+    }
+}
+
+/// Switches a connection to TLS, authenticating with the pairing record's host certificate
+fn tls(mut stream: UnixStream, pair_record: &PairRecord) -> Result<Connection, Error> {
+    let pem = |name: &str, pem: &[u8]| {
+        let invalid = |e| {
+            Error::Device(format!(
+                "The pairing record's {name} isn't a valid PEM file ({e}). Try pairing again."
+            ))
+        };
+        der::pem::decode_vec(pem)
+            .map(|(_, der)| der)
+            .map_err(invalid)
+    };
+    let host_certificate = pem("host certificate", &pair_record.host_certificate)?;
+    let host_key = pem("host private key", &pair_record.host_private_key)?;
+    let device_certificate = pem("device certificate", &pair_record.device_certificate)?;
+
+    let provider = Arc::new(ring::default_provider());
+    let verifier = PairedDevice {
+        certificate: device_certificate,
+        provider: provider.clone(),
+    };
+    let tls_error = |e: rustls::Error| {
+        Error::Device(format!(
+            "Couldn't start a TLS session with the device ({e})"
+        ))
+    };
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(tls_error)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_client_auth_cert(
+            vec![CertificateDer::from(host_certificate)],
+            PrivateKeyDer::Pkcs1(host_key.into()),
+        )
+        .map_err(tls_error)?;
+    // The device's certificate doesn't have a name, so this is never checked
+    let name = ServerName::try_from("device").unwrap();
+    let mut conn = ClientConnection::new(Arc::new(config), name).map_err(tls_error)?;
+    // Handshake now, so failures are about TLS rather than the next request
+    while conn.is_handshaking() {
+        if let Err(e) = conn.complete_io(&mut stream) {
+            return Err(Error::Device(format!(
+                "Couldn't start a TLS session with the device ({e}). Try unpairing and pairing it again."
+            )));
+        }
+    }
+    Ok(Connection::Tls(Box::new(StreamOwned::new(conn, stream))))
+}
+
+/// Devices use a certificate from their pairing record, which isn't issued by anyone TLS
+/// knows about. It's checked against the pairing record instead.
+#[derive(Debug)]
+struct PairedDevice {
+    certificate: Vec<u8>,
+    provider: Arc<CryptoProvider>,
+}
+impl ServerCertVerifier for PairedDevice {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        match end_entity.as_ref() == self.certificate {
+            true => Ok(ServerCertVerified::assertion()),
+            false => Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            )),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
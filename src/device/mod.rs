@@ -0,0 +1,185 @@
+//! Talking to iOS devices over USB, like libimobiledevice does. usbmuxd forwards connections to
+//! services on the device, starting with lockdownd, which starts every other service (like AFC,
+//! for copying files, and installation_proxy, for installing apps).
+//! See: https://www.theiphonewiki.com/wiki/Usbmux
+
+pub mod afc;
+pub mod crash_reports;
+pub mod installation_proxy;
+pub mod lockdown;
+pub mod syslog_relay;
+pub mod usbmux;
+
+use {
+    crate::error::Error,
+    lockdown::Lockdown,
+    plist::{Dictionary, Value},
+    rustls::{ClientConnection, StreamOwned},
+    std::{
+        io::{self, Cursor, Read, Write},
+        os::unix::net::UnixStream,
+        path::Path,
+    },
+    usbmux::{Device, PairRecord, Usbmux},
+};
+
+/// The largest plist a device service should ever send
+const MAX_PLIST_LEN: u32 = 16 << 20;
+
+/// A connection to a service on a device, through usbmuxd. Some services (like lockdownd,
+/// once a session's started) switch to TLS.
+pub enum Connection {
+    Plain(UnixStream),
+    Tls(Box<StreamOwned<ClientConnection, UnixStream>>),
+}
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(stream) => stream.read(buf),
+            Self::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(stream) => stream.write(buf),
+            Self::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(stream) => stream.flush(),
+            Self::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// A device that's been connected to, with a lockdownd session started with its pairing record
+pub struct Session {
+    usbmux: Usbmux,
+    pub device: Device,
+    pair_record: PairRecord,
+    pub lockdown: Lockdown,
+}
+impl Session {
+    /// Connects to the device with the given UDID, or the only one plugged in over USB, through
+    /// the usbmuxd at `socket`
+    pub fn connect(socket: &Path, udid: Option<&str>) -> Result<Self, Error> {
+        let mut usbmux = Usbmux::new(socket);
+        let device = pick_device(usbmux.devices()?, udid)?;
+        let pair_record = usbmux.pair_record(&device.udid)?;
+        let lockdown = Lockdown::connect(&mut usbmux, &device, &pair_record)?;
+        Ok(Self {
+            usbmux,
+            device,
+            pair_record,
+            lockdown,
+        })
+    }
+
+    /// Prints which device this is, and returns its name (like "Jane's iPhone")
+    pub fn announce(&mut self) -> Result<Option<String>, Error> {
+        let mut value = |key: &str| -> Result<Option<String>, Error> {
+            let value = self.lockdown.value(key)?;
+            Ok(value.and_then(|value| value.as_string().map(str::to_owned)))
+        };
+        let name = value("DeviceName")?;
+        match (&name, value("ProductVersion")?) {
+            (Some(name), Some(version)) => println!("|- Connected to {name} (iOS {version})"),
+            _ => println!("|- Connected to `{}`", self.device.udid),
+        }
+        Ok(name)
+    }
+
+    /// Starts a service on the device, like `com.apple.afc`, and connects to it
+    pub fn start_service(&mut self, service: &str) -> Result<Connection, Error> {
+        let service = self.lockdown.start_service(service)?;
+        lockdown::connect_service(&mut self.usbmux, &self.device, &self.pair_record, &service)
+    }
+}
+
+/// Picks the device to use: the one with the given UDID, or the only one plugged in
+fn pick_device(devices: Vec<Device>, udid: Option<&str>) -> Result<Device, Error> {
+    if let Some(udid) = udid {
+        return devices
+            .into_iter()
+            .find(|device| device.udid == udid)
+            .ok_or_else(|| {
+                Error::Device(format!(
+                    "`{udid}` isn't connected. Is it plugged in and unlocked?"
+                ))
+            });
+    }
+    // Devices on the same Wi-Fi show up too, but they're slow & flaky to use
+    let mut usb: Vec<_> = devices
+        .into_iter()
+        .filter(|device| device.connection_type == "USB")
+        .collect();
+    match usb.len() {
+        0 => Err(Error::Device(
+            "No devices are plugged in. Plug in an iPhone or iPad, and unlock it.".into(),
+        )),
+        1 => Ok(usb.remove(0)),
+        _ => {
+            let udids: Vec<_> = usb.iter().map(|device| device.udid.as_str()).collect();
+            Err(Error::Config(format!(
+                "More than one device is plugged in. Pick one with `--device`: {}",
+                udids.join(", ")
+            )))
+        }
+    }
+}
+
+/// Sends a plist the way lockdownd and most other services expect: as XML, after its length
+/// as a big-endian 32-bit integer
+pub fn send_plist(stream: &mut impl Write, message: Dictionary) -> io::Result<()> {
+    let mut xml = Vec::new();
+    Value::Dictionary(message)
+        .to_writer_xml(&mut xml)
+        .map_err(io::Error::other)?;
+    let mut data = (xml.len() as u32).to_be_bytes().to_vec();
+    data.extend_from_slice(&xml);
+    stream.write_all(&data)?;
+    stream.flush()
+}
+
+/// Reads a plist sent with [`send_plist`]'s framing. Services can reply with XML or binary
+/// plists.
+pub fn read_plist(stream: &mut impl Read) -> io::Result<Dictionary> {
+    let mut len = [0; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len);
+    if len > MAX_PLIST_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("the device sent a {len} byte message"),
+        ));
+    }
+    let mut data = vec![0; len as usize];
+    stream.read_exact(&mut data)?;
+    match Value::from_reader(Cursor::new(data)) {
+        Ok(Value::Dictionary(message)) => Ok(message),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the device sent a plist that isn't a dictionary",
+        )),
+        Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+    }
+}
+
+/// An error for a connection to a device service that broke
+fn lost_connection(service: &str, e: io::Error) -> Error {
+    Error::Device(format!(
+        "Lost the connection to the device's {service} ({e}). Is it still plugged in?"
+    ))
+}
+
+/// Gets a string out of a message from a device
+fn string(message: &Dictionary, key: &str) -> Option<String> {
+    message
+        .get(key)
+        .and_then(Value::as_string)
+        .map(str::to_owned)
+}
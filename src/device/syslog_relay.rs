@@ -0,0 +1,51 @@
+//! syslog_relay, which streams the device's system log as it's written. Each message is sent as
+//! text, ending with a NUL byte, like
+//! `Oct 18 12:34:56 Janes-iPhone my-app(CoreFoundation)[1234] <Notice>: Hello`.
+
+use {
+    super::{lost_connection, Connection},
+    crate::error::Error,
+    regex::Regex,
+    std::{
+        io::{BufRead, BufReader},
+        sync::LazyLock,
+    },
+};
+
+/// lockdownd's name for the syslog_relay service
+pub const SYSLOG_RELAY_SERVICE: &str = "com.apple.syslog_relay";
+const SERVICE: &str = "syslog_relay";
+
+/// A message's timestamp, device name, then process name (and maybe the library that logged
+/// it), PID, and level
+static PROCESS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\w{3} [ \d]\d \d\d:\d\d:\d\d .+? ([^\s(\[]+)(?:\([^)]*\))?\[\d+\] <\w+>:")
+        .unwrap()
+});
+
+/// Reads messages from the device's log as they're written, calling `on_message` with each
+/// one, until the device closes the connection
+pub fn stream(conn: Connection, mut on_message: impl FnMut(&str)) -> Result<(), Error> {
+    let mut reader = BufReader::new(conn);
+    let mut message = Vec::new();
+    loop {
+        message.clear();
+        match reader.read_until(0, &mut message) {
+            Ok(0) => return Ok(()),
+            Ok(_) => {}
+            Err(e) => return Err(lost_connection(SERVICE, e)),
+        }
+        let message = String::from_utf8_lossy(&message);
+        let message = message.trim_end_matches(['\0', '\n']);
+        if !message.is_empty() {
+            on_message(message);
+        }
+    }
+}
+
+/// Finds the name of the process that logged a message. Device names can have spaces, so
+/// this looks for the `name[pid] <Level>:` after them.
+pub fn process_name(message: &str) -> Option<&str> {
+    let captures = PROCESS.captures(message)?;
+    captures.get(1).map(|name| name.as_str())
+}
@@ -0,0 +1,200 @@
+//! usbmuxd, which lists the devices plugged into the computer, keeps their pairing records, and
+//! forwards connections to their services. Messages are plists after a 16-byte header.
+
+use {
+    super::string,
+    crate::error::Error,
+    plist::{Dictionary, Value},
+    std::{
+        io::{Cursor, Read, Write},
+        os::unix::net::UnixStream,
+        path::PathBuf,
+    },
+};
+
+/// Where usbmuxd listens, on both macOS and Linux
+pub const USBMUXD_SOCKET: &str = "/var/run/usbmuxd";
+const PLIST_VERSION: u32 = 1;
+const MESSAGE_PLIST: u32 = 8;
+const HEADER_LEN: u32 = 16;
+
+/// A device usbmuxd knows about
+#[derive(Clone, Debug)]
+pub struct Device {
+    /// usbmuxd's ID for the device, which only lasts while it's plugged in
+    pub device_id: u64,
+    pub udid: String,
+    /// `USB` or `Network`
+    pub connection_type: String,
+}
+
+/// The keys & certificates a computer got when a device trusted it. They're PEM files.
+pub struct PairRecord {
+    pub host_id: String,
+    pub system_buid: String,
+    pub host_certificate: Vec<u8>,
+    pub host_private_key: Vec<u8>,
+    pub device_certificate: Vec<u8>,
+}
+
+pub struct Usbmux {
+    socket: PathBuf,
+    tag: u32,
+}
+impl Usbmux {
+    /// A client for the usbmuxd listening at `socket`, normally [`USBMUXD_SOCKET`]
+    pub fn new(socket: impl Into<PathBuf>) -> Self {
+        Self {
+            socket: socket.into(),
+            tag: 0,
+        }
+    }
+
+    /// usbmuxd turns connections into tunnels to devices, so every request has its own
+    fn open(&self) -> Result<UnixStream, Error> {
+        UnixStream::connect(&self.socket).map_err(|e| {
+            Error::Device(format!(
+                "Couldn't connect to usbmuxd at `{}` ({e}). It's always running on macOS; on Linux, install the `usbmuxd` package.",
+                self.socket.display()
+            ))
+        })
+    }
+
+    fn request(
+        &mut self,
+        stream: &mut UnixStream,
+        message: Dictionary,
+    ) -> Result<Dictionary, Error> {
+        self.tag += 1;
+        let mut payload = Vec::new();
+        if let Err(e) = Value::Dictionary(message).to_writer_xml(&mut payload) {
+            return Err(Error::Device(format!(
+                "Failed to write a usbmuxd request: {e}"
+            )));
+        }
+        let mut data = Vec::new();
+        for field in [
+            HEADER_LEN + payload.len() as u32,
+            PLIST_VERSION,
+            MESSAGE_PLIST,
+            self.tag,
+        ] {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        data.extend_from_slice(&payload);
+        let lost = |e| Error::Device(format!("Lost the connection to usbmuxd ({e})"));
+        stream.write_all(&data).map_err(lost)?;
+
+        let mut header = [0; HEADER_LEN as usize];
+        stream.read_exact(&mut header).map_err(lost)?;
+        let len = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let Some(payload_len) = len.checked_sub(HEADER_LEN).filter(|len| *len < 16 << 20) else {
+            return Err(Error::Device(format!(
+                "usbmuxd sent a message cargo-ipa doesn't understand ({len} bytes long)"
+            )));
+        };
+        let mut payload = vec![0; payload_len as usize];
+        stream.read_exact(&mut payload).map_err(lost)?;
+        match Value::from_reader(Cursor::new(payload)) {
+            Ok(Value::Dictionary(reply)) => Ok(reply),
+            _ => Err(Error::Device(
+                "usbmuxd sent a reply cargo-ipa doesn't understand".into(),
+            )),
+        }
+    }
+
+    fn message(&self, message_type: &str) -> Dictionary {
+        let mut message = Dictionary::new();
+        message.insert("MessageType".into(), message_type.into());
+        message.insert("ClientVersionString".into(), "cargo-ipa".into());
+        message.insert("ProgName".into(), "cargo-ipa".into());
+        message
+    }
+
+    /// Lists the connected devices
+    pub fn devices(&mut self) -> Result<Vec<Device>, Error> {
+        let mut stream = self.open()?;
+        let reply = self.request(&mut stream, self.message("ListDevices"))?;
+        let Some(list) = reply.get("DeviceList").and_then(Value::as_array) else {
+            return Err(result_error("list the devices", &reply));
+        };
+        let devices = list
+            .iter()
+            .filter_map(|device| {
+                let properties = device.as_dictionary()?.get("Properties")?.as_dictionary()?;
+                Some(Device {
+                    device_id: properties.get("DeviceID")?.as_unsigned_integer()?,
+                    udid: string(properties, "SerialNumber")?,
+                    connection_type: string(properties, "ConnectionType").unwrap_or_default(),
+                })
+            })
+            .collect();
+        Ok(devices)
+    }
+
+    /// Reads the pairing record for a device, which only exists once it's trusted this computer
+    pub fn pair_record(&mut self, udid: &str) -> Result<PairRecord, Error> {
+        let mut stream = self.open()?;
+        let mut message = self.message("ReadPairRecord");
+        message.insert("PairRecordID".into(), udid.into());
+        let reply = self.request(&mut stream, message)?;
+        let not_paired = || {
+            Error::Device(format!(
+                "`{udid}` isn't paired with this computer. Unlock it, and tap Trust when it asks whether to trust this computer."
+            ))
+        };
+        let Some(data) = reply.get("PairRecordData").and_then(Value::as_data) else {
+            return Err(not_paired());
+        };
+        let Ok(Value::Dictionary(record)) = Value::from_reader(Cursor::new(data)) else {
+            return Err(Error::Device(format!(
+                "The pairing record for `{udid}` isn't a valid plist"
+            )));
+        };
+        let data = |key: &str| -> Result<Vec<u8>, Error> {
+            match record.get(key).and_then(Value::as_data) {
+                Some(data) => Ok(data.to_vec()),
+                None => Err(Error::Device(format!(
+                    "The pairing record for `{udid}` doesn't have a `{key}`. Try unpairing and pairing the device again."
+                ))),
+            }
+        };
+        Ok(PairRecord {
+            host_id: string(&record, "HostID").ok_or_else(not_paired)?,
+            system_buid: string(&record, "SystemBUID").ok_or_else(not_paired)?,
+            host_certificate: data("HostCertificate")?,
+            host_private_key: data("HostPrivateKey")?,
+            device_certificate: data("DeviceCertificate")?,
+        })
+    }
+
+    /// Connects to a port on a device. The connection goes straight to the device from then on.
+    pub fn connect(&mut self, device: &Device, port: u16) -> Result<UnixStream, Error> {
+        let mut stream = self.open()?;
+        let mut message = self.message("Connect");
+        message.insert("DeviceID".into(), device.device_id.into());
+        // usbmuxd wants the port in network byte order
+        message.insert("PortNumber".into(), u64::from(port.to_be()).into());
+        let reply = self.request(&mut stream, message)?;
+        match reply.get("Number").and_then(Value::as_unsigned_integer) {
+            Some(0) => Ok(stream),
+            _ => Err(result_error(
+                &format!("connect to port {port} on `{}`", device.udid),
+                &reply,
+            )),
+        }
+    }
+}
+
+/// An error for a `Result` message from usbmuxd
+fn result_error(action: &str, reply: &Dictionary) -> Error {
+    let reason = match reply.get("Number").and_then(Value::as_unsigned_integer) {
+        Some(1) => "it didn't understand the request".to_owned(),
+        Some(2) => "the device isn't connected".to_owned(),
+        Some(3) => "the device refused the connection".to_owned(),
+        Some(6) => "it doesn't support this version of its protocol".to_owned(),
+        Some(number) => format!("error {number}"),
+        None => "it didn't say why".to_owned(),
+    };
+    Error::Device(format!("usbmuxd couldn't {action}: {reason}"))
+}
@@ -0,0 +1,315 @@
+use clap::Args;
+use std::io;
+
+use crate::{
+    build::{gen_targets_list, get_target_triple},
+    config,
+    context::*,
+    error::Error,
+    runner::{run_checked, SystemRunner, ToolCall, ToolRunner},
+};
+
+#[derive(Args)]
+pub struct DoctorArgs {
+    /// Only check the toolchain for 1 platform instead of both
+    #[arg(short, long, value_enum)]
+    platform: Option<Platform>,
+    /// Only check the toolchain for 1 architecture instead of both
+    #[arg(short, long, value_enum)]
+    architecture: Option<Architecture>,
+    /// Install missing rustup targets
+    #[arg(long)]
+    fix: bool,
+}
+
+/// How a single check went
+#[derive(Debug, PartialEq, Eq)]
+pub enum Status {
+    /// Everything's fine
+    Ok,
+    /// Something's off, but it only matters for some projects
+    Warning,
+    /// cargo-ipa won't work until this is fixed
+    Failed,
+}
+
+/// The result of a single environment check
+pub struct Check {
+    pub status: Status,
+    /// What was checked, and what was found
+    pub message: String,
+    /// How to fix the problem, if there is one
+    pub fix: Option<String>,
+}
+impl Check {
+    fn ok(message: String) -> Self {
+        Self {
+            status: Status::Ok,
+            message,
+            fix: None,
+        }
+    }
+    fn warning(message: String, fix: impl Into<String>) -> Self {
+        Self {
+            status: Status::Warning,
+            message,
+            fix: Some(fix.into()),
+        }
+    }
+    fn failed(message: String, fix: impl Into<String>) -> Self {
+        Self {
+            status: Status::Failed,
+            message,
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+/// The tools cargo-ipa runs, and an argument that makes each one exit without doing anything
+const REQUIRED_TOOLS: &[(&str, &str)] = &[
+    ("cargo", "--version"),
+    ("rustup", "--version"),
+    ("xcrun", "--version"),
+    ("xcode-select", "--version"),
+    ("zip", "-v"),
+    ("dsymutil", "--version"),
+    ("chmod", "--help"),
+    ("strip", "--version"),
+    ("unzip", "-v"),
+    ("git", "--version"),
+];
+
+pub fn doctor(args: DoctorArgs) -> Result<(), Error> {
+    let runner = SystemRunner;
+    let targets = gen_targets_list(args.platform, args.architecture);
+
+    // Swift is only required if the project uses swift-bridge
+    let needs_swift = find_cargo_toml()
+        .and_then(|cargo_toml| config::load(&cargo_toml))
+        .map(|manifest| manifest.config.swift_library.is_some())
+        .unwrap_or(false);
+
+    println!("Checking your environment...");
+    let mut checks = diagnose(&runner, &targets, needs_swift);
+
+    // If fixing fails (e.g. rustup is missing), still show the report so it's clear why
+    let mut fix_result = Ok(());
+    if args.fix {
+        fix_result = missing_targets(&runner, &targets).and_then(|missing| {
+            if !missing.is_empty() {
+                println!("Installing missing rustup targets...");
+                install_targets(&runner, &missing)?;
+                checks = diagnose(&runner, &targets, needs_swift);
+            }
+            Ok(())
+        });
+    }
+
+    for check in &checks {
+        let label = match check.status {
+            Status::Ok => "[ OK ]",
+            Status::Warning => "[WARN]",
+            Status::Failed => "[FAIL]",
+        };
+        println!("{label} {}", check.message);
+        if let Some(ref fix) = check.fix {
+            println!("       Fix: {fix}");
+        }
+    }
+
+    fix_result?;
+    let failures = checks
+        .iter()
+        .filter(|check| check.status == Status::Failed)
+        .count();
+    if failures > 0 {
+        Err(Error::ToolchainMissing {
+            message: format!("Found {failures} problem(s) that will stop cargo-ipa from working."),
+            source: None,
+        })
+    } else {
+        println!("Done! Your environment is ready for cargo-ipa.");
+        Ok(())
+    }
+}
+
+/// Runs every environment check for the given targets
+pub fn diagnose(
+    runner: &dyn ToolRunner,
+    targets: &[(Platform, Architecture)],
+    needs_swift: bool,
+) -> Vec<Check> {
+    let mut checks = Vec::new();
+    checks.extend(check_tools(runner));
+    checks.extend(check_targets(runner, targets));
+    checks.push(check_xcode(runner));
+
+    let mut platforms: Vec<Platform> = targets.iter().map(|(platform, _)| *platform).collect();
+    platforms.sort();
+    platforms.dedup();
+    for platform in platforms {
+        checks.push(check_sdk(runner, platform));
+    }
+
+    checks.push(check_swift(runner, needs_swift));
+    checks
+}
+
+/// Makes sure every tool cargo-ipa runs is installed
+fn check_tools(runner: &dyn ToolRunner) -> Vec<Check> {
+    REQUIRED_TOOLS
+        .iter()
+        .map(|(tool, arg)| match runner.run(tool, &[arg]) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Check::failed(
+                format!("`{tool}` isn't installed, or isn't on your PATH"),
+                format!("Install `{tool}`, and make sure it's on your PATH"),
+            ),
+            Err(e) => Check::failed(
+                format!("`{tool}` couldn't be run: {e}"),
+                format!("Make sure `{tool}` is executable"),
+            ),
+            // Some tools (like chmod) don't have a "do nothing" argument, so as long as the
+            // tool ran, it's installed
+            Ok(_) => Check::ok(format!("`{tool}` is installed")),
+        })
+        .collect()
+}
+
+/// Makes sure the rustup target for every selected platform & architecture is installed
+fn check_targets(runner: &dyn ToolRunner, targets: &[(Platform, Architecture)]) -> Vec<Check> {
+    let installed = match installed_targets(runner) {
+        Ok(installed) => installed,
+        Err(e) => {
+            return vec![Check::failed(
+                format!("Couldn't list installed rustup targets: {e}"),
+                "Install rustup from https://rustup.rs",
+            )]
+        }
+    };
+
+    targets
+        .iter()
+        .map(|(platform, architecture)| {
+            let target = get_target_triple(*platform, *architecture);
+            if installed.contains(&target) {
+                Check::ok(format!("rustup target `{target}` is installed"))
+            } else {
+                Check::failed(
+                    format!("rustup target `{target}` isn't installed"),
+                    format!("Run `rustup target add {target}` (or `cargo ipa doctor --fix`)"),
+                )
+            }
+        })
+        .collect()
+}
+
+/// Makes sure xcode-select points at a full Xcode install
+fn check_xcode(runner: &dyn ToolRunner) -> Check {
+    let fix = "Install Xcode, then run `sudo xcode-select --switch /Applications/Xcode.app`";
+    match runner.run("xcode-select", &["--print-path"]) {
+        Ok(output) if output.success => {
+            let path = output.stdout.trim();
+            if path.contains("CommandLineTools") {
+                Check::failed(
+                    format!("xcode-select points at the Command Line Tools (`{path}`), which don't include the iOS SDK"),
+                    fix,
+                )
+            } else {
+                Check::ok(format!("xcode-select points at `{path}`"))
+            }
+        }
+        Ok(output) => Check::failed(
+            format!(
+                "xcode-select couldn't find a developer directory: {}",
+                output.stderr.trim()
+            ),
+            fix,
+        ),
+        Err(e) => Check::failed(format!("Couldn't run xcode-select: {e}"), fix),
+    }
+}
+
+/// Makes sure the SDK for a platform is available
+fn check_sdk(runner: &dyn ToolRunner, platform: Platform) -> Check {
+    let sdk = match platform {
+        Platform::macOS => "macosx",
+        Platform::iOS => "iphoneos",
+    };
+    match runner.run("xcrun", &["--sdk", sdk, "--show-sdk-path"]) {
+        Ok(output) if output.success && !output.stdout.trim().is_empty() => {
+            Check::ok(format!("The `{sdk}` SDK is at `{}`", output.stdout.trim()))
+        }
+        _ => Check::failed(
+            format!("The `{sdk}` SDK couldn't be found"),
+            "Install Xcode (and its platform support), then make sure xcode-select points at it",
+        ),
+    }
+}
+
+/// Makes sure the Swift toolchain is installed
+fn check_swift(runner: &dyn ToolRunner, needs_swift: bool) -> Check {
+    match runner.run("swift", &["--version"]) {
+        Ok(output) if output.success => {
+            // Swift prints its version to stderr on some systems
+            let version = if output.stdout.trim().is_empty() {
+                output.stderr
+            } else {
+                output.stdout
+            };
+            Check::ok(format!(
+                "Swift is installed ({})",
+                version.lines().next().unwrap_or_default().trim()
+            ))
+        }
+        _ => {
+            let message = "The Swift toolchain isn't installed".to_string();
+            let fix = "Install Xcode, or a Swift toolchain from https://swift.org";
+            if needs_swift {
+                Check::failed(message + ", but this project uses swift-bridge", fix)
+            } else {
+                Check::warning(message + " (only needed for swift-bridge projects)", fix)
+            }
+        }
+    }
+}
+
+/// Lists the rustup targets that are installed
+fn installed_targets(runner: &dyn ToolRunner) -> Result<Vec<String>, Error> {
+    let call = ToolCall::new("rustup", &["target", "list", "--installed"]);
+    let output = run_checked(runner, &call, |output| Error::ToolchainMissing {
+        message: format!(
+            "rustup failed to list the installed targets: {}",
+            output.stderr.trim()
+        ),
+        source: None,
+    })?;
+    Ok(output.stdout.lines().map(str::to_string).collect())
+}
+
+/// Finds the selected targets that rustup hasn't installed
+pub fn missing_targets(
+    runner: &dyn ToolRunner,
+    targets: &[(Platform, Architecture)],
+) -> Result<Vec<String>, Error> {
+    let installed = installed_targets(runner)?;
+    Ok(targets
+        .iter()
+        .map(|(platform, architecture)| get_target_triple(*platform, *architecture))
+        .filter(|target| !installed.contains(target))
+        .collect())
+}
+
+/// Installs rustup targets
+pub fn install_targets(runner: &dyn ToolRunner, targets: &[String]) -> Result<(), Error> {
+    let mut args = vec!["target", "add"];
+    args.extend(targets.iter().map(|target| target.as_str()));
+    println!("|- Running `rustup {}`...", args.join(" "));
+
+    run_checked(runner, &ToolCall::new("rustup", &args), |output| {
+        Error::ToolchainMissing {
+            message: "rustup failed to install the targets!".into(),
+            source: Some(io::Error::other(output.stderr)),
+        }
+    })?;
+    Ok(())
+}
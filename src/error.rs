@@ -0,0 +1,161 @@
+use std::{error::Error as StdError, fmt, io, path::PathBuf};
+
+/// Everything that can go wrong in cargo-ipa.
+///
+/// Each kind of error has its own exit code (see [`Error::exit_code`]),
+/// so scripts can tell them apart.
+#[derive(Debug)]
+pub enum Error {
+    /// Cargo.toml couldn't be found, or its settings are missing or invalid
+    Config(String),
+    /// A tool, target, or SDK cargo-ipa needs isn't installed (or couldn't be run)
+    ToolchainMissing {
+        message: String,
+        source: Option<io::Error>,
+    },
+    /// Cargo or Swift failed to compile the project
+    CompileFailed {
+        /// The compiler that failed, eg "Cargo" or "Swift"
+        tool: String,
+        /// Everything the compiler printed to stderr
+        stderr: CompilerOutput,
+    },
+    /// Something went wrong while assembling or compressing the app
+    Packaging {
+        message: String,
+        source: Option<Box<dyn StdError + Send + Sync>>,
+    },
+    /// Reading or writing a file failed
+    Io {
+        /// What cargo-ipa was trying to do
+        message: String,
+        /// The file or folder it was trying to do it to
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// A binary couldn't be code signed
+    Signing(String),
+    /// An Apple service (like the notary service) couldn't be reached, or rejected the app
+    Service(String),
+    /// A device or simulator couldn't be reached, or refused to install or launch the app
+    Device(String),
+}
+impl Error {
+    /// An error for a tool that failed to start
+    pub fn tool_missing(tool: &str, source: io::Error) -> Self {
+        Self::ToolchainMissing {
+            message: format!(
+                "Failed to run `{tool}`. Is it installed? (`cargo ipa doctor` can check)"
+            ),
+            source: Some(source),
+        }
+    }
+
+    /// An error for a file operation that failed
+    pub fn io(message: impl Into<String>, path: impl Into<PathBuf>, source: io::Error) -> Self {
+        Self::Io {
+            message: message.into(),
+            path: path.into(),
+            source,
+        }
+    }
+
+    /// An error for a packaging step that failed without an underlying cause
+    pub fn packaging(message: impl Into<String>) -> Self {
+        Self::Packaging {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    /// The exit code cargo-ipa should use for this error. These are stable,
+    /// and shouldn't be changed:
+    ///
+    /// - 2: configuration errors
+    /// - 3: missing tools, targets, or SDKs
+    /// - 4: compilation failures
+    /// - 5: packaging failures
+    /// - 6: file errors
+    /// - 7: code signing failures
+    /// - 8: failures from Apple's services
+    /// - 9: failures talking to (or installing on) a device or simulator
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Config(_) => 2,
+            Self::ToolchainMissing { .. } => 3,
+            Self::CompileFailed { .. } => 4,
+            Self::Packaging { .. } => 5,
+            Self::Io { .. } => 6,
+            Self::Signing(_) => 7,
+            Self::Service(_) => 8,
+            Self::Device(_) => 9,
+        }
+    }
+
+    /// Renders the error and everything that caused it, for printing to stderr
+    pub fn report(&self) -> String {
+        let mut buffer = format!("Error: {self}");
+        let mut source = self.source();
+        while let Some(cause) = source {
+            let cause_text = cause.to_string();
+            source = cause.source();
+            // Tools don't always explain why they failed
+            if cause_text.trim().is_empty() {
+                continue;
+            }
+            let mut lines = cause_text.trim_end().lines();
+            buffer += &format!("\n  Caused by: {}", lines.next().unwrap_or_default());
+            for line in lines {
+                buffer += "\n    ";
+                buffer += line;
+            }
+        }
+        buffer
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Config(message) => write!(f, "{message}"),
+            Self::ToolchainMissing { message, .. } => write!(f, "{message}"),
+            Self::CompileFailed { tool, .. } => {
+                write!(f, "{tool} failed to compile the project! Aborting.")
+            }
+            Self::Packaging { message, .. } => write!(f, "{message}"),
+            Self::Io { message, path, .. } => write!(f, "{message} (`{}`)", path.display()),
+            Self::Signing(message) => write!(f, "Failed to sign the app: {message}"),
+            Self::Service(message) => write!(f, "{message}"),
+            Self::Device(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Config(_) => None,
+            Self::ToolchainMissing { source, .. } => source.as_ref().map(|e| e as _),
+            Self::CompileFailed { stderr, .. } => {
+                if stderr.0.trim().is_empty() {
+                    None
+                } else {
+                    Some(stderr)
+                }
+            }
+            Self::Packaging { source, .. } => source.as_deref().map(|e| e as _),
+            Self::Io { source, .. } => Some(source),
+            Self::Signing(_) | Self::Service(_) | Self::Device(_) => None,
+        }
+    }
+}
+
+/// A compiler's stderr, so it can show up as the cause of a [`Error::CompileFailed`]
+#[derive(Debug)]
+pub struct CompilerOutput(pub String);
+impl fmt::Display for CompilerOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.trim_end())
+    }
+}
+impl StdError for CompilerOutput {}
@@ -0,0 +1,359 @@
+use clap::Args;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+use toml_edit::{value, Array, Decor, Document, Item, Table, TableLike};
+
+use crate::error::Error;
+
+#[derive(Args)]
+pub struct InitArgs {
+    /// The app's human-readable name. If left unprovided, cargo-ipa
+    /// will make one from the package name.
+    #[arg(short, long)]
+    pub name: Option<String>,
+    /// The app's reverse-DNS bundle ID. If left unprovided, cargo-ipa
+    /// will make one from the package's authors or repository.
+    #[arg(short, long)]
+    pub bundle_id: Option<String>,
+    /// Also scaffold a Swift package for swift-bridge integration, in
+    /// the provided folder (`swift-library` by default).
+    #[arg(short, long, num_args = 0..=1, default_missing_value = "swift-library")]
+    pub swift: Option<String>,
+    /// Update the name, bundle ID, and Swift package in an existing
+    /// [package.metadata.cargo-ipa] section. Its other settings are kept.
+    #[arg(short, long)]
+    pub force: bool,
+}
+
+pub fn init(args: InitArgs) -> Result<(), Error> {
+    match env::current_dir() {
+        Ok(dir) => init_with(&args, &dir),
+        Err(e) => Err(Error::io("Failed to get current directory", ".", e)),
+    }
+}
+
+/// Sets up the package that `dir` is in
+pub fn init_with(args: &InitArgs, dir: &Path) -> Result<(), Error> {
+    let cargo_toml = find_package_cargo_toml(dir)?;
+    let root_dir = match cargo_toml.parent() {
+        Some(dir) => dir.to_owned(),
+        None => {
+            return Err(Error::Config(
+                "Failed to get project's root directory".to_owned(),
+            ))
+        }
+    };
+
+    // Parse Cargo.toml without losing its formatting or comments
+    let source = match fs::read_to_string(&cargo_toml) {
+        Ok(source) => source,
+        Err(e) => return Err(Error::io("Failed to read Cargo.toml", cargo_toml, e)),
+    };
+    let mut doc = match source.parse::<Document>() {
+        Ok(doc) => doc,
+        Err(e) => return Err(Error::Config(format!("Failed to parse Cargo.toml: {e}"))),
+    };
+
+    // ========== INSPECT THE PACKAGE ==========
+    println!("Inspecting `{}`...", cargo_toml.display());
+    let Some(package) = doc.get("package").and_then(Item::as_table_like) else {
+        return Err(Error::Config(
+            "Invalid Cargo.toml detected! Failed to find the [package] section.".into(),
+        ));
+    };
+    let Some(package_name) = package.get("name").and_then(Item::as_str) else {
+        return Err(Error::Config(
+            "Invalid Cargo.toml detected! Failed to get package name.".into(),
+        ));
+    };
+    let package_name = package_name.to_string();
+    let authors: Vec<String> = package
+        .get("authors")
+        .and_then(Item::as_array)
+        .map(|authors| {
+            authors
+                .iter()
+                .filter_map(|author| author.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default();
+    let repository = package
+        .get("repository")
+        .and_then(Item::as_str)
+        .map(str::to_string);
+
+    let name = args
+        .name
+        .clone()
+        .unwrap_or_else(|| suggest_name(&package_name));
+    let bundle_id = args
+        .bundle_id
+        .clone()
+        .unwrap_or_else(|| suggest_bundle_id(&package_name, &authors, repository.as_deref()));
+    println!("|- App name: {name}");
+    println!("|- Bundle ID: {bundle_id}");
+
+    // ========== WRITE THE CONFIGURATION ==========
+    let package = doc["package"].as_table_like_mut().unwrap();
+    let metadata = package
+        .entry("metadata")
+        .or_insert_with(|| {
+            let mut metadata = Table::new();
+            metadata.set_implicit(true);
+            Item::Table(metadata)
+        })
+        .as_table_like_mut();
+    let Some(metadata) = metadata else {
+        return Err(Error::Config(
+            "Invalid Cargo.toml detected! `package.metadata` isn't a table.".into(),
+        ));
+    };
+    if metadata.contains_key("cargo-ipa") && !args.force {
+        return Err(Error::Config(
+            "Cargo.toml already has a [package.metadata.cargo-ipa] section. Use --force to update it."
+                .into(),
+        ));
+    }
+    let config = gen_config_table(&name, &bundle_id, args.swift.as_deref());
+    let updated = match metadata
+        .get_mut("cargo-ipa")
+        .and_then(Item::as_table_like_mut)
+    {
+        Some(existing) => {
+            merge_config_table(existing, config);
+            true
+        }
+        None => {
+            metadata.insert("cargo-ipa", Item::Table(config));
+            false
+        }
+    };
+
+    if let Err(e) = fs::write(&cargo_toml, doc.to_string()) {
+        return Err(Error::io("Failed to write to Cargo.toml", cargo_toml, e));
+    }
+    if updated {
+        println!("Updated the [package.metadata.cargo-ipa] section in Cargo.toml");
+    } else {
+        println!("Added a [package.metadata.cargo-ipa] section to Cargo.toml");
+    }
+
+    // ========== SCAFFOLD THE SWIFT PACKAGE ==========
+    if let Some(ref swift_library) = args.swift {
+        println!("Scaffolding the `{swift_library}` Swift package...");
+        scaffold_swift_library(&root_dir.join(swift_library), &package_name)?;
+    }
+
+    println!("Done! Run `cargo ipa build` to build your app.");
+    Ok(())
+}
+
+/// Generates the commented [package.metadata.cargo-ipa] section
+fn gen_config_table(name: &str, bundle_id: &str, swift_library: Option<&str>) -> Table {
+    let mut table = Table::new();
+    table.decor_mut().set_prefix(
+        "\n# Settings for cargo-ipa. See https://github.com/loki-chat/cargo-ipa#configuration\n",
+    );
+
+    table.insert("name", value(name));
+    set_comment(
+        &mut table,
+        "name",
+        "The app's name, as it appears on the home screen/app list",
+    );
+    table.insert("bundle-id", value(bundle_id));
+    set_comment(
+        &mut table,
+        "bundle-id",
+        "The app's unique, reverse-DNS bundle ID",
+    );
+
+    if let Some(swift_library) = swift_library {
+        table.insert("swift-library", value(swift_library));
+        set_comment(
+            &mut table,
+            "swift-library",
+            "The Swift package to compile and link with swift-bridge",
+        );
+        table.insert("swift-bridges", value(Array::new()));
+        set_comment(
+            &mut table,
+            "swift-bridges",
+            "Rust files that use `#[swift_bridge::bridge]`, eg [\"src/swift.rs\"]",
+        );
+    }
+
+    table
+}
+
+/// Copies the settings `init` chose into an existing section, keeping the user's other
+/// settings. `swift-bridges` is only a placeholder, so an existing list is left alone.
+fn merge_config_table(existing: &mut dyn TableLike, config: Table) {
+    for (key, item) in config {
+        match existing.get_mut(&key) {
+            Some(_) if key.as_str() == "swift-bridges" => {}
+            Some(old) => *old = item,
+            None => {
+                existing.insert(&key, item);
+            }
+        }
+    }
+}
+
+/// Locates the Cargo.toml of the package `dir` is in. This is the nearest one, so running
+/// `init` in a workspace member sets up the member, not the workspace.
+fn find_package_cargo_toml(dir: &Path) -> Result<PathBuf, Error> {
+    dir.ancestors()
+        .map(|dir| dir.join("Cargo.toml"))
+        .find(|path| path.exists())
+        .ok_or_else(|| Error::Config("Failed to locate Cargo.toml".to_string()))
+}
+
+/// Puts a comment above a key in a table
+fn set_comment(table: &mut Table, key: &str, comment: &str) {
+    if let Some(decor) = table.key_decor_mut(key) {
+        *decor = Decor::new(format!("# {comment}\n"), " ");
+    }
+}
+
+/// Makes a human-readable app name from the package name, eg `my-cool_app` -> `My Cool App`
+fn suggest_name(package_name: &str) -> String {
+    package_name
+        .split(['-', '_'])
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Makes a reverse-DNS bundle ID for the package. This tries to use the domain
+/// of an author's email, then the package's repository, and finally falls back
+/// to `com.<package name>` (which is what `cargo ipa build` defaults to).
+fn suggest_bundle_id(package_name: &str, authors: &[String], repository: Option<&str>) -> String {
+    // Authors are usually formatted as `Name <email@domain.com>`
+    let email_domain = authors.iter().find_map(|author| {
+        let email = author.split_once('<')?.1.split_once('>')?.0;
+        Some(email.split_once('@')?.1.to_string())
+    });
+
+    let prefix = if let Some(domain) = email_domain {
+        domain.split('.').rev().collect::<Vec<_>>().join(".")
+    } else if let Some(url) = repository.and_then(|url| url.split_once("://")) {
+        // eg https://github.com/loki-chat/cargo-ipa -> com.github.loki-chat
+        let mut parts = url.1.split('/');
+        let host = parts.next().unwrap_or_default();
+        let mut prefix = host.split('.').rev().collect::<Vec<_>>().join(".");
+        if let Some(owner) = parts.next().filter(|owner| !owner.is_empty()) {
+            prefix = prefix + "." + owner;
+        }
+        prefix
+    } else {
+        "com".to_string()
+    };
+
+    sanitize_bundle_id(&(prefix + "." + package_name))
+}
+
+/// Bundle IDs can only contain letters, numbers, hyphens, and periods
+fn sanitize_bundle_id(bundle_id: &str) -> String {
+    bundle_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// Creates a Swift package that swift-bridge can generate code into
+fn scaffold_swift_library(library_path: &Path, package_name: &str) -> Result<(), Error> {
+    let library_name = match library_path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name.to_string(),
+        None => {
+            return Err(Error::Config(format!(
+                "Invalid Swift package folder name: `{}`",
+                library_path.display()
+            )))
+        }
+    };
+    let source_path = library_path.join("Sources").join(&library_name);
+    if library_path.join("Package.swift").exists() {
+        println!("|- `Package.swift` already exists, leaving the Swift package as-is");
+        return Ok(());
+    }
+    if let Err(e) = fs::create_dir_all(&source_path) {
+        return Err(Error::io(
+            "Failed to create the Swift package's folders",
+            source_path,
+            e,
+        ));
+    }
+
+    let files = [
+        (
+            library_path.join("Package.swift"),
+            gen_package_swift(&library_name),
+        ),
+        (
+            source_path.join("bridging-header.h"),
+            gen_bridging_header(package_name),
+        ),
+        (
+            source_path.join(library_name.clone() + ".swift"),
+            "// Your Swift code goes here\n".to_string(),
+        ),
+    ];
+    for (path, contents) in files {
+        println!("|- Writing `{}`...", path.display());
+        if let Err(e) = fs::write(&path, contents) {
+            return Err(Error::io("Failed to write a Swift package file", path, e));
+        }
+    }
+
+    Ok(())
+}
+
+/// Generates a Package.swift for a static library
+fn gen_package_swift(library_name: &str) -> String {
+    format!(
+        r#"// swift-tools-version:5.5
+import PackageDescription
+
+let package = Package(
+    name: "{library_name}",
+    products: [
+        .library(name: "{library_name}", type: .static, targets: ["{library_name}"]),
+    ],
+    dependencies: [],
+    targets: [
+        .target(name: "{library_name}", dependencies: [])
+    ]
+)
+"#
+    )
+}
+
+/// Generates a bridging header that includes swift-bridge's generated headers
+fn gen_bridging_header(package_name: &str) -> String {
+    format!(
+        r#"#ifndef BridgingHeader_h
+#define BridgingHeader_h
+
+#include "./generated/SwiftBridgeCore.h"
+#include "./generated/{package_name}/{package_name}.h"
+
+#endif
+"#
+    )
+}
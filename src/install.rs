@@ -0,0 +1,115 @@
+use clap::Args;
+use std::{fs, path::PathBuf};
+
+use crate::{
+    context::{find_cargo_toml, Ctx},
+    device::{
+        afc::{Afc, AFC_SERVICE},
+        installation_proxy::{self, INSTALLATION_PROXY_SERVICE},
+        usbmux, Session,
+    },
+    error::Error,
+};
+
+/// Where apps are uploaded to before they're installed, in the device's media folder
+const STAGING_DIR: &str = "PublicStaging";
+
+#[derive(Args)]
+pub struct InstallArgs {
+    /// The IPA to install. Defaults to the aarch64 iOS IPA `cargo ipa build` made.
+    pub ipa: Option<PathBuf>,
+    /// The app's name, for finding the IPA `cargo ipa build` made. If left unprovided,
+    /// cargo-ipa will search for it in Cargo.toml.
+    #[arg(short, long)]
+    pub name: Option<String>,
+    /// The UDID of the device to install on. Only needed if more than one is plugged in.
+    #[arg(short, long)]
+    pub device: Option<String>,
+    /// usbmuxd's socket, eg to test against a fake one
+    #[arg(long, default_value = usbmux::USBMUXD_SOCKET)]
+    pub usbmux_socket: PathBuf,
+}
+
+/// An app that was installed on a device
+#[derive(Debug)]
+pub struct Installation {
+    /// The device's UDID
+    pub udid: String,
+    /// The device's name, like "Jane's iPhone"
+    pub device_name: Option<String>,
+    /// Where the IPA was uploaded to on the device
+    pub staged_path: String,
+}
+
+pub fn install(args: InstallArgs) -> Result<(), Error> {
+    // Cargo.toml's only needed to find the IPA
+    let ctx = match args.ipa {
+        Some(_) => None,
+        None => Some(Ctx::from_cargo_toml(find_cargo_toml()?, &args.name)?),
+    };
+    install_with(&args, ctx.as_ref()).map(|_| ())
+}
+
+/// Installs an IPA on a USB-connected device, through the usbmuxd at `args.usbmux_socket`
+pub fn install_with(args: &InstallArgs, ctx: Option<&Ctx>) -> Result<Installation, Error> {
+    // ========== SETUP ==========
+    let ipa = match (&args.ipa, ctx) {
+        (Some(ipa), _) => ipa.clone(),
+        (None, Some(ctx)) => ctx
+            .cargo_ipa_dir
+            .join(format!("{}.aarch64-apple-ios.ipa", ctx.project_name)),
+        (None, None) => {
+            return Err(Error::Config(
+                "No IPA was given, and there's no project to find one in".into(),
+            ))
+        }
+    };
+    println!("Installing `{}`...", ipa.display());
+    if ipa.extension().is_none_or(|ext| ext != "ipa") {
+        return Err(Error::Config(format!(
+            "`{}` isn't an IPA. Only IPAs can be installed on devices.",
+            ipa.display()
+        )));
+    }
+    let data = match fs::read(&ipa) {
+        Ok(data) => data,
+        Err(e) => return Err(Error::io("Failed to read the IPA", ipa, e)),
+    };
+    let file_name = ipa
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    // ========== CONNECT ==========
+    let mut session = Session::connect(&args.usbmux_socket, args.device.as_deref())?;
+    let device_name = session.announce()?;
+
+    // ========== UPLOAD ==========
+    println!("|- Copying the app to the device...");
+    let mut afc = Afc::new(session.start_service(AFC_SERVICE)?);
+    afc.make_dir(STAGING_DIR)?;
+    let staged_path = format!("{STAGING_DIR}/{file_name}");
+    let mut reported = 0;
+    afc.upload(&staged_path, &data, |sent| {
+        // Every 10%, so big apps don't flood the terminal
+        let percent = sent * 100 / data.len().max(1);
+        if percent >= reported + 10 || sent == data.len() {
+            reported = percent;
+            println!("   |- {percent}%");
+        }
+    })?;
+
+    // ========== INSTALL ==========
+    println!("|- Installing...");
+    let mut conn = session.start_service(INSTALLATION_PROXY_SERVICE)?;
+    installation_proxy::install(&mut conn, &staged_path, |status, percent| {
+        println!("   |- {status} ({percent}%)");
+    })?;
+
+    println!("|- Installed!");
+    Ok(Installation {
+        udid: session.device.udid,
+        device_name,
+        staged_path,
+    })
+}
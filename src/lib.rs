@@ -1,15 +1,58 @@
-mod context;
+pub mod config;
+pub mod context;
+pub mod error;
+pub mod runner;
 mod swift;
 
+// The cargo-ipa CLI's subcommands
+#[cfg(feature = "binary")]
+pub mod build;
+#[cfg(feature = "binary")]
+pub mod build_manifest;
+#[cfg(feature = "binary")]
+pub mod builds;
+#[cfg(feature = "binary")]
+pub mod connect;
+#[cfg(feature = "binary")]
+pub mod crashes;
+#[cfg(feature = "binary")]
+pub mod device;
+#[cfg(feature = "binary")]
+pub mod doctor;
+#[cfg(feature = "binary")]
+pub mod init;
+#[cfg(feature = "binary")]
+pub mod install;
+#[cfg(feature = "binary")]
+pub mod logs;
+#[cfg(feature = "binary")]
+pub mod notarize;
+#[cfg(feature = "binary")]
+pub mod resign;
+#[cfg(feature = "binary")]
+pub mod run;
+#[cfg(feature = "binary")]
+pub mod sign;
+#[cfg(feature = "binary")]
+pub mod staple;
+#[cfg(feature = "binary")]
+pub mod symbolicate;
+#[cfg(feature = "binary")]
+pub mod upload;
+#[cfg(feature = "binary")]
+pub mod verify;
+
 use {
     context::*,
-    std::{env, process::Command},
+    error::{CompilerOutput, Error},
+    runner::{run_checked, SystemRunner, ToolCall},
+    std::env,
     swift::SwiftCtx,
 };
 
 /// Uses swift-bridge to generate FFI bindings between Swift and Rust
 #[cfg(feature = "swift-bridge")]
-pub fn generate_bindings() -> Result<(), String> {
+pub fn generate_bindings() -> Result<(), Error> {
     let ctx = Ctx::new(&None)?;
     let swift_ctx = SwiftCtx::new(&ctx, release_mode())?;
     swift_bridge_build::parse_bridges(swift_ctx.bridges)
@@ -19,23 +62,29 @@ pub fn generate_bindings() -> Result<(), String> {
 }
 
 /// Compiles the Swift package, and tells Cargo to statically link it
-pub fn compile_and_link_swift() -> Result<(), String> {
+pub fn compile_and_link_swift() -> Result<(), Error> {
     // Setup
+    let runner = &SystemRunner;
     let release_mode = release_mode();
     let ctx = Ctx::new(&None)?;
     let swift_ctx = SwiftCtx::new(&ctx, release_mode)?;
     let static_swift_args = swift::static_swiftc_args(&swift_ctx, release_mode);
-    let target_triple = env::var("TARGET")
+    let mut target_triple = env::var("TARGET")
         .unwrap()
         .replace("aarch64", "arm64") // Map the Rust target triple to a Swift target triple
         .replace("ios", "ios14")
         .replace("darwin", "macosx11");
+    // Like `aarch64-apple-ios-sim`, which Swift calls `arm64-apple-ios14-simulator`
+    let simulator = target_triple.ends_with("-sim");
+    if simulator {
+        target_triple.push_str("ulator");
+    }
     let platform = if target_triple.contains("ios") {
         Platform::iOS
     } else {
         Platform::macOS
     };
-    let sdk = swift::get_sdk(platform);
+    let sdk = swift::get_sdk(runner, platform, simulator)?;
 
     // Compile the Swift package
     let mut swift_args = vec![
@@ -48,19 +97,14 @@ pub fn compile_and_link_swift() -> Result<(), String> {
         &sdk,
     ];
     swift_args.extend(static_swift_args.iter().map(|item| item.as_str()));
-    let build_status = Command::new("swift")
-        .args(swift_args)
-        .spawn()
-        .unwrap()
-        .wait_with_output()
-        .unwrap();
-    if !build_status.status.success() {
-        return Err(format!(
-            "Swift failed to compile the project! Stdout:\n{}\n\nStderr:\n{}",
-            String::from_utf8(build_status.stderr).unwrap(),
-            String::from_utf8(build_status.stdout).unwrap()
-        ));
-    }
+    let swift_call = ToolCall::new("swift", &swift_args).echo();
+    run_checked(runner, &swift_call, |output| Error::CompileFailed {
+        tool: "Swift".into(),
+        stderr: CompilerOutput(format!(
+            "Stdout:\n{}\n\nStderr:\n{}",
+            output.stdout, output.stderr
+        )),
+    })?;
 
     // Tell Cargo to statically link the Swift package
     println!("cargo:rustc-link-lib=static={}", &swift_ctx.library_name);
@@ -70,9 +114,10 @@ pub fn compile_and_link_swift() -> Result<(), String> {
     );
     println!(
         "cargo:rustc-link-search={}",
-        detect_xcode()
+        detect_xcode(runner)
             .join(match platform {
                 Platform::macOS => "macosx",
+                Platform::iOS if simulator => "iphonesimulator",
                 Platform::iOS => "iphoneos",
             })
             .to_str()
@@ -0,0 +1,80 @@
+use clap::Args;
+use std::path::PathBuf;
+
+use crate::{
+    context::{find_cargo_toml, Ctx},
+    device::{
+        syslog_relay::{self, SYSLOG_RELAY_SERVICE},
+        usbmux, Session,
+    },
+    error::Error,
+};
+
+#[derive(Args)]
+pub struct LogsArgs {
+    /// The app's name, for finding its executable's name. If left unprovided, cargo-ipa will
+    /// search for it in Cargo.toml.
+    #[arg(short, long)]
+    pub name: Option<String>,
+    /// Only show messages from this process. Defaults to the app's executable.
+    #[arg(long)]
+    pub process: Option<String>,
+    /// Show messages from every process on the device
+    #[arg(long, conflicts_with = "process")]
+    pub all: bool,
+    /// The UDID of the device to show logs from. Only needed if more than one is plugged in.
+    #[arg(short, long)]
+    pub device: Option<String>,
+    /// usbmuxd's socket, eg to test against a fake one
+    #[arg(long, default_value = usbmux::USBMUXD_SOCKET)]
+    pub usbmux_socket: PathBuf,
+}
+
+pub fn logs(args: LogsArgs) -> Result<(), Error> {
+    // Cargo.toml's only needed to find the app's executable
+    let ctx = match args.process.is_some() || args.all {
+        true => None,
+        false => Some(Ctx::from_cargo_toml(find_cargo_toml()?, &args.name)?),
+    };
+    logs_with(&args, ctx.as_ref(), |message| println!("{message}"))
+}
+
+/// Streams a USB-connected device's system log, through the usbmuxd at `args.usbmux_socket`,
+/// calling `on_message` with each message from the app's process. Returns when the device
+/// stops sending messages.
+pub fn logs_with(
+    args: &LogsArgs,
+    ctx: Option<&Ctx>,
+    mut on_message: impl FnMut(&str),
+) -> Result<(), Error> {
+    // ========== SETUP ==========
+    // The app's process is named after its executable, which is named after the package
+    let process = match (&args.process, ctx) {
+        _ if args.all => None,
+        (Some(process), _) => Some(process.as_str()),
+        (None, Some(ctx)) => Some(ctx.project_id.as_str()),
+        (None, None) => {
+            return Err(Error::Config(
+                "No process was given, and there's no project to find one in. Pass `--process`, or `--all`.".into(),
+            ))
+        }
+    };
+    let mut session = Session::connect(&args.usbmux_socket, args.device.as_deref())?;
+    session.announce()?;
+
+    // ========== STREAM ==========
+    let conn = session.start_service(SYSLOG_RELAY_SERVICE)?;
+    match process {
+        Some(process) => {
+            println!("|- Showing log messages from `{process}`. Press Ctrl-C to stop.")
+        }
+        None => println!("|- Showing every log message. Press Ctrl-C to stop."),
+    }
+    syslog_relay::stream(conn, |message| {
+        if process.is_none_or(|process| syslog_relay::process_name(message) == Some(process)) {
+            on_message(message);
+        }
+    })?;
+    println!("|- The device stopped sending log messages");
+    Ok(())
+}
@@ -1,10 +1,9 @@
+use cargo_ipa::{
+    build, builds, crashes, doctor, init, install, logs, notarize, resign, run, staple,
+    symbolicate, upload, verify,
+};
 use clap::{Parser, Subcommand};
 
-mod build;
-mod context;
-use context::*;
-mod swift;
-
 // The CLI application
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -17,6 +16,32 @@ enum Cli {
 enum Commands {
     /// Compile a Rust binary or library example into an IPA.
     Build(build::BuildArgs),
+    /// List, show, or prune the archived builds in target/cargo-ipa/builds.
+    Builds(builds::BuildsArgs),
+    /// Add a [package.metadata.cargo-ipa] section to Cargo.toml.
+    Init(init::InitArgs),
+    /// Check that the tools cargo-ipa needs are installed and set up.
+    Doctor(doctor::DoctorArgs),
+    /// Re-sign an existing IPA, optionally changing its bundle ID, profile, or entitlements.
+    Resign(resign::ResignArgs),
+    /// Check an app or IPA's code signatures, certificates, entitlements, and profile.
+    Verify(verify::VerifyArgs),
+    /// Upload a macOS app to Apple's notary service, and wait for it to be notarized.
+    Notarize(notarize::NotarizeArgs),
+    /// Attach a notarized macOS app's ticket to it, so it can be checked offline.
+    Staple(staple::StapleArgs),
+    /// Upload an IPA to App Store Connect, and wait for it to be ready for TestFlight.
+    Upload(upload::UploadArgs),
+    /// Install an IPA on a USB-connected iPhone or iPad.
+    Install(install::InstallArgs),
+    /// Run an app on the iOS Simulator or macOS, and show its output.
+    Run(run::RunArgs),
+    /// Show the app's messages from a USB-connected device's system log.
+    Logs(logs::LogsArgs),
+    /// Copy the app's crash reports from a USB-connected device.
+    Crashes(crashes::CrashesArgs),
+    /// Symbolicate a crash report (.ips file) with the debug info from earlier builds.
+    Symbolicate(symbolicate::SymbolicateArgs),
 }
 
 fn main() {
@@ -24,11 +49,26 @@ fn main() {
     let Cli::Ipa(cmd) = Cli::parse();
 
     // Match the command & run code accordingly
-    match cmd {
-        Commands::Build(args) => {
-            if let Err(e) = build::build(args) {
-                println!("{e}");
-            }
-        }
+    let result = match cmd {
+        Commands::Build(args) => build::build(args),
+        Commands::Builds(args) => builds::builds(args),
+        Commands::Init(args) => init::init(args),
+        Commands::Doctor(args) => doctor::doctor(args),
+        Commands::Resign(args) => resign::resign(args),
+        Commands::Verify(args) => verify::verify(args),
+        Commands::Notarize(args) => notarize::notarize(args),
+        Commands::Staple(args) => staple::staple(args),
+        Commands::Upload(args) => upload::upload(args),
+        Commands::Install(args) => install::install(args),
+        // Exit with the app's exit code, like `cargo run` does
+        Commands::Run(args) => run::run(args).map(|code| std::process::exit(code)),
+        Commands::Logs(args) => logs::logs(args),
+        Commands::Crashes(args) => crashes::crashes(args),
+        Commands::Symbolicate(args) => symbolicate::symbolicate(args),
     };
+
+    if let Err(e) = result {
+        eprintln!("{}", e.report());
+        std::process::exit(e.exit_code());
+    }
 }
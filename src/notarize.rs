@@ -0,0 +1,239 @@
+use clap::Args;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    connect::{
+        notary::{self, NotaryClient, NotaryIssue},
+        ticket, ApiKey,
+    },
+    error::Error,
+    runner::{run_checked, SystemRunner, ToolCall, ToolRunner},
+    staple::staple_app,
+    verify::{verify_with, VerifyArgs},
+};
+
+#[derive(Args)]
+pub struct NotarizeArgs {
+    /// The macOS app to notarize. A .zip, .dmg, or .pkg is uploaded as-is.
+    pub path: PathBuf,
+    /// The App Store Connect API key (`AuthKey_<key ID>.p8`) to authenticate with
+    #[arg(long)]
+    pub api_key: Option<PathBuf>,
+    /// The API key's ID. Defaults to the one in the key's file name.
+    #[arg(long)]
+    pub api_key_id: Option<String>,
+    /// The API key's issuer ID, from the Keys tab in App Store Connect
+    #[arg(long)]
+    pub api_issuer: Option<String>,
+    /// Staple the notarization ticket to the app once it's notarized
+    #[arg(long)]
+    pub staple: bool,
+    /// How often to check on the submission, in seconds
+    #[arg(long, default_value_t = 15)]
+    pub poll_interval: u64,
+    /// How long to wait for the notary service, in minutes
+    #[arg(long, default_value_t = 60)]
+    pub timeout: u64,
+    /// The notary service's URL, eg to test against a mock server
+    #[arg(long, default_value = notary::NOTARY_URL)]
+    pub notary_url: String,
+    /// Where submissions are uploaded to, eg to test against a mock server
+    #[arg(long, default_value = notary::UPLOAD_URL)]
+    pub upload_url: String,
+    /// Where notarization tickets are fetched from, eg to test against a mock server
+    #[arg(long, default_value = ticket::TICKET_URL)]
+    pub ticket_url: String,
+}
+
+/// A finished (and accepted) submission
+#[derive(Debug)]
+pub struct Notarization {
+    /// The submission's ID, which Apple's tools can look it up by
+    pub id: String,
+    /// Where the notary log was saved
+    pub log: PathBuf,
+    /// Warnings from the notary log
+    pub warnings: Vec<String>,
+    /// Where the ticket was stapled, if `--staple` was passed
+    pub ticket: Option<PathBuf>,
+}
+
+pub fn notarize(args: NotarizeArgs) -> Result<(), Error> {
+    notarize_with(&args, &SystemRunner).map(|_| ())
+}
+
+/// Notarizes an app, running zip through `runner`
+pub fn notarize_with(args: &NotarizeArgs, runner: &dyn ToolRunner) -> Result<Notarization, Error> {
+    println!("Notarizing `{}`...", args.path.display());
+    let key = ApiKey::load(
+        args.api_key.clone(),
+        args.api_key_id.clone(),
+        args.api_issuer.clone(),
+    )?;
+    let Some(stem) = args.path.file_stem().map(|stem| stem.to_string_lossy()) else {
+        return Err(Error::Config(format!(
+            "`{}` isn't an app",
+            args.path.display()
+        )));
+    };
+    let is_app = args.path.extension().is_some_and(|ext| ext == "app");
+    if !args.path.exists() {
+        return Err(Error::Config(format!(
+            "`{}` doesn't exist",
+            args.path.display()
+        )));
+    }
+    if args.staple && !is_app {
+        return Err(Error::Config(
+            "`--staple` only works with .app folders; staple .dmg and .pkg files with `xcrun stapler staple`".into(),
+        ));
+    }
+
+    // ========== PACK ==========
+    let upload = if is_app {
+        preflight(&args.path, runner)?;
+        println!("|- Compressing the app...");
+        zip_app(&args.path, runner)?
+    } else {
+        args.path.clone()
+    };
+    let data = match fs::read(&upload) {
+        Ok(data) => data,
+        Err(e) => return Err(Error::io("Failed to read the submission", upload, e)),
+    };
+    let name = upload
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    // The zip's only needed for the upload
+    if is_app {
+        if let Err(e) = fs::remove_file(&upload) {
+            return Err(Error::io("Failed to remove the zipped app", upload, e));
+        }
+    }
+
+    // ========== UPLOAD ==========
+    let mut client = NotaryClient::new(&key);
+    client.notary_url = args.notary_url.trim_end_matches('/').to_owned();
+    client.upload_url = args.upload_url.trim_end_matches('/').to_owned();
+    println!("|- Uploading `{name}` to the notary service...");
+    let (id, submission) = client.submit(&name, &notary::submission_hash(&data))?;
+    client.upload(&submission, &data)?;
+    println!("   |- Submitted as `{id}`");
+
+    // ========== WAIT ==========
+    println!("|- Waiting for the notary service...");
+    let started = Instant::now();
+    let status = loop {
+        let status = client.status(&id)?;
+        if status.status != "In Progress" {
+            break status.status;
+        }
+        if started.elapsed() > Duration::from_secs(args.timeout * 60) {
+            return Err(Error::Service(format!(
+                "The notary service is still checking submission `{id}` after {} minutes. Check on it later with `xcrun notarytool info {id}`.",
+                args.timeout
+            )));
+        }
+        thread::sleep(Duration::from_secs(args.poll_interval));
+    };
+
+    // ========== LOG ==========
+    let (raw_log, log) = client.log(&id)?;
+    let log_path = args
+        .path
+        .with_file_name(format!("{stem}.notarization.json"));
+    let pretty = serde_json::to_string_pretty(&raw_log).unwrap_or_default();
+    if let Err(e) = fs::write(&log_path, pretty) {
+        return Err(Error::io("Failed to save the notary log", log_path, e));
+    }
+    println!("   |- Saved the notary log to `{}`", log_path.display());
+
+    let issues = log.issues.unwrap_or_default();
+    let describe = |severity: &str| -> Vec<String> {
+        issues
+            .iter()
+            .filter(|issue| issue.severity == severity)
+            .map(|issue: &NotaryIssue| issue.describe(&name))
+            .collect()
+    };
+    let warnings = describe("warning");
+    for warning in &warnings {
+        println!("   |- Warning: {warning}");
+    }
+    if status != "Accepted" {
+        let mut message = format!(
+            "The notary service didn't accept the app ({status}): {}",
+            log.status_summary
+                .as_deref()
+                .unwrap_or("no reason was given")
+        );
+        for error in describe("error") {
+            message += &format!("\n  - {error}");
+        }
+        message += &format!("\nThe full log is in `{}`.", log_path.display());
+        return Err(Error::Service(message));
+    }
+
+    println!("|- Notarized!");
+    let ticket = match args.staple {
+        true => Some(staple_app(&args.path, &args.ticket_url)?),
+        false => None,
+    };
+    Ok(Notarization {
+        id,
+        log: log_path,
+        warnings,
+        ticket,
+    })
+}
+
+/// Warns about anything `verify --notarization` would flag, since it's quicker to find out
+/// here than from the notary service. It's only a warning in case the checks are out of date.
+fn preflight(app: &Path, runner: &dyn ToolRunner) -> Result<(), Error> {
+    let args = VerifyArgs {
+        path: app.to_owned(),
+        json: false,
+        notarization: true,
+    };
+    let report = verify_with(&args, runner)?;
+    for problem in report.problems() {
+        println!("|- Warning: {problem}");
+    }
+    Ok(())
+}
+
+/// Zips an app for uploading, keeping symlinks (which frameworks use) as symlinks. Returns the
+/// zip's path, which is next to the app.
+fn zip_app(app: &Path, runner: &dyn ToolRunner) -> Result<PathBuf, Error> {
+    let dir = app
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let app_name = app.file_name().unwrap().to_str().unwrap();
+    let zip_name = format!("{}.zip", app_name.trim_end_matches(".app"));
+    // Otherwise, zip adds to the old one
+    let zip = dir.join(&zip_name);
+    if zip.exists() {
+        if let Err(e) = fs::remove_file(&zip) {
+            return Err(Error::io(
+                "The zipped app already exists, and can't be removed",
+                zip,
+                e,
+            ));
+        }
+    }
+    let zip_call = ToolCall::new("zip", &["-ry", &zip_name, app_name])
+        .current_dir(dir)
+        .echo();
+    run_checked(runner, &zip_call, |output| Error::Packaging {
+        message: "Failed to compress the app for notarization! Aborting.".into(),
+        source: Some(output.stderr.into()),
+    })?;
+    Ok(zip)
+}
@@ -0,0 +1,600 @@
+use clap::Args;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{
+    build::zip_payload,
+    config::Config,
+    error::Error,
+    runner::{run_checked, SystemRunner, ToolCall, ToolRunner},
+    sign::{
+        self, entitlements,
+        identity::Identity,
+        macho,
+        profile::{ProvisioningProfile, EMBEDDED_PROFILE_IOS},
+        resources::{self, NESTED_BUNDLE_EXTENSIONS},
+        SigningOptions,
+    },
+};
+
+#[derive(Args)]
+pub struct ResignArgs {
+    /// The IPA to re-sign
+    pub ipa: PathBuf,
+    /// Where to write the re-signed IPA. Defaults to `<name>.resigned.ipa`, next to the original.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// Change the app's bundle ID. App extensions are moved under the new bundle ID too.
+    #[arg(long)]
+    pub bundle_id: Option<String>,
+    /// Change the app's name, as it appears on the home screen
+    #[arg(long)]
+    pub display_name: Option<String>,
+    /// Change the app's version (both CFBundleShortVersionString and CFBundleVersion)
+    #[arg(long)]
+    pub version: Option<String>,
+    /// The .p12 file to sign with. If blank, the app is ad-hoc signed.
+    #[arg(long)]
+    pub identity: Option<PathBuf>,
+    /// A file containing the .p12 file's password
+    #[arg(long)]
+    pub password_file: Option<PathBuf>,
+    /// A certificate that issued the identity's certificate. Can be used more than once.
+    #[arg(long)]
+    pub certificate_chain: Vec<PathBuf>,
+    /// Replace the app's provisioning profile with this one
+    #[arg(long)]
+    pub profile: Option<PathBuf>,
+    /// Replace the app's entitlements with the ones in this plist
+    #[arg(long)]
+    pub entitlements: Option<PathBuf>,
+}
+
+pub fn resign(args: ResignArgs) -> Result<(), Error> {
+    resign_with(&args, &SystemRunner).map(|_| ())
+}
+
+/// Re-signs an IPA, running unzip & zip through `runner`. Returns where the new IPA was written.
+///
+/// Anything in the original IPA outside the Payload folder (like SwiftSupport or Symbols) is
+/// copied into the new one unchanged.
+pub fn resign_with(args: &ResignArgs, runner: &dyn ToolRunner) -> Result<PathBuf, Error> {
+    println!("Re-signing `{}`...", args.ipa.display());
+    let Some(stem) = args.ipa.file_stem().map(|stem| stem.to_string_lossy()) else {
+        return Err(Error::Config(format!(
+            "`{}` isn't an IPA file",
+            args.ipa.display()
+        )));
+    };
+    let output = match args.output {
+        Some(ref output) => output.clone(),
+        None => args.ipa.with_file_name(format!("{stem}.resigned.ipa")),
+    };
+
+    // Load everything up front, so mistakes show up before any unpacking
+    let identity = sign::load_identity_from(
+        args.identity.clone(),
+        args.password_file.clone(),
+        &args.certificate_chain,
+    )?;
+    match identity.as_ref().and_then(Identity::common_name) {
+        Some(name) => println!("|- Signing as `{name}`"),
+        None if identity.is_some() => println!("|- Signing with the given identity"),
+        None => println!("|- No signing identity was given; the app will be ad-hoc signed"),
+    }
+    let new_profile = match args.profile {
+        Some(_) if identity.is_none() => {
+            return Err(Error::Signing(
+                "A provisioning profile was given, but there's no signing identity to go with it. Pass `--identity` too.".into(),
+            ))
+        }
+        Some(ref path) => Some(ProvisioningProfile::load(path)?),
+        None => None,
+    };
+    let new_entitlements = match args.entitlements {
+        Some(ref path) => match plist::Value::from_file(path) {
+            Ok(plist::Value::Dictionary(entitlements)) => Some(entitlements),
+            Ok(_) => {
+                return Err(Error::Config(format!(
+                    "`{}` should be a plist dictionary of entitlements",
+                    path.display()
+                )))
+            }
+            Err(e) => {
+                return Err(Error::Config(format!(
+                    "Failed to read the entitlements in `{}`: {e}",
+                    path.display()
+                )))
+            }
+        },
+        None => None,
+    };
+
+    // ========== UNPACK ==========
+    println!("|- Unpacking the IPA...");
+    let work_dir = output.with_file_name(format!(".{stem}.resign"));
+    if work_dir.exists() {
+        if let Err(e) = fs::remove_dir_all(&work_dir) {
+            return Err(Error::io(
+                "Build files already exist, and can't be removed",
+                work_dir,
+                e,
+            ));
+        }
+    }
+    if let Err(e) = fs::create_dir_all(&work_dir) {
+        return Err(Error::io("Failed to create build directory", work_dir, e));
+    }
+    let unzip_call = ToolCall::new(
+        "unzip",
+        &[
+            "-q",
+            args.ipa.to_str().unwrap(),
+            "-d",
+            work_dir.to_str().unwrap(),
+        ],
+    );
+    run_checked(runner, &unzip_call, |output| Error::Packaging {
+        message: format!("Failed to unpack `{}`", args.ipa.display()),
+        source: Some(output.stderr.into()),
+    })?;
+    let app = find_app(&work_dir.join("Payload"), &args.ipa)?;
+
+    // ========== INFO.PLIST ==========
+    let info_plist_path = app.join("Info.plist");
+    let mut info_plist = read_info_plist(&app)?;
+    let Some(old_bundle_id) = info_plist
+        .get("CFBundleIdentifier")
+        .and_then(plist::Value::as_string)
+        .map(str::to_string)
+    else {
+        return Err(Error::Packaging {
+            message: "The app's Info.plist doesn't have a CFBundleIdentifier".into(),
+            source: None,
+        });
+    };
+    let bundle_id = args.bundle_id.clone().unwrap_or(old_bundle_id.clone());
+    if args.bundle_id.is_some() || args.display_name.is_some() || args.version.is_some() {
+        println!("|- Updating Info.plist...");
+        info_plist.insert("CFBundleIdentifier".into(), bundle_id.clone().into());
+        if let Some(ref display_name) = args.display_name {
+            info_plist.insert("CFBundleDisplayName".into(), display_name.clone().into());
+        }
+        if let Some(ref version) = args.version {
+            info_plist.insert("CFBundleShortVersionString".into(), version.clone().into());
+            info_plist.insert("CFBundleVersion".into(), version.clone().into());
+        }
+        write_info_plist(&info_plist_path, info_plist.clone())?;
+    }
+
+    // ========== PROVISIONING PROFILE ==========
+    let profile_path = app.join(EMBEDDED_PROFILE_IOS);
+    let profile = match new_profile {
+        Some(profile) => {
+            println!("|- Replacing the provisioning profile...");
+            if let Err(e) = fs::write(&profile_path, &profile.data) {
+                return Err(Error::io(
+                    "Failed to embed the provisioning profile in the app",
+                    profile_path,
+                    e,
+                ));
+            }
+            Some(profile)
+        }
+        // The app's current profile is kept, but it has to work with the new identity
+        None if identity.is_some() && profile_path.exists() => {
+            Some(ProvisioningProfile::load(&profile_path)?)
+        }
+        None => None,
+    };
+    if let (Some(profile), Some(identity)) = (&profile, &identity) {
+        match profile.check(&bundle_id, identity, &[], SystemTime::now()) {
+            Ok(()) => (),
+            Err(Error::Signing(message)) if args.profile.is_none() => {
+                return Err(Error::Signing(
+                    message + " Pass `--profile` to replace the app's provisioning profile.",
+                ))
+            }
+            Err(e) => return Err(e),
+        }
+        println!("|- Using the provisioning profile `{}`", profile.name);
+    }
+
+    // ========== SIGNING ==========
+    let executable = bundle_executable(&app, &info_plist)?;
+    let app_entitlements = match new_entitlements {
+        Some(entitlements) => entitlements,
+        None => read_entitlements(&executable)?.unwrap_or_default(),
+    };
+    let app_entitlements = update_entitlements(
+        app_entitlements,
+        &bundle_id,
+        identity.as_ref(),
+        profile.as_ref(),
+    )?;
+
+    // Nested code has to be signed first, since the app's seal includes its signatures
+    let nested = NestedSigning {
+        identity: identity.as_ref(),
+        // Only a new profile can replace the extensions' own ones
+        profile: profile.as_ref().filter(|_| args.profile.is_some()),
+        rename: (&old_bundle_id, &bundle_id),
+    };
+    sign_nested(&app, &nested)?;
+    println!("|- Signing the app...");
+    sign_bundle(
+        &app,
+        &executable,
+        &bundle_id,
+        app_entitlements.as_deref(),
+        identity.as_ref(),
+    )?;
+
+    // ========== REPACK ==========
+    println!("|- Compressing the app into an IPA...");
+    let ipa_name = "resigned.ipa";
+    // Everything else in the IPA goes back in as it was
+    let mut extra: Vec<String> = match fs::read_dir(&work_dir) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| name != "Payload")
+            .collect(),
+        Err(e) => return Err(Error::io("Failed to read the unpacked IPA", work_dir, e)),
+    };
+    extra.sort();
+    let extra: Vec<&str> = extra.iter().map(String::as_str).collect();
+    zip_payload(runner, &work_dir, ipa_name, &extra)?;
+    if let Err(e) = fs::rename(work_dir.join(ipa_name), &output) {
+        return Err(Error::io("Failed to move the re-signed IPA", output, e));
+    }
+    if let Err(e) = fs::remove_dir_all(&work_dir) {
+        return Err(Error::io("Failed to clean up build files", work_dir, e));
+    }
+
+    println!("Done! The re-signed IPA is at `{}`", output.display());
+    Ok(output)
+}
+
+/// Finds the .app folder in an IPA's Payload folder
+pub(crate) fn find_app(payload: &Path, ipa: &Path) -> Result<PathBuf, Error> {
+    let not_an_ipa = || Error::Packaging {
+        message: format!(
+            "`{}` isn't an IPA: there's no app in its Payload folder",
+            ipa.display()
+        ),
+        source: None,
+    };
+    let Ok(entries) = fs::read_dir(payload) else {
+        return Err(not_an_ipa());
+    };
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .find(|path| path.extension().is_some_and(|ext| ext == "app") && path.is_dir())
+        .ok_or_else(not_an_ipa)
+}
+
+/// How the code nested in an app gets signed
+struct NestedSigning<'a> {
+    identity: Option<&'a Identity>,
+    /// The provisioning profile passed with `--profile`, which is embedded in any extension
+    /// its app ID covers
+    profile: Option<&'a ProvisioningProfile>,
+    /// The app's old & new bundle IDs. Bundle IDs under the old one are moved under the new one.
+    rename: (&'a str, &'a str),
+}
+
+/// Signs every framework, extension, and dylib inside `dir`, innermost first
+fn sign_nested(dir: &Path, nested: &NestedSigning) -> Result<(), Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => return Err(Error::io("Failed to read a folder in the app", dir, e)),
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        if path.is_symlink() {
+            continue;
+        }
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if path.is_dir() && NESTED_BUNDLE_EXTENSIONS.contains(&extension.as_str()) {
+            sign_nested(&path, nested)?;
+
+            let mut info_plist = read_info_plist(&path)?;
+            let executable = bundle_executable(&path, &info_plist)?;
+            let (old, new) = nested.rename;
+            let mut bundle_id = info_plist
+                .get("CFBundleIdentifier")
+                .and_then(plist::Value::as_string)
+                .unwrap_or_default()
+                .to_string();
+            if old != new {
+                if let Some(suffix) = bundle_id.strip_prefix(&format!("{old}.")) {
+                    bundle_id = format!("{new}.{suffix}");
+                    info_plist.insert("CFBundleIdentifier".into(), bundle_id.clone().into());
+                    write_info_plist(&path.join("Info.plist"), info_plist)?;
+                }
+            }
+
+            let name = path.file_name().unwrap().to_string_lossy();
+            println!("   |- Signing `{name}`...");
+            let entitlements = read_entitlements(&executable)?;
+            // Extensions (and watch apps) have their own app ID, so their entitlements and
+            // profile have to be redone for the new identity & bundle ID, like the app's
+            let entitlements = if extension == "appex" || extension == "app" {
+                let profile = nested_profile(&path, &bundle_id, nested)?;
+                let entitlements = update_entitlements(
+                    entitlements.unwrap_or_default(),
+                    &bundle_id,
+                    nested.identity,
+                    profile.as_ref(),
+                );
+                match entitlements {
+                    Ok(entitlements) => entitlements,
+                    Err(Error::Signing(message)) => {
+                        return Err(Error::Signing(format!("`{name}`: {message}")))
+                    }
+                    Err(e) => return Err(e),
+                }
+            } else {
+                match entitlements {
+                    Some(entitlements) => Some(entitlements::to_xml(&entitlements)?),
+                    None => None,
+                }
+            };
+            sign_bundle(
+                &path,
+                &executable,
+                &bundle_id,
+                entitlements.as_deref(),
+                nested.identity,
+            )?;
+        } else if path.is_dir() {
+            sign_nested(&path, nested)?;
+        } else if extension == "dylib" {
+            println!(
+                "   |- Signing `{}`...",
+                path.file_name().unwrap().to_string_lossy()
+            );
+            let identifier = path.file_stem().unwrap().to_string_lossy();
+            let options = SigningOptions {
+                identifier: &identifier,
+                identity: nested.identity,
+                ..Default::default()
+            };
+            sign::sign_file(&path, &options)?;
+        }
+    }
+    Ok(())
+}
+
+/// Finds the provisioning profile for an extension: the new one, if its app ID covers the
+/// extension, or else the extension's own, as long as it works with the new identity & bundle ID
+fn nested_profile(
+    bundle: &Path,
+    bundle_id: &str,
+    nested: &NestedSigning,
+) -> Result<Option<ProvisioningProfile>, Error> {
+    // Without an identity, the extension is ad-hoc signed, and its profile doesn't matter
+    let Some(identity) = nested.identity else {
+        return Ok(None);
+    };
+    let profile_path = bundle.join(EMBEDDED_PROFILE_IOS);
+    if let Some(profile) = nested
+        .profile
+        .filter(|profile| profile.matches_bundle_id(bundle_id))
+    {
+        if let Err(e) = fs::write(&profile_path, &profile.data) {
+            return Err(Error::io(
+                "Failed to embed the provisioning profile in an extension",
+                profile_path,
+                e,
+            ));
+        }
+        return Ok(Some(profile.clone()));
+    }
+    if !profile_path.exists() {
+        return Ok(None);
+    }
+    let profile = ProvisioningProfile::load(&profile_path)?;
+    match profile.check(bundle_id, identity, &[], SystemTime::now()) {
+        Ok(()) => Ok(Some(profile)),
+        Err(Error::Signing(message)) => Err(Error::Signing(format!(
+            "`{}` has its own provisioning profile, which doesn't work with the new identity or bundle ID: {message} Pass a `--profile` whose app ID covers the extension too, like `TEAMID.{}.*`.",
+            bundle.file_name().unwrap().to_string_lossy(),
+            nested.rename.1
+        ))),
+        Err(e) => Err(e),
+    }
+}
+
+/// Entitlements whose values start with the team ID, like `TEAMID.com.example.shared`
+const TEAM_PREFIXED_ENTITLEMENTS: &[&str] = &[
+    "keychain-access-groups",
+    "com.apple.security.application-groups",
+    "com.apple.developer.ubiquity-kvstore-identifier",
+];
+
+/// Redoes the entitlements the identity, bundle ID, and profile decide (the app & team IDs,
+/// get-task-allow, the push environment, and team-prefixed groups), and makes sure the profile
+/// allows the result. Returns the entitlements to sign with, if there are any.
+fn update_entitlements(
+    mut entitlements: plist::Dictionary,
+    bundle_id: &str,
+    identity: Option<&Identity>,
+    profile: Option<&ProvisioningProfile>,
+) -> Result<Option<Vec<u8>>, Error> {
+    // The old ones are for the old team & bundle ID. Ad-hoc signed apps don't get new ones.
+    let old_team_id = entitlements
+        .remove("com.apple.developer.team-identifier")
+        .and_then(plist::Value::into_string)
+        .or_else(|| {
+            let app_id = entitlements.get("application-identifier")?.as_string()?;
+            Some(app_id.split_once('.')?.0.to_string())
+        });
+    entitlements.remove("application-identifier");
+    if let Some(profile) = profile {
+        // The new profile decides whether debuggers can attach...
+        entitlements.remove("get-task-allow");
+        // ...and which push environment the app uses
+        if let Some(environment) = profile.entitlements.get("aps-environment") {
+            if entitlements.contains_key("aps-environment") {
+                entitlements.insert("aps-environment".into(), environment.clone());
+            }
+        }
+    }
+    let generated =
+        entitlements::generate(&Config::default(), bundle_id, identity, profile, false)?;
+    for (key, value) in generated {
+        entitlements.insert(key, value);
+    }
+    // Keychain & app groups shared by a team start with its ID
+    let new_team_id = identity.and_then(Identity::team_id);
+    if let (Some(old), Some(new)) = (old_team_id, new_team_id) {
+        for key in TEAM_PREFIXED_ENTITLEMENTS {
+            if let Some(value) = entitlements.get_mut(key) {
+                change_team_prefix(value, &old, &new);
+            }
+        }
+    }
+    if let Some(profile) = profile {
+        entitlements::check(&entitlements, profile)?;
+    }
+    if entitlements.is_empty() {
+        return Ok(None);
+    }
+    entitlements::to_xml(&entitlements).map(Some)
+}
+
+/// Swaps the `old` team ID prefix for the `new` one in a string or array entitlement
+fn change_team_prefix(value: &mut plist::Value, old: &str, new: &str) {
+    match value {
+        plist::Value::String(string) => {
+            if let Some(rest) = string.strip_prefix(&format!("{old}.")) {
+                *string = format!("{new}.{rest}");
+            }
+        }
+        plist::Value::Array(values) => {
+            for value in values {
+                change_team_prefix(value, old, new);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Seals a bundle's resources, then signs its executable
+fn sign_bundle(
+    bundle: &Path,
+    executable: &Path,
+    bundle_id: &str,
+    entitlements: Option<&[u8]>,
+    identity: Option<&Identity>,
+) -> Result<(), Error> {
+    let info_plist_path = bundle.join("Info.plist");
+    let info_plist = match fs::read(&info_plist_path) {
+        Ok(info_plist) => info_plist,
+        Err(e) => {
+            return Err(Error::io(
+                "Failed to read the app's Info.plist",
+                info_plist_path,
+                e,
+            ))
+        }
+    };
+    let code_resources = resources::seal(bundle, executable.strip_prefix(bundle).unwrap(), false)?;
+    let options = SigningOptions {
+        identifier: bundle_id,
+        info_plist: Some(&info_plist),
+        code_resources: Some(&code_resources),
+        entitlements,
+        identity,
+        ..Default::default()
+    };
+    sign::sign_file(executable, &options)
+}
+
+/// Reads the entitlements a binary is currently signed with
+fn read_entitlements(binary: &Path) -> Result<Option<plist::Dictionary>, Error> {
+    let data = match fs::read(binary) {
+        Ok(data) => data,
+        Err(e) => return Err(Error::io("Failed to read a binary in the app", binary, e)),
+    };
+    let Some(xml) = sign::embedded_entitlements(&data)? else {
+        return Ok(None);
+    };
+    match plist::Value::from_reader_xml(&*xml) {
+        Ok(plist::Value::Dictionary(entitlements)) => Ok(Some(entitlements)),
+        _ => Err(Error::Signing(format!(
+            "`{}` is signed with entitlements that aren't a valid plist",
+            binary.display()
+        ))),
+    }
+}
+
+pub(crate) fn read_info_plist(bundle: &Path) -> Result<plist::Dictionary, Error> {
+    let path = bundle.join("Info.plist");
+    match plist::Value::from_file(&path) {
+        Ok(plist::Value::Dictionary(info_plist)) => Ok(info_plist),
+        Ok(_) => Err(Error::Packaging {
+            message: format!("`{}` isn't a dictionary", path.display()),
+            source: None,
+        }),
+        Err(e) => Err(Error::Packaging {
+            message: format!("Failed to read `{}`", path.display()),
+            source: Some(Box::new(e)),
+        }),
+    }
+}
+
+fn write_info_plist(path: &Path, info_plist: plist::Dictionary) -> Result<(), Error> {
+    if let Err(e) = plist::Value::from(info_plist).to_file_xml(path) {
+        return Err(Error::Packaging {
+            message: format!("Failed to write `{}`", path.display()),
+            source: Some(Box::new(e)),
+        });
+    }
+    Ok(())
+}
+
+/// Finds a bundle's executable from its Info.plist, and makes sure it's a Mach-O
+pub(crate) fn bundle_executable(
+    bundle: &Path,
+    info_plist: &plist::Dictionary,
+) -> Result<PathBuf, Error> {
+    let Some(name) = info_plist
+        .get("CFBundleExecutable")
+        .and_then(plist::Value::as_string)
+    else {
+        return Err(Error::Packaging {
+            message: format!(
+                "`{}` doesn't have a CFBundleExecutable in its Info.plist",
+                bundle.display()
+            ),
+            source: None,
+        });
+    };
+    let executable = bundle.join(name);
+    let magic = fs::read(&executable)
+        .ok()
+        .filter(|data| data.len() >= 4)
+        .map(|data| (macho::read_u32_le(&data, 0), macho::read_u32_be(&data, 0)));
+    match magic {
+        Some((macho::MH_MAGIC_64, _)) | Some((_, macho::FAT_MAGIC)) => Ok(executable),
+        _ => Err(Error::Packaging {
+            message: format!("`{}` isn't a Mach-O binary", executable.display()),
+            source: None,
+        }),
+    }
+}
@@ -0,0 +1,133 @@
+//! Running macOS apps. The binary inside the .app is run directly, rather than with `open`, so
+//! its output can be shown and its exit code passed on.
+
+use {
+    super::{stream_logs, RunArgs},
+    crate::{
+        error::Error,
+        resign::read_info_plist,
+        runner::{ToolCall, ToolRunner},
+    },
+    std::path::{Path, PathBuf},
+};
+
+/// Stops the app before it runs anything, then runs it once a debugger continues it. `$$` is
+/// the app's PID too, since `exec` keeps it.
+const WAIT_FOR_DEBUGGER_SCRIPT: &str = r#"echo "   |- Waiting for a debugger. Attach with \`lldb -p $$\`, then \`continue\`."
+kill -STOP $$
+exec "$0" "$@""#;
+
+/// Runs a macOS app until it exits, or opens it in lldb. Returns its (or lldb's) exit code.
+pub fn run(app: &Path, args: &RunArgs, runner: &dyn ToolRunner) -> Result<i32, Error> {
+    // ========== SETUP ==========
+    let contents = app.join("Contents");
+    let info_plist = read_info_plist(&contents)?;
+    let get = |key: &str| match info_plist.get(key).and_then(plist::Value::as_string) {
+        Some(value) => Ok(value.to_owned()),
+        None => Err(Error::packaging(format!(
+            "`{}` can't be run: its Info.plist doesn't have `{key}`",
+            app.display()
+        ))),
+    };
+    let bundle_id = get("CFBundleIdentifier")?;
+    let binary = contents.join("MacOS").join(get("CFBundleExecutable")?);
+    if !binary.is_file() {
+        return Err(Error::packaging(format!(
+            "`{}` can't be run: its binary is missing",
+            app.display()
+        )));
+    }
+    let binary = binary.to_str().unwrap();
+
+    // Stopped when it's dropped, once the app's exited
+    let _log = match args.log {
+        true => {
+            let subsystem = args.subsystem.as_deref().unwrap_or(&bundle_id);
+            stream_logs(runner, "log", &[], subsystem)
+        }
+        false => None,
+    };
+
+    // ========== RUN ==========
+    let call = if args.lldb {
+        println!("|- Opening the app in lldb. Type `run` to start it.");
+        lldb_call(binary, args, runner)
+    } else {
+        println!("|- Launching `{bundle_id}`...");
+        let app_args = args.app_args.iter().map(String::as_str);
+        let mut call = if args.wait_for_debugger {
+            let mut sh_args = vec!["-c", WAIT_FOR_DEBUGGER_SCRIPT, binary];
+            sh_args.extend(app_args);
+            ToolCall::new("sh", &sh_args)
+        } else {
+            ToolCall::new(binary, &app_args.collect::<Vec<_>>())
+        };
+        for (key, value) in &args.envs {
+            call = call.env(key, value);
+        }
+        call.echo()
+    };
+    let output = match runner.execute(&call) {
+        Ok(output) => output,
+        Err(e) => return Err(Error::tool_missing(&call.program, e)),
+    };
+    Ok(output.code.unwrap_or(1))
+}
+
+/// lldb, with the app as its target, and Rust's source maps and formatters set up like
+/// `rust-lldb` does
+fn lldb_call(binary: &str, args: &RunArgs, runner: &dyn ToolRunner) -> ToolCall {
+    // Commands to run before the target's made, so they apply to it
+    let mut commands = Vec::new();
+    match rust_sources(runner) {
+        Some((commit, sysroot)) => {
+            let etc = sysroot.join("lib/rustlib/etc");
+            commands.push(format!(
+                "settings set target.source-map /rustc/{commit} \"{}\"",
+                sysroot.join("lib/rustlib/src/rust").display()
+            ));
+            commands.push(format!(
+                "command script import \"{}\"",
+                etc.join("lldb_lookup.py").display()
+            ));
+            commands.push(format!(
+                "command source -s 0 \"{}\"",
+                etc.join("lldb_commands").display()
+            ));
+        }
+        None => println!(
+            "   |- Warning: couldn't find Rust's sysroot, so the standard library's sources won't be shown"
+        ),
+    }
+    for (key, value) in &args.envs {
+        commands.push(format!(
+            "settings append target.env-vars \"{key}={}\"",
+            value.replace('"', "\\\"")
+        ));
+    }
+
+    let mut lldb_args = Vec::new();
+    for command in &commands {
+        lldb_args.extend(["-O", command.as_str()]);
+    }
+    lldb_args.extend(["--", binary]);
+    lldb_args.extend(args.app_args.iter().map(String::as_str));
+    ToolCall::new("lldb", &lldb_args).interactive()
+}
+
+/// The commit rustc was built from, which the standard library's debug info refers to its
+/// sources by, and rustc's sysroot, where they are
+fn rust_sources(runner: &dyn ToolRunner) -> Option<(String, PathBuf)> {
+    let version = runner.run("rustc", &["--version", "--verbose"]).ok()?;
+    let commit = version
+        .stdout
+        .lines()
+        .find_map(|line| line.strip_prefix("commit-hash: "))?
+        .trim()
+        .to_string();
+    let sysroot = runner.run("rustc", &["--print", "sysroot"]).ok()?;
+    match sysroot.success {
+        true => Some((commit, PathBuf::from(sysroot.stdout.trim()))),
+        false => None,
+    }
+}
@@ -0,0 +1,156 @@
+//! Running apps straight from `cargo ipa build`'s output. iOS apps run on the Simulator, with
+//! `xcrun simctl`, and macOS apps run from inside their .app, so their resources and Info.plist
+//! are found like they would be if they were opened from Finder.
+
+pub mod macos;
+pub mod simulator;
+
+use {
+    crate::{
+        context::{find_cargo_toml, Ctx, Platform},
+        error::Error,
+        runner::{BackgroundTool, SystemRunner, ToolCall, ToolRunner},
+    },
+    clap::Args,
+    std::{env, path::PathBuf},
+};
+
+#[derive(Args)]
+pub struct RunArgs {
+    /// The app or IPA to run. Defaults to the Simulator IPA for this Mac's architecture that
+    /// `cargo ipa build` made (`aarch64-apple-ios-sim` on Apple silicon, which needs
+    /// `--simulator`), or this Mac's macOS app with `--platform macos`.
+    pub app: Option<PathBuf>,
+    /// The app's name, for finding the IPA `cargo ipa build` made. If left unprovided,
+    /// cargo-ipa will search for it in Cargo.toml.
+    #[arg(short, long)]
+    pub name: Option<String>,
+    /// The platform to run the app on. Defaults to the app's platform, or iOS.
+    #[arg(short, long, value_enum)]
+    pub platform: Option<Platform>,
+    /// The simulator to run the app on, by name (eg "iPhone 15") or UDID. Defaults to the one
+    /// that's already booted, or the newest iPhone.
+    #[arg(short, long)]
+    pub device: Option<String>,
+    /// An environment variable to set for the app. Can be passed more than once.
+    #[arg(short, long = "env", value_name = "KEY=VALUE", value_parser = parse_env)]
+    pub envs: Vec<(String, String)>,
+    /// Show the app's messages from the unified log (eg from `os_log`) while it runs
+    #[arg(long)]
+    pub log: bool,
+    /// The unified log subsystem to show messages from. Defaults to the app's bundle ID.
+    #[arg(long, requires = "log")]
+    pub subsystem: Option<String>,
+    /// Start the app suspended, and wait for a debugger to attach before it runs
+    #[arg(long)]
+    pub wait_for_debugger: bool,
+    /// Open the app in lldb, set up to show Rust's standard library sources and values.
+    /// macOS only.
+    #[arg(long, conflicts_with = "wait_for_debugger")]
+    pub lldb: bool,
+    /// Arguments to pass to the app, after `--`
+    #[arg(last = true)]
+    pub app_args: Vec<String>,
+}
+
+/// Runs the app, and returns its exit code
+pub fn run(args: RunArgs) -> Result<i32, Error> {
+    // Cargo.toml's only needed to find the app
+    let ctx = match args.app {
+        Some(_) => None,
+        None => Some(Ctx::from_cargo_toml(find_cargo_toml()?, &args.name)?),
+    };
+    run_with(&args, ctx.as_ref(), &SystemRunner)
+}
+
+/// Runs the app, running every external tool through `runner`, and returns its exit code
+pub fn run_with(args: &RunArgs, ctx: Option<&Ctx>, runner: &dyn ToolRunner) -> Result<i32, Error> {
+    // macOS apps have a Contents folder; iOS apps & IPAs don't
+    let platform = match (args.platform, &args.app) {
+        (Some(platform), _) => platform,
+        (None, Some(app)) if app.join("Contents/Info.plist").is_file() => Platform::macOS,
+        (None, _) => Platform::iOS,
+    };
+    let app = match (&args.app, ctx) {
+        (Some(app), _) => app.clone(),
+        (None, Some(ctx)) => ctx.cargo_ipa_dir.join(match platform {
+            Platform::iOS => format!("{}.{}.ipa", ctx.project_name, simulator_target()),
+            Platform::macOS => format!(
+                "{}.{}-apple-darwin.app",
+                ctx.project_name,
+                env::consts::ARCH
+            ),
+        }),
+        (None, None) => {
+            return Err(Error::Config(
+                "No app was given, and there's no project to find one in".into(),
+            ))
+        }
+    };
+    if !app.exists() {
+        let build = match (platform, &args.app) {
+            (Platform::iOS, None) if simulator_target().ends_with("-sim") => {
+                "cargo ipa build --platform ios --simulator"
+            }
+            _ => "cargo ipa build",
+        };
+        return Err(Error::Config(format!(
+            "`{}` doesn't exist. Has `{build}` been run?",
+            app.display()
+        )));
+    }
+    println!("Running `{}`...", app.display());
+    match platform {
+        Platform::iOS => simulator::run(&app, args, runner),
+        Platform::macOS => macos::run(&app, args, runner),
+    }
+}
+
+/// The target of the iOS apps this Mac's Simulator runs natively
+fn simulator_target() -> &'static str {
+    match env::consts::ARCH {
+        "aarch64" => "aarch64-apple-ios-sim",
+        _ => "x86_64-apple-ios",
+    }
+}
+
+/// Starts `log stream` (run with `program` & `args`, like `log` or `xcrun simctl spawn <udid> log`),
+/// showing messages from `subsystem`. If it can't be started, the app's run without it.
+fn stream_logs(
+    runner: &dyn ToolRunner,
+    program: &str,
+    args: &[&str],
+    subsystem: &str,
+) -> Option<BackgroundTool> {
+    let predicate = format!("subsystem == \"{subsystem}\"");
+    let mut log_args = args.to_vec();
+    log_args.extend([
+        "stream",
+        "--style",
+        "compact",
+        "--level",
+        "debug",
+        "--predicate",
+        &predicate,
+    ]);
+    match runner.spawn(&ToolCall::new(program, &log_args)) {
+        Ok(log) => {
+            println!("|- Showing log messages from `{subsystem}`");
+            Some(log)
+        }
+        Err(e) => {
+            println!(
+                "|- Warning: couldn't start `log stream` ({e}), so log messages won't be shown"
+            );
+            None
+        }
+    }
+}
+
+/// Parses a `KEY=VALUE` environment variable
+fn parse_env(env: &str) -> Result<(String, String), String> {
+    match env.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("`{env}` isn't in the form `KEY=VALUE`")),
+    }
+}
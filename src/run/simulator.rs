@@ -0,0 +1,305 @@
+//! The iOS Simulator, driven with `xcrun simctl`. Apps are installed from an `.app` folder, then
+//! launched by their bundle ID with `--console-pty`, which connects the app's stdout & stderr to
+//! simctl's until it exits.
+
+use {
+    super::{stream_logs, RunArgs},
+    crate::{
+        error::Error,
+        resign::{find_app, read_info_plist},
+        runner::{run_checked, ToolCall, ToolOutput, ToolRunner},
+    },
+    serde::Deserialize,
+    std::{
+        collections::BTreeMap,
+        fs,
+        path::{Path, PathBuf},
+    },
+};
+
+/// simctl passes its environment variables with this prefix on to the apps it launches
+const CHILD_ENV_PREFIX: &str = "SIMCTL_CHILD_";
+/// The prefix of iOS runtimes' identifiers, like `com.apple.CoreSimulator.SimRuntime.iOS-17-2`
+const IOS_RUNTIME: &str = "com.apple.CoreSimulator.SimRuntime.iOS-";
+
+/// `simctl list devices --json`'s output
+#[derive(Deserialize)]
+struct DeviceList {
+    /// Every runtime's simulators
+    devices: BTreeMap<String, Vec<Simulator>>,
+}
+
+/// A simulated device
+#[derive(Clone, Debug, Deserialize)]
+pub struct Simulator {
+    pub udid: String,
+    /// The simulator's name, like "iPhone 15"
+    pub name: String,
+    /// Eg "Booted" or "Shutdown"
+    pub state: String,
+    /// The iOS version it runs, like `[17, 2]`
+    #[serde(skip)]
+    pub ios_version: Vec<u32>,
+}
+impl Simulator {
+    pub fn is_booted(&self) -> bool {
+        self.state == "Booted"
+    }
+
+    /// The simulator's name and iOS version, like "iPhone 15 (iOS 17.2)"
+    pub fn describe(&self) -> String {
+        let version: Vec<_> = self.ios_version.iter().map(u32::to_string).collect();
+        format!("{} (iOS {})", self.name, version.join("."))
+    }
+}
+
+/// Installs the app (or the app in an IPA) on a simulator, and runs it until it exits.
+/// Returns the app's exit code.
+pub fn run(app: &Path, args: &RunArgs, runner: &dyn ToolRunner) -> Result<i32, Error> {
+    // ========== SETUP ==========
+    if args.lldb {
+        return Err(Error::Config(
+            "`--lldb` only works for macOS apps. To debug on the Simulator, pass `--wait-for-debugger`, then attach to the app with Xcode's Debug > Attach to Process.".into(),
+        ));
+    }
+    let simulator = pick(list(runner)?, args.device.as_deref())?;
+    if simulator.is_booted() {
+        println!("|- Using {}", simulator.describe());
+    } else {
+        println!("|- Booting {}...", simulator.describe());
+        boot(runner, &simulator)?;
+    }
+
+    // ========== INSTALL ==========
+    println!("|- Installing the app...");
+    let is_ipa = app.extension().is_some_and(|ext| ext == "ipa");
+    let unpacked = match is_ipa {
+        true => Some(unpack_ipa(app, runner)?),
+        false => None,
+    };
+    let installed = match unpacked {
+        Some((_, ref bundle)) => install(runner, &simulator, bundle),
+        None => install(runner, &simulator, app),
+    };
+    if let Some((dir, _)) = unpacked {
+        if let Err(e) = fs::remove_dir_all(&dir) {
+            return Err(Error::io("Failed to clean up the unpacked IPA", dir, e));
+        }
+    }
+    let bundle_id = installed?;
+
+    // ========== LAUNCH ==========
+    // Stopped when it's dropped, once the app's exited
+    let _log = match args.log {
+        true => {
+            let subsystem = args.subsystem.as_deref().unwrap_or(&bundle_id);
+            let log_args = ["simctl", "spawn", &simulator.udid, "log"];
+            stream_logs(runner, "xcrun", &log_args, subsystem)
+        }
+        false => None,
+    };
+    println!("|- Launching `{bundle_id}`...");
+    launch(runner, &simulator, &bundle_id, args)
+}
+
+/// Lists the available iOS simulators, newest iOS version first
+pub fn list(runner: &dyn ToolRunner) -> Result<Vec<Simulator>, Error> {
+    let list_call = ToolCall::new(
+        "xcrun",
+        &["simctl", "list", "devices", "available", "--json"],
+    );
+    let output = run_checked(runner, &list_call, |output| Error::ToolchainMissing {
+        message: format!(
+            "`simctl` couldn't list the simulators. Is Xcode installed?\n{}",
+            output.stderr.trim()
+        ),
+        source: None,
+    })?;
+    let list: DeviceList = match serde_json::from_str(&output.stdout) {
+        Ok(list) => list,
+        Err(e) => {
+            return Err(Error::Device(format!(
+                "Couldn't read `simctl`'s list of simulators ({e})"
+            )))
+        }
+    };
+
+    let mut runtimes: Vec<(Vec<u32>, Vec<Simulator>)> = list
+        .devices
+        .into_iter()
+        .filter_map(|(runtime, simulators)| {
+            let version = runtime.strip_prefix(IOS_RUNTIME)?;
+            let version: Vec<u32> = version.split('-').filter_map(|n| n.parse().ok()).collect();
+            Some((version, simulators))
+        })
+        .collect();
+    runtimes.sort_by(|(a, _), (b, _)| b.cmp(a));
+    Ok(runtimes
+        .into_iter()
+        .flat_map(|(version, simulators)| {
+            simulators.into_iter().map(move |simulator| Simulator {
+                ios_version: version.clone(),
+                ..simulator
+            })
+        })
+        .collect())
+}
+
+/// Picks the simulator to run on: the one with the given name or UDID, or else the one that's
+/// already booted, or else the newest iPhone
+pub fn pick(simulators: Vec<Simulator>, wanted: Option<&str>) -> Result<Simulator, Error> {
+    if simulators.is_empty() {
+        return Err(Error::ToolchainMissing {
+            message: "No iOS simulators are installed. Add one in Xcode's Platforms settings."
+                .into(),
+            source: None,
+        });
+    }
+    let candidates: Vec<_> = match wanted {
+        Some(wanted) => simulators
+            .into_iter()
+            .filter(|simulator| simulator.udid == wanted || simulator.name == wanted)
+            .collect(),
+        None => simulators,
+    };
+    // Simulators are newest first, so this prefers newer iOS versions when names are shared
+    let picked = candidates
+        .iter()
+        .find(|simulator| simulator.is_booted())
+        .or_else(|| match wanted {
+            Some(_) => candidates.first(),
+            None => candidates
+                .iter()
+                .find(|simulator| simulator.name.starts_with("iPhone"))
+                .or(candidates.first()),
+        });
+    match picked {
+        Some(simulator) => Ok(simulator.clone()),
+        None => Err(Error::Device(format!(
+            "There's no simulator called `{}`. `xcrun simctl list devices available` lists them.",
+            wanted.unwrap_or_default()
+        ))),
+    }
+}
+
+/// Boots a simulator, and opens the Simulator app so it can be seen
+pub fn boot(runner: &dyn ToolRunner, simulator: &Simulator) -> Result<(), Error> {
+    let boot_call = ToolCall::new("xcrun", &["simctl", "boot", &simulator.udid]);
+    run_checked(runner, &boot_call, |output| {
+        simctl_failed(&format!("boot {}", simulator.describe()), output)
+    })?;
+    // The simulator runs without it; it just can't be seen
+    if runner.run("open", &["-a", "Simulator"]).is_err() {
+        println!("   |- Couldn't open the Simulator app, so the simulator's running hidden");
+    }
+    Ok(())
+}
+
+/// Installs an app on a simulator, and returns its bundle ID
+pub fn install(
+    runner: &dyn ToolRunner,
+    simulator: &Simulator,
+    app: &Path,
+) -> Result<String, Error> {
+    let info_plist = read_info_plist(app)?;
+    let Some(bundle_id) = info_plist
+        .get("CFBundleIdentifier")
+        .and_then(plist::Value::as_string)
+    else {
+        return Err(Error::packaging(format!(
+            "`{}` can't be run: its Info.plist doesn't have `CFBundleIdentifier`",
+            app.display()
+        )));
+    };
+    let install_call = ToolCall::new(
+        "xcrun",
+        &["simctl", "install", &simulator.udid, app.to_str().unwrap()],
+    );
+    run_checked(runner, &install_call, |output| {
+        simctl_failed("install the app", output)
+    })?;
+    Ok(bundle_id.to_owned())
+}
+
+/// Launches an installed app, streaming its output until it exits. Returns its exit code.
+pub fn launch(
+    runner: &dyn ToolRunner,
+    simulator: &Simulator,
+    bundle_id: &str,
+    args: &RunArgs,
+) -> Result<i32, Error> {
+    let mut launch_args = vec![
+        "simctl",
+        "launch",
+        "--console-pty",
+        // Restart the app if an old build's still running
+        "--terminate-running-process",
+    ];
+    if args.wait_for_debugger {
+        println!("   |- Waiting for a debugger; attach with Xcode's Debug > Attach to Process");
+        launch_args.push("--wait-for-debugger");
+    }
+    launch_args.extend([simulator.udid.as_str(), bundle_id]);
+    launch_args.extend(args.app_args.iter().map(String::as_str));
+    let mut launch_call = ToolCall::new("xcrun", &launch_args).echo();
+    for (key, value) in &args.envs {
+        launch_call = launch_call.env(&format!("{CHILD_ENV_PREFIX}{key}"), value);
+    }
+
+    let output = match runner.execute(&launch_call) {
+        Ok(output) => output,
+        Err(e) => return Err(Error::tool_missing("xcrun", e)),
+    };
+    // simctl exits with the app's exit code, and only prints its PID once it's launched
+    if launched_pid(&output.stdout, bundle_id).is_none() {
+        return Err(simctl_failed("launch the app", output));
+    }
+    Ok(output.code.unwrap_or(1))
+}
+
+/// Finds the PID in the `<bundle ID>: <PID>` line simctl prints when it launches an app
+pub fn launched_pid(stdout: &str, bundle_id: &str) -> Option<u32> {
+    stdout.lines().find_map(|line| {
+        let pid = line.trim().strip_prefix(bundle_id)?.strip_prefix(':')?;
+        pid.trim().parse().ok()
+    })
+}
+
+/// Unzips an IPA next to itself, and returns the folder it was unzipped to and the app in it
+fn unpack_ipa(ipa: &Path, runner: &dyn ToolRunner) -> Result<(PathBuf, PathBuf), Error> {
+    let stem = ipa.file_stem().unwrap().to_string_lossy();
+    let dir = ipa.with_file_name(format!(".{stem}.run"));
+    if dir.exists() {
+        if let Err(e) = fs::remove_dir_all(&dir) {
+            return Err(Error::io(
+                "Old unpacked files exist, and can't be removed",
+                dir,
+                e,
+            ));
+        }
+    }
+    if let Err(e) = fs::create_dir_all(&dir) {
+        return Err(Error::io(
+            "Failed to create a folder to unpack into",
+            dir,
+            e,
+        ));
+    }
+    let unzip_call = ToolCall::new(
+        "unzip",
+        &["-q", ipa.to_str().unwrap(), "-d", dir.to_str().unwrap()],
+    );
+    run_checked(runner, &unzip_call, |output| Error::Packaging {
+        message: format!("Failed to unpack `{}`", ipa.display()),
+        source: Some(output.stderr.into()),
+    })?;
+    let app = find_app(&dir.join("Payload"), ipa)?;
+    Ok((dir, app))
+}
+
+fn simctl_failed(action: &str, output: ToolOutput) -> Error {
+    Error::Device(format!(
+        "The simulator couldn't {action}:\n{}",
+        output.stderr.trim()
+    ))
+}
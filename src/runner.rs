@@ -0,0 +1,283 @@
+use crate::error::Error;
+#[cfg(any(test, feature = "test-support"))]
+use std::{cell::RefCell, collections::HashMap};
+use std::{
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    thread,
+};
+
+/// An external tool to run, and how to run it
+#[derive(Clone, Debug)]
+pub struct ToolCall {
+    /// The tool's name (or path)
+    pub program: String,
+    /// The arguments to pass to the tool
+    pub args: Vec<String>,
+    /// The directory to run the tool in. If blank, the tool runs in the current directory.
+    pub current_dir: Option<PathBuf>,
+    /// Environment variables to set for the tool, on top of cargo-ipa's own
+    pub envs: Vec<(String, String)>,
+    /// If the tool's output should be shown to the user while it runs, as well as captured
+    pub echo: bool,
+    /// If the tool should be connected straight to the terminal, like a debugger the user types
+    /// into. Its output isn't captured.
+    pub interactive: bool,
+}
+impl ToolCall {
+    pub fn new(program: &str, args: &[&str]) -> Self {
+        Self {
+            program: program.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            current_dir: None,
+            envs: Vec::new(),
+            echo: false,
+            interactive: false,
+        }
+    }
+
+    /// Run the tool in `dir` instead of the current directory
+    pub fn current_dir(mut self, dir: &Path) -> Self {
+        self.current_dir = Some(dir.to_owned());
+        self
+    }
+
+    /// Set an environment variable for the tool
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.envs.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Show the tool's output to the user while it runs
+    pub fn echo(mut self) -> Self {
+        self.echo = true;
+        self
+    }
+
+    /// Connect the tool straight to the terminal, so the user can type into it
+    pub fn interactive(mut self) -> Self {
+        self.interactive = true;
+        self
+    }
+}
+
+/// The result of running an external tool
+pub struct ToolOutput {
+    /// If the tool exited successfully
+    pub success: bool,
+    /// The tool's exit code. Blank if it was killed by a signal.
+    pub code: Option<i32>,
+    /// Everything the tool printed to stdout
+    pub stdout: String,
+    /// Everything the tool printed to stderr
+    pub stderr: String,
+}
+impl ToolOutput {
+    /// Output for a tool that succeeded and printed `stdout`
+    pub fn success(stdout: &str) -> Self {
+        Self {
+            success: true,
+            code: Some(0),
+            stdout: stdout.to_string(),
+            stderr: String::new(),
+        }
+    }
+
+    /// Output for a tool that failed and printed `stderr`
+    pub fn failure(stderr: &str) -> Self {
+        Self {
+            success: false,
+            code: Some(1),
+            stdout: String::new(),
+            stderr: stderr.to_string(),
+        }
+    }
+}
+
+/// Runs external tools, like cargo, swift, xcrun, and zip.
+///
+/// Code that needs an external tool takes a `&dyn ToolRunner` instead of
+/// using `std::process::Command`, so it can be run against fake tools.
+pub trait ToolRunner {
+    /// Runs a tool to completion, capturing its output.
+    /// Returns an error with `io::ErrorKind::NotFound` if the tool isn't installed.
+    fn execute(&self, call: &ToolCall) -> io::Result<ToolOutput>;
+
+    /// Runs `program` with `args` in the current directory
+    fn run(&self, program: &str, args: &[&str]) -> io::Result<ToolOutput> {
+        self.execute(&ToolCall::new(program, args))
+    }
+
+    /// Starts a tool in the background, showing its output to the user until the returned
+    /// [`BackgroundTool`] is dropped
+    fn spawn(&self, call: &ToolCall) -> io::Result<BackgroundTool>;
+}
+
+/// A tool running in the background, like `log stream`. It's stopped when this is dropped.
+pub struct BackgroundTool(Option<Child>);
+impl Drop for BackgroundTool {
+    fn drop(&mut self) {
+        if let Some(ref mut child) = self.0 {
+            // It may have already stopped on its own
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Runs a tool, returning its output if it succeeded. If the tool couldn't be
+/// started, this returns [`Error::ToolchainMissing`]; if it ran but failed,
+/// `on_failure` turns its output into an error.
+pub fn run_checked(
+    runner: &dyn ToolRunner,
+    call: &ToolCall,
+    on_failure: impl FnOnce(ToolOutput) -> Error,
+) -> Result<ToolOutput, Error> {
+    match runner.execute(call) {
+        Ok(output) if output.success => Ok(output),
+        Ok(output) => Err(on_failure(output)),
+        Err(e) => Err(Error::tool_missing(&call.program, e)),
+    }
+}
+
+/// Runs the real tools installed on this machine
+pub struct SystemRunner;
+impl ToolRunner for SystemRunner {
+    fn execute(&self, call: &ToolCall) -> io::Result<ToolOutput> {
+        let mut command = Command::new(&call.program);
+        command.args(&call.args);
+        command.envs(call.envs.iter().map(|(key, value)| (key, value)));
+        if let Some(ref dir) = call.current_dir {
+            command.current_dir(dir);
+        }
+
+        if !call.echo {
+            let output = command.output()?;
+            return Ok(ToolOutput {
+                success: output.status.success(),
+                code: output.status.code(),
+                stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            });
+        }
+
+        // Copy the tool's output to our own stdout/stderr as it comes in, while also capturing it
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+        let stdout = thread::spawn(move || tee(stdout, io::stdout()));
+        let stderr = thread::spawn(move || tee(stderr, io::stderr()));
+        let status = child.wait()?;
+
+        Ok(ToolOutput {
+            success: status.success(),
+            code: status.code(),
+            stdout: String::from_utf8_lossy(&stdout.join().unwrap_or_default()).into_owned(),
+            stderr: String::from_utf8_lossy(&stderr.join().unwrap_or_default()).into_owned(),
+        })
+    }
+
+    fn spawn(&self, call: &ToolCall) -> io::Result<BackgroundTool> {
+        let mut command = Command::new(&call.program);
+        command.args(&call.args).stdin(Stdio::null());
+        command.envs(call.envs.iter().map(|(key, value)| (key, value)));
+        if let Some(ref dir) = call.current_dir {
+            command.current_dir(dir);
+        }
+        Ok(BackgroundTool(Some(command.spawn()?)))
+    }
+}
+
+/// Copies everything from `from` to `to`, and returns a copy of what was copied
+fn tee(mut from: impl Read, mut to: impl Write) -> Vec<u8> {
+    let mut captured = Vec::new();
+    let mut buffer = [0; 4096];
+    while let Ok(read) = from.read(&mut buffer) {
+        if read == 0 {
+            break;
+        }
+        // Failing to show the output shouldn't stop it from being captured
+        let _ = to.write_all(&buffer[..read]).and_then(|_| to.flush());
+        captured.extend_from_slice(&buffer[..read]);
+    }
+    captured
+}
+
+/// Decides how a fake tool responds to a call
+#[cfg(any(test, feature = "test-support"))]
+type FakeTool = Box<dyn Fn(&ToolCall) -> io::Result<ToolOutput>>;
+
+/// A fake toolchain, for testing. It records every call it gets, and answers
+/// them with scripted fake tools. Tools that haven't been scripted act like
+/// they aren't installed. Background tools are only recorded, not run.
+#[cfg(any(test, feature = "test-support"))]
+#[derive(Default)]
+pub struct FakeRunner {
+    tools: HashMap<String, FakeTool>,
+    calls: RefCell<Vec<ToolCall>>,
+}
+#[cfg(any(test, feature = "test-support"))]
+impl FakeRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a tool that always succeeds and prints `stdout`
+    pub fn with_output(self, program: &str, stdout: &str) -> Self {
+        let stdout = stdout.to_string();
+        self.with_tool(program, move |_| Ok(ToolOutput::success(&stdout)))
+    }
+
+    /// Adds a tool that runs `tool` to decide what to do
+    pub fn with_tool(
+        mut self,
+        program: &str,
+        tool: impl Fn(&ToolCall) -> io::Result<ToolOutput> + 'static,
+    ) -> Self {
+        self.tools.insert(program.to_string(), Box::new(tool));
+        self
+    }
+
+    /// Every call that's been made so far, in order
+    pub fn calls(&self) -> Vec<ToolCall> {
+        self.calls.borrow().clone()
+    }
+
+    /// Every call to `program` that's been made so far, in order
+    pub fn calls_to(&self, program: &str) -> Vec<ToolCall> {
+        self.calls
+            .borrow()
+            .iter()
+            .filter(|call| call.program == program)
+            .cloned()
+            .collect()
+    }
+}
+#[cfg(any(test, feature = "test-support"))]
+impl ToolRunner for FakeRunner {
+    fn execute(&self, call: &ToolCall) -> io::Result<ToolOutput> {
+        self.calls.borrow_mut().push(call.clone());
+        match self.tools.get(&call.program) {
+            Some(tool) => tool(call),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("`{}` isn't installed", call.program),
+            )),
+        }
+    }
+
+    fn spawn(&self, call: &ToolCall) -> io::Result<BackgroundTool> {
+        self.calls.borrow_mut().push(call.clone());
+        match self.tools.contains_key(&call.program) {
+            true => Ok(BackgroundTool(None)),
+            false => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("`{}` isn't installed", call.program),
+            )),
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! The blobs that make up a code signature. Everything in here is big-endian, unlike the
+//! Mach-O itself.
+//! See: https://github.com/apple-oss-distributions/Security/blob/main/OSX/libsecurity_codesigning/lib/codedirectory.h
+
+use sha2::{Digest, Sha256};
+
+pub const CSMAGIC_REQUIREMENT: u32 = 0xfade0c00;
+pub const CSMAGIC_REQUIREMENTS: u32 = 0xfade0c01;
+pub const CSMAGIC_CODEDIRECTORY: u32 = 0xfade0c02;
+pub const CSMAGIC_EMBEDDED_SIGNATURE: u32 = 0xfade0cc0;
+pub const CSMAGIC_EMBEDDED_ENTITLEMENTS: u32 = 0xfade7171;
+pub const CSMAGIC_EMBEDDED_DER_ENTITLEMENTS: u32 = 0xfade7172;
+pub const CSMAGIC_BLOBWRAPPER: u32 = 0xfade0b01;
+
+/// Where each blob goes in the SuperBlob's index
+pub const CSSLOT_CODEDIRECTORY: u32 = 0;
+pub const CSSLOT_REQUIREMENTS: u32 = 2;
+pub const CSSLOT_ENTITLEMENTS: u32 = 5;
+/// The DER version of the entitlements, which iOS 15 and later use
+pub const CSSLOT_DER_ENTITLEMENTS: u32 = 7;
+pub const CSSLOT_SIGNATURESLOT: u32 = 0x10000;
+
+/// The special slots in the CodeDirectory. These hash files that aren't part of the binary
+/// itself, and are stored in front of the code hashes (so slot 1 is the hash right before
+/// the first page's hash).
+pub const SPECIAL_SLOT_INFO_PLIST: usize = 1;
+pub const SPECIAL_SLOT_REQUIREMENTS: usize = 2;
+pub const SPECIAL_SLOT_RESOURCES: usize = 3;
+pub const SPECIAL_SLOT_ENTITLEMENTS: usize = 5;
+pub const SPECIAL_SLOT_DER_ENTITLEMENTS: usize = 7;
+
+/// The CodeDirectory version we generate, which supports the executable segment fields
+const CODEDIRECTORY_VERSION: u32 = 0x20400;
+/// The size of a version 0x20400 CodeDirectory's header
+const CODEDIRECTORY_HEADER_SIZE: u32 = 88;
+/// `CS_ADHOC`: the binary isn't signed by a certificate
+pub const CS_ADHOC: u32 = 0x2;
+/// `CS_RUNTIME`: the binary opts into the hardened runtime
+pub const CS_RUNTIME: u32 = 0x10000;
+/// `CS_EXECSEG_MAIN_BINARY`: the binary is an executable, not a library
+pub const CS_EXECSEG_MAIN_BINARY: u64 = 0x1;
+/// `kSecDesignatedRequirementType`: where the designated requirement goes in a requirement set
+pub const DESIGNATED_REQUIREMENT: u32 = 3;
+/// A requirement that's an expression, rather than a reference to another one
+const EXPRESSION_FORM: u32 = 1;
+/// The requirement language's opcodes that the designated requirement uses.
+/// See: https://github.com/apple-oss-distributions/Security/blob/main/OSX/libsecurity_codesigning/lib/requirement.h
+const OP_IDENT: u32 = 2;
+const OP_AND: u32 = 6;
+const OP_CERT_FIELD: u32 = 11;
+const OP_APPLE_GENERIC_ANCHOR: u32 = 15;
+const MATCH_EQUAL: u32 = 1;
+/// The leaf certificate's index in a requirement's certificate fields
+const LEAF_CERTIFICATE: u32 = 0;
+/// `CS_HASHTYPE_SHA256`
+const HASH_TYPE_SHA256: u8 = 2;
+/// Code is hashed in 4KiB pages (stored as a power of 2)
+pub const PAGE_SIZE_BITS: u8 = 12;
+pub const PAGE_SIZE: usize = 1 << PAGE_SIZE_BITS;
+
+/// Everything that goes into a CodeDirectory
+pub struct CodeDirectory<'a> {
+    /// The signing identifier; for apps, this is the bundle ID
+    pub identifier: &'a str,
+    /// The team ID of the certificate the binary is signed with. Ad-hoc signatures don't have one.
+    pub team_id: Option<&'a str>,
+    pub flags: u32,
+    /// Hashes for the special slots, where index 0 is slot 1. Empty slots are `None`.
+    pub special_slots: Vec<Option<[u8; 32]>>,
+    /// The code being signed (everything in the binary before the signature)
+    pub code: &'a [u8],
+    /// The file offset and size of the __TEXT segment
+    pub exec_seg_base: u64,
+    pub exec_seg_limit: u64,
+    pub exec_seg_flags: u64,
+}
+impl CodeDirectory<'_> {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let code_slots = self.code.len().div_ceil(PAGE_SIZE) as u32;
+        let special_slots = self.special_slots.len() as u32;
+        let ident_offset = CODEDIRECTORY_HEADER_SIZE;
+        let team_offset = ident_offset + self.identifier.len() as u32 + 1;
+        let team_size = self.team_id.map_or(0, |team_id| team_id.len() as u32 + 1);
+        let hash_offset = team_offset + team_size + special_slots * 32;
+        let length = hash_offset + code_slots * 32;
+
+        let mut blob = Vec::with_capacity(length as usize);
+        put_u32(&mut blob, CSMAGIC_CODEDIRECTORY);
+        put_u32(&mut blob, length);
+        put_u32(&mut blob, CODEDIRECTORY_VERSION);
+        put_u32(&mut blob, self.flags);
+        put_u32(&mut blob, hash_offset);
+        put_u32(&mut blob, ident_offset);
+        put_u32(&mut blob, special_slots);
+        put_u32(&mut blob, code_slots);
+        put_u32(&mut blob, self.code.len() as u32);
+        blob.push(32); // hashSize
+        blob.push(HASH_TYPE_SHA256);
+        blob.push(0); // platform
+        blob.push(PAGE_SIZE_BITS);
+        put_u32(&mut blob, 0); // spare2
+        put_u32(&mut blob, 0); // scatterOffset
+        put_u32(
+            &mut blob,
+            if self.team_id.is_some() {
+                team_offset
+            } else {
+                0
+            },
+        );
+        put_u32(&mut blob, 0); // spare3
+        put_u64(&mut blob, 0); // codeLimit64
+        put_u64(&mut blob, self.exec_seg_base);
+        put_u64(&mut blob, self.exec_seg_limit);
+        put_u64(&mut blob, self.exec_seg_flags);
+
+        blob.extend_from_slice(self.identifier.as_bytes());
+        blob.push(0);
+        if let Some(team_id) = self.team_id {
+            blob.extend_from_slice(team_id.as_bytes());
+            blob.push(0);
+        }
+
+        // Special slots are stored backwards, so the highest slot comes first
+        for hash in self.special_slots.iter().rev() {
+            blob.extend_from_slice(&hash.unwrap_or([0; 32]));
+        }
+        for page in self.code.chunks(PAGE_SIZE) {
+            blob.extend_from_slice(&sha256(page));
+        }
+
+        blob
+    }
+}
+
+/// An empty requirement set, for ad-hoc signatures and certificates without a team. There's no
+/// team for a designated requirement to check, but signatures still need the blob.
+pub fn empty_requirements() -> Vec<u8> {
+    let mut blob = Vec::new();
+    put_u32(&mut blob, CSMAGIC_REQUIREMENTS);
+    put_u32(&mut blob, 12);
+    put_u32(&mut blob, 0); // count
+    blob
+}
+
+/// A requirement set with codesign's designated requirement for code signed with an Apple
+/// certificate: `identifier "<identifier>" and anchor apple generic and
+/// certificate leaf[subject.OU] = "<team_id>"`. Keychain ACLs and `codesign --verify -R`
+/// check the code against it.
+pub fn designated_requirement(identifier: &str, team_id: &str) -> Vec<u8> {
+    // Expressions are stored in prefix order, so `a and b and c` is `and(and(a, b), c)`
+    let mut expression = Vec::new();
+    put_u32(&mut expression, EXPRESSION_FORM);
+    put_u32(&mut expression, OP_AND);
+    put_u32(&mut expression, OP_AND);
+    put_u32(&mut expression, OP_IDENT);
+    put_data(&mut expression, identifier.as_bytes());
+    put_u32(&mut expression, OP_APPLE_GENERIC_ANCHOR);
+    put_u32(&mut expression, OP_CERT_FIELD);
+    put_u32(&mut expression, LEAF_CERTIFICATE);
+    put_data(&mut expression, b"subject.OU");
+    put_u32(&mut expression, MATCH_EQUAL);
+    put_data(&mut expression, team_id.as_bytes());
+    let requirement = wrap(CSMAGIC_REQUIREMENT, &expression);
+
+    let mut blob = Vec::new();
+    put_u32(&mut blob, CSMAGIC_REQUIREMENTS);
+    put_u32(&mut blob, 20 + requirement.len() as u32);
+    put_u32(&mut blob, 1); // count
+    put_u32(&mut blob, DESIGNATED_REQUIREMENT);
+    put_u32(&mut blob, 20); // offset
+    blob.extend_from_slice(&requirement);
+    blob
+}
+
+/// Wraps an entitlements plist (in XML form)
+pub fn entitlements(xml: &[u8]) -> Vec<u8> {
+    wrap(CSMAGIC_EMBEDDED_ENTITLEMENTS, xml)
+}
+
+/// Wraps DER-encoded entitlements
+pub fn der_entitlements(der: &[u8]) -> Vec<u8> {
+    wrap(CSMAGIC_EMBEDDED_DER_ENTITLEMENTS, der)
+}
+
+/// Wraps a CMS signature. Ad-hoc signatures use an empty one.
+pub fn signature(cms: &[u8]) -> Vec<u8> {
+    wrap(CSMAGIC_BLOBWRAPPER, cms)
+}
+
+/// Bundles blobs into a SuperBlob, the thing LC_CODE_SIGNATURE actually points to
+pub fn super_blob(blobs: &[(u32, Vec<u8>)]) -> Vec<u8> {
+    let header_size = 12 + blobs.len() as u32 * 8;
+    let length = header_size + blobs.iter().map(|(_, blob)| blob.len() as u32).sum::<u32>();
+
+    let mut super_blob = Vec::with_capacity(length as usize);
+    put_u32(&mut super_blob, CSMAGIC_EMBEDDED_SIGNATURE);
+    put_u32(&mut super_blob, length);
+    put_u32(&mut super_blob, blobs.len() as u32);
+    let mut offset = header_size;
+    for (slot, blob) in blobs {
+        put_u32(&mut super_blob, *slot);
+        put_u32(&mut super_blob, offset);
+        offset += blob.len() as u32;
+    }
+    for (_, blob) in blobs {
+        super_blob.extend_from_slice(blob);
+    }
+    super_blob
+}
+
+/// Adds a magic & length header to some data
+fn wrap(magic: u32, data: &[u8]) -> Vec<u8> {
+    let mut blob = Vec::with_capacity(data.len() + 8);
+    put_u32(&mut blob, magic);
+    put_u32(&mut blob, data.len() as u32 + 8);
+    blob.extend_from_slice(data);
+    blob
+}
+
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+fn put_u32(blob: &mut Vec<u8>, value: u32) {
+    blob.extend_from_slice(&value.to_be_bytes());
+}
+fn put_u64(blob: &mut Vec<u8>, value: u64) {
+    blob.extend_from_slice(&value.to_be_bytes());
+}
+/// Requirements store strings with their length first, padded to 4 bytes
+fn put_data(blob: &mut Vec<u8>, data: &[u8]) {
+    put_u32(blob, data.len() as u32);
+    blob.extend_from_slice(data);
+    blob.resize(blob.len().next_multiple_of(4), 0);
+}
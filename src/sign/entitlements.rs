@@ -0,0 +1,393 @@
+//! Builds an app's entitlements from the `entitlements` and `capabilities` settings, and
+//! checks them against the provisioning profile.
+//! See: https://developer.apple.com/documentation/bundleresources/entitlements
+
+use {
+    super::{identity::Identity, profile::ProvisioningProfile},
+    crate::{
+        config::{suggest, Config},
+        context::ExportMethod,
+        error::Error,
+    },
+    plist::{Dictionary, Value},
+};
+
+/// Every capability that can go in the `capabilities` setting
+const CAPABILITIES: &[&str] = &["push", "app-groups", "keychain-sharing", "icloud"];
+/// Every exception that can go in the `runtime-exceptions` setting, and its entitlement
+const RUNTIME_EXCEPTIONS: &[(&str, &str)] = &[
+    ("allow-jit", "com.apple.security.cs.allow-jit"),
+    (
+        "allow-unsigned-executable-memory",
+        "com.apple.security.cs.allow-unsigned-executable-memory",
+    ),
+    (
+        "allow-dyld-environment-variables",
+        "com.apple.security.cs.allow-dyld-environment-variables",
+    ),
+    (
+        "disable-library-validation",
+        "com.apple.security.cs.disable-library-validation",
+    ),
+    (
+        "disable-executable-page-protection",
+        "com.apple.security.cs.disable-executable-page-protection",
+    ),
+    ("debugger", "com.apple.security.cs.debugger"),
+    ("audio-input", "com.apple.security.device.audio-input"),
+    ("camera", "com.apple.security.device.camera"),
+    (
+        "location",
+        "com.apple.security.personal-information.location",
+    ),
+    (
+        "addressbook",
+        "com.apple.security.personal-information.addressbook",
+    ),
+    (
+        "calendars",
+        "com.apple.security.personal-information.calendars",
+    ),
+    (
+        "photos-library",
+        "com.apple.security.personal-information.photos-library",
+    ),
+    ("apple-events", "com.apple.security.automation.apple-events"),
+];
+
+/// Builds the entitlements for an app. Returns an empty dictionary if it doesn't need any.
+///
+/// The app ID & team ID come first, then the capabilities are expanded, then the
+/// `entitlements` table is applied on top (so it can override anything).
+pub fn generate(
+    cfg: &Config,
+    bundle_id: &str,
+    identity: Option<&Identity>,
+    profile: Option<&ProvisioningProfile>,
+    macos: bool,
+) -> Result<Dictionary, Error> {
+    let mut entitlements = Dictionary::new();
+    let team_id = identity.and_then(Identity::team_id);
+
+    if let Some(ref team_id) = team_id {
+        let app_id_key = if macos {
+            "com.apple.application-identifier"
+        } else {
+            "application-identifier"
+        };
+        entitlements.insert(app_id_key.into(), format!("{team_id}.{bundle_id}").into());
+        entitlements.insert(
+            "com.apple.developer.team-identifier".into(),
+            team_id.clone().into(),
+        );
+    }
+    // Development profiles let debuggers attach, and the app has to agree
+    let get_task_allow = if macos {
+        "com.apple.security.get-task-allow"
+    } else {
+        "get-task-allow"
+    };
+    if let Some(value) = profile.and_then(|profile| profile.entitlements.get(get_task_allow)) {
+        entitlements.insert(get_task_allow.into(), value.clone());
+    }
+
+    for capability in &cfg.capabilities {
+        let (name, value) = match capability.split_once(':') {
+            Some((name, value)) => (name, Some(value)),
+            None => (capability.as_str(), None),
+        };
+        match name {
+            "push" => {
+                // Use the same environment as the profile, so development builds don't
+                // claim to be production
+                let key = if macos {
+                    "com.apple.developer.aps-environment"
+                } else {
+                    "aps-environment"
+                };
+                let environment = value
+                    .map(Value::from)
+                    .or_else(|| {
+                        profile
+                            .and_then(|profile| profile.entitlements.get(key))
+                            .cloned()
+                    })
+                    .unwrap_or_else(|| "development".into());
+                entitlements.insert(key.into(), environment);
+            }
+            "app-groups" => {
+                let Some(group) = value else {
+                    return Err(missing_value(capability, "group.com.example.my-app"));
+                };
+                push(
+                    &mut entitlements,
+                    "com.apple.security.application-groups",
+                    group,
+                );
+            }
+            "keychain-sharing" => {
+                let group = value.unwrap_or(bundle_id);
+                let group = match team_id {
+                    Some(ref team_id) => format!("{team_id}.{group}"),
+                    None => group.to_string(),
+                };
+                push(&mut entitlements, "keychain-access-groups", &group);
+            }
+            "icloud" => {
+                let container = match value {
+                    Some(container) => container.to_string(),
+                    None => format!("iCloud.{bundle_id}"),
+                };
+                push(
+                    &mut entitlements,
+                    "com.apple.developer.icloud-container-identifiers",
+                    &container,
+                );
+                push(
+                    &mut entitlements,
+                    "com.apple.developer.icloud-services",
+                    "CloudKit",
+                );
+            }
+            _ => {
+                let mut message = format!("Unknown capability `{name}` in `capabilities`.");
+                if let Some(suggestion) = suggest(name, CAPABILITIES) {
+                    message += &format!(" Did you mean `{suggestion}`?");
+                }
+                message += &format!(" The known capabilities are: {}", CAPABILITIES.join(", "));
+                return Err(Error::Config(message));
+            }
+        }
+    }
+
+    // Only macOS has a hardened runtime, so iOS builds ignore its exceptions
+    let exceptions = &cfg.signing.runtime_exceptions;
+    if !exceptions.is_empty() && !cfg.signing.hardened_runtime {
+        return Err(Error::Config(
+            "`signing.runtime-exceptions` is set, but they only apply with `signing.hardened-runtime = true`".into(),
+        ));
+    }
+    for exception in exceptions.iter().filter(|_| macos) {
+        let Some((_, key)) = RUNTIME_EXCEPTIONS
+            .iter()
+            .find(|(name, _)| name == exception)
+        else {
+            let known: Vec<_> = RUNTIME_EXCEPTIONS.iter().map(|(name, _)| *name).collect();
+            let mut message =
+                format!("Unknown exception `{exception}` in `signing.runtime-exceptions`.");
+            if let Some(suggestion) = suggest(exception, &known) {
+                message += &format!(" Did you mean `{suggestion}`?");
+            }
+            message += &format!(" The known exceptions are: {}", known.join(", "));
+            return Err(Error::Config(message));
+        };
+        entitlements.insert(key.to_string(), true.into());
+    }
+
+    for (key, value) in &cfg.entitlements {
+        entitlements.insert(key.clone(), value.clone());
+    }
+    Ok(entitlements)
+}
+
+/// Sets the entitlements an export method decides: only development builds let debuggers
+/// attach, and only they use the development push environment. get-task-allow is left out
+/// when it's off, unless the profile lists it.
+pub fn apply_export_method(
+    entitlements: &mut Dictionary,
+    method: ExportMethod,
+    profile: Option<&ProvisioningProfile>,
+    macos: bool,
+) {
+    let development = method == ExportMethod::Development;
+    let (get_task_allow, aps_environment) = if macos {
+        (
+            "com.apple.security.get-task-allow",
+            "com.apple.developer.aps-environment",
+        )
+    } else {
+        ("get-task-allow", "aps-environment")
+    };
+    let profile_lists_it =
+        profile.is_some_and(|profile| profile.entitlements.contains_key(get_task_allow));
+    if development || profile_lists_it {
+        entitlements.insert(get_task_allow.into(), development.into());
+    } else {
+        entitlements.remove(get_task_allow);
+    }
+    if entitlements.contains_key(aps_environment) {
+        let environment = if development {
+            "development"
+        } else {
+            "production"
+        };
+        entitlements.insert(aps_environment.into(), environment.into());
+    }
+}
+
+/// Makes sure the provisioning profile allows every entitlement the app has
+pub fn check(entitlements: &Dictionary, profile: &ProvisioningProfile) -> Result<(), Error> {
+    for (key, value) in entitlements {
+        // Profiles don't list macOS's sandbox entitlements, since anyone can use them
+        if key.starts_with("com.apple.security.")
+            && key != "com.apple.security.application-groups"
+            && key != "com.apple.security.get-task-allow"
+        {
+            continue;
+        }
+        let Some(allowed) = profile.entitlements.get(key) else {
+            // Turning a boolean off doesn't need the profile's permission
+            if *value == Value::Boolean(false) {
+                continue;
+            }
+            return Err(Error::Signing(format!(
+                "The app has the entitlement `{key}`, but the provisioning profile `{}` doesn't allow it. Turn on the capability for your app ID on the Apple Developer website, then download the profile again.",
+                profile.name
+            )));
+        };
+        if !is_allowed(value, allowed) {
+            return Err(Error::Signing(format!(
+                "The provisioning profile `{}` doesn't allow the value of the entitlement `{key}`. It allows: {}",
+                profile.name,
+                describe(allowed)
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Serialises entitlements into the XML plist that gets embedded in the signature
+pub fn to_xml(entitlements: &Dictionary) -> Result<Vec<u8>, Error> {
+    let mut xml = Vec::new();
+    if let Err(e) = Value::from(entitlements.clone()).to_writer_xml(&mut xml) {
+        return Err(Error::Signing(format!(
+            "Failed to write the app's entitlements: {e}"
+        )));
+    }
+    Ok(xml)
+}
+
+/// Encodes entitlements the way the DER entitlements blob has them: a version number, then
+/// the dictionary, with its keys sorted. Dictionaries are `[16]`-tagged sets of key-value
+/// sequences; everything else uses its usual ASN.1 type.
+pub fn to_der(entitlements: &Dictionary) -> Result<Vec<u8>, Error> {
+    let mut contents = Vec::new();
+    der_tlv(0x02, &[1], &mut contents); // Version
+    der_value(&Value::Dictionary(entitlements.clone()), &mut contents)?;
+    let mut der = Vec::new();
+    der_tlv(0x70, &contents, &mut der); // [APPLICATION 16]
+    Ok(der)
+}
+
+fn der_value(value: &Value, der: &mut Vec<u8>) -> Result<(), Error> {
+    match value {
+        Value::Boolean(value) => der_tlv(0x01, &[if *value { 0xff } else { 0 }], der),
+        Value::Integer(integer) => {
+            let Some(integer) = integer.as_signed() else {
+                return Err(Error::Signing(format!(
+                    "The entitlement value `{integer}` is too big"
+                )));
+            };
+            let bytes = integer.to_be_bytes();
+            // Integers use as few bytes as they can, without changing their sign
+            let start = (0..7)
+                .find(|&i| {
+                    !(bytes[i] == 0 && bytes[i + 1] & 0x80 == 0
+                        || bytes[i] == 0xff && bytes[i + 1] & 0x80 != 0)
+                })
+                .unwrap_or(7);
+            der_tlv(0x02, &bytes[start..], der);
+        }
+        Value::String(string) => der_tlv(0x0c, string.as_bytes(), der),
+        Value::Array(array) => {
+            let mut contents = Vec::new();
+            for value in array {
+                der_value(value, &mut contents)?;
+            }
+            der_tlv(0x30, &contents, der);
+        }
+        Value::Dictionary(dictionary) => {
+            let mut entries: Vec<_> = dictionary.iter().collect();
+            entries.sort_by_key(|(key, _)| key.as_str());
+            let mut contents = Vec::new();
+            for (key, value) in entries {
+                let mut entry = Vec::new();
+                der_tlv(0x0c, key.as_bytes(), &mut entry);
+                der_value(value, &mut entry)?;
+                der_tlv(0x30, &entry, &mut contents);
+            }
+            der_tlv(0xb0, &contents, der); // [16], constructed
+        }
+        _ => {
+            return Err(Error::Signing(
+                "Entitlements can only be booleans, integers, strings, arrays, and dictionaries"
+                    .into(),
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Writes a DER tag, length, and value
+fn der_tlv(tag: u8, value: &[u8], der: &mut Vec<u8>) {
+    der.push(tag);
+    let length = value.len();
+    if length < 0x80 {
+        der.push(length as u8);
+    } else {
+        let bytes = length.to_be_bytes();
+        let skip = length.leading_zeros() as usize / 8;
+        der.push(0x80 | (bytes.len() - skip) as u8);
+        der.extend_from_slice(&bytes[skip..]);
+    }
+    der.extend_from_slice(value);
+}
+
+/// Whether the profile's value for an entitlement allows ours. Profiles can use `*`
+/// wildcards in strings, and list every value that's allowed in arrays.
+fn is_allowed(value: &Value, allowed: &Value) -> bool {
+    match (value, allowed) {
+        (Value::String(value), Value::String(allowed)) => match allowed.strip_suffix('*') {
+            Some(prefix) => value.starts_with(prefix),
+            None => value == allowed,
+        },
+        (Value::Array(values), Value::Array(allowed)) => values
+            .iter()
+            .all(|value| allowed.iter().any(|allowed| is_allowed(value, allowed))),
+        (Value::Array(values), Value::String(_)) => {
+            values.iter().all(|value| is_allowed(value, allowed))
+        }
+        // Profiles allow turning a boolean off
+        (Value::Boolean(value), Value::Boolean(allowed)) => !value || *allowed,
+        (value, allowed) => value == allowed,
+    }
+}
+
+/// A short description of an entitlement's value, for error messages
+fn describe(value: &Value) -> String {
+    match value {
+        Value::String(string) => format!("`{string}`"),
+        Value::Boolean(boolean) => format!("`{boolean}`"),
+        Value::Array(values) => values.iter().map(describe).collect::<Vec<_>>().join(", "),
+        _ => "a value of a different type".into(),
+    }
+}
+
+/// Adds a value to an array entitlement, unless it's already there
+fn push(entitlements: &mut Dictionary, key: &str, value: &str) {
+    if !entitlements.contains_key(key) {
+        entitlements.insert(key.into(), Value::Array(Vec::new()));
+    }
+    if let Some(Value::Array(array)) = entitlements.get_mut(key) {
+        let value = Value::from(value);
+        if !array.contains(&value) {
+            array.push(value);
+        }
+    }
+}
+
+fn missing_value(capability: &str, example: &str) -> Error {
+    Error::Config(format!(
+        "The capability `{capability}` needs a value, like `{capability}:{example}`"
+    ))
+}
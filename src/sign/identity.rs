@@ -0,0 +1,247 @@
+//! Signing identities (a certificate & its private key), and the CMS signatures made with them
+
+use {
+    crate::error::Error,
+    cms::{
+        builder::{SignedDataBuilder, SignerInfoBuilder},
+        cert::{CertificateChoices, IssuerAndSerialNumber},
+        signed_data::{EncapsulatedContentInfo, SignerIdentifier},
+    },
+    rsa::{
+        pkcs1v15::{Signature, SigningKey},
+        pkcs8::DecodePrivateKey,
+        RsaPrivateKey,
+    },
+    sha2::{Digest, Sha256},
+    std::{fs, path::Path, time::SystemTime},
+    x509_cert::{
+        attr::{Attribute, AttributeValue},
+        der::{
+            asn1::{Ia5StringRef, PrintableStringRef, SetOfVec, UtcTime, Utf8StringRef},
+            oid::ObjectIdentifier,
+            Decode, Encode,
+        },
+        name::Name,
+        spki::AlgorithmIdentifierOwned,
+        Certificate,
+    },
+};
+
+/// `id-data`, the content type of the (detached) CodeDirectory
+const ID_DATA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.7.1");
+/// `id-signingTime`
+const ID_SIGNING_TIME: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.5");
+/// `id-sha256`
+const ID_SHA_256: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.1");
+/// `id-at-commonName`
+pub(super) const ID_COMMON_NAME: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.5.4.3");
+/// `id-at-organizationalUnitName`, which Apple uses for the team ID
+const ID_ORGANIZATIONAL_UNIT: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.5.4.11");
+
+/// A certificate and private key to sign apps with, like an Apple Distribution certificate
+/// exported from Keychain Access
+pub struct Identity {
+    key: RsaPrivateKey,
+    /// The signing certificate, followed by the certificates that issued it
+    pub chain: Vec<Certificate>,
+}
+impl Identity {
+    /// Loads an identity from a .p12 file, then adds any extra certificates in `chain`
+    /// (DER or PEM) to its certificate chain
+    pub fn load(path: &Path, password: &str, chain: &[impl AsRef<Path>]) -> Result<Self, Error> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) => return Err(Error::io("Failed to read the signing identity", path, e)),
+        };
+        let mut identity = match Self::from_pkcs12(&data, password) {
+            Ok(identity) => identity,
+            Err(Error::Signing(message)) => {
+                return Err(Error::Signing(format!("`{}`: {message}", path.display())))
+            }
+            Err(e) => return Err(e),
+        };
+
+        for path in chain {
+            let path = path.as_ref();
+            let data = match fs::read(path) {
+                Ok(data) => data,
+                Err(e) => return Err(Error::io("Failed to read a chain certificate", path, e)),
+            };
+            // PEM files can hold more than one certificate
+            let certificates = if data.starts_with(b"-----BEGIN") {
+                Certificate::load_pem_chain(&data)
+            } else {
+                Certificate::from_der(&data).map(|certificate| vec![certificate])
+            };
+            match certificates {
+                Ok(certificates) if certificates.is_empty() => {
+                    return Err(Error::Signing(format!(
+                        "`{}` doesn't contain any certificates",
+                        path.display()
+                    )))
+                }
+                Ok(certificates) => {
+                    for certificate in certificates {
+                        identity.add_to_chain(certificate);
+                    }
+                }
+                Err(e) => {
+                    return Err(Error::Signing(format!(
+                        "`{}` isn't a valid certificate: {e}",
+                        path.display()
+                    )))
+                }
+            }
+        }
+
+        Ok(identity)
+    }
+
+    /// Reads the first certificate & private key in a PKCS#12 archive
+    pub fn from_pkcs12(data: &[u8], password: &str) -> Result<Self, Error> {
+        let keystore = match p12_keystore::KeyStore::from_pkcs12(data, password) {
+            Ok(keystore) => keystore,
+            Err(e) => {
+                return Err(Error::Signing(format!(
+                    "Failed to open the .p12 file. Is the password right? ({e})"
+                )))
+            }
+        };
+        // Keys without their certificate aren't listed, so both are missing here
+        let not_an_identity = || {
+            Error::Signing(
+                "The .p12 file doesn't contain a private key and its certificate. Export it from Keychain Access again, with the certificate selected rather than just the key.".into(),
+            )
+        };
+        let Some((_, key_chain)) = keystore.private_key_chain() else {
+            return Err(not_an_identity());
+        };
+
+        let key = match RsaPrivateKey::from_pkcs8_der(key_chain.key()) {
+            Ok(key) => key,
+            Err(e) => {
+                return Err(Error::Signing(format!(
+                    "The .p12 file's private key isn't an RSA key, which is the only kind cargo-ipa supports ({e})"
+                )))
+            }
+        };
+        let mut chain = Vec::new();
+        for certificate in key_chain.chain() {
+            match Certificate::from_der(certificate.as_der()) {
+                Ok(certificate) => chain.push(certificate),
+                Err(e) => {
+                    return Err(Error::Signing(format!(
+                        "The .p12 file contains an invalid certificate ({e})"
+                    )))
+                }
+            }
+        }
+        if chain.is_empty() {
+            return Err(not_an_identity());
+        }
+
+        Ok(Self { key, chain })
+    }
+
+    /// Adds a certificate to the end of the chain, unless it's already there
+    pub fn add_to_chain(&mut self, certificate: Certificate) {
+        if !self.chain.contains(&certificate) {
+            self.chain.push(certificate);
+        }
+    }
+
+    /// The signing certificate. Identities always have one, since loading them checks.
+    pub fn certificate(&self) -> &Certificate {
+        &self.chain[0]
+    }
+
+    /// The certificate's common name, eg `Apple Distribution: Example Inc (ABCDE12345)`
+    pub fn common_name(&self) -> Option<String> {
+        name_attribute(&self.certificate().tbs_certificate.subject, ID_COMMON_NAME)
+    }
+
+    /// The team ID the certificate belongs to, eg `ABCDE12345`
+    pub fn team_id(&self) -> Option<String> {
+        name_attribute(
+            &self.certificate().tbs_certificate.subject,
+            ID_ORGANIZATIONAL_UNIT,
+        )
+    }
+
+    /// Signs a CodeDirectory, returning a DER-encoded CMS SignedData with the certificate chain
+    /// and signing time. The CodeDirectory itself isn't included in the signature.
+    pub fn sign_code_directory(
+        &self,
+        code_directory: &[u8],
+        signing_time: SystemTime,
+    ) -> Result<Vec<u8>, Error> {
+        let digest = Sha256::digest(code_directory);
+        let content = EncapsulatedContentInfo {
+            econtent_type: ID_DATA,
+            econtent: None,
+        };
+        let signer = SigningKey::<Sha256>::new(self.key.clone());
+        let certificate = self.certificate();
+        let signer_id = SignerIdentifier::IssuerAndSerialNumber(IssuerAndSerialNumber {
+            issuer: certificate.tbs_certificate.issuer.clone(),
+            serial_number: certificate.tbs_certificate.serial_number.clone(),
+        });
+        let digest_algorithm = AlgorithmIdentifierOwned {
+            oid: ID_SHA_256,
+            parameters: None,
+        };
+
+        let build = || -> Result<Vec<u8>, cms::builder::Error> {
+            let mut signer_info = SignerInfoBuilder::new(
+                &signer,
+                signer_id,
+                digest_algorithm.clone(),
+                &content,
+                Some(&digest),
+            )?;
+            signer_info.add_signed_attribute(signing_time_attribute(signing_time)?)?;
+
+            let mut signed_data = SignedDataBuilder::new(&content);
+            signed_data.add_digest_algorithm(digest_algorithm.clone())?;
+            for certificate in &self.chain {
+                signed_data
+                    .add_certificate(CertificateChoices::Certificate(certificate.clone()))?;
+            }
+            signed_data.add_signer_info::<_, Signature>(signer_info)?;
+            Ok(signed_data.build()?.to_der()?)
+        };
+        build().map_err(|e| Error::Signing(format!("Failed to build the CMS signature: {e}")))
+    }
+}
+
+/// Builds the signing time attribute. Unlike cms's version, this one doesn't have to be now.
+fn signing_time_attribute(time: SystemTime) -> Result<Attribute, x509_cert::der::Error> {
+    let time = UtcTime::from_system_time(time)?;
+    let mut values = SetOfVec::new();
+    values.insert(AttributeValue::from_der(&time.to_der()?)?)?;
+    Ok(Attribute {
+        oid: ID_SIGNING_TIME,
+        values,
+    })
+}
+
+/// Finds an attribute (like the common name) in a certificate's subject or issuer
+pub(super) fn name_attribute(name: &Name, oid: ObjectIdentifier) -> Option<String> {
+    let value = name
+        .0
+        .iter()
+        .flat_map(|rdn| rdn.0.iter())
+        .find(|attribute| attribute.oid == oid)?
+        .value
+        .clone();
+    // Certificates can use a few different string types
+    if let Ok(string) = Utf8StringRef::try_from(&value) {
+        Some(string.to_string())
+    } else if let Ok(string) = PrintableStringRef::try_from(&value) {
+        Some(string.to_string())
+    } else {
+        Ia5StringRef::try_from(&value)
+            .ok()
+            .map(|string| string.to_string())
+    }
+}
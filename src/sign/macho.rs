@@ -0,0 +1,352 @@
+//! Just enough Mach-O parsing to find where a code signature goes.
+//! See: https://github.com/apple-oss-distributions/xnu/blob/main/EXTERNAL_HEADERS/mach-o/loader.h
+
+use crate::error::Error;
+
+/// `MH_MAGIC_64`, for 64-bit Mach-O files (stored little-endian)
+pub const MH_MAGIC_64: u32 = 0xfeedfacf;
+/// `FAT_MAGIC`, for universal binaries (stored big-endian)
+pub const FAT_MAGIC: u32 = 0xcafebabe;
+/// `MH_EXECUTE`, the file type for executables
+pub const MH_EXECUTE: u32 = 0x2;
+/// `CPU_TYPE_ARM64`
+pub const CPU_TYPE_ARM64: u32 = 0x0100000c;
+
+pub const LC_LOAD_DYLIB: u32 = 0xc;
+pub const LC_LOAD_WEAK_DYLIB: u32 = 0x80000018;
+pub const LC_SEGMENT_64: u32 = 0x19;
+pub const LC_UUID: u32 = 0x1b;
+pub const LC_CODE_SIGNATURE: u32 = 0x1d;
+
+/// The size of a 64-bit Mach-O header
+const HEADER_SIZE: usize = 32;
+/// The size of a `linkedit_data_command`, like LC_CODE_SIGNATURE
+const LINKEDIT_DATA_COMMAND_SIZE: u32 = 16;
+
+/// A segment from an LC_SEGMENT_64 load command
+#[derive(Clone, Debug)]
+pub struct Segment {
+    pub name: String,
+    /// Where the load command starts in the file
+    pub command_offset: usize,
+    pub vmaddr: u64,
+    pub vmsize: u64,
+    pub fileoff: u64,
+    pub filesize: u64,
+    /// The lowest file offset of any of the segment's sections, if it has any
+    pub first_section_offset: Option<u64>,
+}
+
+/// The LC_CODE_SIGNATURE load command
+#[derive(Clone, Copy, Debug)]
+pub struct CodeSignatureCommand {
+    /// Where the load command starts in the file
+    pub command_offset: usize,
+    pub dataoff: u32,
+    pub datasize: u32,
+}
+
+/// A parsed 64-bit Mach-O file
+#[derive(Clone, Debug)]
+pub struct MachO {
+    pub cputype: u32,
+    pub filetype: u32,
+    pub ncmds: u32,
+    pub sizeofcmds: u32,
+    pub segments: Vec<Segment>,
+    pub code_signature: Option<CodeSignatureCommand>,
+    pub uuid: Option<[u8; 16]>,
+    /// The install names of the dylibs the binary links against, like
+    /// `@rpath/libswiftCore.dylib`
+    pub dylibs: Vec<String>,
+}
+impl MachO {
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < HEADER_SIZE || read_u32_le(data, 0) != MH_MAGIC_64 {
+            return Err(Error::Signing(
+                "Only 64-bit Mach-O binaries can be signed".into(),
+            ));
+        }
+        let cputype = read_u32_le(data, 4);
+        let filetype = read_u32_le(data, 12);
+        let ncmds = read_u32_le(data, 16);
+        let sizeofcmds = read_u32_le(data, 20);
+        if HEADER_SIZE + sizeofcmds as usize > data.len() {
+            return Err(Error::Signing(
+                "The Mach-O's load commands are truncated".into(),
+            ));
+        }
+
+        let mut segments = Vec::new();
+        let mut code_signature = None;
+        let mut uuid = None;
+        let mut dylibs = Vec::new();
+        let mut offset = HEADER_SIZE;
+        for _ in 0..ncmds {
+            if offset + 8 > HEADER_SIZE + sizeofcmds as usize {
+                return Err(Error::Signing(
+                    "The Mach-O's load commands are truncated".into(),
+                ));
+            }
+            let cmd = read_u32_le(data, offset);
+            let cmdsize = read_u32_le(data, offset + 4) as usize;
+            if cmdsize < 8 || offset + cmdsize > HEADER_SIZE + sizeofcmds as usize {
+                return Err(Error::Signing(
+                    "The Mach-O has an invalid load command".into(),
+                ));
+            }
+
+            match cmd {
+                LC_SEGMENT_64 => {
+                    let invalid = || Error::Signing("The Mach-O has an invalid segment".into());
+                    if cmdsize < 72 {
+                        return Err(invalid());
+                    }
+                    let nsects = read_u32_le(data, offset + 64) as usize;
+                    if 72 + nsects * 80 > cmdsize {
+                        return Err(invalid());
+                    }
+                    let first_section_offset = (0..nsects)
+                        .map(|i| read_u32_le(data, offset + 72 + i * 80 + 48) as u64)
+                        .filter(|offset| *offset != 0)
+                        .min();
+                    segments.push(Segment {
+                        name: read_name(&data[offset + 8..offset + 24]),
+                        command_offset: offset,
+                        vmaddr: read_u64_le(data, offset + 24),
+                        vmsize: read_u64_le(data, offset + 32),
+                        fileoff: read_u64_le(data, offset + 40),
+                        filesize: read_u64_le(data, offset + 48),
+                        first_section_offset,
+                    });
+                }
+                LC_CODE_SIGNATURE => {
+                    if cmdsize < 16 {
+                        return Err(Error::Signing(
+                            "The Mach-O has an invalid code signature command".into(),
+                        ));
+                    }
+                    code_signature = Some(CodeSignatureCommand {
+                        command_offset: offset,
+                        dataoff: read_u32_le(data, offset + 8),
+                        datasize: read_u32_le(data, offset + 12),
+                    })
+                }
+                LC_UUID if cmdsize >= 24 => {
+                    uuid = Some(data[offset + 8..offset + 24].try_into().unwrap());
+                }
+                LC_LOAD_DYLIB | LC_LOAD_WEAK_DYLIB => {
+                    // The name is stored after the command's fixed fields, at `name_offset`
+                    let name_offset = if cmdsize >= 24 {
+                        read_u32_le(data, offset + 8) as usize
+                    } else {
+                        0
+                    };
+                    if name_offset < 24 || name_offset >= cmdsize {
+                        return Err(Error::Signing(
+                            "The Mach-O has an invalid dylib command".into(),
+                        ));
+                    }
+                    dylibs.push(read_name(&data[offset + name_offset..offset + cmdsize]));
+                }
+                _ => {}
+            }
+            offset += cmdsize;
+        }
+
+        Ok(Self {
+            cputype,
+            filetype,
+            ncmds,
+            sizeofcmds,
+            segments,
+            code_signature,
+            uuid,
+            dylibs,
+        })
+    }
+
+    pub fn segment(&self, name: &str) -> Option<&Segment> {
+        self.segments.iter().find(|segment| segment.name == name)
+    }
+
+    /// The page size the kernel uses for this binary's architecture
+    pub fn page_size(&self) -> u64 {
+        if self.cputype == CPU_TYPE_ARM64 {
+            0x4000
+        } else {
+            0x1000
+        }
+    }
+
+    /// How much space is left between the end of the load commands and the first section
+    fn header_padding(&self) -> u64 {
+        let first_data = self
+            .segments
+            .iter()
+            .filter_map(|segment| {
+                segment.first_section_offset.or({
+                    // Segments without sections start at their file offset
+                    // (except __TEXT, which starts at 0 and includes the header)
+                    (segment.filesize > 0 && segment.fileoff > 0).then_some(segment.fileoff)
+                })
+            })
+            .min()
+            .unwrap_or(u64::MAX);
+        first_data.saturating_sub(HEADER_SIZE as u64 + self.sizeofcmds as u64)
+    }
+}
+
+/// Prepares a thin Mach-O for a new code signature of `signature_size` bytes.
+///
+/// This removes any existing signature, adds an LC_CODE_SIGNATURE load command
+/// if there isn't one, and grows __LINKEDIT to fit the new signature. Returns the
+/// prepared binary (without the signature) and where the signature should start,
+/// which is also the limit of the code that the signature covers.
+pub fn prepare_for_signature(data: &[u8], signature_size: u32) -> Result<(Vec<u8>, usize), Error> {
+    let macho = MachO::parse(data)?;
+    let Some(linkedit) = macho.segment("__LINKEDIT").cloned() else {
+        return Err(Error::Signing(
+            "The Mach-O doesn't have a __LINKEDIT segment".into(),
+        ));
+    };
+    let mut data = data.to_vec();
+
+    // Find where the signature goes, and make sure there's a load command pointing at it
+    let (code_limit, command_offset) = match macho.code_signature {
+        Some(command) => {
+            let dataoff = command.dataoff as usize;
+            if dataoff < linkedit.fileoff as usize || dataoff > data.len() {
+                return Err(Error::Signing(
+                    "The Mach-O's existing code signature is outside of __LINKEDIT".into(),
+                ));
+            }
+            (dataoff, command.command_offset)
+        }
+        None => {
+            if macho.header_padding() < LINKEDIT_DATA_COMMAND_SIZE as u64 {
+                return Err(Error::Signing(
+                    "There isn't enough space in the Mach-O's header for a code signature. Try linking with `-C link-arg=-Wl,-headerpad,0x100`".into(),
+                ));
+            }
+            // The signature is the last thing in __LINKEDIT, and must be 16-byte aligned
+            let linkedit_end = (linkedit.fileoff + linkedit.filesize) as usize;
+            let code_limit = align(linkedit_end, 16);
+
+            let command_offset = HEADER_SIZE + macho.sizeofcmds as usize;
+            write_u32_le(&mut data, command_offset, LC_CODE_SIGNATURE);
+            write_u32_le(&mut data, command_offset + 4, LINKEDIT_DATA_COMMAND_SIZE);
+            write_u32_le(&mut data, 16, macho.ncmds + 1);
+            write_u32_le(&mut data, 20, macho.sizeofcmds + LINKEDIT_DATA_COMMAND_SIZE);
+            (code_limit, command_offset)
+        }
+    };
+
+    // Drop the old signature (or pad up to the alignment for a new one)
+    data.resize(code_limit, 0);
+    write_u32_le(&mut data, command_offset + 8, code_limit as u32);
+    write_u32_le(&mut data, command_offset + 12, signature_size);
+
+    // Grow __LINKEDIT to include the new signature
+    let filesize = (code_limit + signature_size as usize) as u64 - linkedit.fileoff;
+    let vmsize = align(filesize as usize, macho.page_size() as usize) as u64;
+    write_u64_le(&mut data, linkedit.command_offset + 32, vmsize);
+    write_u64_le(&mut data, linkedit.command_offset + 48, filesize);
+
+    Ok((data, code_limit))
+}
+
+/// A slice in a universal binary
+pub struct FatArch {
+    pub cputype: u32,
+    pub cpusubtype: u32,
+    pub offset: u32,
+    pub size: u32,
+    /// The slice's alignment, as a power of 2
+    pub align: u32,
+}
+
+/// Splits a universal binary into its slices. Returns `None` if the binary isn't universal.
+pub fn parse_fat(data: &[u8]) -> Result<Option<Vec<FatArch>>, Error> {
+    if data.len() < 8 || read_u32_be(data, 0) != FAT_MAGIC {
+        return Ok(None);
+    }
+    let count = read_u32_be(data, 4) as usize;
+    if data.len() < 8 + count * 20 {
+        return Err(Error::Signing(
+            "The universal binary's header is truncated".into(),
+        ));
+    }
+    let mut arches = Vec::new();
+    for i in 0..count {
+        let entry = 8 + i * 20;
+        let arch = FatArch {
+            cputype: read_u32_be(data, entry),
+            cpusubtype: read_u32_be(data, entry + 4),
+            offset: read_u32_be(data, entry + 8),
+            size: read_u32_be(data, entry + 12),
+            align: read_u32_be(data, entry + 16),
+        };
+        if arch.offset as usize + arch.size as usize > data.len() {
+            return Err(Error::Signing(
+                "A universal binary slice is truncated".into(),
+            ));
+        }
+        arches.push(arch);
+    }
+    Ok(Some(arches))
+}
+
+/// Builds a universal binary from its slices
+pub fn build_fat(slices: &[(FatArch, Vec<u8>)]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&FAT_MAGIC.to_be_bytes());
+    data.extend_from_slice(&(slices.len() as u32).to_be_bytes());
+
+    // Lay the slices out after the header, each aligned like it was before
+    let mut offset = 8 + slices.len() * 20;
+    let mut offsets = Vec::new();
+    for (arch, slice) in slices {
+        offset = align(offset, 1 << arch.align);
+        offsets.push(offset);
+        offset += slice.len();
+    }
+    for ((arch, slice), offset) in slices.iter().zip(&offsets) {
+        data.extend_from_slice(&arch.cputype.to_be_bytes());
+        data.extend_from_slice(&arch.cpusubtype.to_be_bytes());
+        data.extend_from_slice(&(*offset as u32).to_be_bytes());
+        data.extend_from_slice(&(slice.len() as u32).to_be_bytes());
+        data.extend_from_slice(&arch.align.to_be_bytes());
+    }
+    for ((_, slice), offset) in slices.iter().zip(&offsets) {
+        data.resize(*offset, 0);
+        data.extend_from_slice(slice);
+    }
+    data
+}
+
+/// Rounds `value` up to a multiple of `alignment`
+pub fn align(value: usize, alignment: usize) -> usize {
+    value.div_ceil(alignment) * alignment
+}
+
+fn read_name(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+pub fn read_u32_le(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+pub fn read_u64_le(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+pub fn read_u32_be(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+fn write_u32_le(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+fn write_u64_le(data: &mut [u8], offset: usize, value: u64) {
+    data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
@@ -0,0 +1,356 @@
+//! Code signing for Mach-O binaries, without needing `codesign`.
+//! See: https://developer.apple.com/documentation/technotes/tn3126-inside-code-signing-hashes
+
+pub mod blob;
+pub mod entitlements;
+pub mod identity;
+pub mod macho;
+pub mod profile;
+pub mod resources;
+pub mod timestamp;
+pub mod verify;
+
+use {
+    crate::{context::Ctx, error::Error},
+    blob::*,
+    identity::Identity,
+    macho::{FatArch, MachO, MH_EXECUTE},
+    profile::ProvisioningProfile,
+    std::{
+        env, fs,
+        path::{Path, PathBuf},
+        time::SystemTime,
+    },
+};
+
+/// Overrides the `signing.identity` setting
+pub const IDENTITY_ENV: &str = "CARGO_IPA_IDENTITY";
+/// The signing identity's password. Overrides the `signing.password-file` setting.
+pub const PASSWORD_ENV: &str = "CARGO_IPA_IDENTITY_PASSWORD";
+
+/// What to sign a binary with
+#[derive(Default)]
+pub struct SigningOptions<'a> {
+    /// The signing identifier; for apps, this is the bundle ID
+    pub identifier: &'a str,
+    /// The app's Info.plist, exactly as it's stored in the app
+    pub info_plist: Option<&'a [u8]>,
+    /// The app's `_CodeSignature/CodeResources`, exactly as it's stored in the app
+    pub code_resources: Option<&'a [u8]>,
+    /// The app's entitlements, as an XML plist
+    pub entitlements: Option<&'a [u8]>,
+    /// The certificate to sign with. If blank, the binary is ad-hoc signed.
+    pub identity: Option<&'a Identity>,
+    /// When the binary was signed. If blank, it's the current time.
+    pub signing_time: Option<SystemTime>,
+    /// Turns on the hardened runtime, which notarized macOS apps need
+    pub hardened_runtime: bool,
+    /// An RFC 3161 timestamp authority to timestamp the signature with. Ad-hoc signatures
+    /// can't be timestamped.
+    pub timestamp_url: Option<&'a str>,
+}
+
+/// Loads the signing identity set up in Cargo.toml or the environment. Returns `None` if
+/// there isn't one, in which case apps should be ad-hoc signed.
+pub fn load_identity(ctx: &Ctx) -> Result<Option<Identity>, Error> {
+    let signing = &ctx.cfg.signing;
+    let path = signing
+        .identity
+        .as_ref()
+        .map(|path| ctx.root_dir.join(path));
+    let password_file = signing
+        .password_file
+        .as_ref()
+        .map(|path| ctx.root_dir.join(path));
+    let chain: Vec<_> = signing
+        .certificate_chain
+        .iter()
+        .map(|path| ctx.root_dir.join(path))
+        .collect();
+    load_identity_from(path, password_file, &chain)
+}
+
+/// Loads a signing identity from a .p12 file and a password file, either of which can be
+/// overridden by the environment
+pub fn load_identity_from(
+    path: Option<PathBuf>,
+    password_file: Option<PathBuf>,
+    chain: &[PathBuf],
+) -> Result<Option<Identity>, Error> {
+    let path = match env::var_os(IDENTITY_ENV) {
+        Some(path) => path.into(),
+        None => match path {
+            Some(path) => path,
+            None => return Ok(None),
+        },
+    };
+
+    let password = if let Ok(password) = env::var(PASSWORD_ENV) {
+        password
+    } else if let Some(password_file) = password_file {
+        match fs::read_to_string(&password_file) {
+            // Editors like to add a newline to the end of files
+            Ok(password) => password.trim_end_matches(['\r', '\n']).to_string(),
+            Err(e) => {
+                return Err(Error::io(
+                    "Failed to read the signing identity's password file",
+                    password_file,
+                    e,
+                ))
+            }
+        }
+    } else {
+        String::new()
+    };
+
+    Identity::load(&path, &password, chain).map(Some)
+}
+
+/// Loads the provisioning profile set up in Cargo.toml, and makes sure it works with the
+/// app's bundle ID, the signing identity, and the devices listed in Cargo.toml.
+pub fn load_profile(
+    ctx: &Ctx,
+    identity: Option<&Identity>,
+) -> Result<Option<ProvisioningProfile>, Error> {
+    let signing = &ctx.cfg.signing;
+    let Some(ref path) = signing.provisioning_profile else {
+        return Ok(None);
+    };
+    let Some(identity) = identity else {
+        return Err(Error::Signing(
+            "A provisioning profile is set, but there's no signing identity to go with it. Set `signing.identity` too.".into(),
+        ));
+    };
+    let profile = ProvisioningProfile::load(&ctx.root_dir.join(path))?;
+    profile.check(
+        &ctx.bundle_id,
+        identity,
+        &signing.devices,
+        SystemTime::now(),
+    )?;
+    Ok(Some(profile))
+}
+
+/// Signs the binary at `path`, replacing it with the signed version
+pub fn sign_file(path: &Path, options: &SigningOptions) -> Result<(), Error> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) => return Err(Error::io("Failed to read the binary to sign", path, e)),
+    };
+    let signed = sign(&data, options)?;
+    if let Err(e) = fs::write(path, signed) {
+        return Err(Error::io("Failed to write the signed binary", path, e));
+    }
+    Ok(())
+}
+
+/// Signs a Mach-O binary (or universal binary), returning the signed binary.
+/// Any existing signature is replaced.
+pub fn sign(data: &[u8], options: &SigningOptions) -> Result<Vec<u8>, Error> {
+    let Some(arches) = macho::parse_fat(data)? else {
+        return sign_thin(data, options);
+    };
+
+    // Universal binaries have a signature for every slice
+    let mut slices = Vec::new();
+    for arch in arches {
+        let slice = &data[arch.offset as usize..(arch.offset + arch.size) as usize];
+        let signed = sign_thin(slice, options)?;
+        slices.push((
+            FatArch {
+                size: signed.len() as u32,
+                ..arch
+            },
+            signed,
+        ));
+    }
+    Ok(macho::build_fat(&slices))
+}
+
+/// Finds a signed binary's CDHash: the first 20 bytes of its CodeDirectory's SHA-256 hash.
+/// For universal binaries, this is the first slice's CDHash. Returns `None` if the binary
+/// isn't signed.
+pub fn cdhash(data: &[u8]) -> Result<Option<[u8; 20]>, Error> {
+    let Some(code_directory) = find_blob(data, CSSLOT_CODEDIRECTORY)? else {
+        return match find_blob(data, CSSLOT_SIGNATURESLOT)? {
+            // Signed, but without a CodeDirectory
+            Some(_) => Err(Error::Signing(
+                "The binary's signature doesn't have a CodeDirectory".into(),
+            )),
+            None => Ok(None),
+        };
+    };
+    let mut cdhash = [0; 20];
+    cdhash.copy_from_slice(&sha256(code_directory)[..20]);
+    Ok(Some(cdhash))
+}
+
+/// Finds the entitlements (as an XML plist) a binary was signed with, if it has any
+pub fn embedded_entitlements(data: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    Ok(find_blob(data, CSSLOT_ENTITLEMENTS)?.map(|blob| blob[8..].to_vec()))
+}
+
+/// Finds a blob (including its magic & length) in a signed binary's SuperBlob. For universal
+/// binaries, this looks in the first slice.
+pub fn find_blob(data: &[u8], slot: u32) -> Result<Option<&[u8]>, Error> {
+    let data = match macho::parse_fat(data)? {
+        Some(arches) => match arches.first() {
+            Some(arch) => &data[arch.offset as usize..(arch.offset + arch.size) as usize],
+            None => return Ok(None),
+        },
+        None => data,
+    };
+    let Some(code_signature) = MachO::parse(data)?.code_signature else {
+        return Ok(None);
+    };
+    let start = code_signature.dataoff as usize;
+    let Some(super_blob) = data.get(start..start + code_signature.datasize as usize) else {
+        return Err(Error::Signing(
+            "The binary's signature is outside of the file".into(),
+        ));
+    };
+
+    let count = super_blob
+        .get(8..12)
+        .map_or(0, |_| macho::read_u32_be(super_blob, 8));
+    for i in 0..count as usize {
+        let index = 12 + i * 8;
+        if index + 8 > super_blob.len() {
+            break;
+        }
+        if macho::read_u32_be(super_blob, index) != slot {
+            continue;
+        }
+        let offset = macho::read_u32_be(super_blob, index + 4) as usize;
+        let blob = super_blob
+            .get(offset..offset + 8)
+            .map(|_| macho::read_u32_be(super_blob, offset + 4) as usize)
+            .and_then(|length| super_blob.get(offset..offset + length))
+            .filter(|blob| blob.len() >= 8);
+        return match blob {
+            Some(blob) => Ok(Some(blob)),
+            None => Err(Error::Signing("The binary's signature is corrupted".into())),
+        };
+    }
+    Ok(None)
+}
+
+/// Signs a single-architecture Mach-O
+fn sign_thin(data: &[u8], options: &SigningOptions) -> Result<Vec<u8>, Error> {
+    let macho = MachO::parse(data)?;
+
+    // The signature's size only depends on how much code it covers, not what the code is,
+    // so build one for placeholder code first to find out how much space to make for it
+    let (_, code_limit) = macho::prepare_for_signature(data, 0)?;
+    let signing_time = options.signing_time.unwrap_or_else(SystemTime::now);
+    // The timestamp isn't requested yet, since it's for a specific signature
+    let mut size =
+        build_signature(&macho, &vec![0; code_limit], options, signing_time, false)?.len();
+    if options.timestamp_url.is_some() && options.identity.is_some() {
+        size += timestamp::TIMESTAMP_RESERVE;
+    }
+    let size = macho::align(size, 16);
+
+    let (mut signed, code_limit) = macho::prepare_for_signature(data, size as u32)?;
+    let signature = build_signature(&macho, &signed[..code_limit], options, signing_time, true)?;
+    if signature.len() > size {
+        return Err(Error::Signing(
+            "The signature ended up bigger than the space made for it".into(),
+        ));
+    }
+    signed.extend_from_slice(&signature);
+    signed.resize(code_limit + size, 0);
+    Ok(signed)
+}
+
+/// Builds the SuperBlob for a binary whose code (everything before the signature) is `code`
+fn build_signature(
+    macho: &MachO,
+    code: &[u8],
+    options: &SigningOptions,
+    signing_time: SystemTime,
+    timestamp: bool,
+) -> Result<Vec<u8>, Error> {
+    let team_id = options.identity.and_then(Identity::team_id);
+    let requirements = match team_id {
+        Some(ref team_id) => designated_requirement(options.identifier, team_id),
+        None => empty_requirements(),
+    };
+    let entitlements_der = match options.entitlements {
+        Some(xml) => match plist::Value::from_reader_xml(xml) {
+            Ok(plist::Value::Dictionary(dictionary)) => {
+                Some(der_entitlements(&entitlements::to_der(&dictionary)?))
+            }
+            _ => {
+                return Err(Error::Signing(
+                    "The app's entitlements aren't a plist dictionary".into(),
+                ))
+            }
+        },
+        None => None,
+    };
+    let entitlements = options.entitlements.map(entitlements);
+
+    let mut special_slots = vec![None; SPECIAL_SLOT_RESOURCES];
+    special_slots[SPECIAL_SLOT_INFO_PLIST - 1] = options.info_plist.map(sha256);
+    special_slots[SPECIAL_SLOT_REQUIREMENTS - 1] = Some(sha256(&requirements));
+    special_slots[SPECIAL_SLOT_RESOURCES - 1] = options.code_resources.map(sha256);
+    if let Some(ref entitlements) = entitlements {
+        special_slots.resize(SPECIAL_SLOT_ENTITLEMENTS, None);
+        special_slots[SPECIAL_SLOT_ENTITLEMENTS - 1] = Some(sha256(entitlements));
+    }
+    if let Some(ref entitlements_der) = entitlements_der {
+        special_slots.resize(SPECIAL_SLOT_DER_ENTITLEMENTS, None);
+        special_slots[SPECIAL_SLOT_DER_ENTITLEMENTS - 1] = Some(sha256(entitlements_der));
+    }
+
+    let (exec_seg_base, exec_seg_limit) = macho
+        .segment("__TEXT")
+        .map(|text| (text.fileoff, text.filesize))
+        .unwrap_or_default();
+    let code_directory = CodeDirectory {
+        identifier: options.identifier,
+        team_id: team_id.as_deref(),
+        flags: if options.identity.is_some() {
+            0
+        } else {
+            CS_ADHOC
+        } | if options.hardened_runtime {
+            CS_RUNTIME
+        } else {
+            0
+        },
+        special_slots,
+        code,
+        exec_seg_base,
+        exec_seg_limit,
+        exec_seg_flags: if macho.filetype == MH_EXECUTE {
+            CS_EXECSEG_MAIN_BINARY
+        } else {
+            0
+        },
+    };
+
+    let code_directory = code_directory.to_bytes();
+    let cms = match options.identity {
+        Some(identity) => identity.sign_code_directory(&code_directory, signing_time)?,
+        None => Vec::new(),
+    };
+    let cms = match options.timestamp_url {
+        Some(url) if timestamp && !cms.is_empty() => timestamp::add_timestamp(&cms, url)?,
+        _ => cms,
+    };
+
+    let mut blobs = vec![
+        (CSSLOT_CODEDIRECTORY, code_directory),
+        (CSSLOT_REQUIREMENTS, requirements),
+    ];
+    if let Some(entitlements) = entitlements {
+        blobs.push((CSSLOT_ENTITLEMENTS, entitlements));
+    }
+    if let Some(entitlements_der) = entitlements_der {
+        blobs.push((CSSLOT_DER_ENTITLEMENTS, entitlements_der));
+    }
+    blobs.push((CSSLOT_SIGNATURESLOT, signature(&cms)));
+    Ok(super_blob(&blobs))
+}
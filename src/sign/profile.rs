@@ -0,0 +1,263 @@
+//! Provisioning profiles, which say which devices (and certificates) an app can run with.
+//! They're a plist wrapped in a CMS signature from Apple.
+//! See: https://developer.apple.com/documentation/technotes/tn3125-inside-code-signing-provisioning-profiles
+
+use {
+    super::identity::Identity,
+    crate::{
+        context::{ExportMethod, Platform},
+        error::Error,
+    },
+    cms::{content_info::ContentInfo, signed_data::SignedData},
+    plist::{Date, Dictionary, Value},
+    std::{fs, path::Path, time::SystemTime},
+    x509_cert::der::{asn1::OctetString, Decode, Encode},
+};
+
+/// Where iOS apps keep their provisioning profile, relative to the .app folder
+pub const EMBEDDED_PROFILE_IOS: &str = "embedded.mobileprovision";
+/// Where macOS apps keep their provisioning profile, relative to the Contents folder
+pub const EMBEDDED_PROFILE_MACOS: &str = "embedded.provisionprofile";
+
+/// The parts of a provisioning profile that cargo-ipa checks
+#[derive(Clone)]
+pub struct ProvisioningProfile {
+    /// The profile's name, as shown on the Apple Developer website
+    pub name: String,
+    /// The app ID the profile is for, including the team ID, eg `ABCDE12345.com.example.app`.
+    /// May end in a `*` wildcard.
+    pub application_identifier: String,
+    /// The DER-encoded certificates that can sign apps using this profile
+    pub developer_certificates: Vec<Vec<u8>>,
+    pub expiration_date: Date,
+    /// The UDIDs of the devices the profile can be installed on. App Store profiles don't
+    /// list any.
+    pub provisioned_devices: Vec<String>,
+    /// Enterprise profiles can be installed on any device
+    pub provisions_all_devices: bool,
+    /// The platforms the profile is for, eg `iOS` or `OSX`. Profiles that don't say are
+    /// treated as being for every platform.
+    pub platforms: Vec<String>,
+    /// The entitlements apps using this profile are allowed to have
+    pub entitlements: Dictionary,
+    /// The profile exactly as it was read, which is what gets embedded in the app
+    pub data: Vec<u8>,
+}
+impl ProvisioningProfile {
+    /// Reads a .mobileprovision or .provisionprofile file
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) => {
+                return Err(Error::io(
+                    "Failed to read the provisioning profile",
+                    path,
+                    e,
+                ))
+            }
+        };
+        match Self::parse(data) {
+            Ok(profile) => Ok(profile),
+            Err(Error::Signing(message)) => {
+                Err(Error::Signing(format!("`{}`: {message}", path.display())))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Parses a provisioning profile
+    pub fn parse(data: Vec<u8>) -> Result<Self, Error> {
+        let plist = match signed_content(&data) {
+            Ok(plist) => plist,
+            Err(e) => {
+                return Err(Error::Signing(format!(
+                    "This isn't a valid provisioning profile ({e})"
+                )))
+            }
+        };
+        let plist = match Value::from_reader(std::io::Cursor::new(plist)) {
+            Ok(Value::Dictionary(plist)) => plist,
+            Ok(_) => {
+                return Err(Error::Signing(
+                    "The provisioning profile's contents aren't a dictionary".into(),
+                ))
+            }
+            Err(e) => {
+                return Err(Error::Signing(format!(
+                    "The provisioning profile's contents aren't a valid plist ({e})"
+                )))
+            }
+        };
+
+        let missing =
+            |key: &str| Error::Signing(format!("The provisioning profile doesn't have a `{key}`"));
+        let name = plist
+            .get("Name")
+            .and_then(Value::as_string)
+            .unwrap_or("Unnamed profile")
+            .to_string();
+        let entitlements = plist
+            .get("Entitlements")
+            .and_then(Value::as_dictionary)
+            .cloned()
+            .ok_or_else(|| missing("Entitlements"))?;
+        let application_identifier = entitlements
+            .get("application-identifier")
+            // macOS profiles use a different key
+            .or_else(|| entitlements.get("com.apple.application-identifier"))
+            .and_then(Value::as_string)
+            .ok_or_else(|| missing("application-identifier"))?
+            .to_string();
+        let developer_certificates = plist
+            .get("DeveloperCertificates")
+            .and_then(Value::as_array)
+            .ok_or_else(|| missing("DeveloperCertificates"))?
+            .iter()
+            .filter_map(|certificate| certificate.as_data().map(<[u8]>::to_vec))
+            .collect();
+        let expiration_date = plist
+            .get("ExpirationDate")
+            .and_then(Value::as_date)
+            .ok_or_else(|| missing("ExpirationDate"))?;
+        let provisioned_devices = plist
+            .get("ProvisionedDevices")
+            .and_then(Value::as_array)
+            .map(|devices| {
+                devices
+                    .iter()
+                    .filter_map(|device| device.as_string().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let provisions_all_devices = plist
+            .get("ProvisionsAllDevices")
+            .and_then(Value::as_boolean)
+            .unwrap_or(false);
+        let platforms = plist
+            .get("Platform")
+            .and_then(Value::as_array)
+            .map(|platforms| {
+                platforms
+                    .iter()
+                    .filter_map(|platform| platform.as_string().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(Self {
+            name,
+            application_identifier,
+            developer_certificates,
+            expiration_date,
+            provisioned_devices,
+            provisions_all_devices,
+            platforms,
+            entitlements,
+            data,
+        })
+    }
+
+    /// Whether apps for `platform` can use the profile
+    pub fn supports(&self, platform: Platform) -> bool {
+        let name = match platform {
+            Platform::iOS => "iOS",
+            Platform::macOS => "OSX",
+        };
+        self.platforms.is_empty() || self.platforms.iter().any(|p| p == name)
+    }
+
+    /// Whether the profile's app ID covers `bundle_id`, including wildcard app IDs
+    pub fn matches_bundle_id(&self, bundle_id: &str) -> bool {
+        // Strip the team ID
+        let app_id = match self.application_identifier.split_once('.') {
+            Some((_, app_id)) => app_id,
+            None => &self.application_identifier,
+        };
+        match app_id.strip_suffix('*') {
+            Some(prefix) => bundle_id.starts_with(prefix),
+            None => app_id == bundle_id,
+        }
+    }
+
+    /// Which export method the profile is for. Development profiles are the only ones that
+    /// let debuggers attach, and App Store profiles are the only ones without devices.
+    pub fn export_method(&self) -> ExportMethod {
+        let get_task_allow = ["get-task-allow", "com.apple.security.get-task-allow"]
+            .iter()
+            .any(|key| self.entitlements.get(key).and_then(Value::as_boolean) == Some(true));
+        if self.provisions_all_devices {
+            ExportMethod::Enterprise
+        } else if self.provisioned_devices.is_empty() {
+            ExportMethod::AppStore
+        } else if get_task_allow {
+            ExportMethod::Development
+        } else {
+            ExportMethod::AdHoc
+        }
+    }
+
+    /// Whether apps using this profile can be installed on the device with this UDID
+    pub fn allows_device(&self, udid: &str) -> bool {
+        self.provisions_all_devices
+            || self
+                .provisioned_devices
+                .iter()
+                .any(|device| device.eq_ignore_ascii_case(udid))
+    }
+
+    /// Makes sure the profile can be used for an app with this bundle ID, signed by
+    /// `identity`, on `devices`, at the time `now`
+    pub fn check(
+        &self,
+        bundle_id: &str,
+        identity: &Identity,
+        devices: &[String],
+        now: SystemTime,
+    ) -> Result<(), Error> {
+        let name = &self.name;
+        if !self.matches_bundle_id(bundle_id) {
+            return Err(Error::Signing(format!(
+                "The provisioning profile `{name}` is for the app ID `{}`, which doesn't match the bundle ID `{bundle_id}`",
+                self.application_identifier
+            )));
+        }
+
+        let certificate = identity.certificate().to_der().unwrap_or_default();
+        if !self.developer_certificates.contains(&certificate) {
+            let certificate = identity
+                .common_name()
+                .unwrap_or_else(|| "the signing certificate".into());
+            return Err(Error::Signing(format!(
+                "The provisioning profile `{name}` doesn't include `{certificate}`. Add the certificate to the profile on the Apple Developer website, then download it again."
+            )));
+        }
+
+        if SystemTime::from(self.expiration_date) <= now {
+            return Err(Error::Signing(format!(
+                "The provisioning profile `{name}` expired on {}. Download a new one from the Apple Developer website.",
+                self.expiration_date.to_xml_format()
+            )));
+        }
+
+        for device in devices {
+            if !self.allows_device(device) {
+                return Err(Error::Signing(format!(
+                    "The device `{device}` isn't in the provisioning profile `{name}`. Register it on the Apple Developer website, then download the profile again."
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Gets the content out of a CMS SignedData. Apple's signature isn't checked, since the
+/// device checks it anyway.
+fn signed_content(data: &[u8]) -> Result<Vec<u8>, x509_cert::der::Error> {
+    let content_info = ContentInfo::from_der(data)?;
+    let signed_data: SignedData = content_info.content.decode_as()?;
+    let Some(content) = signed_data.encap_content_info.econtent else {
+        return Ok(Vec::new());
+    };
+    Ok(content.decode_as::<OctetString>()?.into_bytes())
+}
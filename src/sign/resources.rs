@@ -0,0 +1,414 @@
+//! Generates `_CodeSignature/CodeResources`, which seals every file in a bundle that isn't
+//! the main executable. The main executable's CodeDirectory then seals CodeResources.
+//! See: https://developer.apple.com/library/archive/technotes/tn2206/_index.html#//apple_ref/doc/uid/DTS40007919-CH1-TNTAG205
+
+use {
+    super::{blob::sha256, cdhash},
+    crate::error::Error,
+    plist::{Dictionary, Value},
+    regex::Regex,
+    sha1::{Digest, Sha1},
+    std::{
+        collections::BTreeMap,
+        fs,
+        path::{Path, PathBuf},
+    },
+};
+
+/// Where CodeResources goes, relative to the bundle's root (or its Contents folder, on macOS)
+pub const CODE_RESOURCES_PATH: &str = "_CodeSignature/CodeResources";
+/// Where `stapler` puts a macOS app's notarization ticket, relative to its Contents folder.
+/// It's added after signing, so it isn't sealed.
+pub const STAPLED_TICKET_PATH: &str = "CodeResources";
+
+/// Folder extensions that mean a folder is a bundle with its own signature
+pub const NESTED_BUNDLE_EXTENSIONS: &[&str] = &["app", "appex", "framework", "bundle", "xpc"];
+
+/// How a rule treats the files it matches
+#[derive(Clone, Copy, Default)]
+struct Rule {
+    /// The file isn't sealed at all
+    omit: bool,
+    /// The file is sealed, but may be deleted (like localisations)
+    optional: bool,
+    /// The file is code with its own signature, which is sealed instead of its contents
+    nested: bool,
+    /// When several rules match, the heaviest one wins
+    weight: f64,
+}
+
+const fn rule(weight: f64) -> Rule {
+    Rule {
+        omit: false,
+        optional: false,
+        nested: false,
+        weight,
+    }
+}
+const fn omit(weight: f64) -> Rule {
+    Rule {
+        omit: true,
+        ..rule(weight)
+    }
+}
+const fn optional(weight: f64) -> Rule {
+    Rule {
+        optional: true,
+        ..rule(weight)
+    }
+}
+const fn nested(weight: f64) -> Rule {
+    Rule {
+        nested: true,
+        ..rule(weight)
+    }
+}
+
+/// The rules Xcode uses for iOS apps (version 1, for old OSes)
+const IOS_RULES: &[(&str, Rule)] = &[
+    ("^.*", rule(1.0)),
+    ("^.*\\.lproj/", optional(1000.0)),
+    ("^.*\\.lproj/locversion.plist$", omit(1100.0)),
+    ("^Base\\.lproj/", rule(1010.0)),
+    ("^version.plist$", rule(1.0)),
+];
+/// The rules Xcode uses for iOS apps (version 2)
+const IOS_RULES2: &[(&str, Rule)] = &[
+    (".*\\.dSYM($|/)", rule(11.0)),
+    ("^(.*/)?\\.DS_Store$", omit(2000.0)),
+    ("^.*", rule(1.0)),
+    ("^.*\\.lproj/", optional(1000.0)),
+    ("^.*\\.lproj/locversion.plist$", omit(1100.0)),
+    ("^Base\\.lproj/", rule(1010.0)),
+    ("^Info\\.plist$", omit(20.0)),
+    ("^PkgInfo$", omit(20.0)),
+    ("^embedded\\.provisionprofile$", rule(20.0)),
+    ("^version\\.plist$", rule(20.0)),
+];
+/// The rules Xcode uses for macOS apps (version 1, for old OSes). Paths are relative to
+/// the app's Contents folder.
+const MACOS_RULES: &[(&str, Rule)] = &[
+    ("^Resources/", rule(1.0)),
+    ("^Resources/.*\\.lproj/", optional(1000.0)),
+    ("^Resources/.*\\.lproj/locversion.plist$", omit(1100.0)),
+    ("^Resources/Base\\.lproj/", rule(1010.0)),
+    ("^version.plist$", rule(1.0)),
+];
+/// The rules Xcode uses for macOS apps (version 2)
+const MACOS_RULES2: &[(&str, Rule)] = &[
+    (".*\\.dSYM($|/)", rule(11.0)),
+    ("^(.*/)?\\.DS_Store$", omit(2000.0)),
+    (
+        "^(Frameworks|SharedFrameworks|PlugIns|Plug-ins|XPCServices|Helpers|MacOS|Library/(Automator|Spotlight|LoginItems))/",
+        nested(10.0),
+    ),
+    ("^.*", rule(1.0)),
+    ("^Info\\.plist$", omit(20.0)),
+    ("^PkgInfo$", omit(20.0)),
+    ("^Resources/", rule(20.0)),
+    ("^Resources/.*\\.lproj/", optional(1000.0)),
+    ("^Resources/.*\\.lproj/locversion.plist$", omit(1100.0)),
+    ("^Resources/Base\\.lproj/", rule(1010.0)),
+    ("^[^/]+$", nested(10.0)),
+    ("^embedded\\.provisionprofile$", rule(20.0)),
+    ("^version\\.plist$", rule(20.0)),
+];
+
+/// A compiled set of rules
+struct Rules(Vec<(&'static str, Regex, Rule)>);
+impl Rules {
+    fn new(rules: &[(&'static str, Rule)]) -> Self {
+        Self(
+            rules
+                .iter()
+                .map(|(pattern, rule)| (*pattern, Regex::new(pattern).unwrap(), *rule))
+                .collect(),
+        )
+    }
+
+    /// Finds the heaviest rule that matches `path`
+    fn find(&self, path: &str) -> Option<Rule> {
+        let mut best: Option<Rule> = None;
+        for (_, regex, rule) in &self.0 {
+            if regex.is_match(path) && best.is_none_or(|best| rule.weight > best.weight) {
+                best = Some(*rule);
+            }
+        }
+        best
+    }
+
+    /// The rules, as they're written in CodeResources
+    fn to_plist(&self) -> Value {
+        let mut dict = Dictionary::new();
+        for (pattern, _, rule) in &self.0 {
+            // Simple rules are just `true`
+            if !rule.omit && !rule.optional && !rule.nested && rule.weight == 1.0 {
+                dict.insert(pattern.to_string(), true.into());
+                continue;
+            }
+            let mut rule_dict = Dictionary::new();
+            if rule.nested {
+                rule_dict.insert("nested".into(), true.into());
+            }
+            if rule.omit {
+                rule_dict.insert("omit".into(), true.into());
+            }
+            if rule.optional {
+                rule_dict.insert("optional".into(), true.into());
+            }
+            rule_dict.insert("weight".into(), rule.weight.into());
+            dict.insert(pattern.to_string(), rule_dict.into());
+        }
+        sorted(dict).into()
+    }
+}
+
+/// Generates CodeResources for the bundle whose files are in `root` (the .app folder on iOS,
+/// or its Contents folder on macOS). `main_executable` is relative to `root`, and isn't sealed.
+pub fn code_resources(root: &Path, main_executable: &Path, macos: bool) -> Result<Vec<u8>, Error> {
+    let (rules, rules2) = if macos {
+        (Rules::new(MACOS_RULES), Rules::new(MACOS_RULES2))
+    } else {
+        (Rules::new(IOS_RULES), Rules::new(IOS_RULES2))
+    };
+
+    let mut entries = BTreeMap::new();
+    collect(root, root, main_executable, &mut entries)?;
+
+    let mut files = Dictionary::new();
+    let mut files2 = Dictionary::new();
+    for (path, entry) in &entries {
+        let key = path.to_string();
+        if let Some(rule) = rules.find(path).filter(|rule| !rule.omit) {
+            // Version 1 doesn't know about symlinks or nested code, and just hashes the files
+            // inside nested bundles
+            for (path, data) in entry.files(path)? {
+                if rules.find(&path).is_some_and(|rule| !rule.omit) {
+                    files.insert(path, v1_entry(&data, rule.optional));
+                }
+            }
+        }
+        if let Some(rule) = rules2.find(path).filter(|rule| !rule.omit) {
+            files2.insert(key, entry.v2_entry(path, rule)?);
+        }
+    }
+
+    let mut plist = Dictionary::new();
+    plist.insert("files".into(), sorted(files).into());
+    plist.insert("files2".into(), sorted(files2).into());
+    plist.insert("rules".into(), rules.to_plist());
+    plist.insert("rules2".into(), rules2.to_plist());
+
+    let mut xml = Vec::new();
+    if let Err(e) = Value::from(plist).to_writer_xml(&mut xml) {
+        return Err(Error::Signing(format!(
+            "Failed to write the app's CodeResources: {e}"
+        )));
+    }
+    Ok(xml)
+}
+
+/// Generates CodeResources for a bundle, and writes it to `_CodeSignature/CodeResources`.
+/// Returns what was written, so it can be sealed in the main executable's signature.
+pub fn seal(root: &Path, main_executable: &Path, macos: bool) -> Result<Vec<u8>, Error> {
+    let code_resources = code_resources(root, main_executable, macos)?;
+    let path = root.join(CODE_RESOURCES_PATH);
+    let folder = path.parent().unwrap();
+    if let Err(e) = fs::create_dir_all(folder) {
+        return Err(Error::io(
+            "Failed to create the app's _CodeSignature folder",
+            folder,
+            e,
+        ));
+    }
+    if let Err(e) = fs::write(&path, &code_resources) {
+        return Err(Error::io(
+            "Failed to write the app's CodeResources",
+            path,
+            e,
+        ));
+    }
+    Ok(code_resources)
+}
+
+/// Something in the bundle that gets sealed
+enum Entry {
+    File(PathBuf),
+    Symlink(PathBuf),
+    /// A bundle (like a framework) with its own signature
+    NestedBundle(PathBuf),
+}
+impl Entry {
+    /// Every regular file this entry contains, with their paths relative to the bundle's root
+    fn files(&self, path: &str) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        match self {
+            Self::File(file) => Ok(vec![(path.to_string(), read(file)?)]),
+            Self::Symlink(_) => Ok(Vec::new()),
+            Self::NestedBundle(bundle) => {
+                let mut entries = BTreeMap::new();
+                collect(bundle, bundle, Path::new(""), &mut entries)?;
+                let mut files = Vec::new();
+                for (inner_path, entry) in entries {
+                    files.extend(entry.files(&format!("{path}/{inner_path}"))?);
+                }
+                Ok(files)
+            }
+        }
+    }
+
+    /// How this entry is sealed in `files2`
+    fn v2_entry(&self, path: &str, rule: Rule) -> Result<Value, Error> {
+        let mut dict = Dictionary::new();
+        match self {
+            Self::Symlink(link) => {
+                let target = match fs::read_link(link) {
+                    Ok(target) => target,
+                    Err(e) => {
+                        return Err(Error::io("Failed to read a symlink in the app", link, e))
+                    }
+                };
+                dict.insert(
+                    "symlink".into(),
+                    target.to_string_lossy().into_owned().into(),
+                );
+            }
+            Self::NestedBundle(bundle) => {
+                let Some(executable) = bundle_executable(bundle) else {
+                    return Err(Error::Signing(format!(
+                        "Couldn't find the executable for the nested bundle `{path}`"
+                    )));
+                };
+                insert_cdhash(&mut dict, path, &read(&executable)?)?;
+            }
+            Self::File(file) => {
+                let data = read(file)?;
+                if rule.nested && is_macho(&data) {
+                    insert_cdhash(&mut dict, path, &data)?;
+                } else {
+                    dict.insert("hash".into(), Value::Data(Sha1::digest(&data).to_vec()));
+                    dict.insert("hash2".into(), Value::Data(sha256(&data).to_vec()));
+                }
+            }
+        }
+        if rule.optional {
+            dict.insert("optional".into(), true.into());
+        }
+        Ok(sorted(dict).into())
+    }
+}
+
+/// Finds everything to seal in `dir`, keyed by their paths relative to `root`
+fn collect(
+    root: &Path,
+    dir: &Path,
+    main_executable: &Path,
+    entries: &mut BTreeMap<String, Entry>,
+) -> Result<(), Error> {
+    let read_dir = match fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(e) => return Err(Error::io("Failed to read a folder in the app", dir, e)),
+    };
+    for item in read_dir {
+        let item = match item {
+            Ok(item) => item,
+            Err(e) => return Err(Error::io("Failed to read a folder in the app", dir, e)),
+        };
+        let path = item.path();
+        let relative = path.strip_prefix(root).unwrap();
+        // The bundle's own signature (and stapled ticket) isn't sealed, and neither is the
+        // main executable, since it's sealed by its own signature
+        if relative == Path::new(CODE_RESOURCES_PATH).parent().unwrap()
+            || relative == Path::new(STAPLED_TICKET_PATH)
+            || relative == main_executable
+        {
+            continue;
+        }
+        // Paths always use forward slashes, even on Windows
+        let key = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        let file_type = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata.file_type(),
+            Err(e) => return Err(Error::io("Failed to read a file in the app", path, e)),
+        };
+        if file_type.is_symlink() {
+            entries.insert(key, Entry::Symlink(path));
+        } else if file_type.is_dir() {
+            let is_bundle = path
+                .extension()
+                .is_some_and(|ext| NESTED_BUNDLE_EXTENSIONS.contains(&&*ext.to_string_lossy()));
+            if is_bundle {
+                entries.insert(key, Entry::NestedBundle(path));
+            } else {
+                collect(root, &path, main_executable, entries)?;
+            }
+        } else {
+            entries.insert(key, Entry::File(path));
+        }
+    }
+    Ok(())
+}
+
+/// Finds a nested bundle's executable, from its Info.plist
+fn bundle_executable(bundle: &Path) -> Option<PathBuf> {
+    let (info_plist, executable_dir) = [
+        ("Info.plist", ""),
+        ("Contents/Info.plist", "Contents/MacOS"),
+        ("Resources/Info.plist", ""),
+    ]
+    .into_iter()
+    .find(|(info_plist, _)| bundle.join(info_plist).is_file())?;
+    let info_plist = Value::from_file(bundle.join(info_plist)).ok()?;
+    let executable = info_plist
+        .as_dictionary()?
+        .get("CFBundleExecutable")?
+        .as_string()?;
+    Some(bundle.join(executable_dir).join(executable))
+}
+
+/// Seals nested code by its CDHash, with a requirement that only matches that CDHash
+fn insert_cdhash(dict: &mut Dictionary, path: &str, binary: &[u8]) -> Result<(), Error> {
+    let Some(cdhash) = cdhash(binary)? else {
+        return Err(Error::Signing(format!(
+            "`{path}` is nested code, but isn't signed. Nested code has to be signed before the app."
+        )));
+    };
+    let hex: String = cdhash.iter().map(|byte| format!("{byte:02x}")).collect();
+    dict.insert("cdhash".into(), Value::Data(cdhash.to_vec()));
+    dict.insert("requirement".into(), format!("cdhash H\"{hex}\"").into());
+    Ok(())
+}
+
+/// How a file is sealed in `files`
+fn v1_entry(data: &[u8], optional: bool) -> Value {
+    let hash = Value::Data(Sha1::digest(data).to_vec());
+    if !optional {
+        return hash;
+    }
+    let mut dict = Dictionary::new();
+    dict.insert("hash".into(), hash);
+    dict.insert("optional".into(), true.into());
+    dict.into()
+}
+
+fn is_macho(data: &[u8]) -> bool {
+    data.len() >= 4
+        && (super::macho::read_u32_le(data, 0) == super::macho::MH_MAGIC_64
+            || super::macho::read_u32_be(data, 0) == super::macho::FAT_MAGIC)
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, Error> {
+    match fs::read(path) {
+        Ok(data) => Ok(data),
+        Err(e) => Err(Error::io("Failed to read a file in the app", path, e)),
+    }
+}
+
+/// plist dictionaries keep their insertion order, but CodeResources' keys have to be sorted
+fn sorted(dict: Dictionary) -> Dictionary {
+    let sorted: BTreeMap<String, Value> = dict.into_iter().collect();
+    sorted.into_iter().collect()
+}
@@ -0,0 +1,279 @@
+//! Secure timestamps from an RFC 3161 timestamp authority, which notarization requires.
+//! The authority signs a hash of our CMS signature, and its token gets added to the signature
+//! as an unsigned attribute, proving the signature existed before the certificate expired.
+//! See: https://www.rfc-editor.org/rfc/rfc3161
+
+use {
+    crate::error::Error,
+    cms::{
+        content_info::ContentInfo,
+        signed_data::{SignedData, SignerInfos},
+    },
+    sha2::{Digest, Sha256},
+    std::{
+        io::Read,
+        time::{SystemTime, UNIX_EPOCH},
+    },
+    x509_cert::{
+        attr::{Attribute, AttributeValue},
+        der::{
+            asn1::{GeneralizedTime, Int, OctetString, SetOfVec, Uint, Utf8StringRef},
+            oid::ObjectIdentifier,
+            Any, Decode, Encode, Sequence, Tag,
+        },
+        ext::Extensions,
+        spki::AlgorithmIdentifierOwned,
+    },
+};
+
+/// `id-aa-timeStampToken`, the unsigned attribute timestamps go in
+pub const ID_TIMESTAMP_TOKEN: ObjectIdentifier =
+    ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.16.2.14");
+/// `id-ct-TSTInfo`, the content type of a timestamp token
+pub const ID_TST_INFO: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.16.1.4");
+/// `id-sha256`
+const ID_SHA_256: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.1");
+/// Timestamp tokens include the authority's certificates, so they're a few KiB. Apple's are
+/// about 5KiB.
+pub const TIMESTAMP_RESERVE: usize = 12 * 1024;
+
+/// What's being timestamped: a hash of the CMS signature's signature value
+#[derive(Clone, Debug, Eq, PartialEq, Sequence)]
+pub struct MessageImprint {
+    pub hash_algorithm: AlgorithmIdentifierOwned,
+    pub hashed_message: OctetString,
+}
+
+/// The request sent to the timestamp authority
+#[derive(Clone, Debug, Sequence)]
+pub struct TimeStampReq {
+    pub version: u8,
+    pub message_imprint: MessageImprint,
+    #[asn1(optional = "true")]
+    pub req_policy: Option<ObjectIdentifier>,
+    #[asn1(optional = "true")]
+    pub nonce: Option<Uint>,
+    #[asn1(default = "Default::default")]
+    pub cert_req: bool,
+    #[asn1(context_specific = "0", optional = "true", tag_mode = "IMPLICIT")]
+    pub extensions: Option<Extensions>,
+}
+
+/// The authority's response. `time_stamp_token` is a CMS SignedData with a [`TstInfo`] in it.
+#[derive(Clone, Debug, Sequence)]
+pub struct TimeStampResp {
+    pub status: PkiStatusInfo,
+    #[asn1(optional = "true")]
+    pub time_stamp_token: Option<ContentInfo>,
+}
+
+#[derive(Clone, Debug, Sequence)]
+pub struct PkiStatusInfo {
+    /// 0 and 1 mean the timestamp was granted; anything else is a failure
+    pub status: u8,
+    #[asn1(optional = "true")]
+    pub status_string: Option<Vec<Any>>,
+    #[asn1(optional = "true")]
+    pub fail_info: Option<Any>,
+}
+
+/// What the authority signed
+#[derive(Clone, Debug, Sequence)]
+pub struct TstInfo {
+    pub version: u8,
+    pub policy: ObjectIdentifier,
+    pub message_imprint: MessageImprint,
+    pub serial_number: Int,
+    /// A GeneralizedTime. It's kept as-is since authorities often include fractional
+    /// seconds, which strict DER doesn't allow.
+    pub gen_time: Any,
+    #[asn1(optional = "true")]
+    pub accuracy: Option<Accuracy>,
+    #[asn1(default = "Default::default")]
+    pub ordering: bool,
+    #[asn1(optional = "true")]
+    pub nonce: Option<Uint>,
+    #[asn1(context_specific = "0", optional = "true", tag_mode = "EXPLICIT")]
+    pub tsa: Option<Any>,
+    #[asn1(context_specific = "1", optional = "true", tag_mode = "IMPLICIT")]
+    pub extensions: Option<Extensions>,
+}
+impl TstInfo {
+    /// When the authority says it made the timestamp
+    pub fn time(&self) -> Option<SystemTime> {
+        let value = self.gen_time.value();
+        // Drop any fractional seconds, eg `20240102030405.123Z`
+        let whole = match value.iter().position(|byte| *byte == b'.') {
+            Some(dot) => [&value[..dot], b"Z"].concat(),
+            None => value.to_vec(),
+        };
+        let time = Any::new(Tag::GeneralizedTime, whole).ok()?;
+        let time: GeneralizedTime = time.decode_as().ok()?;
+        Some(time.to_system_time())
+    }
+}
+
+/// How far off [`TstInfo::gen_time`] might be
+#[derive(Clone, Debug, Sequence)]
+pub struct Accuracy {
+    #[asn1(optional = "true")]
+    pub seconds: Option<Int>,
+    #[asn1(context_specific = "0", optional = "true", tag_mode = "IMPLICIT")]
+    pub millis: Option<u16>,
+    #[asn1(context_specific = "1", optional = "true", tag_mode = "IMPLICIT")]
+    pub micros: Option<u16>,
+}
+
+/// Gets a timestamp for a CMS signature from the authority at `url`, and adds it to the
+/// signature. Returns the new CMS signature.
+pub fn add_timestamp(cms: &[u8], url: &str) -> Result<Vec<u8>, Error> {
+    let corrupted = |e| Error::Signing(format!("Failed to read the CMS signature ({e})"));
+    let content_info = ContentInfo::from_der(cms).map_err(corrupted)?;
+    let mut signed_data: SignedData = content_info.content.decode_as().map_err(corrupted)?;
+
+    let Some(signature) = signed_data
+        .signer_infos
+        .0
+        .get(0)
+        .map(|signer_info| signer_info.signature.as_bytes().to_vec())
+    else {
+        return Err(Error::Signing(
+            "The CMS signature doesn't have a signer to timestamp".into(),
+        ));
+    };
+    let token = request(url, &signature)?;
+
+    let mut build = || -> Result<Vec<u8>, x509_cert::der::Error> {
+        let mut values = SetOfVec::new();
+        values.insert(AttributeValue::from_der(&token)?)?;
+        let attribute = Attribute {
+            oid: ID_TIMESTAMP_TOKEN,
+            values,
+        };
+        let mut signer_infos = signed_data.signer_infos.0.clone().into_vec();
+        let mut attributes = signer_infos[0].unsigned_attrs.take().unwrap_or_default();
+        attributes.insert(attribute)?;
+        signer_infos[0].unsigned_attrs = Some(attributes);
+
+        signed_data.signer_infos = SignerInfos(SetOfVec::try_from(signer_infos)?);
+        ContentInfo {
+            content_type: content_info.content_type,
+            content: Any::encode_from(&signed_data)?,
+        }
+        .to_der()
+    };
+    build()
+        .map_err(|e| Error::Signing(format!("Failed to add the timestamp to the signature: {e}")))
+}
+
+/// Asks the authority at `url` to timestamp `signature`, returning its token (a DER-encoded
+/// ContentInfo)
+pub fn request(url: &str, signature: &[u8]) -> Result<Vec<u8>, Error> {
+    let message_imprint = imprint(signature);
+    let nonce = nonce();
+    let request = TimeStampReq {
+        version: 1,
+        message_imprint: message_imprint.clone(),
+        req_policy: None,
+        nonce: Some(nonce.clone()),
+        // The authority's certificates have to be in the token for it to be checked later
+        cert_req: true,
+        extensions: None,
+    };
+    let request = match request.to_der() {
+        Ok(request) => request,
+        Err(e) => {
+            return Err(Error::Signing(format!(
+                "Failed to build the timestamp request: {e}"
+            )))
+        }
+    };
+
+    let response = ureq::post(url)
+        .set("Content-Type", "application/timestamp-query")
+        .send_bytes(&request);
+    let response = match response {
+        Ok(response) => response,
+        Err(ureq::Error::Status(code, _)) => {
+            return Err(Error::Signing(format!(
+                "The timestamp server `{url}` responded with HTTP {code}"
+            )))
+        }
+        Err(e) => {
+            return Err(Error::Signing(format!(
+                "Couldn't reach the timestamp server `{url}`. Check your internet connection, or remove `timestamp-url` to sign without a timestamp. ({e})"
+            )))
+        }
+    };
+    let mut body = Vec::new();
+    if let Err(e) = response.into_reader().take(1 << 20).read_to_end(&mut body) {
+        return Err(Error::Signing(format!(
+            "Failed to read the timestamp server's response: {e}"
+        )));
+    }
+
+    let invalid = |reason: String| Error::Signing(format!("The timestamp server `{url}` {reason}"));
+    let response = TimeStampResp::from_der(&body)
+        .map_err(|e| invalid(format!("sent an invalid response ({e})")))?;
+    if response.status.status > 1 {
+        let reason = response
+            .status
+            .status_string
+            .iter()
+            .flatten()
+            .filter_map(|text| Utf8StringRef::try_from(text).ok())
+            .map(|text| text.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        return Err(invalid(format!(
+            "refused to timestamp the signature (status {}): {reason}",
+            response.status.status
+        )));
+    }
+    let Some(token) = response.time_stamp_token else {
+        return Err(invalid("didn't send a timestamp".into()));
+    };
+    let info = tst_info(&token).map_err(|e| invalid(format!("sent an invalid timestamp ({e})")))?;
+    if info.message_imprint != message_imprint {
+        return Err(invalid(
+            "timestamped something other than this signature".into(),
+        ));
+    }
+    if info.nonce != Some(nonce) {
+        return Err(invalid("sent a timestamp for a different request".into()));
+    }
+    token
+        .to_der()
+        .map_err(|e| invalid(format!("sent an invalid timestamp ({e})")))
+}
+
+/// Reads the TSTInfo out of a timestamp token
+pub fn tst_info(token: &ContentInfo) -> Result<TstInfo, x509_cert::der::Error> {
+    let signed_data: SignedData = token.content.decode_as()?;
+    let Some(content) = signed_data.encap_content_info.econtent else {
+        return Err(Tag::OctetString.value_error());
+    };
+    TstInfo::from_der(content.decode_as::<OctetString>()?.as_bytes())
+}
+
+/// The imprint a timestamp for `signature` should have
+pub fn imprint(signature: &[u8]) -> MessageImprint {
+    MessageImprint {
+        hash_algorithm: AlgorithmIdentifierOwned {
+            oid: ID_SHA_256,
+            parameters: None,
+        },
+        hashed_message: OctetString::new(Sha256::digest(signature).to_vec()).unwrap(),
+    }
+}
+
+/// A nonce, so a replayed response can't be passed off as this one. It only has to be
+/// unique, not secret.
+fn nonce() -> Uint {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    let nonce = time ^ (u64::from(std::process::id()) << 32);
+    Uint::new(&nonce.to_be_bytes()).unwrap()
+}
@@ -0,0 +1,628 @@
+//! Checks existing code signatures: the CodeDirectory's hashes, the files its special slots
+//! seal, and the CMS signature & certificates.
+
+use {
+    super::{
+        blob::*,
+        identity::{name_attribute, ID_COMMON_NAME},
+        macho,
+        timestamp::{self, ID_TIMESTAMP_TOKEN},
+    },
+    crate::error::Error,
+    cms::{
+        cert::CertificateChoices,
+        content_info::ContentInfo,
+        signed_data::{SignedData, SignerIdentifier},
+    },
+    rsa::{pkcs1v15, pkcs8::DecodePublicKey, signature::Verifier, RsaPublicKey},
+    serde::Serialize,
+    sha1::Sha1,
+    sha2::{Digest, Sha256, Sha384},
+    std::time::SystemTime,
+    x509_cert::{
+        der::{
+            asn1::{OctetString, UtcTime},
+            oid::ObjectIdentifier,
+            Decode, Encode,
+        },
+        Certificate,
+    },
+};
+
+/// The first alternate CodeDirectory slot. Apple's signatures have a SHA-1 CodeDirectory
+/// in slot 0, and a SHA-256 one here.
+const CSSLOT_ALTERNATE_CODEDIRECTORIES: u32 = 0x1000;
+
+const ID_MESSAGE_DIGEST: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.4");
+const ID_SIGNING_TIME: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.5");
+const ID_SHA_1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.14.3.2.26");
+const ID_SHA_256: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.1");
+
+/// The files outside of the binary that its signature can seal
+#[derive(Default)]
+pub struct SealedFiles<'a> {
+    pub info_plist: Option<&'a [u8]>,
+    pub code_resources: Option<&'a [u8]>,
+}
+
+/// What was found in one slice of a binary's signature
+#[derive(Serialize)]
+pub struct SliceReport {
+    pub architecture: String,
+    /// The signing identifier; for apps, this is the bundle ID
+    pub identifier: Option<String>,
+    pub team_id: Option<String>,
+    pub ad_hoc: bool,
+    /// The slice's CDHash, in hex
+    pub cdhash: Option<String>,
+    /// The hash type of the CodeDirectory the CDHash is from (eg 2 for SHA-256)
+    #[serde(skip)]
+    pub cdhash_type: Option<u8>,
+    pub signing_time: Option<String>,
+    /// Whether the hardened runtime is on
+    pub hardened_runtime: bool,
+    /// When the signature was timestamped by a timestamp authority, if it was
+    pub timestamp: Option<String>,
+    /// The certificate chain, starting with the signing certificate
+    pub certificates: Vec<CertificateReport>,
+    /// Things that make the signature invalid
+    pub problems: Vec<String>,
+    /// Things that might stop the app from installing, but aren't wrong as such
+    pub warnings: Vec<String>,
+    /// The DER-encoded signing certificate
+    #[serde(skip)]
+    pub signing_certificate: Option<Vec<u8>>,
+}
+
+/// A certificate in a signature's certificate chain
+#[derive(Serialize)]
+pub struct CertificateReport {
+    pub common_name: Option<String>,
+    pub subject: String,
+    pub issuer: String,
+    pub not_before: String,
+    pub not_after: String,
+    pub expired: bool,
+}
+
+/// Checks every slice of a binary's signature. `files` are the files the signature should
+/// seal, and `now` is the time certificates have to be valid at.
+pub fn verify(
+    data: &[u8],
+    files: &SealedFiles,
+    now: SystemTime,
+) -> Result<Vec<SliceReport>, Error> {
+    let slices = match macho::parse_fat(data)? {
+        Some(arches) => arches
+            .iter()
+            .map(|arch| &data[arch.offset as usize..(arch.offset + arch.size) as usize])
+            .collect(),
+        None => vec![data],
+    };
+    slices
+        .into_iter()
+        .map(|slice| verify_slice(slice, files, now))
+        .collect()
+}
+
+fn verify_slice(data: &[u8], files: &SealedFiles, now: SystemTime) -> Result<SliceReport, Error> {
+    let parsed = macho::MachO::parse(data)?;
+    let mut report = SliceReport {
+        architecture: architecture_name(parsed.cputype),
+        identifier: None,
+        team_id: None,
+        ad_hoc: false,
+        cdhash: None,
+        cdhash_type: None,
+        signing_time: None,
+        hardened_runtime: false,
+        timestamp: None,
+        certificates: Vec::new(),
+        problems: Vec::new(),
+        warnings: Vec::new(),
+        signing_certificate: None,
+    };
+    let Some(code_signature) = parsed.code_signature else {
+        report.problems.push("The binary isn't signed".into());
+        return Ok(report);
+    };
+
+    let start = code_signature.dataoff as usize;
+    let Some(super_blob) = data.get(start..start + code_signature.datasize as usize) else {
+        report
+            .problems
+            .push("The signature is outside of the binary".into());
+        return Ok(report);
+    };
+    let blobs = match parse_super_blob(super_blob) {
+        Ok(blobs) => blobs,
+        Err(problem) => {
+            report.problems.push(problem);
+            return Ok(report);
+        }
+    };
+    let blob = |slot: u32| {
+        blobs
+            .iter()
+            .find(|(blob_slot, _)| *blob_slot == slot)
+            .map(|(_, blob)| *blob)
+    };
+
+    let Some(code_directory) = blob(CSSLOT_CODEDIRECTORY) else {
+        report
+            .problems
+            .push("The signature doesn't have a CodeDirectory".into());
+        return Ok(report);
+    };
+    let mut code_directories = vec![code_directory];
+    code_directories.extend(
+        (CSSLOT_ALTERNATE_CODEDIRECTORIES..CSSLOT_ALTERNATE_CODEDIRECTORIES + 5).filter_map(blob),
+    );
+
+    // The primary CodeDirectory has the details; the rest should match it
+    let primary = match CodeDirectoryInfo::parse(code_directory) {
+        Ok(primary) => primary,
+        Err(problem) => {
+            report.problems.push(problem);
+            return Ok(report);
+        }
+    };
+    report.identifier = Some(primary.identifier.clone());
+    report.team_id = primary.team_id.clone();
+    report.ad_hoc = primary.flags & CS_ADHOC != 0;
+    report.hardened_runtime = primary.flags & CS_RUNTIME != 0;
+    if let Some(hash) = hash(primary.hash_type, code_directory) {
+        report.cdhash = Some(hex(&hash[..20]));
+        report.cdhash_type = Some(primary.hash_type);
+    }
+
+    for code_directory in code_directories {
+        let info = match CodeDirectoryInfo::parse(code_directory) {
+            Ok(info) => info,
+            Err(problem) => {
+                report.problems.push(problem);
+                continue;
+            }
+        };
+        let Some(hash_name) = hash_name(info.hash_type) else {
+            report.warnings.push(format!(
+                "A CodeDirectory uses an unknown hash type ({}), so it wasn't checked",
+                info.hash_type
+            ));
+            continue;
+        };
+        check_code_directory(
+            code_directory,
+            &info,
+            hash_name,
+            data,
+            &blob,
+            files,
+            &mut report.problems,
+        );
+    }
+
+    // The CMS signature
+    let cms = blob(CSSLOT_SIGNATURESLOT)
+        .map(|wrapper| &wrapper[8..])
+        .unwrap_or_default();
+    if cms.is_empty() {
+        if !report.ad_hoc {
+            report
+                .problems
+                .push("The binary isn't ad-hoc signed, but doesn't have a CMS signature".into());
+        }
+    } else {
+        check_cms(cms, code_directory, now, &mut report);
+    }
+
+    Ok(report)
+}
+
+/// Checks a CodeDirectory's code & special slot hashes
+fn check_code_directory<'a>(
+    code_directory: &[u8],
+    info: &CodeDirectoryInfo,
+    hash_name: &str,
+    data: &[u8],
+    blob: &impl Fn(u32) -> Option<&'a [u8]>,
+    files: &SealedFiles,
+    problems: &mut Vec<String>,
+) {
+    let hash_size = info.hash_size;
+    let slot_hash = |index: isize| {
+        let start = info.hash_offset as isize + index * hash_size as isize;
+        if start < 0 {
+            return None;
+        }
+        code_directory.get(start as usize..start as usize + hash_size)
+    };
+
+    // Code pages
+    let code_limit = info.code_limit as usize;
+    if code_limit > data.len() {
+        problems.push(format!(
+            "The {hash_name} CodeDirectory covers more code than the binary has"
+        ));
+        return;
+    }
+    let page_size = if info.page_size_bits == 0 {
+        code_limit.max(1)
+    } else {
+        1 << info.page_size_bits
+    };
+    let pages = code_limit.div_ceil(page_size);
+    if pages != info.code_slots as usize {
+        problems.push(format!(
+            "The {hash_name} CodeDirectory has {} page hashes, but the code has {pages} pages",
+            info.code_slots
+        ));
+    }
+    let mut bad_pages = Vec::new();
+    for (i, page) in data[..code_limit]
+        .chunks(page_size)
+        .take(info.code_slots as usize)
+        .enumerate()
+    {
+        let expected = hash(info.hash_type, page).unwrap();
+        if slot_hash(i as isize) != Some(&expected[..hash_size]) {
+            bad_pages.push(i);
+        }
+    }
+    if !bad_pages.is_empty() {
+        problems.push(format!(
+            "The binary was changed after it was signed: {} of its pages don't match the {hash_name} CodeDirectory (the first is page {})",
+            bad_pages.len(),
+            bad_pages[0]
+        ));
+    }
+
+    // Special slots
+    let special = |slot: usize| {
+        if slot > info.special_slots as usize {
+            return None;
+        }
+        slot_hash(-(slot as isize)).filter(|hash| hash.iter().any(|byte| *byte != 0))
+    };
+    let mut check_special =
+        |slot: usize, name: &str, contents: Option<&[u8]>| match (special(slot), contents) {
+            (Some(sealed), Some(contents)) => {
+                if sealed != &hash(info.hash_type, contents).unwrap()[..hash_size] {
+                    problems.push(format!(
+                        "{name} was changed after the binary was signed ({hash_name})"
+                    ));
+                }
+            }
+            (Some(_), None) => problems.push(format!(
+                "The signature seals {name}, but it's missing ({hash_name})"
+            )),
+            (None, Some(_)) => problems.push(format!(
+                "{name} exists, but the signature doesn't seal it ({hash_name})"
+            )),
+            (None, None) => (),
+        };
+    check_special(SPECIAL_SLOT_INFO_PLIST, "Info.plist", files.info_plist);
+    check_special(
+        SPECIAL_SLOT_REQUIREMENTS,
+        "The requirements blob",
+        blob(CSSLOT_REQUIREMENTS),
+    );
+    check_special(
+        SPECIAL_SLOT_RESOURCES,
+        "_CodeSignature/CodeResources",
+        files.code_resources,
+    );
+    check_special(
+        SPECIAL_SLOT_ENTITLEMENTS,
+        "The entitlements blob",
+        blob(CSSLOT_ENTITLEMENTS),
+    );
+    check_special(
+        SPECIAL_SLOT_DER_ENTITLEMENTS,
+        "The DER entitlements blob",
+        blob(CSSLOT_DER_ENTITLEMENTS),
+    );
+}
+
+/// Checks the CMS signature over the primary CodeDirectory, and reports its certificates
+fn check_cms(cms: &[u8], code_directory: &[u8], now: SystemTime, report: &mut SliceReport) {
+    let signed_data =
+        match ContentInfo::from_der(cms).and_then(|info| info.content.decode_as::<SignedData>()) {
+            Ok(signed_data) => signed_data,
+            Err(e) => {
+                report
+                    .problems
+                    .push(format!("The CMS signature can't be read ({e})"));
+                return;
+            }
+        };
+    let certificates: Vec<Certificate> = signed_data
+        .certificates
+        .iter()
+        .flat_map(|set| set.0.iter())
+        .filter_map(|choice| match choice {
+            CertificateChoices::Certificate(certificate) => Some(certificate.clone()),
+            _ => None,
+        })
+        .collect();
+    let Some(signer) = signed_data.signer_infos.0.get(0) else {
+        report
+            .problems
+            .push("The CMS signature doesn't have a signer".into());
+        return;
+    };
+    let SignerIdentifier::IssuerAndSerialNumber(ref signer_id) = signer.sid else {
+        report
+            .problems
+            .push("The CMS signature's signer isn't identified by its certificate".into());
+        return;
+    };
+    let Some(certificate) = certificates.iter().find(|certificate| {
+        certificate.tbs_certificate.issuer == signer_id.issuer
+            && certificate.tbs_certificate.serial_number == signer_id.serial_number
+    }) else {
+        report
+            .problems
+            .push("The CMS signature doesn't include the signing certificate".into());
+        return;
+    };
+    report.signing_certificate = certificate.to_der().ok();
+
+    // The certificate chain, following each certificate's issuer
+    let mut chain = vec![certificate];
+    while let Some(issuer) = certificates.iter().find(|issuer| {
+        let last = chain.last().unwrap();
+        issuer.tbs_certificate.subject == last.tbs_certificate.issuer && !chain.contains(issuer)
+    }) {
+        chain.push(issuer);
+    }
+    for certificate in &chain {
+        let tbs = &certificate.tbs_certificate;
+        let not_before = tbs.validity.not_before.to_system_time();
+        let not_after = tbs.validity.not_after.to_system_time();
+        let expired = not_after < now;
+        let subject = tbs.subject.to_string();
+        if expired {
+            report.problems.push(format!(
+                "The certificate `{subject}` expired on {}",
+                format_time(not_after)
+            ));
+        } else if not_before > now {
+            report.problems.push(format!(
+                "The certificate `{subject}` isn't valid until {}",
+                format_time(not_before)
+            ));
+        }
+        report.certificates.push(CertificateReport {
+            common_name: name_attribute(&tbs.subject, ID_COMMON_NAME),
+            subject,
+            issuer: tbs.issuer.to_string(),
+            not_before: format_time(not_before),
+            not_after: format_time(not_after),
+            expired,
+        });
+    }
+    let last = chain.last().unwrap();
+    if last.tbs_certificate.subject != last.tbs_certificate.issuer {
+        report.warnings.push(format!(
+            "The certificate chain stops at `{}`, before a root certificate. Devices might not trust it; add the missing certificates with `certificate-chain`.",
+            last.tbs_certificate.subject
+        ));
+    }
+
+    // The signed attributes
+    let Some(ref attributes) = signer.signed_attrs else {
+        report
+            .problems
+            .push("The CMS signature doesn't have any signed attributes".into());
+        return;
+    };
+    let attribute = |oid: ObjectIdentifier| {
+        attributes
+            .iter()
+            .find(|attribute| attribute.oid == oid)
+            .and_then(|attribute| attribute.values.get(0))
+            .and_then(|value| value.to_der().ok())
+    };
+    if let Some(time) = attribute(ID_SIGNING_TIME).and_then(|time| UtcTime::from_der(&time).ok()) {
+        report.signing_time = Some(format_time(time.to_system_time()));
+    }
+    if let Some(token) = signer
+        .unsigned_attrs
+        .iter()
+        .flat_map(|attributes| attributes.iter())
+        .find(|attribute| attribute.oid == ID_TIMESTAMP_TOKEN)
+        .and_then(|attribute| attribute.values.get(0))
+    {
+        let info = token
+            .decode_as::<ContentInfo>()
+            .and_then(|token| timestamp::tst_info(&token));
+        match info {
+            Ok(info) if info.message_imprint != timestamp::imprint(signer.signature.as_bytes()) => {
+                report
+                    .problems
+                    .push("The signature's timestamp is for a different signature".into())
+            }
+            Ok(info) => report.timestamp = info.time().map(format_time),
+            Err(e) => report
+                .problems
+                .push(format!("The signature's timestamp can't be read ({e})")),
+        }
+    }
+
+    let digest_algorithm = signer.digest_alg.oid;
+    let expected_digest = if digest_algorithm == ID_SHA_256 {
+        Sha256::digest(code_directory).to_vec()
+    } else if digest_algorithm == ID_SHA_1 {
+        Sha1::digest(code_directory).to_vec()
+    } else {
+        report.warnings.push(format!(
+            "The CMS signature uses an unknown digest algorithm ({digest_algorithm}), so it wasn't checked"
+        ));
+        return;
+    };
+    let digest =
+        attribute(ID_MESSAGE_DIGEST).and_then(|digest| OctetString::from_der(&digest).ok());
+    if digest.as_ref().map(OctetString::as_bytes) != Some(&expected_digest[..]) {
+        report
+            .problems
+            .push("The CMS signature isn't for this CodeDirectory".into());
+        return;
+    }
+
+    // Finally, the signature itself
+    let verified = (|| {
+        let public_key = certificate
+            .tbs_certificate
+            .subject_public_key_info
+            .to_der()
+            .ok()?;
+        let public_key = RsaPublicKey::from_public_key_der(&public_key).ok()?;
+        let signature = pkcs1v15::Signature::try_from(signer.signature.as_bytes()).ok()?;
+        let signed = attributes.to_der().ok()?;
+        let result = if digest_algorithm == ID_SHA_256 {
+            pkcs1v15::VerifyingKey::<Sha256>::new(public_key).verify(&signed, &signature)
+        } else {
+            pkcs1v15::VerifyingKey::<Sha1>::new(public_key).verify(&signed, &signature)
+        };
+        Some(result.is_ok())
+    })();
+    match verified {
+        Some(true) => (),
+        Some(false) => report
+            .problems
+            .push("The CMS signature doesn't match the signing certificate".into()),
+        None => report.warnings.push(
+            "The CMS signature couldn't be checked; only RSA certificates are supported".into(),
+        ),
+    }
+}
+
+/// The parts of a CodeDirectory's header that get checked
+struct CodeDirectoryInfo {
+    flags: u32,
+    hash_offset: u32,
+    special_slots: u32,
+    code_slots: u32,
+    code_limit: u32,
+    hash_size: usize,
+    hash_type: u8,
+    page_size_bits: u8,
+    identifier: String,
+    team_id: Option<String>,
+}
+impl CodeDirectoryInfo {
+    fn parse(blob: &[u8]) -> Result<Self, String> {
+        if blob.len() < 44 || macho::read_u32_be(blob, 0) != CSMAGIC_CODEDIRECTORY {
+            return Err("A CodeDirectory is corrupted".into());
+        }
+        let version = macho::read_u32_be(blob, 8);
+        let string_at = |offset: usize| {
+            let bytes = blob.get(offset..)?;
+            let end = bytes.iter().position(|byte| *byte == 0)?;
+            Some(String::from_utf8_lossy(&bytes[..end]).into_owned())
+        };
+        let team_offset = if version >= 0x20200 && blob.len() >= 52 {
+            macho::read_u32_be(blob, 48) as usize
+        } else {
+            0
+        };
+        // Hashes can be truncated, but not lengthened
+        let (hash_size, hash_type, page_size_bits) = (blob[36] as usize, blob[37], blob[39]);
+        if let Some(digest) = hash(hash_type, &[]) {
+            if hash_size == 0 || hash_size > digest.len() {
+                return Err(format!(
+                    "A CodeDirectory is corrupted: its hash size ({hash_size}) doesn't fit its hash type ({} bytes)",
+                    digest.len()
+                ));
+            }
+        }
+        // Pages are 4 or 16 KiB in practice; anything that doesn't fit in a u32 is nonsense
+        if page_size_bits >= 32 {
+            return Err(format!(
+                "A CodeDirectory is corrupted: its page size (2^{page_size_bits} bytes) is too big"
+            ));
+        }
+        Ok(Self {
+            flags: macho::read_u32_be(blob, 12),
+            hash_offset: macho::read_u32_be(blob, 16),
+            special_slots: macho::read_u32_be(blob, 24),
+            code_slots: macho::read_u32_be(blob, 28),
+            code_limit: macho::read_u32_be(blob, 32),
+            hash_size,
+            hash_type,
+            page_size_bits,
+            identifier: string_at(macho::read_u32_be(blob, 20) as usize)
+                .ok_or("A CodeDirectory's identifier is corrupted")?,
+            team_id: if team_offset == 0 {
+                None
+            } else {
+                string_at(team_offset)
+            },
+        })
+    }
+}
+
+/// Splits a SuperBlob into its blobs, with the slots they're in
+fn parse_super_blob(super_blob: &[u8]) -> Result<Vec<(u32, &[u8])>, String> {
+    let corrupted = || "The signature is corrupted".to_string();
+    if super_blob.len() < 12 || macho::read_u32_be(super_blob, 0) != CSMAGIC_EMBEDDED_SIGNATURE {
+        return Err(corrupted());
+    }
+    let count = macho::read_u32_be(super_blob, 8) as usize;
+    let mut blobs = Vec::new();
+    for i in 0..count {
+        let index = 12 + i * 8;
+        if index + 8 > super_blob.len() {
+            return Err(corrupted());
+        }
+        let slot = macho::read_u32_be(super_blob, index);
+        let offset = macho::read_u32_be(super_blob, index + 4) as usize;
+        let blob = super_blob
+            .get(offset..offset + 8)
+            .map(|_| macho::read_u32_be(super_blob, offset + 4) as usize)
+            .and_then(|length| super_blob.get(offset..offset + length))
+            .filter(|blob| blob.len() >= 8)
+            .ok_or_else(corrupted)?;
+        blobs.push((slot, blob));
+    }
+    Ok(blobs)
+}
+
+/// Hashes `data` with a CodeDirectory hash type. The result may need truncating to the
+/// CodeDirectory's hash size.
+fn hash(hash_type: u8, data: &[u8]) -> Option<Vec<u8>> {
+    match hash_type {
+        1 => Some(Sha1::digest(data).to_vec()),
+        2 | 3 => Some(Sha256::digest(data).to_vec()),
+        4 => Some(Sha384::digest(data).to_vec()),
+        _ => None,
+    }
+}
+
+fn hash_name(hash_type: u8) -> Option<&'static str> {
+    match hash_type {
+        1 => Some("SHA-1"),
+        2 => Some("SHA-256"),
+        3 => Some("truncated SHA-256"),
+        4 => Some("SHA-384"),
+        _ => None,
+    }
+}
+
+fn architecture_name(cputype: u32) -> String {
+    match cputype {
+        macho::CPU_TYPE_ARM64 => "arm64".into(),
+        0x01000007 => "x86_64".into(),
+        cputype => format!("cputype {cputype:#x}"),
+    }
+}
+
+/// Formats a time like plists do, eg `2023-11-14T22:13:20Z`
+pub fn format_time(time: SystemTime) -> String {
+    plist::Date::from(time).to_xml_format()
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
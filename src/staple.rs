@@ -0,0 +1,76 @@
+use clap::Args;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{
+    connect::ticket,
+    error::Error,
+    resign::{bundle_executable, read_info_plist},
+    sign::{
+        resources::STAPLED_TICKET_PATH,
+        verify::{self as verify_code, SealedFiles},
+    },
+};
+
+#[derive(Args)]
+pub struct StapleArgs {
+    /// The notarized macOS app to staple
+    pub app: PathBuf,
+    /// The ticket service's URL, eg to test against a mock server
+    #[arg(long, default_value = ticket::TICKET_URL)]
+    pub ticket_url: String,
+}
+
+pub fn staple(args: StapleArgs) -> Result<(), Error> {
+    staple_app(&args.app, &args.ticket_url).map(|_| ())
+}
+
+/// Downloads a notarized app's ticket from the ticket service at `ticket_url`, and staples it
+/// to the app, so Gatekeeper can check it offline. Returns where the ticket was written.
+pub fn staple_app(app: &Path, ticket_url: &str) -> Result<PathBuf, Error> {
+    println!("Stapling `{}`...", app.display());
+    let contents = app.join("Contents");
+    if !contents.join("Info.plist").is_file() {
+        return Err(Error::Config(format!(
+            "`{}` isn't a macOS app. Only macOS apps can be notarized and stapled.",
+            app.display()
+        )));
+    }
+
+    // The ticket's looked up by the main executable's CDHash. Every slice's CDHash is in the
+    // ticket, so any of them will do.
+    let info_plist = read_info_plist(&contents)?;
+    let executable = bundle_executable(&contents.join("MacOS"), &info_plist)?;
+    let binary = match fs::read(&executable) {
+        Ok(binary) => binary,
+        Err(e) => {
+            return Err(Error::io(
+                "Failed to read the app's executable",
+                executable,
+                e,
+            ))
+        }
+    };
+    let slices = verify_code::verify(&binary, &SealedFiles::default(), SystemTime::now())?;
+    let cdhash = slices
+        .iter()
+        .find_map(|slice| Some((slice.cdhash_type?, slice.cdhash.clone()?)));
+    let Some((cdhash_type, cdhash)) = cdhash else {
+        return Err(Error::Signing(format!(
+            "`{}` isn't signed, so it can't have been notarized",
+            executable.display()
+        )));
+    };
+    println!("|- Fetching the ticket for `{cdhash}`...");
+    let ticket = ticket::fetch(ticket_url, cdhash_type, &cdhash)?;
+
+    let path = contents.join(STAPLED_TICKET_PATH);
+    if let Err(e) = fs::write(&path, ticket) {
+        return Err(Error::io("Failed to staple the ticket", path, e));
+    }
+    println!("|- Stapled the ticket to `{}`", path.display());
+    Ok(path)
+}
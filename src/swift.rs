@@ -1,8 +1,8 @@
+#[cfg(feature = "swift-bridge")]
+use crate::context::Architecture;
 use {
-    crate::context::Ctx,
-    crate::context::{Architecture, Platform},
+    crate::context::Ctx, crate::context::Platform, crate::error::Error, crate::runner::ToolRunner,
     std::path::PathBuf,
-    std::process::Command,
 };
 
 pub struct SwiftCtx {
@@ -10,68 +10,58 @@ pub struct SwiftCtx {
     pub library_name: String,
     /// The path to the Swift library to statically compile
     pub library_path: PathBuf,
-    /// The path to the Swift library's source code
-    pub source_path: PathBuf,
     /// The path to the Swift library's build files
     pub build_path: PathBuf,
     /// The path to swift-bridge's `generated` folder
+    #[cfg_attr(not(feature = "swift-bridge"), allow(dead_code))]
     pub generated_code_path: PathBuf,
     /// The path to swift-bridge's `bridging-header.h` file
     pub bridging_header_path: PathBuf,
     /// All of the "bridges" to target with swift-bridge
+    #[cfg_attr(not(feature = "swift-bridge"), allow(dead_code))]
     pub bridges: Vec<PathBuf>,
 }
 impl SwiftCtx {
-    pub fn new(ctx: &Ctx, release_mode: bool) -> Result<Self, String> {
-        if let Some(cfg) = &ctx.cfg {
-            if let Some(toml::Value::Array(bridges)) = cfg.get("swift-bridges") {
-                if let Some(toml::Value::String(swift_library_path)) = cfg.get("swift-library") {
-                    // Convert the toml values to their correct Rust types
-                    let bridges: Vec<PathBuf> = bridges
-                        .iter()
-                        .map(|val| {
-                            ctx.root_dir.join(
-                                val.to_string()
-                                    .strip_prefix('"')
-                                    .unwrap()
-                                    .strip_suffix('"')
-                                    .unwrap(),
-                            )
-                        })
-                        .collect();
-                    let library_path = ctx.root_dir.join(swift_library_path);
-                    let library_name = library_path
-                        .file_name()
-                        .unwrap()
-                        .to_str()
-                        .unwrap()
-                        .to_string();
-                    let source_path = library_path.join("Sources").join(&library_name);
-                    let build_path = library_path.join(".build").join(if release_mode {
-                        "release"
-                    } else {
-                        "debug"
-                    });
-                    let generated_code_path = source_path.join("generated");
-                    let bridging_header_path = source_path.join("bridging-header.h");
-
-                    Ok(Self {
-                        library_name,
-                        library_path,
-                        source_path,
-                        build_path,
-                        generated_code_path,
-                        bridging_header_path,
-                        bridges,
-                    })
+    pub fn new(ctx: &Ctx, release_mode: bool) -> Result<Self, Error> {
+        if let Some(bridges) = &ctx.cfg.swift_bridges {
+            if let Some(swift_library_path) = &ctx.cfg.swift_library {
+                // Make the configured paths relative to the project root
+                let bridges: Vec<PathBuf> = bridges
+                    .iter()
+                    .map(|bridge| ctx.root_dir.join(bridge))
+                    .collect();
+                let library_path = ctx.root_dir.join(swift_library_path);
+                let library_name = match library_path.file_name().and_then(|name| name.to_str()) {
+                    Some(name) => name.to_string(),
+                    None => {
+                        return Err(Error::Config(format!(
+                            "Invalid `swift-library` setting: `{}` isn't a folder name!",
+                            swift_library_path.display()
+                        )))
+                    }
+                };
+                let source_path = library_path.join("Sources").join(&library_name);
+                let build_path = library_path.join(".build").join(if release_mode {
+                    "release"
                 } else {
-                    Err("No `swift-library` setting set!".to_string())
-                }
+                    "debug"
+                });
+                let generated_code_path = source_path.join("generated");
+                let bridging_header_path = source_path.join("bridging-header.h");
+
+                Ok(Self {
+                    library_name,
+                    library_path,
+                    build_path,
+                    generated_code_path,
+                    bridging_header_path,
+                    bridges,
+                })
             } else {
-                Err("No `swift-bridges` setting set!".to_string())
+                Err(Error::Config("No `swift-library` setting set!".to_string()))
             }
         } else {
-            Err("Failed to get configuration!".to_string())
+            Err(Error::Config("No `swift-bridges` setting set!".to_string()))
         }
     }
 }
@@ -133,27 +123,34 @@ pub fn static_swiftc_args(swift_ctx: &SwiftCtx, release_mode: bool) -> Vec<Strin
     swift_args
 }
 
-/// Find the path to the macOS or iOS SDK
-pub fn get_sdk(platform: Platform) -> String {
+/// Find the path to the macOS or iOS SDK, or the iOS Simulator's if `simulator`
+pub fn get_sdk(
+    runner: &dyn ToolRunner,
+    platform: Platform,
+    simulator: bool,
+) -> Result<String, Error> {
     let sdk = match platform {
         Platform::macOS => "macosx",
+        Platform::iOS if simulator => "iphonesimulator",
         Platform::iOS => "iphoneos",
     };
-    let output = Command::new("xcrun")
-        .arg("--sdk")
-        .arg(sdk)
-        .arg("--show-sdk-path")
-        .output()
-        .unwrap();
-    String::from_utf8(output.stdout.as_slice().into())
-        .unwrap()
-        .trim()
-        .to_string()
+    match runner.run("xcrun", &["--sdk", sdk, "--show-sdk-path"]) {
+        Ok(output) if output.success => Ok(output.stdout.trim().to_string()),
+        Ok(output) => Err(Error::ToolchainMissing {
+            message: format!(
+                "Failed to find the `{sdk}` SDK! xcrun said: {}",
+                output.stderr.trim()
+            ),
+            source: None,
+        }),
+        Err(e) => Err(Error::tool_missing("xcrun", e)),
+    }
 }
 
 /// Get Swift's target-triple for a platform & architecture
 ///
 /// Swift has different target-triples than Rust does. This function gets Swift's.
+#[cfg(feature = "swift-bridge")]
 pub fn get_target_triple(platform: Platform, architecture: Architecture) -> String {
     String::from(match architecture {
         Architecture::x86_64 => "x86_64",
@@ -0,0 +1,292 @@
+//! Finding and reading the debug info crash reports are symbolicated with. Binaries and dSYMs
+//! are matched to the images in a crash report by their LC_UUID, which the linker makes when
+//! it links the binary, and `dsymutil` copies into its dSYM.
+
+use {
+    crate::error::Error,
+    addr2line::Context,
+    gimli::{EndianRcSlice, RunTimeEndian},
+    object::{
+        read::macho::{FatArch, MachOFatFile32, MachOFatFile64},
+        FileKind, Object, ObjectSection, ObjectSegment,
+    },
+    std::{
+        collections::BTreeMap,
+        fs::{self, File},
+        io::{self, Read},
+        path::{Path, PathBuf},
+        rc::Rc,
+    },
+};
+
+/// Folders in `target` that never have binaries in them, but can have a lot of other files
+const SKIPPED_DIRS: &[&str] = &["incremental", ".fingerprint"];
+
+type Dwarf = Context<EndianRcSlice<RunTimeEndian>>;
+
+/// Where a Mach-O with a particular UUID is
+#[derive(Clone)]
+pub struct Candidate {
+    pub path: PathBuf,
+    /// Whether it has DWARF, rather than just a symbol table
+    pub has_dwarf: bool,
+}
+
+/// Finds every Mach-O binary & dSYM in `paths` (which can be files or folders), by their
+/// uppercase UUIDs. Ones with DWARF come first.
+pub fn find(paths: &[PathBuf]) -> BTreeMap<String, Vec<Candidate>> {
+    let mut found: BTreeMap<String, Vec<Candidate>> = BTreeMap::new();
+    let mut pending = paths.to_vec();
+    while let Some(path) = pending.pop() {
+        if path.is_dir() {
+            let skipped = path
+                .file_name()
+                .is_some_and(|name| SKIPPED_DIRS.iter().any(|skipped| name == *skipped));
+            if let (false, Ok(entries)) = (skipped, fs::read_dir(&path)) {
+                pending.extend(entries.flatten().map(|entry| entry.path()));
+            }
+            continue;
+        }
+        // Most files in `target` aren't Mach-Os, so only ones that start like one are read
+        if !is_mach_o(&path) {
+            continue;
+        }
+        let Ok(data) = fs::read(&path) else { continue };
+        for (uuid, has_dwarf) in uuids(&data) {
+            let candidates = found.entry(uuid).or_default();
+            if !candidates.iter().any(|candidate| candidate.path == path) {
+                candidates.push(Candidate {
+                    path: path.clone(),
+                    has_dwarf,
+                });
+            }
+        }
+    }
+    for candidates in found.values_mut() {
+        candidates.sort_by_key(|candidate| !candidate.has_dwarf);
+    }
+    found
+}
+
+fn is_mach_o(path: &Path) -> bool {
+    let mut magic = [0; 4];
+    let read = File::open(path).and_then(|mut file| file.read_exact(&mut magic));
+    read.is_ok()
+        && matches!(
+            magic,
+            [0xCF, 0xFA, 0xED, 0xFE] | [0xCA, 0xFE, 0xBA, 0xBE] | [0xCA, 0xFE, 0xBA, 0xBF]
+        )
+}
+
+/// Each slice of a (maybe universal) Mach-O
+fn slices(data: &[u8]) -> Vec<&[u8]> {
+    match FileKind::parse(data) {
+        Ok(FileKind::MachO64) => vec![data],
+        Ok(FileKind::MachOFat32) => match MachOFatFile32::parse(data) {
+            Ok(fat) => fat
+                .arches()
+                .iter()
+                .filter_map(|arch| arch.data(data).ok())
+                .collect(),
+            Err(_) => Vec::new(),
+        },
+        Ok(FileKind::MachOFat64) => match MachOFatFile64::parse(data) {
+            Ok(fat) => fat
+                .arches()
+                .iter()
+                .filter_map(|arch| arch.data(data).ok())
+                .collect(),
+            Err(_) => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+/// The UUIDs of a Mach-O's slices, and whether they have DWARF
+fn uuids(data: &[u8]) -> Vec<(String, bool)> {
+    slices(data)
+        .into_iter()
+        .filter_map(|slice| {
+            let file = object::File::parse(slice).ok()?;
+            let uuid = file.mach_uuid().ok()??;
+            let has_dwarf = file.section_by_name(".debug_info").is_some();
+            Some((format_uuid(&uuid), has_dwarf))
+        })
+        .collect()
+}
+
+/// The UUID of a Mach-O, or of the first slice of a universal one
+pub fn read_uuid(path: &Path) -> Option<String> {
+    let data = fs::read(path).ok()?;
+    uuids(&data).into_iter().next().map(|(uuid, _)| uuid)
+}
+
+/// Formats a UUID like `4C4C44BC-5555-3144-A1D2-C94145896540`
+fn format_uuid(uuid: &[u8; 16]) -> String {
+    let hex: String = uuid.iter().map(|byte| format!("{byte:02X}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// One function a frame's in. Inlined functions are in the function they were inlined into,
+/// so a frame can be in more than one.
+pub struct Symbol {
+    pub name: String,
+    /// How far into the function the address is, if it's known
+    pub offset: Option<u64>,
+    /// The file & line the address came from
+    pub location: Option<(String, u32)>,
+    /// Whether the function was inlined into the next one
+    pub inlined: bool,
+}
+
+/// A binary or dSYM's symbols and DWARF, for looking up addresses in it
+pub struct DebugInfo {
+    pub path: PathBuf,
+    /// The address __TEXT is linked at, which image offsets are relative to
+    text_address: u64,
+    /// The symbol table's functions, by address
+    symbols: Vec<(u64, String)>,
+    dwarf: Option<Dwarf>,
+}
+impl DebugInfo {
+    /// Loads the slice of the Mach-O at `path` with the given UUID
+    pub fn load(path: &Path, uuid: &str) -> Result<Self, Error> {
+        let invalid = |message: String| {
+            Error::io(
+                "Failed to read debug info",
+                path,
+                io::Error::new(io::ErrorKind::InvalidData, message),
+            )
+        };
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) => return Err(Error::io("Failed to read debug info", path, e)),
+        };
+        let file = slices(&data)
+            .into_iter()
+            .filter_map(|slice| object::File::parse(slice).ok())
+            .find(|file| {
+                file.mach_uuid()
+                    .ok()
+                    .flatten()
+                    .is_some_and(|slice_uuid| format_uuid(&slice_uuid) == uuid)
+            })
+            .ok_or_else(|| invalid(format!("it doesn't have a slice with the UUID {uuid}")))?;
+
+        let text_address = file
+            .segments()
+            .find(|segment| segment.name().ok().flatten() == Some("__TEXT"))
+            .map_or(0, |segment| segment.address());
+        // Mach-O symbols start with an extra underscore, which crash reports and DWARF leave out
+        let mut symbols: Vec<_> = file
+            .symbol_map()
+            .symbols()
+            .iter()
+            .map(|symbol| {
+                let name = symbol.name();
+                (
+                    symbol.address(),
+                    name.strip_prefix('_').unwrap_or(name).to_owned(),
+                )
+            })
+            .collect();
+        symbols.sort();
+
+        let dwarf = match file.section_by_name(".debug_info") {
+            Some(_) => {
+                let endian = match file.is_little_endian() {
+                    true => RunTimeEndian::Little,
+                    false => RunTimeEndian::Big,
+                };
+                let sections = gimli::Dwarf::load(|id| -> Result<_, gimli::Error> {
+                    let data = file
+                        .section_by_name(id.name())
+                        .and_then(|section| section.data().ok())
+                        .unwrap_or_default();
+                    Ok(EndianRcSlice::new(Rc::from(data), endian))
+                })
+                .map_err(|e| invalid(e.to_string()))?;
+                Some(Context::from_dwarf(sections).map_err(|e| invalid(e.to_string()))?)
+            }
+            None => None,
+        };
+        Ok(Self {
+            path: path.to_owned(),
+            text_address,
+            symbols,
+            dwarf,
+        })
+    }
+
+    /// Finds the functions a frame is in, innermost first. Every frame but the first in a
+    /// backtrace is a return address, after the call it was making, so those are looked up
+    /// a byte earlier, in the call.
+    pub fn lookup(&self, image_offset: u64, is_return_address: bool) -> Vec<Symbol> {
+        let address = self.text_address + image_offset;
+        let lookup_address = match is_return_address {
+            true => address.saturating_sub(1),
+            false => address,
+        };
+        // The symbol table has where functions start; DWARF doesn't, for inlined ones
+        let symbol = self
+            .symbols
+            .partition_point(|(start, _)| *start <= lookup_address)
+            .checked_sub(1)
+            .map(|i| &self.symbols[i]);
+
+        let mut symbols = Vec::new();
+        if let Some(dwarf) = &self.dwarf {
+            if let Ok(mut frames) = dwarf.find_frames(lookup_address).skip_all_loads() {
+                while let Ok(Some(frame)) = frames.next() {
+                    let Some(name) = frame
+                        .function
+                        .and_then(|function| function.raw_name().ok().map(|name| name.to_string()))
+                    else {
+                        continue;
+                    };
+                    let location = frame
+                        .location
+                        .and_then(|location| Some((location.file?.to_owned(), location.line?)));
+                    symbols.push(Symbol {
+                        name: demangle(&name),
+                        offset: None,
+                        location,
+                        inlined: true,
+                    });
+                }
+            }
+        }
+        match (symbols.last_mut(), symbol) {
+            // The outermost function is the one the symbol table has
+            (Some(outermost), Some((start, _))) => {
+                outermost.inlined = false;
+                outermost.offset = Some(address - start);
+            }
+            (Some(outermost), None) => outermost.inlined = false,
+            (None, Some((start, name))) => symbols.push(Symbol {
+                name: demangle(name),
+                offset: Some(address - start),
+                location: None,
+                inlined: false,
+            }),
+            (None, None) => {}
+        }
+        symbols
+    }
+}
+
+/// Demangles a Rust symbol (in the v0 or legacy scheme), without its hash. Other symbols are
+/// left as they are.
+pub fn demangle(symbol: &str) -> String {
+    match rustc_demangle::try_demangle(symbol) {
+        Ok(demangled) => format!("{demangled:#}"),
+        Err(_) => symbol.to_owned(),
+    }
+}
@@ -0,0 +1,120 @@
+//! Crash reports in the `.ips` format iOS 15 and macOS 12 (and later) write. They're two JSON
+//! documents: a one-line header about the app and OS, then the report itself, with every
+//! thread's backtrace as offsets into the binary images that were loaded.
+
+use {
+    crate::error::Error,
+    serde::Deserialize,
+    std::{io, path::Path},
+};
+
+/// The first line of a crash report
+#[derive(Deserialize)]
+pub struct Header {
+    pub app_name: Option<String>,
+    pub app_version: Option<String>,
+    pub build_version: Option<String>,
+    #[serde(rename = "bundleID")]
+    pub bundle_id: Option<String>,
+    pub os_version: Option<String>,
+    pub timestamp: Option<String>,
+    /// What kind of report it is. Crashes are 309.
+    pub bug_type: Option<String>,
+}
+
+/// Everything after the first line of a crash report
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Body {
+    pub proc_name: Option<String>,
+    pub exception: Option<Exception>,
+    pub termination: Option<Termination>,
+    #[serde(default)]
+    pub threads: Vec<Thread>,
+    /// Where an Objective-C exception was thrown from, if one caused the crash
+    pub last_exception_backtrace: Option<Vec<Frame>>,
+    #[serde(default)]
+    pub used_images: Vec<Image>,
+}
+
+#[derive(Deserialize)]
+pub struct Exception {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub signal: Option<String>,
+    pub subtype: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct Termination {
+    pub indicator: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct Thread {
+    /// Whether this thread crashed
+    #[serde(default)]
+    pub triggered: bool,
+    pub name: Option<String>,
+    pub queue: Option<String>,
+    #[serde(default)]
+    pub frames: Vec<Frame>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Frame {
+    /// Which of [`Body::used_images`] the frame's in
+    pub image_index: usize,
+    /// How far into the image the frame's address is
+    pub image_offset: u64,
+    /// The device's guess at the frame's function, from the image's symbol table
+    pub symbol: Option<String>,
+    /// How far into `symbol` the frame's address is
+    pub symbol_location: Option<u64>,
+}
+
+/// A binary (an executable, framework, or library) that was loaded
+#[derive(Deserialize)]
+pub struct Image {
+    pub name: Option<String>,
+    pub path: Option<String>,
+    pub arch: Option<String>,
+    /// The address it was loaded at
+    #[serde(default)]
+    pub base: u64,
+    #[serde(default)]
+    pub size: u64,
+    /// The image's LC_UUID, which its dSYM shares
+    pub uuid: Option<String>,
+}
+impl Image {
+    /// The image's name, or the last part of its path
+    pub fn name(&self) -> &str {
+        match (&self.name, &self.path) {
+            (Some(name), _) => name,
+            (None, Some(path)) => path.rsplit('/').next().unwrap_or(path),
+            (None, None) => "???",
+        }
+    }
+}
+
+pub struct CrashReport {
+    pub header: Header,
+    pub body: Body,
+}
+
+/// Parses a crash report. `path` is only used for errors.
+pub fn parse(text: &str, path: &Path) -> Result<CrashReport, Error> {
+    let invalid = |e: serde_json::Error| {
+        Error::io(
+            "Failed to read the crash report. Is it an .ips file from iOS 15 or later?",
+            path,
+            io::Error::new(io::ErrorKind::InvalidData, e),
+        )
+    };
+    let (header, body) = text.split_once('\n').unwrap_or((text, ""));
+    let header = serde_json::from_str(header).map_err(invalid)?;
+    let body = serde_json::from_str(body).map_err(invalid)?;
+    Ok(CrashReport { header, body })
+}
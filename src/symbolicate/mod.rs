@@ -0,0 +1,260 @@
+//! Symbolicating crash reports from the app's testers. The `.ips` files iOS writes only have
+//! the app's frames as offsets into its binary, so they're looked up in the DWARF of the
+//! binaries & dSYMs kept from earlier builds, for the function, file, and line they're in.
+
+pub mod debug_info;
+pub mod ips;
+
+use {
+    crate::{
+        context::{find_cargo_toml, Ctx},
+        error::Error,
+    },
+    clap::Args,
+    debug_info::{demangle, Candidate, DebugInfo},
+    ips::{CrashReport, Frame, Image},
+    std::{collections::BTreeMap, fmt::Write, fs, path::PathBuf},
+};
+
+#[derive(Args)]
+pub struct SymbolicateArgs {
+    /// The crash report (.ips file) to symbolicate
+    pub crash_report: PathBuf,
+    /// A dSYM, binary, or folder of them, to look for debug info in. The project's `target`
+    /// folder is always searched. Can be passed more than once.
+    #[arg(short, long = "search", value_name = "PATH")]
+    pub search_paths: Vec<PathBuf>,
+    /// Save the symbolicated crash report here, instead of printing it
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// The app's name. If left unprovided, cargo-ipa will search for it in Cargo.toml.
+    #[arg(short, long)]
+    pub name: Option<String>,
+}
+
+pub fn symbolicate(args: SymbolicateArgs) -> Result<(), Error> {
+    // Cargo.toml's optional if there's somewhere else to search
+    let ctx = match find_cargo_toml() {
+        Ok(cargo_toml) => Some(Ctx::from_cargo_toml(cargo_toml, &args.name)?),
+        Err(_) => None,
+    };
+    let report = symbolicate_with(&args, ctx.as_ref())?;
+    match &args.output {
+        Some(output) => {
+            if let Err(e) = fs::write(output, report) {
+                return Err(Error::io(
+                    "Failed to save the symbolicated crash report",
+                    output,
+                    e,
+                ));
+            }
+            println!(
+                "|- Saved the symbolicated crash report to `{}`",
+                output.display()
+            );
+        }
+        None => print!("\n{report}"),
+    }
+    Ok(())
+}
+
+/// Symbolicates a crash report with the debug info in `args.search_paths` and the project's
+/// `target` folder, and returns it as text
+pub fn symbolicate_with(args: &SymbolicateArgs, ctx: Option<&Ctx>) -> Result<String, Error> {
+    // ========== SETUP ==========
+    let text = match fs::read_to_string(&args.crash_report) {
+        Ok(text) => text,
+        Err(e) => {
+            return Err(Error::io(
+                "Failed to read the crash report",
+                &args.crash_report,
+                e,
+            ))
+        }
+    };
+    let report = ips::parse(&text, &args.crash_report)?;
+
+    let mut search_paths = args.search_paths.clone();
+    if let Some(ctx) = ctx {
+        search_paths.push(ctx.target_dir.clone());
+    }
+    if search_paths.is_empty() {
+        return Err(Error::Config(
+            "There's no project to find debug info in. Pass a dSYM, or a folder with them in, with `--search`.".into(),
+        ));
+    }
+    println!("|- Searching for debug info...");
+    let candidates = debug_info::find(&search_paths);
+
+    // ========== LOOKUP ==========
+    // Only images with frames in the report are loaded
+    let mut used = vec![false; report.body.used_images.len()];
+    let backtraces = report
+        .body
+        .threads
+        .iter()
+        .map(|thread| &thread.frames)
+        .chain(&report.body.last_exception_backtrace);
+    for frame in backtraces.flatten() {
+        if let Some(used) = used.get_mut(frame.image_index) {
+            *used = true;
+        }
+    }
+    let mut debug_info = BTreeMap::new();
+    for (index, image) in report.body.used_images.iter().enumerate() {
+        let Some(uuid) = image.uuid.as_ref().map(|uuid| uuid.to_uppercase()) else {
+            continue;
+        };
+        match candidates.get(&uuid).and_then(|found| found.first()) {
+            Some(Candidate { path, has_dwarf }) if used[index] => {
+                let info = DebugInfo::load(path, &uuid)?;
+                println!(
+                    "|- Found {} for `{}` in `{}`",
+                    if *has_dwarf { "debug info" } else { "symbols" },
+                    image.name(),
+                    path.display()
+                );
+                debug_info.insert(index, info);
+            }
+            Some(_) => {}
+            // The system's libraries are symbolicated on the device; the app's aren't
+            None if used[index] && is_in_app(image) => println!(
+                "   |- Warning: couldn't find debug info for `{}` ({uuid}). Pass its dSYM with `--search`.",
+                image.name()
+            ),
+            None => {}
+        }
+    }
+
+    // ========== REPORT ==========
+    Ok(render(&report, &debug_info))
+}
+
+/// Whether an image is part of the app, rather than the OS
+fn is_in_app(image: &Image) -> bool {
+    image
+        .path
+        .as_ref()
+        .is_some_and(|path| path.contains(".app/"))
+}
+
+/// Renders a symbolicated crash report, like the ones Xcode shows
+fn render(report: &CrashReport, debug_info: &BTreeMap<usize, DebugInfo>) -> String {
+    let (header, body) = (&report.header, &report.body);
+    let images = &body.used_images;
+    let mut text = String::new();
+
+    let app = header
+        .app_name
+        .as_deref()
+        .or(body.proc_name.as_deref())
+        .unwrap_or("???");
+    let _ = write!(text, "{app}");
+    if let Some(version) = &header.app_version {
+        let _ = write!(text, " {version}");
+    }
+    if let Some(build) = &header.build_version {
+        let _ = write!(text, " ({build})");
+    }
+    if let Some(bundle_id) = &header.bundle_id {
+        let _ = write!(text, ", {bundle_id}");
+    }
+    text.push('\n');
+    match (&header.os_version, &header.timestamp) {
+        (Some(os), Some(timestamp)) => {
+            let _ = writeln!(text, "{os}, {timestamp}");
+        }
+        (Some(line), None) | (None, Some(line)) => {
+            let _ = writeln!(text, "{line}");
+        }
+        (None, None) => {}
+    }
+    if let Some(exception) = &body.exception {
+        let _ = write!(text, "Exception: {}", exception.kind);
+        if let Some(signal) = &exception.signal {
+            let _ = write!(text, " ({signal})");
+        }
+        if let Some(subtype) = &exception.subtype {
+            let _ = write!(text, ": {subtype}");
+        }
+        text.push('\n');
+    }
+    if let Some(indicator) = body.termination.as_ref().and_then(|t| t.indicator.as_ref()) {
+        let _ = writeln!(text, "Termination: {indicator}");
+    }
+
+    let name_width = images
+        .iter()
+        .map(|image| image.name().len())
+        .max()
+        .unwrap_or(0);
+    let backtrace = |text: &mut String, frames: &[Frame]| {
+        for (i, frame) in frames.iter().enumerate() {
+            let image = images.get(frame.image_index);
+            let name = image.map_or("???", Image::name);
+            let address = image.map_or(0, |image| image.base) + frame.image_offset;
+            let prefix = format!("{i:<4}{name:<name_width$}  {address:#018x}");
+
+            let symbols = debug_info
+                .get(&frame.image_index)
+                .map(|info| info.lookup(frame.image_offset, i > 0))
+                .unwrap_or_default();
+            if symbols.is_empty() {
+                // Fall back to what the device found, if anything
+                let _ = match (&frame.symbol, frame.symbol_location) {
+                    (Some(symbol), Some(offset)) => {
+                        writeln!(text, "{prefix} {} + {offset}", demangle(symbol))
+                    }
+                    (Some(symbol), None) => writeln!(text, "{prefix} {}", demangle(symbol)),
+                    (None, _) => writeln!(text, "{prefix} ???"),
+                };
+            }
+            for symbol in symbols {
+                let _ = write!(text, "{prefix} {}", symbol.name);
+                if let Some(offset) = symbol.offset {
+                    let _ = write!(text, " + {offset}");
+                }
+                if let Some((file, line)) = &symbol.location {
+                    let _ = write!(text, " ({file}:{line})");
+                }
+                if symbol.inlined {
+                    text.push_str(" [inlined]");
+                }
+                text.push('\n');
+            }
+        }
+    };
+
+    if let Some(frames) = &body.last_exception_backtrace {
+        text.push_str("\nLast Exception Backtrace:\n");
+        backtrace(&mut text, frames);
+    }
+    for (i, thread) in body.threads.iter().enumerate() {
+        let _ = write!(text, "\nThread {i}");
+        if thread.triggered {
+            text.push_str(" Crashed");
+        }
+        match (&thread.name, &thread.queue) {
+            (Some(name), _) | (None, Some(name)) => {
+                let _ = writeln!(text, " ({name}):");
+            }
+            (None, None) => text.push_str(":\n"),
+        }
+        backtrace(&mut text, &thread.frames);
+    }
+
+    text.push_str("\nBinary Images:\n");
+    for image in images {
+        let end = (image.base + image.size).saturating_sub(1);
+        let _ = writeln!(
+            text,
+            "{:#018x} - {end:#018x} {} {} <{}> {}",
+            image.base,
+            image.name(),
+            image.arch.as_deref().unwrap_or("???"),
+            image.uuid.as_deref().unwrap_or("???"),
+            image.path.as_deref().unwrap_or("???"),
+        );
+    }
+    text
+}
@@ -0,0 +1,262 @@
+use clap::Args;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    connect::{
+        app_store::{self, AppStoreClient, BuildVersion},
+        ApiKey,
+    },
+    context::{find_cargo_toml, Ctx},
+    error::Error,
+    resign::{find_app, read_info_plist},
+    runner::{run_checked, SystemRunner, ToolCall, ToolRunner},
+};
+
+#[derive(Args)]
+pub struct UploadArgs {
+    /// The IPA to upload. Defaults to the iOS IPA `cargo ipa build` made.
+    pub ipa: Option<PathBuf>,
+    /// The app's name, for finding the IPA `cargo ipa build` made. If left unprovided,
+    /// cargo-ipa will search for it in Cargo.toml.
+    #[arg(short, long)]
+    pub name: Option<String>,
+    /// The App Store Connect API key (`AuthKey_<key ID>.p8`) to authenticate with
+    #[arg(long)]
+    pub api_key: Option<PathBuf>,
+    /// The API key's ID. Defaults to the one in the key's file name.
+    #[arg(long)]
+    pub api_key_id: Option<String>,
+    /// The API key's issuer ID, from the Keys tab in App Store Connect
+    #[arg(long)]
+    pub api_issuer: Option<String>,
+    /// The app's Apple ID in App Store Connect. If blank, it's looked up by bundle ID.
+    #[arg(long)]
+    pub app_id: Option<String>,
+    /// Don't wait for App Store Connect to finish processing the build
+    #[arg(long)]
+    pub no_wait: bool,
+    /// How often to check on the build, in seconds
+    #[arg(long, default_value_t = 30)]
+    pub poll_interval: u64,
+    /// How long to wait for the build to be processed, in minutes
+    #[arg(long, default_value_t = 60)]
+    pub timeout: u64,
+    /// The App Store Connect API's URL, eg to test against a mock server
+    #[arg(long, default_value = app_store::API_URL)]
+    pub api_url: String,
+}
+
+/// A build that was uploaded to App Store Connect
+#[derive(Debug)]
+pub struct Upload {
+    /// The build upload's ID
+    pub id: String,
+    /// The app's Apple ID
+    pub app_id: String,
+    /// The build upload's state: `COMPLETE`, or `PROCESSING` with `--no-wait`
+    pub state: String,
+    /// Warnings from processing the build
+    pub warnings: Vec<String>,
+}
+
+pub fn upload(args: UploadArgs) -> Result<(), Error> {
+    // The settings in Cargo.toml are optional if the IPA (and the key) are given
+    let ctx = match (&args.ipa, find_cargo_toml()) {
+        (_, Ok(cargo_toml)) => Some(Ctx::from_cargo_toml(cargo_toml, &args.name)?),
+        (None, Err(e)) => return Err(e),
+        (Some(_), Err(_)) => None,
+    };
+    upload_with(&args, ctx.as_ref(), &SystemRunner).map(|_| ())
+}
+
+/// Uploads an IPA to App Store Connect, using the settings in `ctx` (if there's a project)
+/// and running unzip through `runner`
+pub fn upload_with(
+    args: &UploadArgs,
+    ctx: Option<&Ctx>,
+    runner: &dyn ToolRunner,
+) -> Result<Upload, Error> {
+    // ========== SETUP ==========
+    let settings = ctx.map(|ctx| &ctx.cfg.app_store_connect);
+    let ipa = match (&args.ipa, ctx) {
+        (Some(ipa), _) => ipa.clone(),
+        (None, Some(ctx)) => ctx
+            .cargo_ipa_dir
+            .join(format!("{}.aarch64-apple-ios.ipa", ctx.project_name)),
+        (None, None) => {
+            return Err(Error::Config(
+                "No IPA was given, and there's no project to find one in".into(),
+            ))
+        }
+    };
+    println!("Uploading `{}` to App Store Connect...", ipa.display());
+    let api_key = args.api_key.clone().or_else(|| {
+        let path = settings?.api_key.as_ref()?;
+        Some(ctx?.root_dir.join(path))
+    });
+    let key = ApiKey::load(
+        api_key,
+        args.api_key_id
+            .clone()
+            .or_else(|| settings?.api_key_id.clone()),
+        args.api_issuer
+            .clone()
+            .or_else(|| settings?.api_issuer.clone()),
+    )?;
+    if ipa.extension().is_none_or(|ext| ext != "ipa") {
+        return Err(Error::Config(format!(
+            "`{}` isn't an IPA. Only IPAs can be uploaded.",
+            ipa.display()
+        )));
+    }
+    if !ipa.is_file() {
+        let hint = match args.ipa {
+            Some(_) => "",
+            None => " Build it with `cargo ipa build --release --platform ios` first.",
+        };
+        return Err(Error::Config(format!(
+            "`{}` doesn't exist.{hint}",
+            ipa.display()
+        )));
+    }
+
+    let (bundle_id, version) = read_version(&ipa, runner)?;
+    println!(
+        "|- `{bundle_id}` version {} (build {})",
+        version.version, version.build
+    );
+    let data = match fs::read(&ipa) {
+        Ok(data) => data,
+        Err(e) => return Err(Error::io("Failed to read the IPA", ipa, e)),
+    };
+    let file_name = ipa
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut client = AppStoreClient::new(&key);
+    client.api_url = args.api_url.trim_end_matches('/').to_owned();
+    let app_id = match args.app_id.clone().or_else(|| settings?.app_id.clone()) {
+        Some(app_id) => app_id,
+        None => client.find_app(&bundle_id)?,
+    };
+
+    // ========== UPLOAD ==========
+    let id = client.create_build_upload(&app_id, &version)?;
+    println!("|- Created build upload `{id}`");
+    let (file_id, file) = client.reserve_file(&id, &file_name, data.len())?;
+    let parts = file.upload_operations.len();
+    for (i, operation) in file.upload_operations.iter().enumerate() {
+        println!("   |- Uploading part {} of {parts}...", i + 1);
+        client.upload_part(operation, &data)?;
+    }
+    client.commit_file(&file_id, &app_store::file_checksum(&data))?;
+    println!("|- Uploaded!");
+
+    if args.no_wait {
+        return Ok(Upload {
+            id,
+            app_id,
+            state: "PROCESSING".into(),
+            warnings: Vec::new(),
+        });
+    }
+
+    // ========== WAIT ==========
+    println!("|- Waiting for App Store Connect to process the build...");
+    let started = Instant::now();
+    let state = loop {
+        let state = client.state(&id)?;
+        if state.state != "AWAITING_UPLOAD" && state.state != "PROCESSING" {
+            break state;
+        }
+        if started.elapsed() > Duration::from_secs(args.timeout * 60) {
+            return Err(Error::Service(format!(
+                "App Store Connect is still processing build upload `{id}` after {} minutes. It'll show up in TestFlight once it's done.",
+                args.timeout
+            )));
+        }
+        thread::sleep(Duration::from_secs(args.poll_interval));
+    };
+
+    let warnings: Vec<_> = state.warnings.iter().map(|w| w.describe()).collect();
+    for warning in &warnings {
+        println!("   |- Warning: {warning}");
+    }
+    if state.state != "COMPLETE" {
+        let mut message = format!(
+            "App Store Connect couldn't process the build ({})",
+            state.state
+        );
+        for error in &state.errors {
+            message += &format!("\n  - {}", error.describe());
+        }
+        return Err(Error::Service(message));
+    }
+
+    println!("|- Processed! It's ready for TestFlight.");
+    Ok(Upload {
+        id,
+        app_id,
+        state: state.state,
+        warnings,
+    })
+}
+
+/// Reads the bundle ID and version out of an IPA's Info.plist, unpacking it with `runner`
+fn read_version(ipa: &Path, runner: &dyn ToolRunner) -> Result<(String, BuildVersion), Error> {
+    let stem = ipa.file_stem().unwrap().to_string_lossy();
+    let dir = ipa.with_file_name(format!(".{stem}.upload"));
+    if dir.exists() {
+        if let Err(e) = fs::remove_dir_all(&dir) {
+            return Err(Error::io(
+                "Old upload files exist, and can't be removed",
+                dir,
+                e,
+            ));
+        }
+    }
+    if let Err(e) = fs::create_dir_all(&dir) {
+        return Err(Error::io(
+            "Failed to create a folder to unpack into",
+            dir,
+            e,
+        ));
+    }
+    let unzip_call = ToolCall::new(
+        "unzip",
+        &["-q", ipa.to_str().unwrap(), "-d", dir.to_str().unwrap()],
+    );
+    let info_plist = run_checked(runner, &unzip_call, |output| Error::Packaging {
+        message: format!("Failed to unpack `{}`", ipa.display()),
+        source: Some(output.stderr.into()),
+    })
+    .and_then(|_| find_app(&dir.join("Payload"), ipa))
+    .and_then(|app| read_info_plist(&app));
+    if let Err(e) = fs::remove_dir_all(&dir) {
+        return Err(Error::io("Failed to clean up the unpacked IPA", dir, e));
+    }
+    let info_plist = info_plist?;
+
+    let get = |key: &str| match info_plist.get(key).and_then(plist::Value::as_string) {
+        Some(value) => Ok(value.to_owned()),
+        None => Err(Error::Packaging {
+            message: format!(
+                "`{}` can't be uploaded: its Info.plist doesn't have `{key}`",
+                ipa.display()
+            ),
+            source: None,
+        }),
+    };
+    let version = BuildVersion {
+        version: get("CFBundleShortVersionString")?,
+        build: get("CFBundleVersion")?,
+        platform: "IOS".into(),
+    };
+    Ok((get("CFBundleIdentifier")?, version))
+}
@@ -0,0 +1,580 @@
+use clap::Args;
+use serde::Serialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{
+    error::Error,
+    resign::{bundle_executable, find_app, read_info_plist},
+    runner::{run_checked, SystemRunner, ToolCall, ToolRunner},
+    sign::{
+        self, entitlements,
+        profile::{ProvisioningProfile, EMBEDDED_PROFILE_IOS, EMBEDDED_PROFILE_MACOS},
+        resources::{self, CODE_RESOURCES_PATH, NESTED_BUNDLE_EXTENSIONS},
+        verify::{self as verify_code, format_time, SealedFiles, SliceReport},
+    },
+};
+
+#[derive(Args)]
+pub struct VerifyArgs {
+    /// The .app or .ipa to check
+    pub path: PathBuf,
+    /// Print the report as JSON, instead of a summary
+    #[arg(long)]
+    pub json: bool,
+    /// Also check everything notarization needs (macOS apps only)
+    #[arg(long)]
+    pub notarization: bool,
+}
+
+/// Everything `cargo ipa verify` found
+#[derive(Serialize)]
+pub struct Report {
+    pub path: PathBuf,
+    /// Whether there weren't any problems (there can still be warnings)
+    pub valid: bool,
+    /// Whether it's a macOS app, rather than an iOS one
+    pub macos: bool,
+    /// The app, then all the code nested inside it
+    pub code: Vec<CodeReport>,
+    /// What would stop the app from being notarized, if `--notarization` was passed
+    pub notarization: Option<Vec<String>>,
+}
+
+/// What was found in a bundle, or a standalone dylib
+#[derive(Serialize)]
+pub struct CodeReport {
+    /// Where the code is, relative to the folder the app is in
+    pub path: PathBuf,
+    /// The bundle's ID. Dylibs don't have one.
+    pub bundle_id: Option<String>,
+    pub slices: Vec<SliceReport>,
+    /// The entitlements the code was signed with
+    pub entitlements: Option<plist::Value>,
+    /// The bundle's embedded provisioning profile
+    pub profile: Option<ProfileReport>,
+    /// Problems with the bundle as a whole, like its resource seal. The slices have their
+    /// own problems too.
+    pub problems: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct ProfileReport {
+    pub name: String,
+    pub application_identifier: String,
+    pub expiration_date: String,
+    pub expired: bool,
+    pub provisioned_devices: Vec<String>,
+    pub provisions_all_devices: bool,
+}
+
+impl Report {
+    /// Every problem found, with where it was found
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for code in &self.code {
+            let path = code.path.display();
+            problems.extend(code.problems.iter().map(|p| format!("`{path}`: {p}")));
+            for slice in &code.slices {
+                let arch = &slice.architecture;
+                problems.extend(
+                    slice
+                        .problems
+                        .iter()
+                        .map(|p| format!("`{path}` ({arch}): {p}")),
+                );
+            }
+        }
+        problems.extend(self.notarization.iter().flatten().cloned());
+        problems
+    }
+}
+
+pub fn verify(args: VerifyArgs) -> Result<(), Error> {
+    let report = verify_with(&args, &SystemRunner)?;
+    if args.json {
+        match serde_json::to_string_pretty(&report) {
+            Ok(json) => println!("{json}"),
+            Err(e) => {
+                return Err(Error::Packaging {
+                    message: "Failed to write the report as JSON".into(),
+                    source: Some(Box::new(e)),
+                })
+            }
+        }
+    } else {
+        print_report(&report);
+    }
+
+    let problems = report.problems();
+    if problems.is_empty() {
+        return Ok(());
+    }
+    Err(Error::Signing(format!(
+        "Found {} problem(s) with `{}`'s signature:\n{}",
+        problems.len(),
+        args.path.display(),
+        problems.join("\n")
+    )))
+}
+
+/// Checks an app or IPA's signatures, unpacking IPAs with `runner`. Returns what was found;
+/// a broken signature is reported, not returned as an error.
+pub fn verify_with(args: &VerifyArgs, runner: &dyn ToolRunner) -> Result<Report, Error> {
+    let is_ipa = args.path.extension().is_some_and(|ext| ext == "ipa");
+    if !is_ipa && !args.path.is_dir() {
+        return Err(Error::Config(format!(
+            "`{}` isn't an app or an IPA",
+            args.path.display()
+        )));
+    }
+
+    let mut work_dir = None;
+    let app = if is_ipa {
+        let stem = args.path.file_stem().unwrap().to_string_lossy();
+        let dir = args.path.with_file_name(format!(".{stem}.verify"));
+        if dir.exists() {
+            if let Err(e) = fs::remove_dir_all(&dir) {
+                return Err(Error::io(
+                    "Old verification files exist, and can't be removed",
+                    dir,
+                    e,
+                ));
+            }
+        }
+        if let Err(e) = fs::create_dir_all(&dir) {
+            return Err(Error::io(
+                "Failed to create a folder to unpack into",
+                dir,
+                e,
+            ));
+        }
+        let unzip_call = ToolCall::new(
+            "unzip",
+            &[
+                "-q",
+                args.path.to_str().unwrap(),
+                "-d",
+                dir.to_str().unwrap(),
+            ],
+        );
+        run_checked(runner, &unzip_call, |output| Error::Packaging {
+            message: format!("Failed to unpack `{}`", args.path.display()),
+            source: Some(output.stderr.into()),
+        })?;
+        let app = find_app(&dir.join("Payload"), &args.path)?;
+        work_dir = Some(dir);
+        app
+    } else {
+        args.path.clone()
+    };
+
+    let base = app.parent().unwrap_or(Path::new(""));
+    let mut code = Vec::new();
+    let result = verify_bundle(base, &app, &mut code);
+    if let Some(dir) = work_dir {
+        if let Err(e) = fs::remove_dir_all(&dir) {
+            return Err(Error::io("Failed to clean up the unpacked IPA", dir, e));
+        }
+    }
+    result?;
+
+    let mut report = Report {
+        path: args.path.clone(),
+        valid: false,
+        macos: app.join("Contents/Info.plist").exists(),
+        code,
+        notarization: None,
+    };
+    if args.notarization {
+        report.notarization = Some(notarization_problems(&report));
+    }
+    report.valid = report.problems().is_empty();
+    Ok(report)
+}
+
+/// Checks a bundle, then everything nested inside it
+fn verify_bundle(base: &Path, bundle: &Path, reports: &mut Vec<CodeReport>) -> Result<(), Error> {
+    // macOS apps keep everything in a Contents folder
+    let macos = bundle.join("Contents/Info.plist").exists();
+    let root = if macos {
+        bundle.join("Contents")
+    } else {
+        bundle.to_owned()
+    };
+    let info_plist = read_info_plist(&root)?;
+    let bundle_id = info_plist
+        .get("CFBundleIdentifier")
+        .and_then(plist::Value::as_string)
+        .map(str::to_string);
+    let executable = if macos {
+        bundle_executable(&root.join("MacOS"), &info_plist)?
+    } else {
+        bundle_executable(&root, &info_plist)?
+    };
+    let mut report = CodeReport {
+        path: bundle.strip_prefix(base).unwrap_or(bundle).to_owned(),
+        bundle_id: bundle_id.clone(),
+        slices: Vec::new(),
+        entitlements: None,
+        profile: None,
+        problems: Vec::new(),
+        warnings: Vec::new(),
+    };
+
+    let binary = read(&executable)?;
+    let info_plist_data = read(&root.join("Info.plist"))?;
+    let code_resources_path = root.join(CODE_RESOURCES_PATH);
+    let code_resources = fs::read(&code_resources_path).ok();
+    report.slices = verify_code::verify(
+        &binary,
+        &SealedFiles {
+            info_plist: Some(&info_plist_data),
+            code_resources: code_resources.as_deref(),
+        },
+        SystemTime::now(),
+    )?;
+    for slice in &report.slices {
+        if slice.identifier.is_some() && slice.identifier != bundle_id {
+            report.problems.push(format!(
+                "The binary is signed as `{}`, but the bundle ID is `{}`",
+                slice.identifier.as_deref().unwrap_or_default(),
+                bundle_id.as_deref().unwrap_or_default()
+            ));
+        }
+    }
+
+    // The resource seal
+    let main_executable = executable.strip_prefix(&root).unwrap();
+    match code_resources {
+        Some(ref sealed) => check_seal(&root, main_executable, macos, sealed, &mut report),
+        None => report.problems.push(format!(
+            "The bundle's resources aren't sealed; there's no `{CODE_RESOURCES_PATH}`"
+        )),
+    }
+
+    let entitlements = match sign::embedded_entitlements(&binary)? {
+        Some(xml) => match plist::Value::from_reader_xml(&*xml) {
+            Ok(plist::Value::Dictionary(entitlements)) => Some(entitlements),
+            _ => {
+                report
+                    .problems
+                    .push("The binary's entitlements aren't a valid plist".into());
+                None
+            }
+        },
+        None => None,
+    };
+
+    // The provisioning profile
+    let profile_path = root.join(if macos {
+        EMBEDDED_PROFILE_MACOS
+    } else {
+        EMBEDDED_PROFILE_IOS
+    });
+    if profile_path.exists() {
+        match ProvisioningProfile::load(&profile_path) {
+            Ok(profile) => {
+                check_profile(&profile, entitlements.as_ref(), &mut report);
+                let expiration_date = SystemTime::from(profile.expiration_date);
+                report.profile = Some(ProfileReport {
+                    name: profile.name,
+                    application_identifier: profile.application_identifier,
+                    expiration_date: format_time(expiration_date),
+                    expired: expiration_date <= SystemTime::now(),
+                    provisioned_devices: profile.provisioned_devices,
+                    provisions_all_devices: profile.provisions_all_devices,
+                });
+            }
+            Err(Error::Signing(message)) => report.problems.push(message),
+            Err(e) => return Err(e),
+        }
+    }
+    report.entitlements = entitlements.map(plist::Value::Dictionary);
+    reports.push(report);
+
+    verify_nested(base, &root, reports)
+}
+
+/// Checks every bundle & dylib inside `dir`
+fn verify_nested(base: &Path, dir: &Path, reports: &mut Vec<CodeReport>) -> Result<(), Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => return Err(Error::io("Failed to read a folder in the app", dir, e)),
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        if path.is_symlink() {
+            continue;
+        }
+        let is_bundle = path
+            .extension()
+            .is_some_and(|ext| NESTED_BUNDLE_EXTENSIONS.contains(&&*ext.to_string_lossy()));
+        if path.is_dir() && is_bundle {
+            verify_bundle(base, &path, reports)?;
+        } else if path.is_dir() {
+            verify_nested(base, &path, reports)?;
+        } else if path.extension().is_some_and(|ext| ext == "dylib") {
+            let binary = read(&path)?;
+            let slices = verify_code::verify(&binary, &SealedFiles::default(), SystemTime::now())?;
+            reports.push(CodeReport {
+                path: path.strip_prefix(base).unwrap_or(&path).to_owned(),
+                bundle_id: None,
+                slices,
+                entitlements: None,
+                profile: None,
+                problems: Vec::new(),
+                warnings: Vec::new(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Compares the bundle's CodeResources with what's actually in the bundle
+fn check_seal(
+    root: &Path,
+    main_executable: &Path,
+    macos: bool,
+    sealed: &[u8],
+    report: &mut CodeReport,
+) {
+    let files2 = |xml: &[u8]| {
+        plist::Value::from_reader_xml(xml)
+            .ok()
+            .and_then(|plist| plist.into_dictionary())
+            .and_then(|mut plist| plist.remove("files2"))
+            .and_then(plist::Value::into_dictionary)
+    };
+    let Some(sealed) = files2(sealed) else {
+        report
+            .problems
+            .push(format!("`{CODE_RESOURCES_PATH}` isn't valid"));
+        return;
+    };
+    let actual = match resources::code_resources(root, main_executable, macos) {
+        Ok(actual) => files2(&actual).unwrap_or_default(),
+        Err(Error::Signing(message)) => {
+            report.problems.push(message);
+            return;
+        }
+        Err(e) => {
+            report
+                .problems
+                .push(format!("The bundle's resources couldn't be read: {e}"));
+            return;
+        }
+    };
+
+    for (path, entry) in &sealed {
+        let optional = entry
+            .as_dictionary()
+            .and_then(|entry| entry.get("optional"))
+            .and_then(plist::Value::as_boolean)
+            .unwrap_or(false);
+        match actual.get(path) {
+            Some(actual) if actual == entry => (),
+            Some(_) => report
+                .problems
+                .push(format!("`{path}` was changed after the bundle was signed")),
+            None if optional => (),
+            None => report
+                .problems
+                .push(format!("`{path}` was removed after the bundle was signed")),
+        }
+    }
+    for path in actual.keys() {
+        if !sealed.contains_key(path) {
+            report
+                .problems
+                .push(format!("`{path}` was added after the bundle was signed"));
+        }
+    }
+}
+
+/// Makes sure the embedded profile works with the bundle, its signature, and its entitlements
+fn check_profile(
+    profile: &ProvisioningProfile,
+    entitlements: Option<&plist::Dictionary>,
+    report: &mut CodeReport,
+) {
+    let name = &profile.name;
+    if let Some(ref bundle_id) = report.bundle_id {
+        if !profile.matches_bundle_id(bundle_id) {
+            report.problems.push(format!(
+                "The provisioning profile `{name}` is for the app ID `{}`, which doesn't match the bundle ID `{bundle_id}`",
+                profile.application_identifier
+            ));
+        }
+    }
+    if SystemTime::from(profile.expiration_date) <= SystemTime::now() {
+        report.problems.push(format!(
+            "The provisioning profile `{name}` expired on {}",
+            profile.expiration_date.to_xml_format()
+        ));
+    }
+    for slice in &report.slices {
+        match slice.signing_certificate {
+            Some(ref certificate) if !profile.developer_certificates.contains(certificate) => {
+                report.problems.push(format!(
+                    "The provisioning profile `{name}` doesn't include the certificate the {} slice was signed with",
+                    slice.architecture
+                ));
+            }
+            Some(_) => (),
+            None if slice.ad_hoc => report.warnings.push(format!(
+                "The {} slice is ad-hoc signed, so the provisioning profile `{name}` won't work with it",
+                slice.architecture
+            )),
+            None => (),
+        }
+    }
+    if let Some(entitlements) = entitlements {
+        if let Err(Error::Signing(message)) = entitlements::check(entitlements, profile) {
+            report.problems.push(message);
+        }
+    }
+}
+
+/// Lists everything Apple's notary service would reject the app for, on top of the problems
+/// `verify` already found. See:
+/// https://developer.apple.com/documentation/security/resolving-common-notarization-issues
+pub fn notarization_problems(report: &Report) -> Vec<String> {
+    if !report.macos {
+        return vec![
+            "Only macOS apps can be notarized; iOS apps are checked by App Store Connect instead"
+                .into(),
+        ];
+    }
+    let mut problems = Vec::new();
+    for code in &report.code {
+        let path = code.path.display();
+        let Some(slice) = code.slices.iter().find(|slice| slice.identifier.is_some()) else {
+            continue;
+        };
+        match slice.certificates.first() {
+            None => problems.push(format!(
+                "`{path}` isn't signed with a certificate. Notarization needs a Developer ID Application certificate; set `signing.identity`."
+            )),
+            Some(certificate)
+                if !certificate
+                    .common_name
+                    .as_deref()
+                    .unwrap_or_default()
+                    .starts_with("Developer ID Application:") =>
+            {
+                problems.push(format!(
+                    "`{path}` is signed with `{}`, but notarization needs a Developer ID Application certificate",
+                    certificate.common_name.as_deref().unwrap_or(&certificate.subject)
+                ))
+            }
+            Some(_) => (),
+        }
+        if code.slices.iter().any(|slice| !slice.hardened_runtime) {
+            problems.push(format!(
+                "`{path}` doesn't have the hardened runtime turned on. Set `signing.hardened-runtime = true`."
+            ));
+        }
+        if !slice.certificates.is_empty()
+            && code.slices.iter().any(|slice| slice.timestamp.is_none())
+        {
+            problems.push(format!(
+                "`{path}`'s signature doesn't have a secure timestamp. Set `signing.timestamp-url`, eg to `http://timestamp.apple.com/ts01`."
+            ));
+        }
+        let get_task_allow = match code.entitlements {
+            Some(plist::Value::Dictionary(ref entitlements)) => entitlements
+                .get("com.apple.security.get-task-allow")
+                .and_then(plist::Value::as_boolean)
+                .unwrap_or(false),
+            _ => false,
+        };
+        if get_task_allow {
+            problems.push(format!(
+                "`{path}` has the `com.apple.security.get-task-allow` entitlement, which notarization doesn't allow. Use a Developer ID provisioning profile instead of a development one."
+            ));
+        }
+    }
+    problems
+}
+
+fn print_report(report: &Report) {
+    println!("Verifying `{}`...", report.path.display());
+    for code in &report.code {
+        match code.bundle_id {
+            Some(ref bundle_id) => println!("|- `{}` ({bundle_id})", code.path.display()),
+            None => println!("|- `{}`", code.path.display()),
+        }
+        for slice in &code.slices {
+            let signer = match slice.certificates.first() {
+                Some(certificate) => format!("signed by `{}`", certificate.subject),
+                None if slice.ad_hoc => "ad-hoc signed".into(),
+                None => "not signed".into(),
+            };
+            match slice.cdhash {
+                Some(ref cdhash) => {
+                    println!("   |- {}: {signer}, CDHash {cdhash}", slice.architecture)
+                }
+                None => println!("   |- {}: {signer}", slice.architecture),
+            }
+            if let Some(certificate) = slice.certificates.first() {
+                println!("   |- The certificate expires on {}", certificate.not_after);
+            }
+            if slice.hardened_runtime {
+                println!("   |- The hardened runtime is on");
+            }
+            if let Some(ref timestamp) = slice.timestamp {
+                println!("   |- Timestamped on {timestamp}");
+            }
+            for warning in &slice.warnings {
+                println!("   |- Warning: {warning}");
+            }
+            for problem in &slice.problems {
+                println!("   |- Problem: {problem}");
+            }
+        }
+        if let Some(plist::Value::Dictionary(ref entitlements)) = code.entitlements {
+            let keys: Vec<_> = entitlements.keys().map(String::as_str).collect();
+            println!("   |- Entitlements: {}", keys.join(", "));
+        }
+        if let Some(ref profile) = code.profile {
+            println!(
+                "   |- Provisioning profile `{}`, which expires on {}",
+                profile.name, profile.expiration_date
+            );
+        }
+        for warning in &code.warnings {
+            println!("   |- Warning: {warning}");
+        }
+        for problem in &code.problems {
+            println!("   |- Problem: {problem}");
+        }
+    }
+    if let Some(ref notarization) = report.notarization {
+        if notarization.is_empty() {
+            println!("|- Ready for notarization");
+        }
+        for problem in notarization {
+            println!("|- Notarization problem: {problem}");
+        }
+    }
+    if report.valid {
+        println!("The signature is valid.");
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, Error> {
+    match fs::read(path) {
+        Ok(data) => Ok(data),
+        Err(e) => Err(Error::io("Failed to read a file in the app", path, e)),
+    }
+}
@@ -0,0 +1,743 @@
+mod common;
+
+use cargo_ipa::{
+    build::{build_with, BuildArgs},
+    build_manifest::BuildManifest,
+    context::{Architecture, Ctx, ExportMethod, Platform},
+    error::Error,
+    runner::ToolOutput,
+    sign::{
+        blob::sha256,
+        macho::{read_u32_be, MachO},
+    },
+    verify::{verify_with, VerifyArgs},
+};
+use common::*;
+use std::{fs, path::Path};
+
+fn args(platform: Option<Platform>, architecture: Option<Architecture>) -> BuildArgs {
+    BuildArgs {
+        example: None,
+        release: false,
+        name: None,
+        platform,
+        architecture,
+        strip: false,
+        archive: false,
+        export_method: None,
+        simulator: false,
+    }
+}
+
+/// Makes sure the binary was signed with the app's bundle ID, and seals the Info.plist and
+/// CodeResources in `bundle_root` (the app, or its Contents folder on macOS)
+fn assert_signed(binary: &Path, bundle_root: &Path) {
+    let binary = fs::read(binary).unwrap();
+    let command = MachO::parse(&binary).unwrap().code_signature.unwrap();
+    let signature = &binary[command.dataoff as usize..];
+    let code_directory = &signature[read_u32_be(signature, 16) as usize..];
+
+    let ident_offset = read_u32_be(code_directory, 20) as usize;
+    let identifier = b"com.example.test-app\0";
+    assert_eq!(
+        &code_directory[ident_offset..ident_offset + identifier.len()],
+        identifier
+    );
+    let hash_offset = read_u32_be(code_directory, 16) as usize;
+    assert_eq!(
+        code_directory[hash_offset - 32..hash_offset],
+        sha256(&fs::read(bundle_root.join("Info.plist")).unwrap())
+    );
+    assert_eq!(
+        code_directory[hash_offset - 96..hash_offset - 64],
+        sha256(&fs::read(bundle_root.join("_CodeSignature/CodeResources")).unwrap())
+    );
+}
+
+#[test]
+fn builds_every_target_by_default() {
+    let project = project(CARGO_TOML);
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    build_with(&mut ctx, &args(None, None), &runner).unwrap();
+
+    let targets: Vec<String> = runner
+        .calls_to("cargo")
+        .iter()
+        .map(|call| call.args[2].clone())
+        .collect();
+    assert_eq!(
+        targets,
+        [
+            "x86_64-apple-ios",
+            "x86_64-apple-darwin",
+            "aarch64-apple-ios",
+            "aarch64-apple-darwin"
+        ]
+    );
+    let out = project.path().join("target/cargo-ipa");
+    for target in ["x86_64-apple-ios", "aarch64-apple-ios"] {
+        assert!(out.join(format!("Test App.{target}.ipa")).exists());
+    }
+    for target in ["x86_64-apple-darwin", "aarch64-apple-darwin"] {
+        assert!(out.join(format!("Test App.{target}.app")).is_dir());
+    }
+}
+
+#[test]
+fn ios_app_is_zipped_from_its_payload_folder() {
+    let project = project(CARGO_TOML);
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let args = args(Some(Platform::iOS), Some(Architecture::aarch64));
+    build_with(&mut ctx, &args, &runner).unwrap();
+
+    // Rust should be compiled for the device, and linked against the iOS Swift libraries
+    let cargo = &runner.calls_to("cargo")[0];
+    assert_eq!(cargo.current_dir.as_deref(), Some(project.path()));
+    assert_eq!(
+        cargo.args,
+        [
+            "rustc",
+            "--target",
+            "aarch64-apple-ios",
+            "-q",
+            "--",
+            "-L",
+            "/Applications/Xcode.app/Contents/Developer/Toolchains/XcodeDefault.xctoolchain/usr/lib/swift/iphoneos"
+        ]
+    );
+
+    let out = project.path().join("target/cargo-ipa");
+    let zip = &runner.calls_to("zip")[0];
+    assert_eq!(zip.current_dir.as_deref(), Some(out.as_path()));
+    assert_eq!(
+        zip.args,
+        ["-r", "Test App.aarch64-apple-ios.ipa", "Payload"]
+    );
+
+    // iOS apps keep everything in the root of the .app
+    let app = out.join("Payload/Test App.aarch64-apple-ios.app");
+    assert_signed(&app.join("test-app"), &app);
+    let chmod = &runner.calls_to("chmod")[0];
+    let unpackaged_app = out.join("Test App.aarch64-apple-ios.app");
+    assert_eq!(
+        chmod.args[1],
+        unpackaged_app.join("test-app").to_str().unwrap()
+    );
+
+    let info_plist = plist::Value::from_file(app.join("Info.plist")).unwrap();
+    let info_plist = info_plist.as_dictionary().unwrap();
+    let get = |key: &str| info_plist.get(key).and_then(plist::Value::as_string);
+    assert_eq!(get("CFBundleExecutable"), Some("test-app"));
+    assert_eq!(get("CFBundleIdentifier"), Some("com.example.test-app"));
+    assert_eq!(get("CFBundleName"), Some("Test App"));
+    assert_eq!(get("CFBundleVersion"), Some("0.1.0"));
+}
+
+#[test]
+fn simulator_builds_use_the_simulator_target() {
+    let project = project(CARGO_TOML);
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let mut args = args(Some(Platform::iOS), None);
+    args.simulator = true;
+    build_with(&mut ctx, &args, &runner).unwrap();
+
+    // x86_64 iOS is always for the Simulator, and aarch64 has its own Simulator target
+    let cargo: Vec<_> = runner
+        .calls_to("cargo")
+        .into_iter()
+        .map(|call| call.args)
+        .collect();
+    assert_eq!(cargo[0][2], "x86_64-apple-ios");
+    assert_eq!(cargo[1][2], "aarch64-apple-ios-sim");
+    assert!(cargo[1][6].ends_with("usr/lib/swift/iphonesimulator"));
+    let out = project.path().join("target/cargo-ipa");
+    assert!(out.join("Test App.aarch64-apple-ios-sim.ipa").exists());
+
+    // Simulator builds can't be distributed
+    args.export_method = Some(ExportMethod::Development);
+    let err = build_with(&mut ctx, &args, &runner).unwrap_err();
+    assert!(matches!(err, Error::Config(_)));
+    assert!(err.to_string().contains("`--simulator`"), "{err}");
+}
+
+#[test]
+fn macos_app_uses_the_contents_layout() {
+    let project = project(CARGO_TOML);
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let args = args(Some(Platform::macOS), Some(Architecture::aarch64));
+    build_with(&mut ctx, &args, &runner).unwrap();
+
+    let app = project
+        .path()
+        .join("target/cargo-ipa/Test App.aarch64-apple-darwin.app");
+    assert!(app.join("Contents/Info.plist").is_file());
+    assert_signed(&app.join("Contents/MacOS/test-app"), &app.join("Contents"));
+    assert!(runner.calls_to("zip").is_empty());
+}
+
+#[test]
+fn examples_and_release_mode_are_passed_to_cargo() {
+    let project = project(CARGO_TOML);
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let mut args = args(Some(Platform::macOS), Some(Architecture::x86_64));
+    args.example = Some("demo".into());
+    args.release = true;
+    build_with(&mut ctx, &args, &runner).unwrap();
+
+    let cargo = &runner.calls_to("cargo")[0];
+    assert!(cargo
+        .args
+        .windows(2)
+        .any(|args| args == ["--example", "demo"]));
+    assert!(cargo.args.contains(&"--release".to_string()));
+    let app = project
+        .path()
+        .join("target/cargo-ipa/Test App.x86_64-apple-darwin.app");
+    assert!(app.join("Contents/MacOS/demo").is_file());
+}
+
+#[test]
+fn compile_errors_stop_the_build() {
+    let project = project(CARGO_TOML);
+    let runner = fake_toolchain(project.path())
+        .with_tool("cargo", |_| Ok(ToolOutput::failure("error[E0425]")));
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let err = build_with(&mut ctx, &args(None, None), &runner).unwrap_err();
+    assert!(matches!(err, Error::CompileFailed { .. }), "{err}");
+    assert_eq!(err.exit_code(), 4);
+    assert_eq!(
+        err.report(),
+        "Error: Cargo failed to compile the project! Aborting.\n  Caused by: error[E0425]"
+    );
+    assert_eq!(runner.calls_to("cargo").len(), 1);
+    assert!(runner.calls_to("zip").is_empty());
+}
+
+#[test]
+fn missing_zip_fails_packaging() {
+    let project = project(CARGO_TOML);
+    let runner = fake_toolchain(project.path()).with_tool("zip", |_| {
+        Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+    });
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let args = args(Some(Platform::iOS), Some(Architecture::aarch64));
+    let err = build_with(&mut ctx, &args, &runner).unwrap_err();
+    assert!(matches!(err, Error::ToolchainMissing { .. }), "{err}");
+    assert_eq!(err.exit_code(), 3);
+    assert!(err.report().starts_with("Error: Failed to run `zip`"));
+}
+
+#[test]
+fn missing_name_is_a_config_error() {
+    let project = project("[package]\nname = \"test-app\"\nversion = \"0.1.0\"\n");
+    let Err(err) = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None) else {
+        panic!("a project without a name shouldn't load");
+    };
+    assert!(matches!(err, Error::Config(_)), "{err}");
+    assert_eq!(err.exit_code(), 2);
+}
+
+#[test]
+fn file_errors_name_the_file() {
+    let project = project(CARGO_TOML);
+    let runner = fake_toolchain(project.path()).with_output("cargo", "");
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    // The fake cargo above doesn't write a binary, so there's nothing to copy into the app
+    let args = args(Some(Platform::macOS), Some(Architecture::aarch64));
+    let err = build_with(&mut ctx, &args, &runner).unwrap_err();
+    assert!(matches!(err, Error::Io { .. }), "{err}");
+    assert_eq!(err.exit_code(), 6);
+    let binary = project
+        .path()
+        .join("target/aarch64-apple-darwin/debug/test-app");
+    assert!(err.to_string().contains(binary.to_str().unwrap()), "{err}");
+}
+
+#[test]
+fn apps_are_signed_with_the_configured_identity() {
+    let project = project(&(CARGO_TOML.to_string() + &signing_settings()));
+    fs::write(project.path().join("password.txt"), "cargo-ipa\n").unwrap();
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let args = args(Some(Platform::macOS), Some(Architecture::aarch64));
+    build_with(&mut ctx, &args, &runner).unwrap();
+
+    let app = project
+        .path()
+        .join("target/cargo-ipa/Test App.aarch64-apple-darwin.app/Contents");
+    assert_signed(&app.join("MacOS/test-app"), &app);
+    let binary = fs::read(app.join("MacOS/test-app")).unwrap();
+    let command = MachO::parse(&binary).unwrap().code_signature.unwrap();
+    let signature = &binary[command.dataoff as usize..];
+    // Not ad-hoc, and with a CMS signature
+    let code_directory = &signature[read_u32_be(signature, 16) as usize..];
+    assert_eq!(read_u32_be(code_directory, 12), 0);
+    // The CMS signature is always the last blob
+    let count = read_u32_be(signature, 8) as usize;
+    let cms = &signature[read_u32_be(signature, 12 + (count - 1) * 8 + 4) as usize..];
+    assert_eq!(read_u32_be(cms, 0), 0xfade0b01);
+    assert!(read_u32_be(cms, 4) > 8);
+}
+
+#[test]
+fn wrong_identity_passwords_fail_before_compiling() {
+    let project = project(&(CARGO_TOML.to_string() + &signing_settings()));
+    fs::write(project.path().join("password.txt"), "hunter2").unwrap();
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let err = build_with(&mut ctx, &args(None, None), &runner).unwrap_err();
+    assert!(matches!(err, Error::Signing(_)), "{err}");
+    assert!(runner.calls_to("cargo").is_empty());
+}
+
+#[test]
+fn provisioning_profiles_are_embedded_and_sealed() {
+    let project = project(&(CARGO_TOML.to_string() + &signing_settings() + &profile_setting()));
+    fs::write(project.path().join("password.txt"), "cargo-ipa").unwrap();
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let args = args(Some(Platform::iOS), Some(Architecture::aarch64));
+    build_with(&mut ctx, &args, &runner).unwrap();
+
+    let app = project
+        .path()
+        .join("target/cargo-ipa/Payload/Test App.aarch64-apple-ios.app");
+    let profile = fs::read(app.join("embedded.mobileprovision")).unwrap();
+    assert_eq!(
+        profile,
+        fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures/profile/test.mobileprovision")
+        )
+        .unwrap()
+    );
+    let code_resources = plist::Value::from_file(app.join("_CodeSignature/CodeResources")).unwrap();
+    let files2 = code_resources.as_dictionary().unwrap()["files2"]
+        .as_dictionary()
+        .unwrap();
+    let sealed = files2["embedded.mobileprovision"].as_dictionary().unwrap();
+    assert_eq!(sealed["hash2"].as_data().unwrap(), sha256(&profile));
+}
+
+#[test]
+fn ios_profiles_are_only_embedded_in_ios_apps() {
+    let project = project(&(CARGO_TOML.to_string() + &signing_settings() + &profile_setting()));
+    fs::write(project.path().join("password.txt"), "cargo-ipa").unwrap();
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    build_with(&mut ctx, &args(None, None), &runner).unwrap();
+
+    let out = project.path().join("target/cargo-ipa");
+    let ios_app = out.join("Payload/Test App.aarch64-apple-ios.app");
+    assert!(ios_app.join("embedded.mobileprovision").is_file());
+    let macos_app = out.join("Test App.aarch64-apple-darwin.app");
+    assert!(!macos_app
+        .join("Contents/embedded.provisionprofile")
+        .exists());
+    assert!(!macos_app.join("Contents/embedded.mobileprovision").exists());
+    assert_signed(
+        &macos_app.join("Contents/MacOS/test-app"),
+        &macos_app.join("Contents"),
+    );
+}
+
+#[test]
+fn unprovisioned_devices_fail_before_compiling() {
+    let cargo_toml = CARGO_TOML.to_string()
+        + &signing_settings()
+        + &profile_setting()
+        + "devices = ['00008110-000000000000001E']\n";
+    let project = project(&cargo_toml);
+    fs::write(project.path().join("password.txt"), "cargo-ipa").unwrap();
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let err = build_with(&mut ctx, &args(None, None), &runner).unwrap_err();
+    assert!(
+        err.to_string().contains("00008110-000000000000001E"),
+        "{err}"
+    );
+    assert!(runner.calls_to("cargo").is_empty());
+}
+
+#[test]
+fn profiles_need_an_identity() {
+    let cargo_toml =
+        CARGO_TOML.to_string() + "\n[package.metadata.cargo-ipa.signing]\n" + &profile_setting();
+    let project = project(&cargo_toml);
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let err = build_with(&mut ctx, &args(None, None), &runner).unwrap_err();
+    assert!(matches!(err, Error::Signing(_)), "{err}");
+    assert!(err.to_string().contains("signing.identity"));
+}
+
+#[test]
+fn entitlements_are_signed_and_written_next_to_the_app() {
+    let cargo_toml = CARGO_TOML.to_string()
+        + "capabilities = ['keychain-sharing']\n"
+        + &signing_settings()
+        + &profile_setting();
+    let project = project(&cargo_toml);
+    fs::write(project.path().join("password.txt"), "cargo-ipa").unwrap();
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let args = args(Some(Platform::iOS), Some(Architecture::aarch64));
+    build_with(&mut ctx, &args, &runner).unwrap();
+
+    let out = project.path().join("target/cargo-ipa");
+    let xcent = fs::read(out.join("Test App.aarch64-apple-ios.xcent")).unwrap();
+    let entitlements = plist::Value::from_reader_xml(&*xcent).unwrap();
+    let entitlements = entitlements.as_dictionary().unwrap();
+    assert_eq!(
+        entitlements["keychain-access-groups"].as_array().unwrap()[0].as_string(),
+        Some("TEAM123456.com.example.test-app")
+    );
+
+    // The entitlements blob holds exactly what's in the .xcent
+    let binary = fs::read(out.join("Payload/Test App.aarch64-apple-ios.app/test-app")).unwrap();
+    let command = MachO::parse(&binary).unwrap().code_signature.unwrap();
+    let signature = &binary[command.dataoff as usize..];
+    assert_eq!(read_u32_be(signature, 28), 5);
+    let blob = &signature[read_u32_be(signature, 32) as usize..];
+    assert_eq!(read_u32_be(blob, 0), 0xfade7171);
+    assert_eq!(&blob[8..read_u32_be(blob, 4) as usize], xcent);
+}
+
+#[test]
+fn entitlements_the_profile_doesnt_allow_fail() {
+    let cargo_toml = CARGO_TOML.to_string()
+        + "capabilities = ['push']\n"
+        + &signing_settings()
+        + &profile_setting();
+    let project = project(&cargo_toml);
+    fs::write(project.path().join("password.txt"), "cargo-ipa").unwrap();
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let args = args(Some(Platform::iOS), Some(Architecture::aarch64));
+    let err = build_with(&mut ctx, &args, &runner).unwrap_err();
+    assert!(matches!(err, Error::Signing(_)), "{err}");
+    assert!(err.to_string().contains("aps-environment"));
+}
+
+#[test]
+fn macos_apps_can_be_hardened_and_timestamped() {
+    let cargo_toml = CARGO_TOML.to_string()
+        + &signing_settings()
+        + &format!(
+            "hardened-runtime = true\nruntime-exceptions = ['allow-jit']\ntimestamp-url = '{}'\n",
+            fake_tsa(TsaMode::Grant)
+        );
+    let project = project(&cargo_toml);
+    fs::write(project.path().join("password.txt"), "cargo-ipa\n").unwrap();
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let args = args(Some(Platform::macOS), Some(Architecture::aarch64));
+    build_with(&mut ctx, &args, &runner).unwrap();
+
+    let app = project
+        .path()
+        .join("target/cargo-ipa/Test App.aarch64-apple-darwin.app");
+    let args = VerifyArgs {
+        path: app,
+        json: false,
+        notarization: true,
+    };
+    let report = verify_with(&args, &runner).unwrap();
+    let code = &report.code[0];
+    assert!(code.slices[0].hardened_runtime);
+    assert!(code.slices[0].timestamp.is_some());
+    let Some(plist::Value::Dictionary(ref entitlements)) = code.entitlements else {
+        panic!("the app should have entitlements");
+    };
+    assert_eq!(
+        entitlements["com.apple.security.cs.allow-jit"].as_boolean(),
+        Some(true)
+    );
+    // Everything but the test identity, which isn't a Developer ID one
+    let notarization = report.notarization.unwrap();
+    assert_eq!(notarization.len(), 1, "{notarization:#?}");
+    assert!(notarization[0].contains("needs a Developer ID Application certificate"));
+}
+
+#[test]
+fn hardened_runtime_is_macos_only() {
+    let project = project(
+        &(CARGO_TOML.to_string()
+            + "\n[package.metadata.cargo-ipa.signing]\nhardened-runtime = true\n"),
+    );
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let args = args(Some(Platform::iOS), Some(Architecture::aarch64));
+    build_with(&mut ctx, &args, &runner).unwrap();
+
+    let binary = fs::read(
+        project
+            .path()
+            .join("target/cargo-ipa/Payload/Test App.aarch64-apple-ios.app/test-app"),
+    )
+    .unwrap();
+    let command = MachO::parse(&binary).unwrap().code_signature.unwrap();
+    let signature = &binary[command.dataoff as usize..];
+    let code_directory = &signature[read_u32_be(signature, 16) as usize..];
+    // Only ad-hoc
+    assert_eq!(read_u32_be(code_directory, 12), 0x2);
+}
+
+#[test]
+fn debug_info_is_saved_to_a_dsym_per_target() {
+    let project = project(CARGO_TOML);
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let args = BuildArgs {
+        release: true,
+        ..args(Some(Platform::iOS), Some(Architecture::aarch64))
+    };
+    build_with(&mut ctx, &args, &runner).unwrap();
+
+    let dsym = project
+        .path()
+        .join("target/cargo-ipa/Test App.aarch64-apple-ios.app.dSYM");
+    let dsymutil = &runner.calls_to("dsymutil")[0];
+    assert_eq!(
+        dsymutil.args,
+        [
+            project
+                .path()
+                .join("target/aarch64-apple-ios/release/test-app")
+                .to_str()
+                .unwrap(),
+            "-o",
+            dsym.to_str().unwrap()
+        ]
+    );
+    assert!(dsym.join("Contents/Resources/DWARF/test-app").is_file());
+    let info = plist::Value::from_file(dsym.join("Contents/Info.plist")).unwrap();
+    let info = info.as_dictionary().unwrap();
+    let get = |key: &str| info.get(key).and_then(|value| value.as_string());
+    assert_eq!(
+        get("CFBundleIdentifier"),
+        Some("com.apple.xcode.dsym.com.example.test-app")
+    );
+    assert_eq!(get("CFBundlePackageType"), Some("dSYM"));
+    assert_eq!(get("CFBundleShortVersionString"), Some("0.1.0"));
+}
+
+#[test]
+fn debug_builds_skip_the_dsym() {
+    let project = project(CARGO_TOML);
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let args = args(Some(Platform::iOS), Some(Architecture::aarch64));
+    build_with(&mut ctx, &args, &runner).unwrap();
+
+    assert!(runner.calls_to("dsymutil").is_empty());
+    let manifest = BuildManifest::load(&ctx.cargo_ipa_dir).unwrap();
+    assert_eq!(manifest.targets[0].dsym, None);
+
+    // Stripping would lose the debug info without one
+    let args = BuildArgs {
+        strip: true,
+        ..args
+    };
+    build_with(&mut ctx, &args, &runner).unwrap();
+    assert_eq!(runner.calls_to("dsymutil").len(), 1);
+}
+
+#[test]
+fn build_manifest_records_each_targets_uuid() {
+    let project = project(CARGO_TOML);
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let args = BuildArgs {
+        release: true,
+        ..args(None, Some(Architecture::aarch64))
+    };
+    build_with(&mut ctx, &args, &runner).unwrap();
+
+    let manifest = BuildManifest::load(&ctx.cargo_ipa_dir).unwrap();
+    assert_eq!(manifest.bundle_id, "com.example.test-app");
+    assert_eq!(manifest.version, "0.1.0");
+    let targets: Vec<_> = manifest
+        .targets
+        .iter()
+        .map(|target| {
+            (
+                target.target.as_str(),
+                target.artifact.as_str(),
+                target.dsym.as_deref().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        targets,
+        [
+            (
+                "aarch64-apple-ios",
+                "Test App.aarch64-apple-ios.ipa",
+                "Test App.aarch64-apple-ios.app.dSYM"
+            ),
+            (
+                "aarch64-apple-darwin",
+                "Test App.aarch64-apple-darwin.app",
+                "Test App.aarch64-apple-darwin.app.dSYM"
+            ),
+        ]
+    );
+    for target in &manifest.targets {
+        assert_eq!(
+            target.uuid.as_deref(),
+            Some("0C0C0C0C-0C0C-0C0C-0C0C-0C0C0C0C0C0C")
+        );
+        assert!(!target.stripped);
+    }
+}
+
+#[test]
+fn stripping_happens_before_signing() {
+    let project = project(CARGO_TOML);
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let mut args = args(Some(Platform::macOS), Some(Architecture::aarch64));
+    build_with(&mut ctx, &args, &runner).unwrap();
+    assert!(runner.calls_to("strip").is_empty());
+
+    let runner = fake_toolchain(project.path());
+    args.strip = true;
+    build_with(&mut ctx, &args, &runner).unwrap();
+
+    let app = project
+        .path()
+        .join("target/cargo-ipa/Test App.aarch64-apple-darwin.app");
+    let binary = app.join("Contents/MacOS/test-app");
+    let strip = &runner.calls_to("strip")[0];
+    assert_eq!(strip.args, ["-S", "-x", binary.to_str().unwrap()]);
+    // The dSYM is made from Cargo's copy of the binary, which is left as it is
+    assert!(
+        runner.calls()[..]
+            .iter()
+            .position(|call| call.program == "dsymutil")
+            < runner
+                .calls()
+                .iter()
+                .position(|call| call.program == "strip")
+    );
+    assert_signed(&binary, &app.join("Contents"));
+    assert!(BuildManifest::load(&ctx.cargo_ipa_dir).unwrap().targets[0].stripped);
+}
+
+#[test]
+fn xcarchives_have_the_app_its_dsym_and_properties() {
+    let project = project(&(CARGO_TOML.to_string() + &signing_settings()));
+    fs::write(project.path().join("password.txt"), "cargo-ipa\n").unwrap();
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let mut args = args(Some(Platform::iOS), Some(Architecture::aarch64));
+    args.archive = true;
+    build_with(&mut ctx, &args, &runner).unwrap();
+
+    let archive = project
+        .path()
+        .join("target/cargo-ipa/Test App.aarch64-apple-ios.xcarchive");
+    // The same signed app that's in the IPA
+    let app = archive.join("Products/Applications/Test App.app");
+    assert_signed(&app.join("test-app"), &app);
+    assert!(archive
+        .join("dSYMs/Test App.app.dSYM/Contents/Resources/DWARF/test-app")
+        .is_file());
+
+    let info = plist::Value::from_file(archive.join("Info.plist")).unwrap();
+    let info = info.as_dictionary().unwrap();
+    assert_eq!(
+        info.get("ArchiveVersion")
+            .and_then(|version| version.as_unsigned_integer()),
+        Some(2)
+    );
+    assert!(info.get("CreationDate").unwrap().as_date().is_some());
+    assert_eq!(
+        info.get("Name").and_then(|name| name.as_string()),
+        Some("Test App")
+    );
+    let properties = info
+        .get("ApplicationProperties")
+        .unwrap()
+        .as_dictionary()
+        .unwrap();
+    let get = |key: &str| properties.get(key).and_then(|value| value.as_string());
+    assert_eq!(get("ApplicationPath"), Some("Applications/Test App.app"));
+    assert_eq!(get("CFBundleIdentifier"), Some("com.example.test-app"));
+    assert_eq!(get("CFBundleShortVersionString"), Some("0.1.0"));
+    assert_eq!(
+        get("SigningIdentity"),
+        Some("Apple Distribution: Test Team (TEAM123456)")
+    );
+    assert_eq!(get("Team"), Some("TEAM123456"));
+    let architectures = properties.get("Architectures").unwrap().as_array().unwrap();
+    assert_eq!(architectures, &[plist::Value::from("arm64")]);
+
+    let manifest = BuildManifest::load(&ctx.cargo_ipa_dir).unwrap();
+    assert_eq!(
+        manifest.targets[0].archive.as_deref(),
+        Some("Test App.aarch64-apple-ios.xcarchive")
+    );
+}
+
+#[test]
+fn xcarchives_are_only_made_when_asked_for() {
+    let project = project(CARGO_TOML);
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let mut args = args(Some(Platform::macOS), Some(Architecture::x86_64));
+    build_with(&mut ctx, &args, &runner).unwrap();
+    let archive = project
+        .path()
+        .join("target/cargo-ipa/Test App.x86_64-apple-darwin.xcarchive");
+    assert!(!archive.exists());
+
+    args.archive = true;
+    build_with(&mut ctx, &args, &runner).unwrap();
+    let app = archive.join("Products/Applications/Test App.app");
+    assert_signed(&app.join("Contents/MacOS/test-app"), &app.join("Contents"));
+    let info = plist::Value::from_file(archive.join("Info.plist")).unwrap();
+    let properties = info
+        .as_dictionary()
+        .and_then(|info| info.get("ApplicationProperties"))
+        .and_then(|properties| properties.as_dictionary())
+        .unwrap();
+    // Ad-hoc signed, without a team
+    assert_eq!(
+        properties
+            .get("SigningIdentity")
+            .and_then(|identity| identity.as_string()),
+        Some("-")
+    );
+    assert!(properties.get("Team").is_none());
+}
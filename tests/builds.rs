@@ -0,0 +1,190 @@
+mod common;
+
+use cargo_ipa::{
+    build::{build_with, BuildArgs},
+    build_manifest::BuildManifest,
+    builds::{builds_dir, list_with, prune_with, show_with, PruneArgs},
+    context::{Architecture, Ctx, Platform},
+    error::Error,
+    runner::{ToolCall, ToolOutput},
+};
+use common::*;
+use std::{fs, io};
+
+fn args() -> BuildArgs {
+    BuildArgs {
+        example: None,
+        release: true,
+        name: None,
+        platform: Some(Platform::iOS),
+        architecture: Some(Architecture::aarch64),
+        strip: false,
+        archive: false,
+        export_method: None,
+        simulator: false,
+    }
+}
+
+/// A git repo at commit `3f2a9c1`, tagged `v0.1.0`
+fn fake_git(call: &ToolCall) -> io::Result<ToolOutput> {
+    match call.args[0].as_str() {
+        "rev-parse" => Ok(ToolOutput::success("3f2a9c1\n")),
+        "tag" => Ok(ToolOutput::success("v0.1.0\n")),
+        _ => Ok(ToolOutput::failure("unknown command")),
+    }
+}
+
+/// Puts a build in the archive, made at `created`
+fn fake_build(ctx: &Ctx, id: &str, created: &str, tags: &[&str]) {
+    let dir = builds_dir(ctx).join(id);
+    fs::create_dir_all(&dir).unwrap();
+    BuildManifest {
+        name: ctx.project_name.clone(),
+        bundle_id: ctx.bundle_id.clone(),
+        version: ctx.project_version.clone(),
+        release: true,
+        example: None,
+        export_method: None,
+        created: created.into(),
+        git_commit: None,
+        git_tags: tags.iter().map(|tag| tag.to_string()).collect(),
+        targets: Vec::new(),
+    }
+    .save(&dir)
+    .unwrap();
+}
+
+fn ids(ctx: &Ctx) -> Vec<String> {
+    list_with(ctx)
+        .unwrap()
+        .into_iter()
+        .map(|build| build.id)
+        .collect()
+}
+
+#[test]
+fn builds_are_archived_with_their_commit() {
+    let project = project(CARGO_TOML);
+    let runner = fake_toolchain(project.path()).with_tool("git", fake_git);
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    build_with(&mut ctx, &args(), &runner).unwrap();
+
+    let builds = list_with(&ctx).unwrap();
+    assert_eq!(builds.len(), 1);
+    let build = &builds[0];
+    // Like `0.1.0-20261018T101010Z-3f2a9c1`
+    let parts: Vec<_> = build.id.split('-').collect();
+    assert_eq!(parts[0], "0.1.0");
+    assert_eq!(parts[1].len(), "20261018T101010Z".len());
+    assert_eq!(parts[2], "3f2a9c1");
+    assert_eq!(build.manifest.git_commit.as_deref(), Some("3f2a9c1"));
+    assert_eq!(build.manifest.git_tags, ["v0.1.0"]);
+    for file in [
+        "Test App.aarch64-apple-ios.ipa",
+        "Test App.aarch64-apple-ios.app.dSYM/Contents/Resources/DWARF/test-app",
+        "Info.plist",
+        "build-manifest.json",
+    ] {
+        assert!(build.path.join(file).is_file(), "{file}");
+    }
+    assert_eq!(
+        runner.calls_to("git")[0].current_dir.as_deref(),
+        Some(project.path())
+    );
+}
+
+#[test]
+fn projects_outside_git_are_archived_without_a_commit() {
+    let project = project(CARGO_TOML);
+    let runner = fake_toolchain(project.path())
+        .with_tool("git", |_| Ok(ToolOutput::failure("not a git repository")));
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    build_with(&mut ctx, &args(), &runner).unwrap();
+
+    let build = show_with(&ctx, None).unwrap();
+    assert_eq!(build.id.split('-').count(), 2, "{}", build.id);
+    assert_eq!(build.manifest.git_commit, None);
+    assert!(build.manifest.git_tags.is_empty());
+}
+
+#[test]
+fn builds_are_listed_newest_first() {
+    let project = project(CARGO_TOML);
+    let ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+    fake_build(&ctx, "0.1.0-20261016T090000Z", "2026-10-16T09:00:00Z", &[]);
+    fake_build(&ctx, "0.2.0-20261018T090000Z", "2026-10-18T09:00:00Z", &[]);
+    fake_build(&ctx, "0.1.1-20261017T090000Z", "2026-10-17T09:00:00Z", &[]);
+    // Not a build
+    fs::create_dir_all(builds_dir(&ctx).join("scratch")).unwrap();
+
+    assert_eq!(
+        ids(&ctx),
+        [
+            "0.2.0-20261018T090000Z",
+            "0.1.1-20261017T090000Z",
+            "0.1.0-20261016T090000Z"
+        ]
+    );
+}
+
+#[test]
+fn builds_can_be_shown_by_the_start_of_their_id() {
+    let project = project(CARGO_TOML);
+    let ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+    fake_build(&ctx, "0.1.0-20261016T090000Z", "2026-10-16T09:00:00Z", &[]);
+    fake_build(&ctx, "0.1.0-20261017T090000Z", "2026-10-17T09:00:00Z", &[]);
+    fake_build(&ctx, "0.2.0-20261018T090000Z", "2026-10-18T09:00:00Z", &[]);
+
+    assert_eq!(show_with(&ctx, None).unwrap().id, "0.2.0-20261018T090000Z");
+    assert_eq!(
+        show_with(&ctx, Some("0.1.0-20261016")).unwrap().id,
+        "0.1.0-20261016T090000Z"
+    );
+
+    let err = show_with(&ctx, Some("0.1.0")).err().unwrap();
+    assert!(matches!(err, Error::Config(_)));
+    assert!(err.to_string().contains("More than one"), "{err}");
+    let err = show_with(&ctx, Some("0.3.0")).err().unwrap();
+    assert!(err.to_string().contains("builds list"), "{err}");
+}
+
+#[test]
+fn pruning_keeps_the_newest_builds() {
+    let project = project(CARGO_TOML);
+    let ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+    fake_build(
+        &ctx,
+        "0.1.0-20261015T090000Z",
+        "2026-10-15T09:00:00Z",
+        &["v0.1.0"],
+    );
+    fake_build(&ctx, "0.1.1-20261016T090000Z", "2026-10-16T09:00:00Z", &[]);
+    fake_build(&ctx, "0.2.0-20261017T090000Z", "2026-10-17T09:00:00Z", &[]);
+    fake_build(&ctx, "0.2.1-20261018T090000Z", "2026-10-18T09:00:00Z", &[]);
+
+    let mut args = PruneArgs {
+        keep: 2,
+        keep_tagged: true,
+        dry_run: true,
+    };
+    assert_eq!(prune_with(&ctx, &args).unwrap(), ["0.1.1-20261016T090000Z"]);
+    assert_eq!(ids(&ctx).len(), 4);
+
+    args.dry_run = false;
+    prune_with(&ctx, &args).unwrap();
+    assert_eq!(
+        ids(&ctx),
+        [
+            "0.2.1-20261018T090000Z",
+            "0.2.0-20261017T090000Z",
+            "0.1.0-20261015T090000Z"
+        ]
+    );
+
+    // Tagged builds only stay while they're asked to
+    args.keep_tagged = false;
+    assert_eq!(prune_with(&ctx, &args).unwrap(), ["0.1.0-20261015T090000Z"]);
+    assert_eq!(ids(&ctx).len(), 2);
+}
@@ -0,0 +1,246 @@
+//! A stand-in for App Store Connect's build uploads, and the servers builds are uploaded to
+
+use super::{notary::check_token, read_http_request, write_http_response, HttpRequest};
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    net::TcpListener,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+/// The test app's Apple ID
+pub const APP_ID: &str = "1234567890";
+const BUILD_UPLOAD_ID: &str = "f6a1c3e2-0b8d-4f57-9a3e-5d2c7b1e8f40";
+const FILE_ID: &str = "0e9b6d1a-7c42-4b1f-8d3a-2f6e5c9b4a71";
+
+/// How [`fake_app_store`] processes builds
+#[derive(Clone, Copy, PartialEq)]
+pub enum AppStoreMode {
+    /// Processed, with a warning
+    Complete,
+    /// Fails processing, with an error
+    Failed,
+    /// Never finishes processing
+    Stuck,
+}
+
+/// Everything the fake App Store Connect was sent
+pub struct AppStoreRecord {
+    /// The apps it has, as (Apple ID, bundle ID)
+    pub apps: Vec<(String, String)>,
+    /// The bundle IDs apps were looked up by
+    pub lookups: Vec<String>,
+    /// The body of the request that created the build upload
+    pub build_upload: Option<Value>,
+    /// The body of the request that reserved the file
+    pub file: Option<Value>,
+    /// The chunks it asked for, as (offset, length)
+    pub operations: Vec<(usize, usize)>,
+    /// The chunks that were uploaded, by offset
+    pub parts: BTreeMap<usize, Vec<u8>>,
+    /// The checksums the file was committed with
+    pub checksums: Option<Value>,
+    pub polls: usize,
+}
+impl Default for AppStoreRecord {
+    fn default() -> Self {
+        Self {
+            apps: vec![
+                (APP_ID.into(), "com.example.test-app".into()),
+                ("1234567891".into(), "com.example.test-app.widget".into()),
+            ],
+            lookups: Vec::new(),
+            build_upload: None,
+            file: None,
+            operations: Vec::new(),
+            parts: BTreeMap::new(),
+            checksums: None,
+            polls: 0,
+        }
+    }
+}
+impl AppStoreRecord {
+    /// The uploaded chunks, put back together
+    pub fn uploaded_file(&self) -> Vec<u8> {
+        self.parts.values().flatten().copied().collect()
+    }
+}
+
+pub struct FakeAppStore {
+    base: String,
+    pub record: Arc<Mutex<AppStoreRecord>>,
+}
+impl FakeAppStore {
+    pub fn api_url(&self) -> String {
+        self.base.clone()
+    }
+}
+
+/// Starts a fake App Store Connect, which only accepts tokens from the test API key. Files
+/// are uploaded in two chunks.
+pub fn fake_app_store(mode: AppStoreMode) -> FakeAppStore {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let record = Arc::new(Mutex::new(AppStoreRecord::default()));
+    let app_store = FakeAppStore {
+        base: base.clone(),
+        record: record.clone(),
+    };
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let Ok(request) = read_http_request(&mut stream) else {
+                continue;
+            };
+            let mut record = record.lock().unwrap();
+            let (status, body) = respond(&request, mode, &base, &mut record);
+            write_http_response(&mut stream, status, "application/json", body.as_bytes());
+        }
+    });
+    app_store
+}
+
+fn respond(
+    request: &HttpRequest,
+    mode: AppStoreMode,
+    base: &str,
+    record: &mut AppStoreRecord,
+) -> (u16, String) {
+    // Uploads are authenticated by their URLs
+    if request.path.starts_with("/upload/") {
+        return upload_part(request, record);
+    }
+
+    if let Err(reason) = check_token(request) {
+        let body = json!({ "errors": [{
+            "status": "401",
+            "code": "NOT_AUTHORIZED",
+            "title": "Authentication credentials are missing or invalid.",
+            "detail": reason,
+        }]});
+        return (401, body.to_string());
+    }
+    let (path, query) = request
+        .path
+        .split_once('?')
+        .unwrap_or((request.path.as_str(), ""));
+    let file_path = format!("/v1/buildUploadFiles/{FILE_ID}");
+    let upload_path = format!("/v1/buildUploads/{BUILD_UPLOAD_ID}");
+    let body = match (request.method.as_str(), path) {
+        ("GET", "/v1/apps") => {
+            let bundle_id = query
+                .replace("%5B", "[")
+                .replace("%5D", "]")
+                .strip_prefix("filter[bundleId]=")
+                .unwrap()
+                .to_owned();
+            // Like the real thing, bundle IDs that start with the filter match too
+            let apps: Vec<_> = record
+                .apps
+                .iter()
+                .filter(|(_, app_bundle_id)| app_bundle_id.starts_with(&bundle_id))
+                .map(|(id, bundle_id)| {
+                    json!({ "type": "apps", "id": id, "attributes": { "bundleId": bundle_id } })
+                })
+                .collect();
+            record.lookups.push(bundle_id);
+            json!({ "data": apps })
+        }
+        ("POST", "/v1/buildUploads") => {
+            let body: Value = serde_json::from_slice(&request.body).unwrap();
+            record.build_upload = Some(body["data"].clone());
+            json!({ "data": {
+                "type": "buildUploads",
+                "id": BUILD_UPLOAD_ID,
+                "attributes": { "state": { "state": "AWAITING_UPLOAD" } },
+            }})
+        }
+        ("POST", "/v1/buildUploadFiles") => {
+            let body: Value = serde_json::from_slice(&request.body).unwrap();
+            let size = body["data"]["attributes"]["fileSize"].as_u64().unwrap() as usize;
+            record.file = Some(body["data"].clone());
+            record.operations = vec![(0, size / 2), (size / 2, size - size / 2)];
+            let operations: Vec<_> = record
+                .operations
+                .iter()
+                .enumerate()
+                .map(|(i, (offset, length))| {
+                    json!({
+                        "method": "PUT",
+                        "url": format!("{base}/upload/{i}?signature=fake"),
+                        "offset": offset,
+                        "length": length,
+                        "requestHeaders": [
+                            { "name": "Content-Type", "value": "application/octet-stream" },
+                        ],
+                    })
+                })
+                .collect();
+            json!({ "data": {
+                "type": "buildUploadFiles",
+                "id": FILE_ID,
+                "attributes": { "uploadOperations": operations },
+            }})
+        }
+        ("PATCH", path) if path == file_path => {
+            let body: Value = serde_json::from_slice(&request.body).unwrap();
+            let attributes = &body["data"]["attributes"];
+            if attributes["uploaded"] != true {
+                return (409, json!({ "errors": [] }).to_string());
+            }
+            record.checksums = Some(attributes["sourceFileChecksums"].clone());
+            json!({ "data": { "type": "buildUploadFiles", "id": FILE_ID, "attributes": {} } })
+        }
+        ("GET", path) if path == upload_path => {
+            record.polls += 1;
+            let state = match mode {
+                _ if record.checksums.is_none() => json!({ "state": "AWAITING_UPLOAD" }),
+                _ if record.polls == 1 => json!({ "state": "PROCESSING" }),
+                AppStoreMode::Stuck => json!({ "state": "PROCESSING" }),
+                AppStoreMode::Complete => json!({
+                    "state": "COMPLETE",
+                    "warnings": [{
+                        "code": "90725",
+                        "description": "This app was built with an old SDK.",
+                    }],
+                }),
+                AppStoreMode::Failed => json!({
+                    "state": "FAILED",
+                    "errors": [{
+                        "code": "90062",
+                        "description": "The value for key CFBundleShortVersionString must be higher than the previously uploaded version.",
+                    }],
+                }),
+            };
+            json!({ "data": {
+                "type": "buildUploads",
+                "id": BUILD_UPLOAD_ID,
+                "attributes": { "state": state },
+            }})
+        }
+        _ => return (404, json!({ "errors": [] }).to_string()),
+    };
+    (200, body.to_string())
+}
+
+/// Takes a chunk, as long as it's the chunk the URL is for
+fn upload_part(request: &HttpRequest, record: &mut AppStoreRecord) -> (u16, String) {
+    let index: usize = request.path["/upload/".len()..]
+        .split('?')
+        .next()
+        .unwrap()
+        .parse()
+        .unwrap();
+    let Some(&(offset, length)) = record.operations.get(index) else {
+        return (404, String::new());
+    };
+    if request.method != "PUT"
+        || request.header("content-type") != Some("application/octet-stream")
+        || request.body.len() != length
+    {
+        return (400, String::new());
+    }
+    record.parts.insert(offset, request.body.clone());
+    (200, String::new())
+}
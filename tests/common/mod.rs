@@ -0,0 +1,363 @@
+#![allow(dead_code)]
+
+#[cfg(feature = "binary")]
+pub mod app_store;
+#[cfg(feature = "binary")]
+pub mod notary;
+#[cfg(feature = "binary")]
+pub mod usbmux;
+
+use cargo_ipa::{
+    runner::{FakeRunner, ToolCall, ToolOutput},
+    sign::timestamp::{self, PkiStatusInfo, TimeStampReq, TimeStampResp, TstInfo, ID_TST_INFO},
+};
+use cms::{
+    builder::{SignedDataBuilder, SignerInfoBuilder},
+    cert::{CertificateChoices, IssuerAndSerialNumber},
+    signed_data::{EncapsulatedContentInfo, SignerIdentifier},
+};
+use der::{
+    asn1::{GeneralizedTime, Int},
+    oid::ObjectIdentifier,
+    Any, Decode, Encode, Tag,
+};
+use rsa::{
+    pkcs1v15::{Signature, SigningKey},
+    pkcs8::DecodePrivateKey,
+    RsaPrivateKey,
+};
+use sha2::Sha256;
+use std::{
+    fs,
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::Path,
+    thread,
+    time::SystemTime,
+};
+use tempfile::TempDir;
+use x509_cert::{spki::AlgorithmIdentifierOwned, Certificate};
+
+/// A Cargo.toml with a cargo-ipa section, for a package called `test-app`
+pub const CARGO_TOML: &str = r#"[package]
+name = "test-app"
+version = "0.1.0"
+edition = "2021"
+
+[package.metadata.cargo-ipa]
+name = "Test App"
+bundle-id = "com.example.test-app"
+"#;
+
+/// The contents of the fake binaries "compiled" by the fake cargo. It's a tiny
+/// (but valid) Mach-O, so it can be signed.
+pub const FAKE_BINARY: &[u8] = include_bytes!("../fixtures/macho/arm64");
+
+/// A [package.metadata.cargo-ipa.signing] section that uses the test identity, with its
+/// password in `password.txt`
+pub fn signing_settings() -> String {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/identity");
+    format!(
+        "\n[package.metadata.cargo-ipa.signing]\nidentity = '{}'\npassword-file = 'password.txt'\ncertificate-chain = ['{}', '{}']\n",
+        fixtures.join("identity.p12").display(),
+        fixtures.join("intermediate.pem").display(),
+        fixtures.join("root.cer").display(),
+    )
+}
+
+/// A `provisioning-profile` setting (for the signing section) that uses the test profile
+pub fn profile_setting() -> String {
+    let profile =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/profile/test.mobileprovision");
+    format!("provisioning-profile = '{}'\n", profile.display())
+}
+
+/// Makes a temporary project with the given Cargo.toml
+pub fn project(cargo_toml: &str) -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("Cargo.toml"), cargo_toml).unwrap();
+    fs::create_dir(dir.path().join("src")).unwrap();
+    fs::write(dir.path().join("src/main.rs"), "fn main() {}").unwrap();
+    dir
+}
+
+/// A fake macOS toolchain, with Xcode installed, that "compiles" projects in `root`
+pub fn fake_toolchain(root: &Path) -> FakeRunner {
+    let root = root.to_owned();
+    FakeRunner::new()
+        .with_output("xcode-select", "/Applications/Xcode.app/Contents/Developer\n")
+        .with_output("xcrun", "/Applications/Xcode.app/Contents/Developer/Platforms/iPhoneOS.platform/Developer/SDKs/iPhoneOS.sdk\n")
+        .with_output("chmod", "")
+        .with_tool("cargo", move |call| fake_cargo(&root, call))
+        .with_tool("zip", fake_zip)
+        .with_tool("dsymutil", fake_dsymutil)
+        .with_output("strip", "")
+}
+
+/// Pretends to compile the package by writing a fake binary where Cargo would put it
+fn fake_cargo(root: &Path, call: &ToolCall) -> io::Result<ToolOutput> {
+    if call.args.first().map(String::as_str) != Some("rustc") {
+        return Ok(ToolOutput::success(""));
+    }
+    let arg_after = |flag: &str| {
+        call.args
+            .iter()
+            .position(|arg| arg == flag)
+            .map(|i| call.args[i + 1].clone())
+    };
+    let target = arg_after("--target").unwrap();
+    let profile = if call.args.iter().any(|arg| arg == "--release") {
+        "release"
+    } else {
+        "debug"
+    };
+
+    let mut bin_dir = root.join("target").join(target).join(profile);
+    let bin_name = match arg_after("--example") {
+        Some(example) => {
+            bin_dir.push("examples");
+            example
+        }
+        None => "test-app".to_string(),
+    };
+    fs::create_dir_all(&bin_dir)?;
+    fs::write(bin_dir.join(bin_name), FAKE_BINARY)?;
+    Ok(ToolOutput::success(""))
+}
+
+/// Pretends to zip a folder. The "archive" is just every file's path, length, and contents
+/// one after another, which [`fake_unzip`] can unpack again.
+pub fn fake_zip(call: &ToolCall) -> io::Result<ToolOutput> {
+    let dir = call.current_dir.clone().unwrap_or_default();
+    let mut archive = Vec::new();
+    for folder in &call.args[2..] {
+        add_to_archive(&dir, &dir.join(folder), &mut archive)?;
+    }
+    fs::write(dir.join(&call.args[1]), archive)?;
+    Ok(ToolOutput::success(""))
+}
+
+fn add_to_archive(root: &Path, path: &Path, archive: &mut Vec<u8>) -> io::Result<()> {
+    if path.is_dir() {
+        let mut entries: Vec<_> = fs::read_dir(path)?
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        entries.sort();
+        for entry in entries {
+            add_to_archive(root, &entry, archive)?;
+        }
+    } else {
+        let data = fs::read(path)?;
+        let name = path.strip_prefix(root).unwrap().to_str().unwrap();
+        archive.extend_from_slice(format!("{name}\n{}\n", data.len()).as_bytes());
+        archive.extend_from_slice(&data);
+    }
+    Ok(())
+}
+
+/// Pretends to make a dSYM with `dsymutil <binary> -o <dSYM>`, by copying the binary into it
+pub fn fake_dsymutil(call: &ToolCall) -> io::Result<ToolOutput> {
+    let bin = Path::new(&call.args[0]);
+    let dsym = Path::new(&call.args[2]);
+    let dwarf_dir = dsym.join("Contents/Resources/DWARF");
+    fs::create_dir_all(&dwarf_dir)?;
+    fs::copy(bin, dwarf_dir.join(bin.file_name().unwrap()))?;
+    fs::write(dsym.join("Contents/Info.plist"), "")?;
+    Ok(ToolOutput::success(""))
+}
+
+/// Pretends to unzip an archive made by [`fake_zip`], with `unzip -q <archive> -d <folder>`
+pub fn fake_unzip(call: &ToolCall) -> io::Result<ToolOutput> {
+    let archive = fs::read(&call.args[1])?;
+    let dest = Path::new(&call.args[3]);
+    let mut rest = archive.as_slice();
+    while !rest.is_empty() {
+        let mut line = || {
+            let end = rest.iter().position(|byte| *byte == b'\n').unwrap();
+            let line = String::from_utf8(rest[..end].to_vec()).unwrap();
+            rest = &rest[end + 1..];
+            line
+        };
+        let name = line();
+        let len: usize = line().parse().unwrap();
+        let path = dest.join(name);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, &rest[..len])?;
+        rest = &rest[len..];
+    }
+    Ok(ToolOutput::success(""))
+}
+
+/// How [`fake_tsa`] responds to timestamp requests
+#[derive(Clone, Copy, PartialEq)]
+pub enum TsaMode {
+    Grant,
+    Reject,
+    /// Timestamps something other than what was asked for
+    WrongImprint,
+}
+
+/// Starts a local RFC 3161 timestamp authority, which signs timestamps with the test
+/// identity. Returns its URL.
+pub fn fake_tsa(mode: TsaMode) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let Ok(request) = read_http_request(&mut stream) else {
+                continue;
+            };
+            let body = timestamp_response(&request.body, mode);
+            write_http_response(&mut stream, 200, "application/timestamp-reply", &body);
+        }
+    });
+    url
+}
+
+/// An HTTP request to one of the fake servers
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    /// With lowercase names
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+impl HttpRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+fn read_http_request(stream: &mut TcpStream) -> io::Result<HttpRequest> {
+    let mut data = Vec::new();
+    let mut buffer = [0; 4096];
+    loop {
+        let read = stream.read(&mut buffer)?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        data.extend_from_slice(&buffer[..read]);
+        let Some(end) = data.windows(4).position(|window| window == b"\r\n\r\n") else {
+            continue;
+        };
+        let head = String::from_utf8_lossy(&data[..end]).into_owned();
+        let mut lines = head.lines();
+        let mut request_line = lines.next().unwrap_or_default().split(' ');
+        let method = request_line.next().unwrap_or_default().to_owned();
+        let path = request_line.next().unwrap_or_default().to_owned();
+        let headers: Vec<_> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(key, value)| (key.trim().to_lowercase(), value.trim().to_owned()))
+            .collect();
+        let length: usize = headers
+            .iter()
+            .find(|(key, _)| key == "content-length")
+            .map_or(0, |(_, length)| length.parse().unwrap());
+        while data.len() < end + 4 + length {
+            let read = stream.read(&mut buffer)?;
+            if read == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            data.extend_from_slice(&buffer[..read]);
+        }
+        return Ok(HttpRequest {
+            method,
+            path,
+            headers,
+            body: data[end + 4..end + 4 + length].to_vec(),
+        });
+    }
+}
+
+fn write_http_response(stream: &mut TcpStream, status: u16, content_type: &str, body: &[u8]) {
+    let head = format!(
+        "HTTP/1.1 {status} Fake\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    let _ = stream.write_all(head.as_bytes());
+    let _ = stream.write_all(body);
+}
+
+fn timestamp_response(request: &[u8], mode: TsaMode) -> Vec<u8> {
+    let request = TimeStampReq::from_der(request).unwrap();
+    if mode == TsaMode::Reject {
+        let response = TimeStampResp {
+            status: PkiStatusInfo {
+                status: 2,
+                status_string: None,
+                fail_info: None,
+            },
+            time_stamp_token: None,
+        };
+        return response.to_der().unwrap();
+    }
+
+    let mut message_imprint = request.message_imprint;
+    if mode == TsaMode::WrongImprint {
+        message_imprint = timestamp::imprint(b"something else");
+    }
+    let info = TstInfo {
+        version: 1,
+        policy: ObjectIdentifier::new_unwrap("1.2.3.4.1"),
+        message_imprint,
+        serial_number: Int::new(&[1]).unwrap(),
+        gen_time: Any::encode_from(&GeneralizedTime::from_system_time(SystemTime::now()).unwrap())
+            .unwrap(),
+        accuracy: None,
+        ordering: false,
+        nonce: request.nonce,
+        tsa: None,
+        extensions: None,
+    };
+
+    // Sign it with the test identity
+    let p12 = fs::read(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/identity/identity.p12"),
+    )
+    .unwrap();
+    let keystore = p12_keystore::KeyStore::from_pkcs12(&p12, "cargo-ipa").unwrap();
+    let (_, key_chain) = keystore.private_key_chain().unwrap();
+    let key = RsaPrivateKey::from_pkcs8_der(key_chain.key()).unwrap();
+    let certificate = Certificate::from_der(key_chain.chain()[0].as_der()).unwrap();
+    let signer = SigningKey::<Sha256>::new(key);
+    let content = EncapsulatedContentInfo {
+        econtent_type: ID_TST_INFO,
+        econtent: Some(Any::new(Tag::OctetString, info.to_der().unwrap()).unwrap()),
+    };
+    let digest_algorithm = AlgorithmIdentifierOwned {
+        oid: ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.1"),
+        parameters: None,
+    };
+    let signer_info = SignerInfoBuilder::new(
+        &signer,
+        SignerIdentifier::IssuerAndSerialNumber(IssuerAndSerialNumber {
+            issuer: certificate.tbs_certificate.issuer.clone(),
+            serial_number: certificate.tbs_certificate.serial_number.clone(),
+        }),
+        digest_algorithm.clone(),
+        &content,
+        None,
+    )
+    .unwrap();
+    let mut signed_data = SignedDataBuilder::new(&content);
+    signed_data
+        .add_digest_algorithm(digest_algorithm)
+        .unwrap()
+        .add_certificate(CertificateChoices::Certificate(certificate))
+        .unwrap()
+        .add_signer_info::<_, Signature>(signer_info)
+        .unwrap();
+    let response = TimeStampResp {
+        status: PkiStatusInfo {
+            status: 0,
+            status_string: None,
+            fail_info: None,
+        },
+        time_stamp_token: Some(signed_data.build().unwrap()),
+    };
+    response.to_der().unwrap()
+}
@@ -0,0 +1,297 @@
+//! A stand-in for Apple's notary service, and the S3 bucket submissions are uploaded to
+
+use super::{read_http_request, write_http_response, HttpRequest};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use p256::{
+    ecdsa::{signature::Verifier, Signature, SigningKey, VerifyingKey},
+    pkcs8::DecodePrivateKey,
+};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs,
+    net::TcpListener,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+/// The issuer ID the fake notary service expects
+pub const API_ISSUER: &str = "57246542-96fe-1a63-e053-0824d011072a";
+const SUBMISSION_ID: &str = "2efe2717-52ef-43a5-96dc-0797e4ca1041";
+const ACCESS_KEY_ID: &str = "FAKEACCESSKEYID";
+const SESSION_TOKEN: &str = "fake-session-token";
+
+/// The test API key, whose ID (`TESTKEY123`) is in its file name
+pub fn api_key_path() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/api-key/AuthKey_TESTKEY123.p8")
+}
+
+/// How [`fake_notary`] judges submissions
+#[derive(Clone, Copy, PartialEq)]
+pub enum NotaryMode {
+    /// Accepted, with a warning
+    Accept,
+    /// Invalid, with an error in the app's binary
+    Invalid,
+    /// Never finishes checking
+    Stuck,
+}
+
+/// Everything the fake notary service was sent
+#[derive(Default)]
+pub struct NotaryRecord {
+    pub submission_name: Option<String>,
+    pub sha256: Option<String>,
+    pub upload: Option<Vec<u8>>,
+    /// The claims of every token it was sent
+    pub claims: Vec<Value>,
+    pub polls: usize,
+    /// Tickets the ticket service has, by record name
+    pub tickets: BTreeMap<String, Vec<u8>>,
+}
+
+pub struct FakeNotary {
+    base: String,
+    pub record: Arc<Mutex<NotaryRecord>>,
+}
+impl FakeNotary {
+    pub fn notary_url(&self) -> String {
+        format!("{}/notary/v2", self.base)
+    }
+
+    pub fn upload_url(&self) -> String {
+        format!("{}/s3", self.base)
+    }
+
+    pub fn ticket_url(&self) -> String {
+        format!("{}/tickets/lookup", self.base)
+    }
+
+    /// Makes a ticket available from the ticket service
+    pub fn publish_ticket(&self, record_name: &str, ticket: &[u8]) {
+        let mut record = self.record.lock().unwrap();
+        record.tickets.insert(record_name.into(), ticket.into());
+    }
+}
+
+/// Starts a fake notary service, which only accepts tokens from the test API key
+pub fn fake_notary(mode: NotaryMode) -> FakeNotary {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let record = Arc::new(Mutex::new(NotaryRecord::default()));
+    let notary = FakeNotary {
+        base: base.clone(),
+        record: record.clone(),
+    };
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let Ok(request) = read_http_request(&mut stream) else {
+                continue;
+            };
+            let mut record = record.lock().unwrap();
+            let (status, body) = respond(&request, mode, &base, &mut record);
+            write_http_response(&mut stream, status, "application/json", body.as_bytes());
+        }
+    });
+    notary
+}
+
+fn respond(
+    request: &HttpRequest,
+    mode: NotaryMode,
+    base: &str,
+    record: &mut NotaryRecord,
+) -> (u16, String) {
+    let submission_path = format!("/notary/v2/submissions/{SUBMISSION_ID}");
+    // The upload is authenticated by S3, and the log by its URL
+    if request.method == "PUT" {
+        return upload(request, record);
+    }
+    if request.path == "/log" {
+        return (200, log(mode, record).to_string());
+    }
+    if request.path == "/tickets/lookup" {
+        return (200, lookup_ticket(request, record).to_string());
+    }
+
+    match check_token(request) {
+        Ok(claims) => record.claims.push(claims),
+        Err(reason) => {
+            let body = json!({ "errors": [{
+                "status": "401",
+                "code": "NOT_AUTHORIZED",
+                "title": "Authentication credentials are missing or invalid.",
+                "detail": reason,
+            }]});
+            return (401, body.to_string());
+        }
+    }
+    let body = match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/notary/v2/submissions") => {
+            let body: Value = serde_json::from_slice(&request.body).unwrap();
+            let name = body["submissionName"].as_str().unwrap().to_owned();
+            record.sha256 = Some(body["sha256"].as_str().unwrap().to_owned());
+            record.submission_name = Some(name.clone());
+            json!({ "data": {
+                "id": SUBMISSION_ID,
+                "type": "newSubmissions",
+                "attributes": {
+                    "awsAccessKeyId": ACCESS_KEY_ID,
+                    "awsSecretAccessKey": "fake-secret",
+                    "awsSessionToken": SESSION_TOKEN,
+                    "bucket": "notary-submissions",
+                    "object": format!("prod/{SUBMISSION_ID}/{name}"),
+                },
+            }})
+        }
+        ("GET", path) if path == submission_path => {
+            record.polls += 1;
+            let status = match mode {
+                _ if record.polls == 1 => "In Progress",
+                NotaryMode::Stuck => "In Progress",
+                NotaryMode::Accept => "Accepted",
+                NotaryMode::Invalid => "Invalid",
+            };
+            json!({ "data": {
+                "id": SUBMISSION_ID,
+                "type": "submissions",
+                "attributes": { "status": status, "name": record.submission_name },
+            }})
+        }
+        ("GET", path) if path == format!("{submission_path}/logs") => json!({ "data": {
+            "id": SUBMISSION_ID,
+            "type": "submissionsLog",
+            "attributes": { "developerLogUrl": format!("{base}/log") },
+        }}),
+        _ => return (404, json!({ "errors": [] }).to_string()),
+    };
+    (200, body.to_string())
+}
+
+/// Checks the request's token was signed by the test API key, returning its claims
+pub fn check_token(request: &HttpRequest) -> Result<Value, String> {
+    let Some(token) = request
+        .header("authorization")
+        .and_then(|header| header.strip_prefix("Bearer "))
+    else {
+        return Err("There's no token".into());
+    };
+    let parts: Vec<_> = token.split('.').collect();
+    let [header, claims, signature] = parts[..] else {
+        return Err("The token isn't a JWT".into());
+    };
+    let decode = |part: &str| URL_SAFE_NO_PAD.decode(part).map_err(|e| e.to_string());
+    let header: Value = serde_json::from_slice(&decode(header)?).map_err(|e| e.to_string())?;
+    let claims: Value = serde_json::from_slice(&decode(claims)?).map_err(|e| e.to_string())?;
+    let signature = Signature::from_slice(&decode(signature)?).map_err(|e| e.to_string())?;
+
+    let pem = fs::read_to_string(api_key_path()).unwrap();
+    let key = VerifyingKey::from(SigningKey::from_pkcs8_pem(&pem).unwrap());
+    let message = token.rsplit_once('.').unwrap().0;
+    if key.verify(message.as_bytes(), &signature).is_err() {
+        return Err("The token's signature is invalid".into());
+    }
+    if header["alg"] != "ES256" || header["kid"] != "TESTKEY123" {
+        return Err(format!("The token's header is wrong: {header}"));
+    }
+    if claims["iss"] != API_ISSUER || claims["aud"] != "appstoreconnect-v1" {
+        return Err(format!("The token's claims are wrong: {claims}"));
+    }
+    Ok(claims)
+}
+
+/// Takes an upload, as long as it's signed with the submission's credentials and is what was
+/// submitted
+fn upload(request: &HttpRequest, record: &mut NotaryRecord) -> (u16, String) {
+    let authorization = request.header("authorization").unwrap_or_default();
+    let hash = format!("{:x}", Sha256::digest(&request.body));
+    let signed = authorization
+        .starts_with(&format!("AWS4-HMAC-SHA256 Credential={ACCESS_KEY_ID}/"))
+        && authorization.contains("/us-west-2/s3/aws4_request")
+        && request.header("x-amz-security-token") == Some(SESSION_TOKEN)
+        && request.header("x-amz-content-sha256") == Some(hash.as_str());
+    if !signed {
+        return (403, "<Error><Code>AccessDenied</Code></Error>".into());
+    }
+    if record.sha256.as_deref() != Some(hash.as_str()) {
+        return (400, "<Error><Code>BadDigest</Code></Error>".into());
+    }
+    record.upload = Some(request.body.clone());
+    (200, String::new())
+}
+
+fn log(mode: NotaryMode, record: &NotaryRecord) -> Value {
+    let name = record.submission_name.clone().unwrap_or_default();
+    let binary = format!(
+        "{name}/{}.app/Contents/MacOS/test-app",
+        name.trim_end_matches(".zip")
+    );
+    let (status, summary, issue) = match mode {
+        NotaryMode::Accept => (
+            "Accepted",
+            "Ready for distribution",
+            json!({
+                "severity": "warning",
+                "code": null,
+                "path": binary,
+                "message": "The binary uses an SDK older than the 10.9 SDK.",
+                "docUrl": null,
+                "architecture": "arm64",
+            }),
+        ),
+        _ => (
+            "Invalid",
+            "Archive contains critical validation errors",
+            json!({
+                "severity": "error",
+                "code": null,
+                "path": binary,
+                "message": "The binary is not signed with a valid Developer ID certificate.",
+                "docUrl": "https://developer.apple.com/documentation/security/resolving-common-notarization-issues#3087721",
+                "architecture": "arm64",
+            }),
+        ),
+    };
+    json!({
+        "logFormatVersion": 1,
+        "jobId": SUBMISSION_ID,
+        "status": status,
+        "statusSummary": summary,
+        "archiveFilename": name,
+        "issues": [issue],
+    })
+}
+
+/// Looks up tickets like CloudKit does, with a record for each name that was asked for
+fn lookup_ticket(request: &HttpRequest, record: &NotaryRecord) -> Value {
+    let body: Value = serde_json::from_slice(&request.body).unwrap();
+    let records: Vec<_> = body["records"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|lookup| {
+            let name = lookup["recordName"].as_str().unwrap();
+            match record.tickets.get(name) {
+                Some(ticket) => json!({
+                    "recordName": name,
+                    "recordType": "DeveloperIDTicket",
+                    "fields": {
+                        "signedTicket": { "value": STANDARD.encode(ticket), "type": "BYTES" },
+                    },
+                }),
+                None => json!({
+                    "recordName": name,
+                    "reason": "Record not found",
+                    "serverErrorCode": "NOT_FOUND",
+                }),
+            }
+        })
+        .collect();
+    json!({ "records": records })
+}
@@ -0,0 +1,619 @@
+//! A stand-in for usbmuxd, with a device behind it running lockdownd, AFC, installation_proxy,
+//! syslog_relay, and the crash report services
+
+use plist::{Dictionary, Value};
+use rustls::{
+    client::danger::HandshakeSignatureValid,
+    crypto::{ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+    pki_types::{CertificateDer, PrivateKeyDer, UnixTime},
+    server::danger::{ClientCertVerified, ClientCertVerifier},
+    CertificateError, DigitallySignedStruct, DistinguishedName, ServerConfig, ServerConnection,
+    SignatureScheme, StreamOwned,
+};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{Cursor, Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+};
+use tempfile::TempDir;
+
+/// The fake device's UDID, which the test pairing record is for
+pub const UDID: &str = "00008030-001A2B3C4D5E802E";
+pub const DEVICE_NAME: &str = "Test iPhone";
+const DEVICE_ID: u64 = 3;
+const LOCKDOWN_PORT: u16 = 62078;
+const AFC_PORT: u16 = 49152;
+const INSTALLATION_PROXY_PORT: u16 = 49153;
+const SYSLOG_RELAY_PORT: u16 = 49154;
+const CRASH_REPORT_MOVER_PORT: u16 = 49155;
+const CRASH_REPORT_COPY_PORT: u16 = 49156;
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/pairing")
+        .join(name)
+}
+
+/// The test pairing record, as usbmuxd keeps it
+fn pair_record() -> Dictionary {
+    Value::from_file(fixture(&format!("{UDID}.plist")))
+        .unwrap()
+        .into_dictionary()
+        .unwrap()
+}
+
+fn pem_to_der(pem: &[u8]) -> Vec<u8> {
+    der::pem::decode_vec(pem).unwrap().1
+}
+
+/// How the fake device behaves
+#[derive(Clone, Copy, PartialEq)]
+pub enum DeviceMode {
+    /// Installs the app
+    Install,
+    /// Rejects the app's signature
+    RejectSignature,
+    /// Has no pairing record
+    Unpaired,
+    /// Runs out of space while the app's uploaded
+    Full,
+}
+
+/// Everything the fake device was sent
+pub struct DeviceRecord {
+    /// The devices usbmuxd lists, as (device ID, UDID, connection type). Only [`UDID`] is
+    /// paired.
+    pub devices: Vec<(u64, String, String)>,
+    /// Every lockdownd request, in order
+    pub lockdown_requests: Vec<String>,
+    /// Whether lockdownd's session switched to TLS, with the pairing record's host certificate
+    pub tls: bool,
+    /// Folders made over AFC
+    pub dirs: Vec<String>,
+    /// Files written over AFC
+    pub files: BTreeMap<String, Vec<u8>>,
+    /// How many packets the files were written in
+    pub writes: usize,
+    /// The package paths installation_proxy was asked to install
+    pub installs: Vec<String>,
+    /// The messages syslog_relay sends, before it closes the connection
+    pub log: Vec<String>,
+    /// The crash reports crashreportcopymobile has
+    pub crash_reports: BTreeMap<String, Vec<u8>>,
+    /// The crash reports that were read over AFC
+    pub reads: Vec<String>,
+}
+impl Default for DeviceRecord {
+    fn default() -> Self {
+        Self {
+            devices: vec![
+                (DEVICE_ID, UDID.into(), "USB".into()),
+                (7, "00008101-000A1B2C3D4E5F60".into(), "Network".into()),
+            ],
+            lockdown_requests: Vec::new(),
+            tls: false,
+            dirs: Vec::new(),
+            files: BTreeMap::new(),
+            writes: 0,
+            installs: Vec::new(),
+            log: [
+                "Oct 18 10:10:09 Test iPhone SpringBoard(FrontBoard)[58] <Notice>: Launching com.example.test-app",
+                "Oct 18 10:10:10 Test iPhone test-app[1234] <Notice>: Hello from the app",
+                "Oct 18 10:10:10 Test iPhone test-app-helper[1240] <Notice>: Not from the app",
+                "Oct 18 10:10:11 Test iPhone test-app(CoreFoundation)[1234] <Error>: Something broke\nacross two lines",
+            ]
+            .map(String::from)
+            .to_vec(),
+            crash_reports: [
+                ("test-app-2026-10-18-101010.ips", b"first crash".to_vec()),
+                // Big enough to be read in a few chunks
+                ("test-app-2026-10-18-111111.ips", vec![7; 150_000]),
+                ("test-app-helper-2026-10-18-101010.ips", b"helper".to_vec()),
+                ("SpringBoard-2026-10-18-101010.ips", b"springboard".to_vec()),
+                ("JetsamEvent-2026-10-18-101010.ips.synced", Vec::new()),
+            ]
+            .map(|(name, data)| (name.to_string(), data))
+            .into(),
+            reads: Vec::new(),
+        }
+    }
+}
+
+pub struct FakeUsbmuxd {
+    _dir: TempDir,
+    pub socket: PathBuf,
+    pub record: Arc<Mutex<DeviceRecord>>,
+}
+
+/// Starts a fake usbmuxd on a socket in a temporary folder
+pub fn fake_usbmuxd(mode: DeviceMode) -> FakeUsbmuxd {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("usbmuxd");
+    let listener = UnixListener::bind(&socket).unwrap();
+    let record = Arc::new(Mutex::new(DeviceRecord::default()));
+    let usbmuxd = FakeUsbmuxd {
+        _dir: dir,
+        socket,
+        record: record.clone(),
+    };
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let record = record.clone();
+            // Connections turn into tunnels to the device, so they're served at the same time
+            thread::spawn(move || serve(stream, mode, &record));
+        }
+    });
+    usbmuxd
+}
+
+fn serve(mut stream: UnixStream, mode: DeviceMode, record: &Mutex<DeviceRecord>) {
+    let mut header = [0; 16];
+    if stream.read_exact(&mut header).is_err() {
+        return;
+    }
+    let field = |i: usize| u32::from_le_bytes(header[i * 4..i * 4 + 4].try_into().unwrap());
+    let (len, tag) = (field(0), field(3));
+    assert_eq!(
+        (field(1), field(2)),
+        (1, 8),
+        "only plist messages are supported"
+    );
+    let mut payload = vec![0; len as usize - 16];
+    stream.read_exact(&mut payload).unwrap();
+    let message = Value::from_reader(Cursor::new(payload))
+        .unwrap()
+        .into_dictionary()
+        .unwrap();
+    let result = |number: u64| {
+        let mut reply = Dictionary::new();
+        reply.insert("MessageType".into(), "Result".into());
+        reply.insert("Number".into(), number.into());
+        reply
+    };
+
+    let message_type = message["MessageType"].as_string().unwrap();
+    let reply = match message_type {
+        "ListDevices" => {
+            let devices: Vec<Value> = record
+                .lock()
+                .unwrap()
+                .devices
+                .iter()
+                .map(|(device_id, udid, connection_type)| {
+                    let mut properties = Dictionary::new();
+                    properties.insert("DeviceID".into(), (*device_id).into());
+                    properties.insert("SerialNumber".into(), udid.clone().into());
+                    properties.insert("ConnectionType".into(), connection_type.clone().into());
+                    let mut device = Dictionary::new();
+                    device.insert("MessageType".into(), "Attached".into());
+                    device.insert("DeviceID".into(), (*device_id).into());
+                    device.insert("Properties".into(), properties.into());
+                    device.into()
+                })
+                .collect();
+            let mut reply = Dictionary::new();
+            reply.insert("DeviceList".into(), devices.into());
+            reply
+        }
+        "ReadPairRecord" => {
+            let udid = message["PairRecordID"].as_string().unwrap();
+            if udid == UDID && mode != DeviceMode::Unpaired {
+                let mut data = Vec::new();
+                Value::from(pair_record()).to_writer_xml(&mut data).unwrap();
+                let mut reply = Dictionary::new();
+                reply.insert("PairRecordData".into(), Value::Data(data));
+                reply
+            } else {
+                result(2)
+            }
+        }
+        "Connect" => {
+            let device_id = message["DeviceID"].as_unsigned_integer().unwrap();
+            let port = u16::from_be(message["PortNumber"].as_unsigned_integer().unwrap() as u16);
+            let known_port = [
+                LOCKDOWN_PORT,
+                AFC_PORT,
+                INSTALLATION_PROXY_PORT,
+                SYSLOG_RELAY_PORT,
+                CRASH_REPORT_MOVER_PORT,
+                CRASH_REPORT_COPY_PORT,
+            ]
+            .contains(&port);
+            match (device_id == DEVICE_ID, known_port) {
+                (true, true) => result(0),
+                (true, false) => result(3),
+                (false, _) => result(2),
+            }
+        }
+        _ => result(1),
+    };
+    let connected = message_type == "Connect" && reply["Number"].as_unsigned_integer() == Some(0);
+    let port = message
+        .get("PortNumber")
+        .and_then(Value::as_unsigned_integer)
+        .map(|port| u16::from_be(port as u16));
+
+    let mut payload = Vec::new();
+    Value::from(reply).to_writer_xml(&mut payload).unwrap();
+    let mut data = Vec::new();
+    for field in [16 + payload.len() as u32, 1, 8, tag] {
+        data.extend_from_slice(&field.to_le_bytes());
+    }
+    data.extend_from_slice(&payload);
+    stream.write_all(&data).unwrap();
+
+    // From here on, the connection goes to the device
+    if connected {
+        match port {
+            Some(LOCKDOWN_PORT) => lockdown(stream, record),
+            Some(AFC_PORT) => afc(stream, mode, false, record),
+            Some(INSTALLATION_PROXY_PORT) => installation_proxy(stream, mode, record),
+            Some(SYSLOG_RELAY_PORT) => syslog_relay(stream, record),
+            Some(CRASH_REPORT_MOVER_PORT) => stream.write_all(b"ping").unwrap(),
+            Some(CRASH_REPORT_COPY_PORT) => afc(stream, mode, true, record),
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn send_plist(stream: &mut impl Write, message: Dictionary) {
+    let mut xml = Vec::new();
+    Value::from(message).to_writer_xml(&mut xml).unwrap();
+    let _ = stream.write_all(&(xml.len() as u32).to_be_bytes());
+    let _ = stream.write_all(&xml);
+    let _ = stream.flush();
+}
+
+fn read_plist(stream: &mut impl Read) -> Option<Dictionary> {
+    let mut len = [0; 4];
+    stream.read_exact(&mut len).ok()?;
+    let mut data = vec![0; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut data).ok()?;
+    Value::from_reader(Cursor::new(data))
+        .ok()?
+        .into_dictionary()
+}
+
+fn lockdown(mut stream: UnixStream, record: &Mutex<DeviceRecord>) {
+    // The session starts in plain text, then switches to TLS
+    while let Some(request) = read_plist(&mut stream) {
+        let (reply, start_tls) = lockdown_reply(&request, record);
+        send_plist(&mut stream, reply);
+        if start_tls {
+            let conn = ServerConnection::new(tls_config()).unwrap();
+            let mut stream = StreamOwned::new(conn, stream);
+            while let Some(request) = read_plist(&mut stream) {
+                record.lock().unwrap().tls = true;
+                let (reply, _) = lockdown_reply(&request, record);
+                send_plist(&mut stream, reply);
+            }
+            return;
+        }
+    }
+}
+
+/// Replies to a lockdownd request, and says whether the session's switching to TLS
+fn lockdown_reply(request: &Dictionary, record: &Mutex<DeviceRecord>) -> (Dictionary, bool) {
+    let name = request["Request"].as_string().unwrap().to_owned();
+    record.lock().unwrap().lockdown_requests.push(name.clone());
+    let mut reply = Dictionary::new();
+    reply.insert("Request".into(), name.clone().into());
+    let mut start_tls = false;
+    match name.as_str() {
+        "QueryType" => {
+            reply.insert("Type".into(), "com.apple.mobile.lockdown".into());
+        }
+        "StartSession" => {
+            let pair_record = pair_record();
+            if request.get("HostID") != pair_record.get("HostID")
+                || request.get("SystemBUID") != pair_record.get("SystemBUID")
+            {
+                reply.insert("Error".into(), "InvalidHostID".into());
+            } else {
+                reply.insert(
+                    "SessionID".into(),
+                    "A1B2C3D4-0000-4000-8000-000000000001".into(),
+                );
+                reply.insert("EnableSessionSSL".into(), true.into());
+                start_tls = true;
+            }
+        }
+        "GetValue" => match request["Key"].as_string().unwrap() {
+            "DeviceName" => {
+                reply.insert("Value".into(), DEVICE_NAME.into());
+            }
+            "ProductVersion" => {
+                reply.insert("Value".into(), "17.4".into());
+            }
+            _ => {
+                reply.insert("Error".into(), "MissingValue".into());
+            }
+        },
+        "StartService" => match request["Service"].as_string().unwrap() {
+            "com.apple.afc" => {
+                reply.insert("Port".into(), u64::from(AFC_PORT).into());
+            }
+            "com.apple.mobile.installation_proxy" => {
+                reply.insert("Port".into(), u64::from(INSTALLATION_PROXY_PORT).into());
+            }
+            "com.apple.syslog_relay" => {
+                reply.insert("Port".into(), u64::from(SYSLOG_RELAY_PORT).into());
+            }
+            "com.apple.crashreportmover" => {
+                reply.insert("Port".into(), u64::from(CRASH_REPORT_MOVER_PORT).into());
+            }
+            "com.apple.crashreportcopymobile" => {
+                reply.insert("Port".into(), u64::from(CRASH_REPORT_COPY_PORT).into());
+            }
+            _ => {
+                reply.insert("Error".into(), "InvalidService".into());
+            }
+        },
+        _ => {
+            reply.insert("Error".into(), "InvalidRequest".into());
+        }
+    }
+    (reply, start_tls)
+}
+
+/// The device's side of lockdownd's TLS sessions, which only accepts the pairing record's
+/// host certificate
+fn tls_config() -> Arc<ServerConfig> {
+    let pair_record = pair_record();
+    let data = |key: &str| pem_to_der(pair_record[key].as_data().unwrap());
+    let device_key = pem_to_der(&fs::read(fixture("device.key")).unwrap());
+    let provider = Arc::new(ring::default_provider());
+    let verifier = PairedHost {
+        certificate: data("HostCertificate"),
+        provider: provider.clone(),
+    };
+    let config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_client_cert_verifier(Arc::new(verifier))
+        .with_single_cert(
+            vec![CertificateDer::from(data("DeviceCertificate"))],
+            PrivateKeyDer::Pkcs1(device_key.into()),
+        )
+        .unwrap();
+    Arc::new(config)
+}
+
+#[derive(Debug)]
+struct PairedHost {
+    certificate: Vec<u8>,
+    provider: Arc<CryptoProvider>,
+}
+impl ClientCertVerifier for PairedHost {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        match end_entity.as_ref() == self.certificate {
+            true => Ok(ClientCertVerified::assertion()),
+            false => Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            )),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        let algorithms = &self.provider.signature_verification_algorithms;
+        verify_tls12_signature(message, cert, dss, algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        let algorithms = &self.provider.signature_verification_algorithms;
+        verify_tls13_signature(message, cert, dss, algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Serves AFC packets: making folders, listing them, and opening, reading, writing, and closing
+/// files. crashreportcopymobile's AFC only has the crash reports.
+fn afc(
+    mut stream: UnixStream,
+    mode: DeviceMode,
+    crash_reports: bool,
+    record: &Mutex<DeviceRecord>,
+) {
+    // Open files' paths, and how far they've been read
+    let mut handles: BTreeMap<u64, (String, usize)> = BTreeMap::new();
+    let mut packet_num = 0;
+    loop {
+        let mut header = [0; 40];
+        if stream.read_exact(&mut header).is_err() {
+            return;
+        }
+        assert_eq!(&header[..8], b"CFA6LPAA");
+        let field =
+            |i: usize| u64::from_le_bytes(header[8 + i * 8..16 + i * 8].try_into().unwrap());
+        let (entire_len, this_len, operation) = (field(0), field(1), field(3));
+        let mut header_data = vec![0; this_len as usize - 40];
+        stream.read_exact(&mut header_data).unwrap();
+        let mut payload = vec![0; (entire_len - this_len) as usize];
+        stream.read_exact(&mut payload).unwrap();
+        let path =
+            |data: &[u8]| String::from_utf8(data.strip_suffix(&[0]).unwrap().to_vec()).unwrap();
+
+        let handle = || u64::from_le_bytes(header_data[..8].try_into().unwrap());
+        let status = |code: u64| (0x01, code.to_le_bytes().to_vec(), Vec::new());
+
+        let mut record = record.lock().unwrap();
+        let (reply_operation, reply_header_data, reply_payload) = match operation {
+            // List a folder. Crash reports are all at the root.
+            0x03 if crash_reports => {
+                let mut names = vec![".".to_string(), "..".to_string()];
+                names.extend(record.crash_reports.keys().cloned());
+                let mut listing = Vec::new();
+                for name in names {
+                    listing.extend_from_slice(name.as_bytes());
+                    listing.push(0);
+                }
+                (0x02, Vec::new(), listing)
+            }
+            // Make a folder
+            0x09 if !crash_reports => {
+                record.dirs.push(path(&header_data));
+                status(0)
+            }
+            // Open a file, for reading (1) or writing (3)
+            0x0D => {
+                let open_mode = u64::from_le_bytes(header_data[..8].try_into().unwrap());
+                let path = path(&header_data[8..]);
+                match (open_mode, crash_reports) {
+                    (1, true)
+                        if record
+                            .crash_reports
+                            .contains_key(path.trim_start_matches('/')) =>
+                    {
+                        record.reads.push(path.clone());
+                    }
+                    (3, false) => {
+                        record.files.insert(path.clone(), Vec::new());
+                    }
+                    _ => {
+                        drop(record);
+                        reply(&mut stream, &mut packet_num, status(8));
+                        continue;
+                    }
+                }
+                let handle = handles.len() as u64 + 1;
+                handles.insert(handle, (path, 0));
+                (0x0E, handle.to_le_bytes().to_vec(), Vec::new())
+            }
+            // Read from a file
+            0x0F => {
+                let len = u64::from_le_bytes(header_data[8..16].try_into().unwrap()) as usize;
+                let (path, offset) = handles.get_mut(&handle()).unwrap();
+                let file = &record.crash_reports[path.trim_start_matches('/')];
+                let end = file.len().min(*offset + len);
+                let chunk = file[*offset..end].to_vec();
+                *offset = end;
+                (0x02, Vec::new(), chunk)
+            }
+            // Write to a file
+            0x10 => {
+                record.writes += 1;
+                if mode == DeviceMode::Full && record.writes > 1 {
+                    status(18)
+                } else {
+                    let file = record.files.get_mut(&handles[&handle()].0).unwrap();
+                    file.extend_from_slice(&payload);
+                    status(0)
+                }
+            }
+            // Close a file
+            0x14 => status(0),
+            _ => status(15),
+        };
+        drop(record);
+        reply(
+            &mut stream,
+            &mut packet_num,
+            (reply_operation, reply_header_data, reply_payload),
+        );
+    }
+}
+
+/// Sends an AFC packet, as its operation, header data, and payload
+fn reply(stream: &mut UnixStream, packet_num: &mut u64, packet: (u64, Vec<u8>, Vec<u8>)) {
+    let (operation, header_data, payload) = packet;
+    let this_len = 40 + header_data.len() as u64;
+    let mut reply = b"CFA6LPAA".to_vec();
+    for field in [
+        this_len + payload.len() as u64,
+        this_len,
+        *packet_num,
+        operation,
+    ] {
+        reply.extend_from_slice(&field.to_le_bytes());
+    }
+    reply.extend_from_slice(&header_data);
+    reply.extend_from_slice(&payload);
+    *packet_num += 1;
+    stream.write_all(&reply).unwrap();
+}
+
+/// Sends the device's log messages, each ending with a newline and NUL like the real thing,
+/// then hangs up
+fn syslog_relay(mut stream: UnixStream, record: &Mutex<DeviceRecord>) {
+    let log = record.lock().unwrap().log.clone();
+    for message in log {
+        let _ = stream.write_all(message.as_bytes());
+        let _ = stream.write_all(b"\n\0");
+    }
+}
+
+/// Installs uploaded packages, reporting progress along the way
+fn installation_proxy(mut stream: UnixStream, mode: DeviceMode, record: &Mutex<DeviceRecord>) {
+    let Some(request) = read_plist(&mut stream) else {
+        return;
+    };
+    assert_eq!(request["Command"].as_string(), Some("Install"));
+    let package_path = request["PackagePath"].as_string().unwrap().to_owned();
+    let uploaded = {
+        let mut record = record.lock().unwrap();
+        record.installs.push(package_path.clone());
+        record.files.contains_key(&package_path)
+    };
+
+    let status = |status: &str, percent: u64| {
+        let mut message = Dictionary::new();
+        message.insert("Status".into(), status.into());
+        message.insert("PercentComplete".into(), percent.into());
+        message
+    };
+    let error = |error: &str, description: &str| {
+        let mut message = Dictionary::new();
+        message.insert("Error".into(), error.into());
+        message.insert("ErrorDescription".into(), description.into());
+        message
+    };
+    send_plist(&mut stream, status("CreatingStagingDirectory", 5));
+    if !uploaded {
+        let message = error("PackageExtractionFailed", "The package doesn't exist");
+        send_plist(&mut stream, message);
+        return;
+    }
+    send_plist(&mut stream, status("ExtractingPackage", 15));
+    send_plist(&mut stream, status("VerifyingApplication", 40));
+    if mode == DeviceMode::RejectSignature {
+        let message = error(
+            "ApplicationVerificationFailed",
+            "Failed to verify code signature of /private/var/installd/Library/Caches/com.apple.mobile.installd.staging/temp.x7Qe2h/extracted/Payload/Test App.app : 0xe8008015 (A valid provisioning profile for this executable was not found.)",
+        );
+        send_plist(&mut stream, message);
+        return;
+    }
+    send_plist(&mut stream, status("InstallingApplication", 80));
+    let mut complete = Dictionary::new();
+    complete.insert("Status".into(), "Complete".into());
+    send_plist(&mut stream, complete);
+}
@@ -0,0 +1,116 @@
+use cargo_ipa::{config, error::Error};
+
+/// A Cargo.toml with `settings` in its cargo-ipa section, which starts on line 6
+fn cargo_toml(settings: &str) -> String {
+    format!(
+        "[package]\nname = \"test-app\"\nversion = \"0.1.0\"\n\n[package.metadata.cargo-ipa]\n{settings}"
+    )
+}
+
+/// Parses a Cargo.toml, expecting a config error, and returns its message
+fn config_error(source: &str) -> String {
+    match config::parse(source) {
+        Err(Error::Config(message)) => message,
+        Err(e) => panic!("expected a config error, got {e}"),
+        Ok(_) => panic!("parsing succeeded"),
+    }
+}
+
+#[test]
+fn known_settings_are_read() {
+    let manifest = config::parse(&cargo_toml(
+        "name = \"Test App\"\nbundle-id = \"com.example.test-app\"\n",
+    ))
+    .unwrap();
+    assert_eq!(manifest.name, "test-app");
+    assert_eq!(manifest.version, "0.1.0");
+    assert_eq!(manifest.config.name.as_deref(), Some("Test App"));
+    assert_eq!(
+        manifest.config.bundle_id.as_deref(),
+        Some("com.example.test-app")
+    );
+    assert!(manifest.warnings.is_empty());
+}
+
+#[test]
+fn unknown_settings_are_warned_about() {
+    let manifest = config::parse(&cargo_toml(
+        "name = \"Test App\"\nfavourite-colour = \"red\"\n",
+    ))
+    .unwrap();
+    assert_eq!(
+        manifest.warnings,
+        ["Unknown setting `package.metadata.cargo-ipa.favourite-colour` (line 7) will be ignored."]
+    );
+}
+
+#[test]
+fn typos_suggest_the_closest_setting() {
+    let manifest = config::parse(&cargo_toml(
+        "bundel-id = \"com.example.test-app\"\ncapabilites = [\"push\"]\n",
+    ))
+    .unwrap();
+    assert_eq!(
+        manifest.warnings,
+        [
+            "Unknown setting `package.metadata.cargo-ipa.bundel-id` (line 6) will be ignored. Did you mean `bundle-id`?",
+            "Unknown setting `package.metadata.cargo-ipa.capabilites` (line 7) will be ignored. Did you mean `capabilities`?",
+        ]
+    );
+    // The misspelt settings don't take effect
+    assert!(manifest.config.bundle_id.is_none());
+}
+
+#[test]
+fn invalid_settings_name_the_setting_and_line() {
+    let message = config_error(&cargo_toml(
+        "name = \"Test App\"\ncapabilities = \"push\"\n",
+    ));
+    assert!(
+        message.starts_with(
+            "Invalid Cargo.toml detected! At `package.metadata.cargo-ipa.capabilities` (line 7): "
+        ),
+        "{message}"
+    );
+
+    // Nested settings are checked too
+    let message = config_error(&cargo_toml(
+        "\n[package.metadata.cargo-ipa.signing]\nhardened-runtime = \"yes\"\n",
+    ));
+    assert!(
+        message.starts_with(
+            "Invalid Cargo.toml detected! At `package.metadata.cargo-ipa.signing.hardened-runtime` (line 8): "
+        ),
+        "{message}"
+    );
+}
+
+#[test]
+fn typos_in_nested_tables_suggest_the_closest_setting() {
+    let manifest = config::parse(&cargo_toml(
+        "\n[package.metadata.cargo-ipa.signing]\nidentity = \"a.p12\"\nprovisioning-profil = \"a.mobileprovision\"\n\n[package.metadata.cargo-ipa.signing.app-store]\npasword-file = \"password.txt\"\n\n[package.metadata.cargo-ipa.app-store-connect]\napi-isuer = \"1234\"\n\n[package.metadata.cargo-ipa.properties]\nUIAnything = true\n",
+    ))
+    .unwrap();
+    assert_eq!(
+        manifest.warnings,
+        [
+            "Unknown setting `package.metadata.cargo-ipa.signing.provisioning-profil` (line 9) will be ignored. Did you mean `provisioning-profile`?",
+            "Unknown setting `package.metadata.cargo-ipa.signing.app-store.pasword-file` (line 12) will be ignored. Did you mean `password-file`?",
+            "Unknown setting `package.metadata.cargo-ipa.app-store-connect.api-isuer` (line 15) will be ignored. Did you mean `api-issuer`?",
+        ]
+    );
+    // The rest of the table still takes effect
+    assert_eq!(
+        manifest.config.signing.identity.as_deref(),
+        Some(std::path::Path::new("a.p12"))
+    );
+}
+
+#[test]
+fn syntax_errors_name_the_line() {
+    let message = config_error(&cargo_toml("name = \"Test App\n"));
+    assert!(
+        message.starts_with("Invalid Cargo.toml detected! At line 6: "),
+        "{message}"
+    );
+}
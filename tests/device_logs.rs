@@ -0,0 +1,178 @@
+mod common;
+
+use cargo_ipa::{
+    context::Ctx,
+    crashes::{crashes_with, CrashesArgs},
+    device::syslog_relay::process_name,
+    error::Error,
+    logs::{logs_with, LogsArgs},
+};
+use common::{usbmux::*, *};
+use std::fs;
+use tempfile::TempDir;
+
+fn test_project() -> (TempDir, Ctx) {
+    let project = project(CARGO_TOML);
+    let ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+    (project, ctx)
+}
+
+fn logs_args(usbmuxd: &FakeUsbmuxd) -> LogsArgs {
+    LogsArgs {
+        name: None,
+        process: None,
+        all: false,
+        device: None,
+        usbmux_socket: usbmuxd.socket.clone(),
+    }
+}
+
+fn crashes_args(usbmuxd: &FakeUsbmuxd) -> CrashesArgs {
+    CrashesArgs {
+        name: None,
+        process: None,
+        all: false,
+        output: None,
+        device: None,
+        usbmux_socket: usbmuxd.socket.clone(),
+    }
+}
+
+// ========== LOGS ==========
+
+#[test]
+fn logs_only_show_the_apps_messages() {
+    let (_project, ctx) = test_project();
+    let usbmuxd = fake_usbmuxd(DeviceMode::Install);
+
+    let mut messages = Vec::new();
+    logs_with(&logs_args(&usbmuxd), Some(&ctx), |message| {
+        messages.push(message.to_string())
+    })
+    .unwrap();
+    // Messages from processes with similar names, and ones that mention the app, are left out
+    assert_eq!(
+        messages,
+        [
+            "Oct 18 10:10:10 Test iPhone test-app[1234] <Notice>: Hello from the app",
+            "Oct 18 10:10:11 Test iPhone test-app(CoreFoundation)[1234] <Error>: Something broke\nacross two lines",
+        ]
+    );
+    let record = usbmuxd.record.lock().unwrap();
+    assert!(record.tls);
+}
+
+#[test]
+fn logs_can_show_other_processes() {
+    let usbmuxd = fake_usbmuxd(DeviceMode::Install);
+
+    let mut args = logs_args(&usbmuxd);
+    args.process = Some("SpringBoard".into());
+    let mut messages = Vec::new();
+    logs_with(&args, None, |message| messages.push(message.to_string())).unwrap();
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].contains("Launching com.example.test-app"));
+
+    args.process = None;
+    args.all = true;
+    let mut count = 0;
+    logs_with(&args, None, |_| count += 1).unwrap();
+    assert_eq!(count, 4);
+}
+
+#[test]
+fn logs_need_a_process_outside_projects() {
+    let usbmuxd = fake_usbmuxd(DeviceMode::Install);
+
+    let err = logs_with(&logs_args(&usbmuxd), None, |_| {}).unwrap_err();
+    assert!(matches!(err, Error::Config(_)));
+    // The device wasn't bothered
+    assert!(usbmuxd.record.lock().unwrap().lockdown_requests.is_empty());
+}
+
+#[test]
+fn process_names_are_found_after_device_names() {
+    let message =
+        "Oct  8 09:00:00 Jane's [Work] iPad (2) backboardd(CoreBrightness)[66] <Notice>: Hi";
+    assert_eq!(process_name(message), Some("backboardd"));
+    assert_eq!(process_name("--- last message repeated 1 time ---"), None);
+}
+
+// ========== CRASHES ==========
+
+#[test]
+fn the_apps_crash_reports_are_copied() {
+    let (_project, ctx) = test_project();
+    let usbmuxd = fake_usbmuxd(DeviceMode::Install);
+
+    let copied = crashes_with(&crashes_args(&usbmuxd), Some(&ctx)).unwrap();
+    let crashes = ctx.cargo_ipa_dir.join("crashes");
+    assert_eq!(
+        copied,
+        [
+            crashes.join("test-app-2026-10-18-101010.ips"),
+            crashes.join("test-app-2026-10-18-111111.ips"),
+        ]
+    );
+    assert_eq!(fs::read(&copied[0]).unwrap(), b"first crash");
+    assert_eq!(fs::read(&copied[1]).unwrap(), vec![7; 150_000]);
+    // The helper's crashes weren't copied
+    assert_eq!(fs::read_dir(&crashes).unwrap().count(), 2);
+
+    // The crash reports were moved before they were listed
+    let record = usbmuxd.record.lock().unwrap();
+    assert!(record.lockdown_requests.iter().any(|r| r == "StartService"));
+    assert_eq!(
+        record.reads,
+        [
+            "/test-app-2026-10-18-101010.ips",
+            "/test-app-2026-10-18-111111.ips"
+        ]
+    );
+}
+
+#[test]
+fn crash_reports_are_only_copied_once() {
+    let (_project, ctx) = test_project();
+    let usbmuxd = fake_usbmuxd(DeviceMode::Install);
+
+    let crashes = ctx.cargo_ipa_dir.join("crashes");
+    fs::create_dir_all(&crashes).unwrap();
+    fs::write(crashes.join("test-app-2026-10-18-101010.ips"), "kept").unwrap();
+
+    let copied = crashes_with(&crashes_args(&usbmuxd), Some(&ctx)).unwrap();
+    assert_eq!(copied, [crashes.join("test-app-2026-10-18-111111.ips")]);
+    let kept = fs::read_to_string(crashes.join("test-app-2026-10-18-101010.ips")).unwrap();
+    assert_eq!(kept, "kept");
+
+    let copied = crashes_with(&crashes_args(&usbmuxd), Some(&ctx)).unwrap();
+    assert!(copied.is_empty());
+}
+
+#[test]
+fn every_crash_report_can_be_copied() {
+    let output = tempfile::tempdir().unwrap();
+    let usbmuxd = fake_usbmuxd(DeviceMode::Install);
+
+    let mut args = crashes_args(&usbmuxd);
+    args.all = true;
+    args.output = Some(output.path().to_path_buf());
+    let copied = crashes_with(&args, None).unwrap();
+    // Only .ips files are crash reports
+    assert_eq!(copied.len(), 4);
+    assert!(!output
+        .path()
+        .join("JetsamEvent-2026-10-18-101010.ips.synced")
+        .exists());
+}
+
+#[test]
+fn crash_reports_need_somewhere_to_go_outside_projects() {
+    let usbmuxd = fake_usbmuxd(DeviceMode::Install);
+
+    let mut args = crashes_args(&usbmuxd);
+    args.process = Some("test-app".into());
+    let err = crashes_with(&args, None).unwrap_err();
+    assert!(matches!(err, Error::Config(_)));
+    assert!(err.to_string().contains("--output"), "{err}");
+}
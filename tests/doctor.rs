@@ -0,0 +1,94 @@
+use cargo_ipa::{
+    context::{Architecture, Platform},
+    doctor::{diagnose, install_targets, missing_targets, Status},
+    runner::{FakeRunner, ToolOutput},
+};
+
+const IOS_DEVICE: &[(Platform, Architecture)] = &[(Platform::iOS, Architecture::aarch64)];
+
+/// A machine with everything cargo-ipa needs
+fn healthy_machine() -> FakeRunner {
+    FakeRunner::new()
+        .with_output("cargo", "cargo 1.70.0")
+        .with_output("rustup", "aarch64-apple-ios\naarch64-apple-darwin\n")
+        .with_output(
+            "xcode-select",
+            "/Applications/Xcode.app/Contents/Developer\n",
+        )
+        .with_output("xcrun", "/path/to/iPhoneOS.sdk\n")
+        .with_output("zip", "")
+        .with_output("dsymutil", "")
+        .with_output("chmod", "")
+        .with_output("strip", "")
+        .with_output("unzip", "")
+        .with_output("git", "git version 2.39.3\n")
+        .with_output("swift", "swift-driver version: 1.75.2\n")
+}
+
+fn failures(runner: &FakeRunner, needs_swift: bool) -> Vec<String> {
+    diagnose(runner, IOS_DEVICE, needs_swift)
+        .into_iter()
+        .filter(|check| check.status == Status::Failed)
+        .map(|check| check.message)
+        .collect()
+}
+
+#[test]
+fn healthy_machine_passes() {
+    assert!(failures(&healthy_machine(), true).is_empty());
+}
+
+#[test]
+fn missing_rustup_target_is_reported() {
+    let runner = healthy_machine().with_output("rustup", "x86_64-apple-darwin\n");
+    assert_eq!(
+        failures(&runner, false),
+        ["rustup target `aarch64-apple-ios` isn't installed"]
+    );
+    assert_eq!(
+        missing_targets(&runner, IOS_DEVICE).unwrap(),
+        ["aarch64-apple-ios"]
+    );
+}
+
+#[test]
+fn command_line_tools_are_not_enough() {
+    let runner =
+        healthy_machine().with_output("xcode-select", "/Library/Developer/CommandLineTools\n");
+    let failures = failures(&runner, false);
+    assert_eq!(failures.len(), 1);
+    assert!(failures[0].contains("Command Line Tools"), "{failures:?}");
+}
+
+#[test]
+fn missing_tools_and_sdks_are_reported() {
+    let runner = healthy_machine()
+        .with_tool("zip", |_| Err(std::io::ErrorKind::NotFound.into()))
+        .with_tool("unzip", |_| Err(std::io::ErrorKind::NotFound.into()))
+        .with_tool("xcrun", |_| {
+            Ok(ToolOutput::failure(
+                "xcrun: error: SDK \"iphoneos\" cannot be located",
+            ))
+        });
+    let failures = failures(&runner, false);
+    assert!(failures.contains(&"`zip` isn't installed, or isn't on your PATH".to_string()));
+    assert!(failures.contains(&"`unzip` isn't installed, or isn't on your PATH".to_string()));
+    assert!(failures.contains(&"The `iphoneos` SDK couldn't be found".to_string()));
+}
+
+#[test]
+fn swift_is_only_required_for_swift_bridge_projects() {
+    let runner = healthy_machine().with_tool("swift", |_| Err(std::io::ErrorKind::NotFound.into()));
+    assert!(failures(&runner, false).is_empty());
+    assert_eq!(failures(&runner, true).len(), 1);
+}
+
+#[test]
+fn fix_installs_missing_targets() {
+    let runner = healthy_machine();
+    install_targets(&runner, &["aarch64-apple-ios".to_string()]).unwrap();
+    assert_eq!(
+        runner.calls_to("rustup")[0].args,
+        ["target", "add", "aarch64-apple-ios"]
+    );
+}
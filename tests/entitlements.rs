@@ -0,0 +1,309 @@
+use cargo_ipa::{
+    config::Config,
+    context::ExportMethod,
+    error::Error,
+    sign::{entitlements, identity::Identity, profile::ProvisioningProfile},
+};
+use plist::{Dictionary, Value};
+use std::path::PathBuf;
+
+const BUNDLE_ID: &str = "com.example.test-app";
+
+fn fixture_path(folder: &str, name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(folder)
+        .join(name)
+}
+
+fn profile() -> ProvisioningProfile {
+    ProvisioningProfile::load(&fixture_path("profile", "test.mobileprovision")).unwrap()
+}
+
+fn identity() -> Identity {
+    Identity::load(
+        &fixture_path("identity", "identity.p12"),
+        "cargo-ipa",
+        &[] as &[PathBuf],
+    )
+    .unwrap()
+}
+
+fn config(capabilities: &[&str]) -> Config {
+    Config {
+        capabilities: capabilities.iter().map(|c| c.to_string()).collect(),
+        ..Default::default()
+    }
+}
+
+fn strings(value: &Value) -> Vec<&str> {
+    value
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|value| value.as_string().unwrap())
+        .collect()
+}
+
+#[test]
+fn nothing_is_generated_for_ad_hoc_apps_without_capabilities() {
+    let entitlements = entitlements::generate(&config(&[]), BUNDLE_ID, None, None, false).unwrap();
+    assert!(entitlements.is_empty());
+}
+
+#[test]
+fn capabilities_expand_into_entitlements() {
+    let identity = identity();
+    let profile = profile();
+    let cfg = config(&[
+        "push",
+        "app-groups:group.com.example.shared",
+        "app-groups:group.com.example.other",
+        "keychain-sharing",
+        "icloud",
+    ]);
+    let entitlements =
+        entitlements::generate(&cfg, BUNDLE_ID, Some(&identity), Some(&profile), false).unwrap();
+
+    assert_eq!(
+        entitlements["application-identifier"].as_string(),
+        Some("TEAM123456.com.example.test-app")
+    );
+    assert_eq!(
+        entitlements["com.apple.developer.team-identifier"].as_string(),
+        Some("TEAM123456")
+    );
+    // Copied from the development profile
+    assert_eq!(entitlements["get-task-allow"].as_boolean(), Some(true));
+    assert_eq!(
+        entitlements["aps-environment"].as_string(),
+        Some("development")
+    );
+    assert_eq!(
+        strings(&entitlements["com.apple.security.application-groups"]),
+        ["group.com.example.shared", "group.com.example.other"]
+    );
+    assert_eq!(
+        strings(&entitlements["keychain-access-groups"]),
+        ["TEAM123456.com.example.test-app"]
+    );
+    assert_eq!(
+        strings(&entitlements["com.apple.developer.icloud-container-identifiers"]),
+        ["iCloud.com.example.test-app"]
+    );
+    assert_eq!(
+        strings(&entitlements["com.apple.developer.icloud-services"]),
+        ["CloudKit"]
+    );
+}
+
+#[test]
+fn macos_uses_its_own_keys() {
+    let identity = identity();
+    let cfg = config(&["push:production"]);
+    let entitlements =
+        entitlements::generate(&cfg, BUNDLE_ID, Some(&identity), None, true).unwrap();
+    assert_eq!(
+        entitlements["com.apple.application-identifier"].as_string(),
+        Some("TEAM123456.com.example.test-app")
+    );
+    assert_eq!(
+        entitlements["com.apple.developer.aps-environment"].as_string(),
+        Some("production")
+    );
+    assert!(!entitlements.contains_key("application-identifier"));
+}
+
+#[test]
+fn the_entitlements_table_overrides_capabilities() {
+    let mut cfg = config(&["push"]);
+    cfg.entitlements
+        .insert("aps-environment".into(), "production".into());
+    cfg.entitlements
+        .insert("com.apple.developer.game-center".into(), true.into());
+    let entitlements = entitlements::generate(&cfg, BUNDLE_ID, None, None, false).unwrap();
+    assert_eq!(
+        entitlements["aps-environment"].as_string(),
+        Some("production")
+    );
+    assert_eq!(
+        entitlements["com.apple.developer.game-center"].as_boolean(),
+        Some(true)
+    );
+}
+
+#[test]
+fn unknown_capabilities_are_config_errors() {
+    let result = entitlements::generate(&config(&["pushh"]), BUNDLE_ID, None, None, false);
+    let Err(err) = result else {
+        panic!("an unknown capability was accepted")
+    };
+    assert!(matches!(err, Error::Config(_)));
+    assert!(err.to_string().contains("Did you mean `push`?"), "{err}");
+
+    let result = entitlements::generate(&config(&["app-groups"]), BUNDLE_ID, None, None, false);
+    assert!(matches!(result, Err(Error::Config(_))));
+}
+
+#[test]
+fn profiles_allow_their_entitlements() {
+    let identity = identity();
+    let profile = profile();
+    let cfg = config(&["keychain-sharing:com.example.shared"]);
+    let entitlements =
+        entitlements::generate(&cfg, BUNDLE_ID, Some(&identity), Some(&profile), false).unwrap();
+    entitlements::check(&entitlements, &profile).unwrap();
+}
+
+#[test]
+fn entitlements_missing_from_the_profile_fail() {
+    let profile = profile();
+    let entitlements =
+        entitlements::generate(&config(&["push"]), BUNDLE_ID, None, Some(&profile), false).unwrap();
+    let Err(err) = entitlements::check(&entitlements, &profile) else {
+        panic!("the profile allowed push notifications")
+    };
+    assert!(matches!(err, Error::Signing(_)));
+    assert!(err.to_string().contains("aps-environment"), "{err}");
+}
+
+#[test]
+fn values_the_profile_doesnt_allow_fail() {
+    let mut entitlements = Dictionary::new();
+    entitlements.insert(
+        "keychain-access-groups".into(),
+        Value::Array(vec!["OTHERTEAM.com.example.test-app".into()]),
+    );
+    let err = entitlements::check(&entitlements, &profile()).unwrap_err();
+    assert!(err.to_string().contains("`TEAM123456.*`"), "{err}");
+
+    let mut entitlements = Dictionary::new();
+    entitlements.insert("get-task-allow".into(), false.into());
+    entitlements::check(&entitlements, &profile()).unwrap();
+}
+
+#[test]
+fn booleans_can_be_off_without_the_profile_listing_them() {
+    let mut entitlements = Dictionary::new();
+    entitlements.insert("com.apple.developer.game-center".into(), false.into());
+    entitlements::check(&entitlements, &profile()).unwrap();
+
+    entitlements.insert("com.apple.developer.game-center".into(), true.into());
+    assert!(entitlements::check(&entitlements, &profile()).is_err());
+}
+
+#[test]
+fn export_methods_only_add_get_task_allow_when_needed() {
+    let mut unlisted = profile();
+    unlisted.entitlements.remove("get-task-allow");
+
+    let mut entitlements = Dictionary::new();
+    entitlements::apply_export_method(
+        &mut entitlements,
+        ExportMethod::AdHoc,
+        Some(&unlisted),
+        false,
+    );
+    assert!(!entitlements.contains_key("get-task-allow"));
+    entitlements::check(&entitlements, &unlisted).unwrap();
+
+    // Profiles that list it get it either way
+    entitlements::apply_export_method(
+        &mut entitlements,
+        ExportMethod::AdHoc,
+        Some(&profile()),
+        false,
+    );
+    assert_eq!(entitlements["get-task-allow"].as_boolean(), Some(false));
+
+    entitlements::apply_export_method(
+        &mut entitlements,
+        ExportMethod::Development,
+        Some(&unlisted),
+        false,
+    );
+    assert_eq!(entitlements["get-task-allow"].as_boolean(), Some(true));
+}
+
+#[test]
+fn entitlements_are_written_as_xml() {
+    let entitlements =
+        entitlements::generate(&config(&["icloud"]), BUNDLE_ID, None, None, false).unwrap();
+    let xml = entitlements::to_xml(&entitlements).unwrap();
+    let parsed = Value::from_reader_xml(&*xml).unwrap();
+    assert_eq!(parsed.into_dictionary().unwrap(), entitlements);
+}
+
+#[test]
+fn entitlements_are_written_as_der() {
+    let mut entitlements = Dictionary::new();
+    // Out of order, so they have to be sorted
+    entitlements.insert("b".into(), vec![Value::from(128), Value::from(-1)].into());
+    entitlements.insert("a".into(), "x".repeat(200).into());
+    let der = entitlements::to_der(&entitlements).unwrap();
+
+    let mut a = vec![0x30, 0x81, 0xce, 0x0c, 0x01, b'a', 0x0c, 0x81, 0xc8];
+    a.extend_from_slice("x".repeat(200).as_bytes());
+    let b = [
+        0x30, 0x0c, 0x0c, 0x01, b'b', 0x30, 0x07, 0x02, 0x02, 0x00, 0x80, 0x02, 0x01, 0xff,
+    ];
+    let mut expected = vec![0x70, 0x81, 0xe5, 0x02, 0x01, 0x01, 0xb0, 0x81, 0xdf];
+    expected.extend_from_slice(&a);
+    expected.extend_from_slice(&b);
+    assert_eq!(der, expected);
+
+    // The DER blob can't hold dates, data, or floats
+    entitlements.insert("c".into(), Value::Real(1.5));
+    let err = entitlements::to_der(&entitlements).unwrap_err();
+    assert!(matches!(err, Error::Signing(_)), "{err}");
+}
+
+#[test]
+fn runtime_exceptions_are_macos_only() {
+    let mut cfg = config(&[]);
+    cfg.signing.hardened_runtime = true;
+    cfg.signing.runtime_exceptions = vec!["allow-jit".into(), "camera".into()];
+
+    let entitlements = entitlements::generate(&cfg, BUNDLE_ID, None, None, true).unwrap();
+    assert_eq!(
+        entitlements.keys().collect::<Vec<_>>(),
+        [
+            "com.apple.security.cs.allow-jit",
+            "com.apple.security.device.camera"
+        ]
+    );
+    assert_eq!(
+        entitlements["com.apple.security.cs.allow-jit"].as_boolean(),
+        Some(true)
+    );
+
+    // iOS doesn't have a hardened runtime
+    let entitlements = entitlements::generate(&cfg, BUNDLE_ID, None, None, false).unwrap();
+    assert!(entitlements.is_empty());
+}
+
+#[test]
+fn bad_runtime_exceptions_are_config_errors() {
+    let mut cfg = config(&[]);
+    cfg.signing.hardened_runtime = true;
+    cfg.signing.runtime_exceptions = vec!["allow-jitt".into()];
+    let Err(err) = entitlements::generate(&cfg, BUNDLE_ID, None, None, true) else {
+        panic!("an unknown exception was accepted")
+    };
+    assert!(matches!(err, Error::Config(_)));
+    assert!(
+        err.to_string().contains("Did you mean `allow-jit`?"),
+        "{err}"
+    );
+
+    // Exceptions don't mean anything without the hardened runtime
+    cfg.signing.hardened_runtime = false;
+    cfg.signing.runtime_exceptions = vec!["allow-jit".into()];
+    let Err(err) = entitlements::generate(&cfg, BUNDLE_ID, None, None, true) else {
+        panic!("exceptions were accepted without the hardened runtime")
+    };
+    assert!(
+        err.to_string().contains("signing.hardened-runtime"),
+        "{err}"
+    );
+}
@@ -0,0 +1,259 @@
+mod common;
+
+use cargo_ipa::{
+    build::{build_with, gen_swift_support, BuildArgs},
+    build_manifest::BuildManifest,
+    context::{Architecture, Ctx, ExportMethod, Platform},
+    error::Error,
+    runner::ToolOutput,
+    sign::{self, profile::ProvisioningProfile},
+};
+use common::*;
+use plist::Dictionary;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+fn profile_fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/profile")
+        .join(name)
+}
+
+fn args(method: ExportMethod, architecture: Option<Architecture>) -> BuildArgs {
+    BuildArgs {
+        example: None,
+        release: true,
+        name: None,
+        platform: Some(Platform::iOS),
+        architecture,
+        strip: false,
+        archive: false,
+        export_method: Some(method),
+        simulator: false,
+    }
+}
+
+/// A project signed with the test identity and development profile, with `extra` settings
+fn signed_project(extra: &str) -> tempfile::TempDir {
+    let cargo_toml = CARGO_TOML.to_string() + extra + &signing_settings() + &profile_setting();
+    let project = project(&cargo_toml);
+    fs::write(project.path().join("password.txt"), "cargo-ipa").unwrap();
+    project
+}
+
+/// Settings that use the App Store profile for `--export-method app-store`
+fn app_store_settings() -> String {
+    format!(
+        "\n[package.metadata.cargo-ipa.signing.app-store]\nprovisioning-profile = '{}'\n",
+        profile_fixture("app-store.mobileprovision").display()
+    )
+}
+
+fn xcent(project: &Path) -> Dictionary {
+    let path = project.join("target/cargo-ipa/Test App.aarch64-apple-ios.xcent");
+    plist::Value::from_file(path)
+        .unwrap()
+        .into_dictionary()
+        .unwrap()
+}
+
+#[test]
+fn profiles_know_their_export_method() {
+    let development = ProvisioningProfile::load(&profile_fixture("test.mobileprovision")).unwrap();
+    assert_eq!(development.export_method(), ExportMethod::Development);
+    let app_store =
+        ProvisioningProfile::load(&profile_fixture("app-store.mobileprovision")).unwrap();
+    assert_eq!(app_store.export_method(), ExportMethod::AppStore);
+}
+
+#[test]
+fn app_store_builds_use_their_own_profile_and_entitlements() {
+    let project = signed_project("capabilities = ['push']\n");
+    let cargo_toml = fs::read_to_string(project.path().join("Cargo.toml")).unwrap();
+    fs::write(
+        project.path().join("Cargo.toml"),
+        cargo_toml + &app_store_settings(),
+    )
+    .unwrap();
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    build_with(&mut ctx, &args(ExportMethod::AppStore, None), &runner).unwrap();
+
+    // The Simulator build was skipped
+    let targets: Vec<_> = runner
+        .calls_to("cargo")
+        .iter()
+        .map(|call| call.args[2].clone())
+        .collect();
+    assert_eq!(targets, ["aarch64-apple-ios"]);
+
+    let app = project
+        .path()
+        .join("target/cargo-ipa/Payload/Test App.aarch64-apple-ios.app");
+    assert_eq!(
+        fs::read(app.join("embedded.mobileprovision")).unwrap(),
+        fs::read(profile_fixture("app-store.mobileprovision")).unwrap()
+    );
+    let entitlements = xcent(project.path());
+    assert_eq!(entitlements["get-task-allow"].as_boolean(), Some(false));
+    assert_eq!(
+        entitlements["aps-environment"].as_string(),
+        Some("production")
+    );
+    // Nothing in the app needs SwiftSupport
+    assert!(!project
+        .path()
+        .join("target/cargo-ipa/SwiftSupport")
+        .exists());
+    assert_eq!(
+        runner.calls_to("zip")[0].args,
+        ["-r", "Test App.aarch64-apple-ios.ipa", "Payload"]
+    );
+
+    let manifest = BuildManifest::load(&ctx.cargo_ipa_dir).unwrap();
+    assert_eq!(manifest.export_method, Some(ExportMethod::AppStore));
+}
+
+#[test]
+fn app_store_builds_embed_swift_libraries_and_their_originals() {
+    let project = signed_project("");
+    let cargo_toml = fs::read_to_string(project.path().join("Cargo.toml")).unwrap();
+    fs::write(
+        project.path().join("Cargo.toml"),
+        cargo_toml + &app_store_settings(),
+    )
+    .unwrap();
+    // Xcode's copy of Swift, which the app's binary loads from its rpath
+    let xcode = tempfile::tempdir().unwrap();
+    let toolchain = xcode
+        .path()
+        .join("Toolchains/XcodeDefault.xctoolchain/usr/lib/swift/iphoneos");
+    fs::create_dir_all(&toolchain).unwrap();
+    fs::write(toolchain.join("libswiftCore.dylib"), FAKE_BINARY).unwrap();
+    let root = project.path().to_owned();
+    let runner = fake_toolchain(project.path())
+        .with_output("xcode-select", &format!("{}\n", xcode.path().display()))
+        .with_tool("cargo", move |_| {
+            let bin_dir = root.join("target/aarch64-apple-ios/release");
+            fs::create_dir_all(&bin_dir)?;
+            let swift_binary =
+                Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/macho/arm64-swift");
+            fs::copy(swift_binary, bin_dir.join("test-app"))?;
+            Ok(ToolOutput::success(""))
+        });
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    build_with(&mut ctx, &args(ExportMethod::AppStore, None), &runner).unwrap();
+
+    // The app gets a signed copy, and SwiftSupport gets Apple's
+    let out = project.path().join("target/cargo-ipa");
+    let embedded =
+        fs::read(out.join("Payload/Test App.aarch64-apple-ios.app/Frameworks/libswiftCore.dylib"))
+            .unwrap();
+    assert!(sign::cdhash(&embedded).unwrap().is_some());
+    assert_eq!(
+        fs::read(out.join("SwiftSupport/iphoneos/libswiftCore.dylib")).unwrap(),
+        FAKE_BINARY
+    );
+    assert_eq!(
+        runner.calls_to("zip")[0].args,
+        [
+            "-r",
+            "Test App.aarch64-apple-ios.ipa",
+            "Payload",
+            "SwiftSupport"
+        ]
+    );
+}
+
+#[test]
+fn development_builds_let_debuggers_attach() {
+    let project = signed_project("");
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let args = args(ExportMethod::Development, Some(Architecture::aarch64));
+    build_with(&mut ctx, &args, &runner).unwrap();
+
+    assert_eq!(
+        xcent(project.path())["get-task-allow"].as_boolean(),
+        Some(true)
+    );
+}
+
+#[test]
+fn profiles_for_other_methods_fail_before_compiling() {
+    let project = signed_project("");
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let err = build_with(&mut ctx, &args(ExportMethod::AdHoc, None), &runner).unwrap_err();
+    assert!(matches!(err, Error::Signing(_)), "{err}");
+    let message = err.to_string();
+    assert!(message.contains("is for development builds"), "{message}");
+    assert!(
+        message.contains("signing.ad-hoc.provisioning-profile"),
+        "{message}"
+    );
+    assert!(runner.calls_to("cargo").is_empty());
+}
+
+#[test]
+fn distribution_methods_need_a_profile() {
+    let project = project(&(CARGO_TOML.to_string() + &signing_settings()));
+    fs::write(project.path().join("password.txt"), "cargo-ipa").unwrap();
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let err = build_with(&mut ctx, &args(ExportMethod::Enterprise, None), &runner).unwrap_err();
+    assert!(matches!(err, Error::Signing(_)), "{err}");
+    assert!(
+        err.to_string()
+            .contains("signing.enterprise.provisioning-profile"),
+        "{err}"
+    );
+}
+
+#[test]
+fn simulator_builds_cant_be_exported() {
+    let project = project(CARGO_TOML);
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let args = args(ExportMethod::Development, Some(Architecture::x86_64));
+    let err = build_with(&mut ctx, &args, &runner).unwrap_err();
+    assert!(matches!(err, Error::Config(_)), "{err}");
+    assert!(err.to_string().contains("Simulator"), "{err}");
+    assert!(runner.calls_to("cargo").is_empty());
+}
+
+#[test]
+fn swift_support_has_the_originals_of_embedded_swift_libraries() {
+    let dir = tempfile::tempdir().unwrap();
+    let app = dir.path().join("Payload/Test App.app");
+    fs::create_dir_all(app.join("Frameworks")).unwrap();
+    fs::write(app.join("Frameworks/libswiftCore.dylib"), "app's copy").unwrap();
+    fs::write(app.join("Frameworks/libengine.dylib"), "not Swift").unwrap();
+    let toolchain = dir.path().join("swift/iphoneos");
+    fs::create_dir_all(&toolchain).unwrap();
+    fs::write(toolchain.join("libswiftCore.dylib"), "Apple's copy").unwrap();
+
+    let swift_support = dir.path().join("SwiftSupport");
+    let libraries = gen_swift_support(&app, &toolchain, &swift_support).unwrap();
+    assert_eq!(libraries, ["libswiftCore.dylib"]);
+    assert_eq!(
+        fs::read_to_string(swift_support.join("iphoneos/libswiftCore.dylib")).unwrap(),
+        "Apple's copy"
+    );
+
+    // Apps without Swift's libraries don't get SwiftSupport
+    fs::remove_dir_all(app.join("Frameworks")).unwrap();
+    fs::remove_dir_all(&swift_support).unwrap();
+    assert!(gen_swift_support(&app, &toolchain, &swift_support)
+        .unwrap()
+        .is_empty());
+    assert!(!swift_support.exists());
+}
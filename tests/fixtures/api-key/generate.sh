@@ -0,0 +1,8 @@
+#!/bin/sh
+# Generates a fake App Store Connect API key for the notarization tests. Like Apple's, it's
+# a P-256 private key in a PKCS#8 PEM file named after its key ID.
+#
+# Run this from this folder to regenerate it.
+set -e
+
+openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:P-256 -out AuthKey_TESTKEY123.p8
@@ -0,0 +1,37 @@
+#!/bin/sh
+# Generates a fake signing identity for the signing tests, shaped like an Apple one:
+# a root CA, an intermediate (like Apple's WWDR certificate), and a distribution
+# certificate with the team ID in its OU.
+#
+# Run this from this folder to regenerate them. The .p12 files' password is `cargo-ipa`.
+set -e
+days=36500
+
+openssl req -x509 -newkey rsa:2048 -nodes -keyout root.key -days $days \
+    -subj "/CN=cargo-ipa Test Root CA/O=cargo-ipa/C=US" -out root.pem
+openssl x509 -in root.pem -outform der -out root.cer
+
+openssl req -newkey rsa:2048 -nodes -keyout intermediate.key \
+    -subj "/CN=cargo-ipa Test WWDR/OU=G3/O=cargo-ipa/C=US" -out intermediate.csr
+printf "basicConstraints=critical,CA:true\nkeyUsage=critical,keyCertSign,cRLSign\n" > ca.ext
+openssl x509 -req -in intermediate.csr -CA root.pem -CAkey root.key -set_serial 2 \
+    -days $days -extfile ca.ext -out intermediate.pem
+
+openssl req -newkey rsa:2048 -nodes -keyout identity.key \
+    -subj "/UID=TEAM123456/CN=Apple Distribution: Test Team (TEAM123456)/OU=TEAM123456/O=Test Team/C=US" \
+    -out identity.csr
+printf "basicConstraints=critical,CA:false\nkeyUsage=critical,digitalSignature\nextendedKeyUsage=critical,codeSigning\n" > leaf.ext
+openssl x509 -req -in identity.csr -CA intermediate.pem -CAkey intermediate.key -set_serial 3 \
+    -days $days -extfile leaf.ext -out identity.pem
+
+# Keychain Access only exports the certificate & key, not the rest of the chain
+openssl pkcs12 -export -inkey identity.key -in identity.pem -name "Test Team" \
+    -passout pass:cargo-ipa -out identity.p12
+# Older versions of Keychain Access use 3DES & RC2 instead of AES
+openssl pkcs12 -export -legacy -inkey identity.key -in identity.pem -name "Test Team" \
+    -passout pass:cargo-ipa -out identity-legacy.p12
+# A broken export, with the key but not its certificate
+openssl pkcs12 -export -nocerts -inkey identity.key -name "Test Team" \
+    -passout pass:cargo-ipa -out key-only.p12
+
+rm root.key intermediate.key intermediate.csr identity.key identity.csr identity.pem ca.ext leaf.ext root.pem
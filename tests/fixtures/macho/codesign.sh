@@ -0,0 +1,20 @@
+#!/bin/sh
+# Dumps what Apple's codesign sees in each signed fixture, so the signing tests can check
+# cargo-ipa's signatures against it, and not just against cargo-ipa's own earlier output.
+# CI runs this on macOS before the tests.
+#
+# Run this from this folder, on a Mac, after the .signed fixtures change.
+set -e
+
+# Writes `codesign -d` for the $2 slice of $1 to $1.$2.codesign.txt. Verbosity 6 includes
+# every hash slot.
+dump() {
+    codesign --display --verbose=6 --arch "$2" "$1" > "$1.$2.codesign.txt" 2>&1
+}
+
+dump arm64.signed arm64
+dump arm64.identity.signed arm64
+dump arm64-linker-signed.signed arm64
+dump x86_64.signed x86_64
+dump universal.signed arm64
+dump universal.signed x86_64
@@ -0,0 +1,154 @@
+#!/usr/bin/env python3
+"""Generates the tiny Mach-O binaries the signing tests use.
+
+They aren't real programs, but they're laid out like the binaries rustc & ld64
+make: a __PAGEZERO, a __TEXT segment that starts with the headers, and a
+__LINKEDIT segment at the end of the file.
+
+Run this from this folder to regenerate them.
+"""
+import struct
+
+CPU_TYPE_X86_64 = 0x01000007
+CPU_TYPE_ARM64 = 0x0100000C
+MH_EXECUTE = 0x2
+LC_LOAD_DYLIB = 0xC
+LC_SEGMENT_64 = 0x19
+LC_UUID = 0x1B
+LC_CODE_SIGNATURE = 0x1D
+LC_MAIN = 0x80000028
+
+
+def segment(name, vmaddr, vmsize, fileoff, filesize, sections=()):
+    command = struct.pack(
+        "<II16sQQQQiiII",
+        LC_SEGMENT_64,
+        72 + 80 * len(sections),
+        name.encode(),
+        vmaddr,
+        vmsize,
+        fileoff,
+        filesize,
+        7 if name == "__LINKEDIT" else 5,
+        1 if name == "__LINKEDIT" else 5,
+        len(sections),
+        0,
+    )
+    for sectname, addr, size, offset in sections:
+        command += struct.pack(
+            "<16s16sQQIIIIIIII",
+            sectname.encode(),
+            name.encode(),
+            addr,
+            size,
+            offset,
+            2,
+            0,
+            0,
+            0x80000400,
+            0,
+            0,
+            0,
+        )
+    return command
+
+
+def dylib(name):
+    name = name.encode() + b"\0"
+    name += bytes(-(24 + len(name)) % 8)
+    return struct.pack("<IIIIII", LC_LOAD_DYLIB, 24 + len(name), 24, 2, 0x10000, 0x10000) + name
+
+
+def macho(cputype, code, headerpad=True, old_signature=None, dylibs=()):
+    page = 0x4000 if cputype == CPU_TYPE_ARM64 else 0x1000
+    base = 0x100000000
+    text_size = 0x4000
+    code_offset = text_size - len(code) if headerpad else None
+    linkedit = bytes(range(256)) * 2
+
+    commands = [
+        segment("__PAGEZERO", 0, base, 0, 0),
+        None,  # __TEXT, once we know where the code goes
+        segment("__LINKEDIT", base + text_size, page, text_size, len(linkedit)),
+        struct.pack("<IIQQ", LC_MAIN, 24, 0, 0),
+        struct.pack("<II16s", LC_UUID, 24, bytes([cputype & 0xFF] * 16)),
+    ]
+    commands += [dylib(name) for name in dylibs]
+    if old_signature is not None:
+        commands.append(
+            struct.pack(
+                "<IIII", LC_CODE_SIGNATURE, 16, text_size + len(linkedit), len(old_signature)
+            )
+        )
+
+    def text(code_offset):
+        return segment(
+            "__TEXT",
+            base,
+            text_size,
+            0,
+            text_size,
+            [("__text", base + code_offset, len(code), code_offset)],
+        )
+
+    commands[1] = text(0)
+    sizeofcmds = sum(len(command) for command in commands)
+    if code_offset is None:
+        # Leave no room after the load commands, like `-headerpad 0` would
+        code_offset = 32 + sizeofcmds
+    commands[1] = text(code_offset)
+    commands[3] = struct.pack("<IIQQ", LC_MAIN, 24, code_offset, 0)
+
+    header = struct.pack(
+        "<IiiIIIII", 0xFEEDFACF, cputype, 0, MH_EXECUTE, len(commands), sizeofcmds, 0x200085, 0
+    )
+    data = bytearray(header + b"".join(commands))
+    data += bytes(code_offset - len(data))
+    data += code
+    data += bytes(text_size - len(data))
+    data += linkedit
+    if old_signature is not None:
+        data += old_signature
+    return bytes(data)
+
+
+def fat(*slices):
+    data = bytearray(struct.pack(">II", 0xCAFEBABE, len(slices)))
+    offset = 0x4000
+    layout = []
+    for cputype, slice in slices:
+        layout.append((cputype, offset, slice))
+        offset += (len(slice) + 0x3FFF) // 0x4000 * 0x4000
+    for cputype, offset, slice in layout:
+        data += struct.pack(">iiIII", cputype, 0, offset, len(slice), 14)
+    for _, offset, slice in layout:
+        data += bytes(offset - len(data)) + slice
+    return bytes(data)
+
+
+# mov w0, #0; ret
+ARM64_CODE = bytes.fromhex("00008052c0035fd6") * 512
+# xor eax, eax; ret
+X86_64_CODE = bytes.fromhex("31c0c3") * 1024
+
+arm64 = macho(CPU_TYPE_ARM64, ARM64_CODE)
+x86_64 = macho(CPU_TYPE_X86_64, X86_64_CODE)
+fixtures = {
+    "arm64": arm64,
+    "x86_64": x86_64,
+    "universal": fat((CPU_TYPE_X86_64, x86_64), (CPU_TYPE_ARM64, arm64)),
+    # ld64 signs arm64 binaries itself, so cargo-ipa has to replace that signature
+    "arm64-linker-signed": macho(
+        CPU_TYPE_ARM64, ARM64_CODE, old_signature=b"\xfa\xde\x0c\xc0" + bytes(60)
+    ),
+    "arm64-no-headerpad": macho(CPU_TYPE_ARM64, ARM64_CODE, headerpad=False),
+    # Apps that use swift-bridge load Swift's libraries from their rpath on older iOS versions
+    "arm64-swift": macho(
+        CPU_TYPE_ARM64,
+        ARM64_CODE,
+        dylibs=("/usr/lib/libSystem.B.dylib", "@rpath/libswiftCore.dylib"),
+    ),
+}
+for name, data in fixtures.items():
+    with open(name, "wb") as file:
+        file.write(data)
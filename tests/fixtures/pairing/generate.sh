@@ -0,0 +1,41 @@
+#!/bin/sh
+# Generates a fake pairing record for the install tests, shaped like the ones usbmuxd keeps in
+# /var/db/lockdown (or /var/lib/lockdown): a root CA, and host & device certificates it
+# issued. Like lockdownd's, the keys are PKCS#1 RSA keys. device.key is the fake device's
+# private key, which a real device never hands out.
+#
+# Run this from this folder to regenerate them.
+set -e
+days=36500
+udid=00008030-001A2B3C4D5E802E
+
+openssl genrsa -traditional -out root.key 2048
+openssl req -x509 -new -key root.key -days $days -subj "/CN=Root Certification Authority" \
+    -addext "basicConstraints=critical,CA:true" -out root.pem
+
+printf "basicConstraints=critical,CA:false\nkeyUsage=critical,digitalSignature,keyEncipherment\n" > leaf.ext
+for name in host device; do
+    openssl genrsa -traditional -out $name.key 2048
+    openssl req -new -key $name.key -subj "/CN=cargo-ipa Test ${name}" -out $name.csr
+    openssl x509 -req -in $name.csr -CA root.pem -CAkey root.key -set_serial 0x$(openssl rand -hex 8) \
+        -days $days -extfile leaf.ext -out $name.pem
+done
+
+data() {
+    printf "\t<data>\n%s\n\t</data>\n" "$(base64 < "$1")"
+}
+{
+    printf '<?xml version="1.0" encoding="UTF-8"?>\n'
+    printf '<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">\n'
+    printf '<plist version="1.0">\n<dict>\n'
+    printf '\t<key>DeviceCertificate</key>\n'; data device.pem
+    printf '\t<key>HostCertificate</key>\n'; data host.pem
+    printf '\t<key>HostID</key>\n\t<string>5B8E1A2C-3D4F-4A6B-8C9D-0E1F2A3B4C5D</string>\n'
+    printf '\t<key>HostPrivateKey</key>\n'; data host.key
+    printf '\t<key>RootCertificate</key>\n'; data root.pem
+    printf '\t<key>SystemBUID</key>\n\t<string>9F8E7D6C-5B4A-4321-9876-FEDCBA012345</string>\n'
+    printf '\t<key>WiFiMACAddress</key>\n\t<string>a4:83:e7:12:34:56</string>\n'
+    printf '</dict>\n</plist>\n'
+} > $udid.plist
+
+rm root.key leaf.ext host.csr device.csr host.key host.pem root.pem device.pem
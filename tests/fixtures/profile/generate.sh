@@ -0,0 +1,97 @@
+#!/bin/sh
+# Generates fake iOS provisioning profiles for the profile tests: a development one, an ad-hoc
+# one, and an App Store one. Like Apple's, they're a plist wrapped in a CMS signature, and list
+# the test identity (from ../identity) as one of their developer certificates.
+#
+# Run this from this folder to regenerate it.
+set -e
+
+# Apple signs profiles with their own certificate, but cargo-ipa doesn't check that
+# signature, so any certificate will do
+openssl req -x509 -newkey rsa:2048 -nodes -keyout signer.key -days 36500 \
+    -subj "/CN=cargo-ipa Test Provisioning Profile Signing/O=cargo-ipa/C=US" -out signer.pem
+
+openssl pkcs12 -in ../identity/identity.p12 -passin pass:cargo-ipa -nokeys -clcerts \
+    | openssl x509 -outform der -out identity.cer
+
+# Writes a profile called $1 to $2, with the extra entitlements in $3 and the devices in $4
+profile() {
+cat > profile.plist <<PLIST
+<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>AppIDName</key>
+	<string>Test App</string>
+	<key>ApplicationIdentifierPrefix</key>
+	<array>
+		<string>TEAM123456</string>
+	</array>
+	<key>CreationDate</key>
+	<date>2023-11-14T22:13:20Z</date>
+	<key>DeveloperCertificates</key>
+	<array>
+		<data>$(base64 -w0 identity.cer)</data>
+	</array>
+	<key>Entitlements</key>
+	<dict>
+		<key>application-identifier</key>
+		<string>TEAM123456.com.example.test-app</string>
+		<key>com.apple.developer.team-identifier</key>
+		<string>TEAM123456</string>
+$3
+		<key>keychain-access-groups</key>
+		<array>
+			<string>TEAM123456.*</string>
+		</array>
+	</dict>
+	<key>ExpirationDate</key>
+	<date>2099-01-01T00:00:00Z</date>
+	<key>Name</key>
+	<string>$1</string>
+	<key>Platform</key>
+	<array>
+		<string>iOS</string>
+	</array>
+$4
+	<key>TeamIdentifier</key>
+	<array>
+		<string>TEAM123456</string>
+	</array>
+	<key>TeamName</key>
+	<string>Test Team</string>
+	<key>UUID</key>
+	<string>5a3f1c2e-7d4b-4e8a-9c6f-0b1d2e3f4a5b</string>
+	<key>Version</key>
+	<integer>1</integer>
+</dict>
+</plist>
+PLIST
+
+openssl cms -sign -nodetach -binary -outform der -in profile.plist \
+    -signer signer.pem -inkey signer.key -out "$2"
+}
+
+DEVICES="\
+	<key>ProvisionedDevices</key>
+	<array>
+		<string>00008030-001A2B3C4D5E6F70</string>
+		<string>00008101-000A1B2C3D4E5F60</string>
+	</array>"
+profile "Test App Development" test.mobileprovision "\
+		<key>get-task-allow</key>
+		<true/>" "$DEVICES"
+# Ad-hoc profiles list devices, but are otherwise like App Store ones
+profile "Test App Ad Hoc" ad-hoc.mobileprovision "\
+		<key>aps-environment</key>
+		<string>production</string>
+		<key>get-task-allow</key>
+		<false/>" "$DEVICES"
+# App Store profiles don't list devices, or let debuggers attach
+profile "Test App App Store" app-store.mobileprovision "\
+		<key>aps-environment</key>
+		<string>production</string>
+		<key>get-task-allow</key>
+		<false/>"
+
+rm signer.key signer.pem identity.cer profile.plist
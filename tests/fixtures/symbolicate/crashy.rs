@@ -0,0 +1,38 @@
+//! The app the symbolication fixtures are built from. It's `no_core`, so it can be built for iOS
+//! without the iOS standard library.
+#![feature(no_core, lang_items)]
+#![allow(internal_features)]
+#![no_core]
+#![no_main]
+
+#[lang = "pointee_sized"]
+pub trait PointeeSized {}
+#[lang = "meta_sized"]
+pub trait MetaSized: PointeeSized {}
+#[lang = "sized"]
+pub trait Sized: MetaSized {}
+#[lang = "copy"]
+pub trait Copy {}
+
+extern "C" {
+    fn boom(x: u32) -> u32;
+}
+
+pub mod render {
+    pub mod layout {
+        #[inline(always)]
+        pub fn measure(width: u32) -> u32 {
+            unsafe { crate::boom(crate::boom(width)) }
+        }
+    }
+
+    #[inline(never)]
+    pub fn draw_frame(width: u32) -> u32 {
+        layout::measure(width)
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn main() -> u32 {
+    unsafe { boom(render::draw_frame(3)) }
+}
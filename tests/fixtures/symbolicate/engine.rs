@@ -0,0 +1,29 @@
+//! A framework the app links, built with legacy symbol mangling
+#![feature(no_core, lang_items)]
+#![allow(internal_features)]
+#![no_core]
+
+#[lang = "pointee_sized"]
+pub trait PointeeSized {}
+#[lang = "meta_sized"]
+pub trait MetaSized: PointeeSized {}
+#[lang = "sized"]
+pub trait Sized: MetaSized {}
+#[lang = "copy"]
+pub trait Copy {}
+
+extern "C" {
+    fn boom(x: u32) -> u32;
+}
+
+pub mod physics {
+    #[inline(never)]
+    pub fn step(dt: u32) -> u32 {
+        unsafe { crate::boom(crate::boom(dt)) }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn engine_tick() -> u32 {
+    unsafe { boom(physics::step(16)) }
+}
@@ -0,0 +1,137 @@
+#!/usr/bin/env python3
+"""Generates the symbolication fixtures: an iOS app and framework built from
+crashy.rs and engine.rs, their dSYMs, and a crash report from them.
+
+The app uses v0 symbol mangling, and the framework uses legacy mangling. They're
+`no_core`, so this only needs a nightly rustc, rust-lld (which ships with it),
+dsymutil, and llvm-objdump, and runs on Linux as well as macOS.
+
+Run this from this folder to regenerate them.
+"""
+import json
+import os
+import re
+import shutil
+import subprocess
+import tempfile
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+# Where the sources "were" when they were built, so the fixtures don't depend on this machine
+SOURCE_DIR = "/Users/dev/crashy/src"
+
+
+def run(*args):
+    return subprocess.run(args, check=True, capture_output=True, text=True).stdout
+
+
+def rust_lld():
+    sysroot = run("rustc", "+nightly", "--print", "sysroot").strip()
+    host = re.search(r"host: (\S+)", run("rustc", "+nightly", "-vV")).group(1)
+    return os.path.join(sysroot, "lib/rustlib", host, "bin/rust-lld")
+
+
+def build(source, mangling, out, link_args):
+    obj = out + ".o"
+    run(
+        "rustc", "+nightly", "--target", "aarch64-apple-ios", "--crate-type", "lib",
+        "--emit=obj", "-g", "-C", "opt-level=1", "-C", "panic=abort",
+        "-Z", "unstable-options", "-C", f"symbol-mangling-version={mangling}",
+        f"--remap-path-prefix={HERE}={SOURCE_DIR}", os.path.join(HERE, source), "-o", obj,
+    )
+    run(
+        rust_lld(), "-flavor", "darwin", "-arch", "arm64", "-platform_version", "ios",
+        "15.0", "15.0", "-undefined", "dynamic_lookup", *link_args, obj, "-o", out,
+    )
+
+
+def uuid(binary):
+    return re.search(r"UUID: (\S+)", run("llvm-dwarfdump", "--uuid", binary)).group(1)
+
+
+def return_addresses(binary, function):
+    """The address after each call in a function, which is where it'd be in a backtrace"""
+    disassembly = run("llvm-objdump", "-d", "--no-show-raw-insn", binary)
+    body = disassembly.split(f"<{function}>:\n")[1].split("\n\n")[0]
+    return [int(address, 16) + 4 for address in re.findall(r"^\s*([0-9a-f]+):\s+bl\s", body, re.M)]
+
+
+def symbol(binary, mangled_part):
+    for line in run("llvm-nm", binary).splitlines():
+        name = line.split()[-1]
+        if mangled_part in name:
+            return name
+    raise KeyError(mangled_part)
+
+
+with tempfile.TemporaryDirectory() as build_dir:
+    crashy = os.path.join(build_dir, "crashy")
+    engine = os.path.join(build_dir, "Engine")
+    build("crashy.rs", "v0", crashy, ["-e", "_main"])
+    build("engine.rs", "legacy", engine, ["-dylib", "-install_name", "@rpath/Engine.framework/Engine"])
+    for binary, dsym in [(crashy, "Crashy.app.dSYM"), (engine, "Engine.framework.dSYM")]:
+        shutil.rmtree(os.path.join(HERE, dsym), ignore_errors=True)
+        run("dsymutil", binary, "-o", os.path.join(HERE, dsym))
+    # The framework's binary is kept too, for symbolicating without its dSYM
+    shutil.copy(engine, os.path.join(HERE, "Engine"))
+
+    # __TEXT starts at 0x100000000 in executables, and 0 in dylibs
+    crashy_base, engine_base = 0x100000000, 0
+    draw_frame = return_addresses(crashy, symbol(crashy, "10draw_frame"))[0]
+    main = return_addresses(crashy, "_main")[0]
+    step = return_addresses(engine, symbol(engine, "4step"))[0]
+
+    images = [
+        {"source": "P", "arch": "arm64", "base": 4302848000, "size": 49152,
+         "uuid": uuid(crashy).lower(), "name": "crashy",
+         "path": "/private/var/containers/Bundle/Application/5B1A7D2C-8E3F-4A6B-9C0D-1E2F3A4B5C6D/Crashy.app/crashy"},
+        {"source": "P", "arch": "arm64", "base": 4303880192, "size": 32768,
+         "uuid": uuid(engine).lower(), "name": "Engine",
+         "path": "/private/var/containers/Bundle/Application/5B1A7D2C-8E3F-4A6B-9C0D-1E2F3A4B5C6D/Crashy.app/Frameworks/Engine.framework/Engine"},
+        {"source": "P", "arch": "arm64", "base": 4304928768, "size": 16384,
+         "uuid": "9f1e2d3c-4b5a-4968-8776-a5b4c3d2e1f0", "name": "Analytics",
+         "path": "/private/var/containers/Bundle/Application/5B1A7D2C-8E3F-4A6B-9C0D-1E2F3A4B5C6D/Crashy.app/Frameworks/Analytics.framework/Analytics"},
+        {"source": "P", "arch": "arm64e", "base": 7717502976, "size": 241656,
+         "uuid": "a7c2f1e4-3b9d-3f5e-8c6a-2d4e6f8a0b1c", "name": "libsystem_kernel.dylib",
+         "path": "/usr/lib/system/libsystem_kernel.dylib"},
+        {"source": "P", "arch": "arm64e", "base": 7739191296, "size": 528416,
+         "uuid": "b3d5e7f9-1a2c-3e4f-5a6b-7c8d9e0f1a2b", "name": "dyld",
+         "path": "/usr/lib/dyld"},
+    ]
+    header = {
+        "app_name": "Crashy", "timestamp": "2026-10-18 10:10:10.00 +0100",
+        "app_version": "1.0", "slice_uuid": uuid(crashy).lower(), "build_version": "1",
+        "platform": 2, "bundleID": "com.example.crashy", "share_with_app_devs": 1,
+        "is_first_party": 0, "bug_type": "309", "os_version": "iPhone OS 17.4 (21E219)",
+        "roots_installed": 0, "name": "Crashy", "incident_id": "0E1D2C3B-4A59-4867-9586-A4B3C2D1E0F9",
+    }
+    body = {
+        "uptime": 4200, "procRole": "Foreground", "version": 2, "userID": 501,
+        "deployVersion": 210, "modelCode": "iPhone13,2", "coalitionID": 512,
+        "osVersion": {"isEmbedded": True, "train": "iPhone OS 17.4", "releaseType": "User", "build": "21E219"},
+        "captureTime": "2026-10-18 10:10:10.0000 +0100", "incident": header["incident_id"],
+        "pid": 1234, "procName": "Crashy",
+        "procPath": images[0]["path"],
+        "bundleInfo": {"CFBundleShortVersionString": "1.0", "CFBundleVersion": "1", "CFBundleIdentifier": "com.example.crashy"},
+        "parentProc": "launchd", "parentPid": 1, "cpuType": "ARM-64",
+        "exception": {"codes": "0x0000000000000000, 0x0000000000000000", "rawCodes": [0, 0], "type": "EXC_CRASH", "signal": "SIGABRT"},
+        "termination": {"flags": 0, "code": 6, "namespace": "SIGNAL", "indicator": "Abort trap: 6", "byProc": "Crashy", "byPid": 1234},
+        "faultingThread": 0,
+        "threads": [
+            {"triggered": True, "id": 81234, "queue": "com.apple.main-thread", "frames": [
+                {"imageOffset": 37852, "symbol": "__pthread_kill", "symbolLocation": 8, "imageIndex": 3},
+                {"imageOffset": draw_frame - crashy_base, "symbol": symbol(crashy, "10draw_frame")[1:], "symbolLocation": draw_frame - 0x100004000, "imageIndex": 0},
+                {"imageOffset": main - crashy_base, "imageIndex": 0},
+                {"imageOffset": 84808, "symbol": "start", "symbolLocation": 2204, "imageIndex": 4},
+            ]},
+            {"id": 81240, "name": "engine", "frames": [
+                {"imageOffset": step - engine_base, "imageIndex": 1},
+                {"imageOffset": 1234, "imageIndex": 2},
+            ]},
+        ],
+        "usedImages": images,
+        "sharedCache": {"base": 7715487744, "size": 3785211904, "uuid": "c4d6e8fa-2b3d-3e5f-6a7b-8c9d0e1f2a3b"},
+        "vmSummary": "ReadOnly portion of Libraries: Total=1.1G resident=0K(0%)",
+    }
+    with open(os.path.join(HERE, "Crashy-2026-10-18-101010.ips"), "w") as file:
+        file.write(json.dumps(header, separators=(",", ":")) + "\n")
+        file.write(json.dumps(body, indent=2) + "\n")
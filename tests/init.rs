@@ -0,0 +1,130 @@
+use cargo_ipa::{
+    error::Error,
+    init::{init_with, InitArgs},
+};
+use std::{fs, path::Path};
+use tempfile::TempDir;
+
+fn args() -> InitArgs {
+    InitArgs {
+        name: None,
+        bundle_id: None,
+        swift: None,
+        force: false,
+    }
+}
+
+/// Makes a package called `my-cool_app`, with `extra` at the end of its [package] section
+fn package(extra: &str) -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join("Cargo.toml"),
+        format!("[package]\nname = \"my-cool_app\"\nversion = \"0.1.0\"\n{extra}"),
+    )
+    .unwrap();
+    dir
+}
+
+/// Runs `cargo ipa init` in `dir`, and returns the app name & bundle ID it chose
+fn init(dir: &Path) -> (String, String) {
+    init_with(&args(), dir).unwrap();
+    let cargo_toml: toml::Value =
+        toml::from_str(&fs::read_to_string(dir.join("Cargo.toml")).unwrap()).unwrap();
+    let settings = &cargo_toml["package"]["metadata"]["cargo-ipa"];
+    (
+        settings["name"].as_str().unwrap().to_string(),
+        settings["bundle-id"].as_str().unwrap().to_string(),
+    )
+}
+
+#[test]
+fn names_and_bundle_ids_come_from_the_package_name() {
+    let dir = package("");
+    let (name, bundle_id) = init(dir.path());
+    assert_eq!(name, "My Cool App");
+    // Underscores aren't allowed in bundle IDs
+    assert_eq!(bundle_id, "com.my-cool-app");
+}
+
+#[test]
+fn bundle_ids_use_the_authors_email_domain() {
+    let dir = package(
+        "authors = [\"Someone\", \"Jo Bloggs <jo@apps.example.co.uk>\"]\nrepository = \"https://github.com/example/my-cool-app\"\n",
+    );
+    let (_, bundle_id) = init(dir.path());
+    assert_eq!(bundle_id, "uk.co.example.apps.my-cool-app");
+}
+
+#[test]
+fn bundle_ids_fall_back_to_the_repository() {
+    let dir = package(
+        "authors = [\"Someone\"]\nrepository = \"https://github.com/loki-chat/cargo-ipa\"\n",
+    );
+    let (_, bundle_id) = init(dir.path());
+    assert_eq!(bundle_id, "com.github.loki-chat.my-cool-app");
+}
+
+#[test]
+fn existing_settings_arent_overwritten() {
+    let settings = "\n[package.metadata.cargo-ipa]\nname = \"Old Name\"\nswift-bridges = [\"src/swift.rs\"]\ncapabilities = [\"push\"]\n\n[package.metadata.cargo-ipa.signing]\nidentity = \"identity.p12\"\n";
+    let dir = package(settings);
+    let cargo_toml = dir.path().join("Cargo.toml");
+    let before = fs::read_to_string(&cargo_toml).unwrap();
+
+    let err = init_with(&args(), dir.path()).unwrap_err();
+    assert!(matches!(err, Error::Config(_)), "{err}");
+    assert!(err.to_string().contains("--force"), "{err}");
+    assert_eq!(fs::read_to_string(&cargo_toml).unwrap(), before);
+
+    let args = InitArgs {
+        force: true,
+        ..args()
+    };
+    init_with(&args, dir.path()).unwrap();
+    let after = fs::read_to_string(&cargo_toml).unwrap();
+    assert!(after.contains("name = \"My Cool App\""), "{after}");
+    assert!(!after.contains("Old Name"), "{after}");
+    // Everything else is kept
+    let parsed: toml::Value = toml::from_str(&after).unwrap();
+    let settings = &parsed["package"]["metadata"]["cargo-ipa"];
+    assert_eq!(settings["bundle-id"].as_str(), Some("com.my-cool-app"));
+    assert_eq!(settings["capabilities"][0].as_str(), Some("push"));
+    assert_eq!(
+        settings["signing"]["identity"].as_str(),
+        Some("identity.p12")
+    );
+
+    // Swift settings are added, but the bridges that are already listed are kept
+    let args = InitArgs {
+        swift: Some("swift-library".into()),
+        ..args
+    };
+    init_with(&args, dir.path()).unwrap();
+    let parsed: toml::Value = toml::from_str(&fs::read_to_string(&cargo_toml).unwrap()).unwrap();
+    let settings = &parsed["package"]["metadata"]["cargo-ipa"];
+    assert_eq!(settings["swift-library"].as_str(), Some("swift-library"));
+    assert_eq!(settings["swift-bridges"][0].as_str(), Some("src/swift.rs"));
+}
+
+#[test]
+fn workspace_members_get_their_own_settings() {
+    let dir = tempfile::tempdir().unwrap();
+    let workspace = "[workspace]\nmembers = [\"app\"]\n";
+    fs::write(dir.path().join("Cargo.toml"), workspace).unwrap();
+    let member = dir.path().join("app");
+    fs::create_dir_all(member.join("src")).unwrap();
+    fs::write(
+        member.join("Cargo.toml"),
+        "[package]\nname = \"app\"\nversion = \"0.1.0\"\n",
+    )
+    .unwrap();
+
+    // From somewhere inside the member, like its src folder
+    init_with(&args(), &member.join("src")).unwrap();
+    let cargo_toml = fs::read_to_string(member.join("Cargo.toml")).unwrap();
+    assert!(cargo_toml.contains("[package.metadata.cargo-ipa]"));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("Cargo.toml")).unwrap(),
+        workspace
+    );
+}
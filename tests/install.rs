@@ -0,0 +1,158 @@
+mod common;
+
+use cargo_ipa::{
+    context::Ctx,
+    error::Error,
+    install::{install_with, InstallArgs},
+};
+use common::{usbmux::*, *};
+use std::{fs, path::PathBuf};
+use tempfile::TempDir;
+
+const STAGED: &str = "PublicStaging/Test App.aarch64-apple-ios.ipa";
+
+/// A project with a "built" IPA, big enough to be uploaded in a few chunks
+fn built_ipa() -> (TempDir, Ctx, Vec<u8>) {
+    let project = project(CARGO_TOML);
+    let ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+    let ipa: Vec<u8> = (0..150_000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(
+        ctx.cargo_ipa_dir.join("Test App.aarch64-apple-ios.ipa"),
+        &ipa,
+    )
+    .unwrap();
+    (project, ctx, ipa)
+}
+
+fn args(usbmuxd: &FakeUsbmuxd) -> InstallArgs {
+    InstallArgs {
+        ipa: None,
+        name: None,
+        device: None,
+        usbmux_socket: usbmuxd.socket.clone(),
+    }
+}
+
+#[test]
+fn ipas_are_installed_over_usb() {
+    let (_project, ctx, ipa) = built_ipa();
+    let usbmuxd = fake_usbmuxd(DeviceMode::Install);
+
+    let installation = install_with(&args(&usbmuxd), Some(&ctx)).unwrap();
+    // The device on Wi-Fi was ignored
+    assert_eq!(installation.udid, UDID);
+    assert_eq!(installation.device_name.as_deref(), Some(DEVICE_NAME));
+    assert_eq!(installation.staged_path, STAGED);
+
+    let record = usbmuxd.record.lock().unwrap();
+    // The session was started with the pairing record, and switched to TLS
+    assert_eq!(record.lockdown_requests[..2], ["QueryType", "StartSession"]);
+    assert!(record.tls);
+    // The IPA was uploaded in chunks, then installed
+    assert_eq!(record.dirs, ["PublicStaging"]);
+    assert_eq!(record.files[STAGED], ipa);
+    assert_eq!(record.writes, 3);
+    assert_eq!(record.installs, [STAGED]);
+}
+
+#[test]
+fn rejected_apps_say_why() {
+    let (_project, ctx, _ipa) = built_ipa();
+    let usbmuxd = fake_usbmuxd(DeviceMode::RejectSignature);
+
+    let err = install_with(&args(&usbmuxd), Some(&ctx)).unwrap_err();
+    assert!(matches!(err, Error::Device(_)));
+    assert_eq!(err.exit_code(), 9);
+    let message = err.to_string();
+    assert!(
+        message.contains("couldn't install the app: ApplicationVerificationFailed"),
+        "{message}"
+    );
+    assert!(
+        message.contains("A valid provisioning profile for this executable was not found."),
+        "{message}"
+    );
+}
+
+#[test]
+fn unpaired_devices_have_to_trust_the_computer() {
+    let (_project, ctx, _ipa) = built_ipa();
+    let usbmuxd = fake_usbmuxd(DeviceMode::Unpaired);
+
+    let err = install_with(&args(&usbmuxd), Some(&ctx)).unwrap_err();
+    assert!(matches!(err, Error::Device(_)));
+    assert!(err.to_string().contains("tap Trust"), "{err}");
+    assert!(usbmuxd.record.lock().unwrap().lockdown_requests.is_empty());
+}
+
+#[test]
+fn devices_can_be_picked_by_udid() {
+    let (_project, ctx, _ipa) = built_ipa();
+    let usbmuxd = fake_usbmuxd(DeviceMode::Install);
+    let other = "00008110-001C2D3E4F506172";
+    usbmuxd
+        .record
+        .lock()
+        .unwrap()
+        .devices
+        .push((9, other.into(), "USB".into()));
+
+    // With two devices plugged in, it can't guess
+    let mut args = args(&usbmuxd);
+    let err = install_with(&args, Some(&ctx)).unwrap_err();
+    assert!(matches!(err, Error::Config(_)));
+    let message = err.to_string();
+    assert!(message.contains("--device"), "{message}");
+    assert!(
+        message.contains(UDID) && message.contains(other),
+        "{message}"
+    );
+
+    args.device = Some(UDID.into());
+    let installation = install_with(&args, Some(&ctx)).unwrap();
+    assert_eq!(installation.udid, UDID);
+
+    args.device = Some("00000000-0000000000000000".into());
+    let err = install_with(&args, Some(&ctx)).unwrap_err();
+    assert!(err.to_string().contains("isn't connected"), "{err}");
+}
+
+#[test]
+fn full_devices_stop_the_upload() {
+    let (_project, ctx, _ipa) = built_ipa();
+    let usbmuxd = fake_usbmuxd(DeviceMode::Full);
+
+    let err = install_with(&args(&usbmuxd), Some(&ctx)).unwrap_err();
+    assert!(err.to_string().contains("out of space"), "{err}");
+    assert!(usbmuxd.record.lock().unwrap().installs.is_empty());
+}
+
+#[test]
+fn usbmuxd_has_to_be_running() {
+    let (project, ctx, _ipa) = built_ipa();
+    let args = InstallArgs {
+        ipa: None,
+        name: None,
+        device: None,
+        usbmux_socket: project.path().join("no-usbmuxd"),
+    };
+
+    let err = install_with(&args, Some(&ctx)).unwrap_err();
+    assert!(matches!(err, Error::Device(_)));
+    assert!(
+        err.to_string().contains("Couldn't connect to usbmuxd"),
+        "{err}"
+    );
+}
+
+#[test]
+fn only_ipas_can_be_installed() {
+    let usbmuxd = fake_usbmuxd(DeviceMode::Install);
+    let mut args = args(&usbmuxd);
+    args.ipa = Some(PathBuf::from(
+        "target/cargo-ipa/Test App.aarch64-apple-darwin.app",
+    ));
+
+    let err = install_with(&args, None).unwrap_err();
+    assert!(matches!(err, Error::Config(_)));
+}
@@ -0,0 +1,223 @@
+mod common;
+
+use cargo_ipa::{
+    build::{build_with, BuildArgs},
+    connect::ticket,
+    context::{Architecture, Ctx, Platform},
+    error::Error,
+    notarize::{notarize_with, NotarizeArgs},
+    runner::FakeRunner,
+    sign::verify::{verify, SealedFiles},
+};
+use common::{notary::*, *};
+use std::{fs, path::PathBuf, time::SystemTime};
+use tempfile::TempDir;
+
+const APP: &str = "target/cargo-ipa/Test App.aarch64-apple-darwin.app";
+
+/// Builds an ad-hoc signed macOS app
+fn built_app() -> (TempDir, FakeRunner) {
+    let project = project(CARGO_TOML);
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+    let args = BuildArgs {
+        example: None,
+        release: false,
+        name: None,
+        platform: Some(Platform::macOS),
+        architecture: Some(Architecture::aarch64),
+        strip: false,
+        archive: false,
+        export_method: None,
+        simulator: false,
+    };
+    build_with(&mut ctx, &args, &runner).unwrap();
+    (project, runner)
+}
+
+fn args(path: PathBuf, notary: &FakeNotary) -> NotarizeArgs {
+    NotarizeArgs {
+        path,
+        api_key: Some(api_key_path()),
+        api_key_id: None,
+        api_issuer: Some(API_ISSUER.into()),
+        poll_interval: 0,
+        timeout: 60,
+        staple: false,
+        notary_url: notary.notary_url(),
+        upload_url: notary.upload_url(),
+        ticket_url: notary.ticket_url(),
+    }
+}
+
+#[test]
+fn accepted_apps_are_notarized() {
+    let (project, runner) = built_app();
+    let notary = fake_notary(NotaryMode::Accept);
+    let app = project.path().join(APP);
+
+    let notarization = notarize_with(&args(app.clone(), &notary), &runner).unwrap();
+    assert_eq!(notarization.id, "2efe2717-52ef-43a5-96dc-0797e4ca1041");
+    assert_eq!(
+        notarization.warnings,
+        ["`Contents/MacOS/test-app` (arm64): The binary uses an SDK older than the 10.9 SDK."]
+    );
+
+    let record = notary.record.lock().unwrap();
+    assert_eq!(
+        record.submission_name.as_deref(),
+        Some("Test App.aarch64-apple-darwin.zip")
+    );
+    // It polled until the submission was done
+    assert_eq!(record.polls, 2);
+    // The app was zipped (with symlinks kept), uploaded, and then cleaned up
+    let upload = record.upload.as_ref().unwrap();
+    assert!(upload.starts_with(b"Test App.aarch64-apple-darwin.app/Contents/"));
+    let zip = &runner.calls_to("zip")[0];
+    assert_eq!(zip.args[0], "-ry");
+    let out = project.path().join("target/cargo-ipa");
+    assert!(!out.join("Test App.aarch64-apple-darwin.zip").exists());
+
+    // Tokens expire after 20 minutes
+    let claims = &record.claims[0];
+    assert_eq!(
+        claims["exp"].as_u64().unwrap() - claims["iat"].as_u64().unwrap(),
+        20 * 60
+    );
+
+    // The log is saved next to the app
+    assert_eq!(
+        notarization.log,
+        out.join("Test App.aarch64-apple-darwin.notarization.json")
+    );
+    let log: serde_json::Value =
+        serde_json::from_slice(&fs::read(&notarization.log).unwrap()).unwrap();
+    assert_eq!(log["status"], "Accepted");
+    assert!(notarization.ticket.is_none());
+}
+
+#[test]
+fn accepted_apps_can_be_stapled() {
+    let (project, runner) = built_app();
+    let notary = fake_notary(NotaryMode::Accept);
+    let app = project.path().join(APP);
+    let binary = fs::read(app.join("Contents/MacOS/test-app")).unwrap();
+    let slices = verify(&binary, &SealedFiles::default(), SystemTime::now()).unwrap();
+    let record_name = ticket::record_name(
+        slices[0].cdhash_type.unwrap(),
+        slices[0].cdhash.as_ref().unwrap(),
+    );
+    notary.publish_ticket(&record_name, b"a ticket");
+
+    let mut args = args(app.clone(), &notary);
+    args.staple = true;
+    let notarization = notarize_with(&args, &runner).unwrap();
+    assert_eq!(
+        notarization.ticket,
+        Some(app.join("Contents/CodeResources"))
+    );
+    assert_eq!(
+        fs::read(app.join("Contents/CodeResources")).unwrap(),
+        b"a ticket"
+    );
+}
+
+#[test]
+fn only_apps_can_be_stapled() {
+    let dir = tempfile::tempdir().unwrap();
+    let zip = dir.path().join("Test App.zip");
+    fs::write(&zip, b"not really a zip").unwrap();
+    let notary = fake_notary(NotaryMode::Accept);
+    let mut args = args(zip, &notary);
+    args.staple = true;
+
+    let err = notarize_with(&args, &FakeRunner::new()).unwrap_err();
+    assert!(matches!(err, Error::Config(_)));
+    // Nothing was uploaded
+    assert!(notary.record.lock().unwrap().upload.is_none());
+}
+
+#[test]
+fn rejections_list_the_issues() {
+    let (project, runner) = built_app();
+    let notary = fake_notary(NotaryMode::Invalid);
+
+    let err = notarize_with(&args(project.path().join(APP), &notary), &runner).unwrap_err();
+    assert!(matches!(err, Error::Service(_)));
+    assert_eq!(err.exit_code(), 8);
+    let message = err.to_string();
+    assert!(
+        message.contains(
+            "didn't accept the app (Invalid): Archive contains critical validation errors"
+        ),
+        "{message}"
+    );
+    assert!(
+        message.contains("`Contents/MacOS/test-app` (arm64): The binary is not signed with a valid Developer ID certificate."),
+        "{message}"
+    );
+    assert!(message.contains("notarization.json"), "{message}");
+}
+
+#[test]
+fn zips_are_uploaded_as_they_are() {
+    let dir = tempfile::tempdir().unwrap();
+    let zip = dir.path().join("Test App.zip");
+    fs::write(&zip, b"not really a zip").unwrap();
+    let notary = fake_notary(NotaryMode::Accept);
+
+    notarize_with(&args(zip, &notary), &FakeRunner::new()).unwrap();
+    let record = notary.record.lock().unwrap();
+    assert_eq!(record.submission_name.as_deref(), Some("Test App.zip"));
+    assert_eq!(record.upload.as_deref(), Some(&b"not really a zip"[..]));
+}
+
+#[test]
+fn slow_submissions_time_out() {
+    let (project, runner) = built_app();
+    let notary = fake_notary(NotaryMode::Stuck);
+    let mut args = args(project.path().join(APP), &notary);
+    args.timeout = 0;
+
+    let err = notarize_with(&args, &runner).unwrap_err();
+    assert!(
+        err.to_string().contains("is still checking submission"),
+        "{err}"
+    );
+}
+
+#[test]
+fn the_wrong_issuer_is_unauthorized() {
+    let (project, runner) = built_app();
+    let notary = fake_notary(NotaryMode::Accept);
+    let mut args = args(project.path().join(APP), &notary);
+    args.api_issuer = Some("someone-else".into());
+
+    let err = notarize_with(&args, &runner).unwrap_err();
+    let message = err.to_string();
+    assert!(message.contains("HTTP 401"), "{message}");
+    assert!(
+        message.contains("Authentication credentials are missing or invalid."),
+        "{message}"
+    );
+    assert!(message.contains("Check the API key"), "{message}");
+}
+
+#[test]
+fn api_keys_are_needed() {
+    let (project, runner) = built_app();
+    let notary = fake_notary(NotaryMode::Accept);
+    let mut args = args(project.path().join(APP), &notary);
+    args.api_key = None;
+    let err = notarize_with(&args, &runner).unwrap_err();
+    assert!(matches!(err, Error::Config(_)));
+    assert!(err.to_string().contains("--api-key"), "{err}");
+
+    // The key ID can't be guessed from other file names
+    let dir = tempfile::tempdir().unwrap();
+    let key = dir.path().join("key.p8");
+    fs::copy(api_key_path(), &key).unwrap();
+    args.api_key = Some(key);
+    let err = notarize_with(&args, &runner).unwrap_err();
+    assert!(err.to_string().contains("--api-key-id"), "{err}");
+}
@@ -0,0 +1,158 @@
+use cargo_ipa::{
+    context::Platform,
+    error::Error,
+    sign::{identity::Identity, profile::ProvisioningProfile},
+};
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+const BUNDLE_ID: &str = "com.example.test-app";
+const DEVICE: &str = "00008030-001A2B3C4D5E6F70";
+
+fn fixture_path(folder: &str, name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(folder)
+        .join(name)
+}
+
+fn profile() -> ProvisioningProfile {
+    ProvisioningProfile::load(&fixture_path("profile", "test.mobileprovision")).unwrap()
+}
+
+fn identity() -> Identity {
+    Identity::load(
+        &fixture_path("identity", "identity.p12"),
+        "cargo-ipa",
+        &[] as &[PathBuf],
+    )
+    .unwrap()
+}
+
+/// A time when the test profile is valid
+fn now() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+}
+
+/// Checks the profile, expecting an error, and returns its message
+fn check_error(
+    profile: &ProvisioningProfile,
+    bundle_id: &str,
+    devices: &[&str],
+    now: SystemTime,
+) -> String {
+    let devices: Vec<String> = devices.iter().map(|device| device.to_string()).collect();
+    let result = profile.check(bundle_id, &identity(), &devices, now);
+    let Err(err) = result else {
+        panic!("checking the profile succeeded")
+    };
+    assert!(matches!(err, Error::Signing(_)));
+    assert_eq!(err.exit_code(), 7);
+    err.to_string()
+}
+
+#[test]
+fn profiles_are_parsed() {
+    let profile = profile();
+    assert_eq!(profile.name, "Test App Development");
+    assert_eq!(
+        profile.application_identifier,
+        "TEAM123456.com.example.test-app"
+    );
+    assert_eq!(profile.developer_certificates.len(), 1);
+    assert_eq!(
+        profile.provisioned_devices,
+        [DEVICE, "00008101-000A1B2C3D4E5F60"]
+    );
+    assert!(!profile.provisions_all_devices);
+    assert_eq!(profile.platforms, ["iOS"]);
+    assert_eq!(
+        profile.entitlements["get-task-allow"].as_boolean(),
+        Some(true)
+    );
+    assert_eq!(
+        profile.expiration_date.to_xml_format(),
+        "2099-01-01T00:00:00Z"
+    );
+}
+
+#[test]
+fn profiles_are_only_for_their_platforms() {
+    let mut profile = profile();
+    assert!(profile.supports(Platform::iOS));
+    assert!(!profile.supports(Platform::macOS));
+    profile.platforms = vec!["OSX".into()];
+    assert!(profile.supports(Platform::macOS));
+    profile.platforms.clear();
+    assert!(profile.supports(Platform::iOS) && profile.supports(Platform::macOS));
+}
+
+#[test]
+fn matching_profiles_pass() {
+    let profile = profile();
+    let devices = [DEVICE.to_lowercase()];
+    profile
+        .check(BUNDLE_ID, &identity(), &devices, now())
+        .unwrap();
+}
+
+#[test]
+fn other_bundle_ids_fail() {
+    let message = check_error(&profile(), "com.example.other-app", &[], now());
+    assert!(message.contains("TEAM123456.com.example.test-app"));
+    assert!(message.contains("com.example.other-app"));
+}
+
+#[test]
+fn wildcard_app_ids_match_prefixes() {
+    let mut profile = profile();
+    profile.application_identifier = "TEAM123456.com.example.*".into();
+    assert!(profile.matches_bundle_id("com.example.other-app"));
+    assert!(!profile.matches_bundle_id("org.example.app"));
+    profile.application_identifier = "TEAM123456.*".into();
+    assert!(profile.matches_bundle_id("org.example.app"));
+}
+
+#[test]
+fn missing_certificates_fail() {
+    let mut profile = profile();
+    profile.developer_certificates.clear();
+    let message = check_error(&profile, BUNDLE_ID, &[], now());
+    assert!(message.contains("Apple Distribution: Test Team (TEAM123456)"));
+}
+
+#[test]
+fn expired_profiles_fail() {
+    let year_2100 = SystemTime::UNIX_EPOCH + Duration::from_secs(4_102_444_800);
+    let message = check_error(&profile(), BUNDLE_ID, &[], year_2100);
+    assert!(message.contains("expired on 2099-01-01T00:00:00Z"));
+}
+
+#[test]
+fn unknown_devices_fail() {
+    let message = check_error(
+        &profile(),
+        BUNDLE_ID,
+        &[DEVICE, "00008110-000000000000001E"],
+        now(),
+    );
+    assert!(message.contains("00008110-000000000000001E"));
+    assert!(!message.contains(DEVICE));
+}
+
+#[test]
+fn enterprise_profiles_allow_every_device() {
+    let mut profile = profile();
+    profile.provisioned_devices.clear();
+    assert!(!profile.allows_device(DEVICE));
+    profile.provisions_all_devices = true;
+    assert!(profile.allows_device(DEVICE));
+}
+
+#[test]
+fn invalid_profiles_fail() {
+    let result = ProvisioningProfile::parse(b"<plist/>".to_vec());
+    assert!(matches!(result, Err(Error::Signing(_))));
+}
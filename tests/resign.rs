@@ -0,0 +1,487 @@
+mod common;
+
+use cargo_ipa::{
+    error::Error,
+    resign::{resign_with, ResignArgs},
+    runner::{FakeRunner, ToolCall},
+    sign::{self, blob::sha256, macho::read_u32_be, SigningOptions},
+};
+use common::*;
+use plist::{Dictionary, Value};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use tempfile::TempDir;
+
+const APP: &str = "Payload/Test App.app";
+
+fn fixture_path(folder: &str, name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(folder)
+        .join(name)
+}
+
+fn args(ipa: &Path) -> ResignArgs {
+    ResignArgs {
+        ipa: ipa.to_owned(),
+        output: None,
+        bundle_id: None,
+        display_name: None,
+        version: None,
+        identity: None,
+        password_file: None,
+        certificate_chain: Vec::new(),
+        profile: None,
+        entitlements: None,
+    }
+}
+
+fn with_identity(args: ResignArgs, dir: &Path) -> ResignArgs {
+    fs::write(dir.join("password.txt"), "cargo-ipa\n").unwrap();
+    ResignArgs {
+        identity: Some(fixture_path("identity", "identity.p12")),
+        password_file: Some(dir.join("password.txt")),
+        certificate_chain: vec![
+            fixture_path("identity", "intermediate.pem"),
+            fixture_path("identity", "root.cer"),
+        ],
+        ..args
+    }
+}
+
+fn runner() -> FakeRunner {
+    FakeRunner::new()
+        .with_tool("zip", fake_zip)
+        .with_tool("unzip", fake_unzip)
+}
+
+fn write_info_plist(bundle: &Path, bundle_id: &str, executable: &str) {
+    let mut info_plist = Dictionary::new();
+    info_plist.insert("CFBundleIdentifier".into(), bundle_id.into());
+    info_plist.insert("CFBundleExecutable".into(), executable.into());
+    info_plist.insert("CFBundleVersion".into(), "1.0.0".into());
+    fs::create_dir_all(bundle).unwrap();
+    Value::from(info_plist)
+        .to_file_xml(bundle.join("Info.plist"))
+        .unwrap();
+}
+
+/// Makes an IPA like one from CI: an ad-hoc signed app with entitlements, a framework, an
+/// app extension, and a dylib
+fn ipa() -> (TempDir, PathBuf) {
+    ipa_with(|_| ())
+}
+
+/// Makes an IPA like [`ipa`], letting `customize` change the app before it's zipped
+fn ipa_with(customize: impl FnOnce(&Path)) -> (TempDir, PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    let app = dir.path().join(APP);
+    write_info_plist(&app, "com.example.test-app", "test-app");
+    fs::write(app.join("icon.png"), b"icon").unwrap();
+    let entitlements = b"<plist version=\"1.0\"><dict><key>keychain-access-groups</key><array><string>TEAM123456.com.example.shared</string></array></dict></plist>";
+    let binary = sign::sign(
+        FAKE_BINARY,
+        &SigningOptions {
+            identifier: "com.example.test-app",
+            entitlements: Some(entitlements),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    fs::write(app.join("test-app"), binary).unwrap();
+
+    let framework = app.join("Frameworks/Example.framework");
+    write_info_plist(&framework, "com.example.framework", "Example");
+    fs::write(framework.join("Example"), FAKE_BINARY).unwrap();
+    fs::write(app.join("Frameworks/libexample.dylib"), FAKE_BINARY).unwrap();
+    let widget = app.join("PlugIns/Widget.appex");
+    write_info_plist(&widget, "com.example.test-app.widget", "Widget");
+    let entitlements = b"<plist version=\"1.0\"><dict><key>application-identifier</key><string>OLDTEAM123.com.example.test-app.widget</string><key>com.apple.developer.team-identifier</key><string>OLDTEAM123</string><key>keychain-access-groups</key><array><string>TEAM123456.com.example.shared</string></array></dict></plist>";
+    let binary = sign::sign(
+        FAKE_BINARY,
+        &SigningOptions {
+            identifier: "com.example.test-app.widget",
+            entitlements: Some(entitlements),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    fs::write(widget.join("Widget"), binary).unwrap();
+    customize(&app);
+
+    let zip = ToolCall::new("zip", &["-r", "Test App.ipa", "Payload"]).current_dir(dir.path());
+    fake_zip(&zip).unwrap();
+    fs::remove_dir_all(dir.path().join("Payload")).unwrap();
+    let ipa = dir.path().join("Test App.ipa");
+    (dir, ipa)
+}
+
+/// Unpacks a re-signed IPA, returning the folder it was unpacked into
+fn unpack(ipa: &Path) -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let call = ToolCall::new(
+        "unzip",
+        &[
+            "-q",
+            ipa.to_str().unwrap(),
+            "-d",
+            dir.path().to_str().unwrap(),
+        ],
+    );
+    fake_unzip(&call).unwrap();
+    dir
+}
+
+fn info_plist(bundle: &Path) -> Dictionary {
+    Value::from_file(bundle.join("Info.plist"))
+        .unwrap()
+        .into_dictionary()
+        .unwrap()
+}
+
+/// Finds the signing identifier & flags in a signed binary's CodeDirectory
+fn code_directory(binary: &Path) -> (String, u32) {
+    let binary = fs::read(binary).unwrap();
+    let cd = sign::find_blob(&binary, 0).unwrap().unwrap();
+    let ident_offset = read_u32_be(cd, 20) as usize;
+    let end = cd[ident_offset..].iter().position(|b| *b == 0).unwrap();
+    let identifier = String::from_utf8(cd[ident_offset..ident_offset + end].to_vec()).unwrap();
+    (identifier, read_u32_be(cd, 12))
+}
+
+fn entitlements(binary: &Path) -> Dictionary {
+    let xml = sign::embedded_entitlements(&fs::read(binary).unwrap())
+        .unwrap()
+        .unwrap();
+    Value::from_reader_xml(&*xml)
+        .unwrap()
+        .into_dictionary()
+        .unwrap()
+}
+
+#[test]
+fn info_plists_are_rewritten() {
+    let (dir, ipa) = ipa();
+    let args = ResignArgs {
+        bundle_id: Some("com.example.qa".into()),
+        display_name: Some("Test App QA".into()),
+        version: Some("2.0.0".into()),
+        ..args(&ipa)
+    };
+    let output = resign_with(&args, &runner()).unwrap();
+    assert_eq!(output, dir.path().join("Test App.resigned.ipa"));
+    // The work folder is cleaned up
+    let mut leftovers: Vec<_> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    leftovers.sort();
+    assert_eq!(leftovers, ["Test App.ipa", "Test App.resigned.ipa"]);
+
+    let unpacked = unpack(&output);
+    let app = unpacked.path().join(APP);
+    let plist = info_plist(&app);
+    assert_eq!(
+        plist["CFBundleIdentifier"].as_string(),
+        Some("com.example.qa")
+    );
+    assert_eq!(
+        plist["CFBundleDisplayName"].as_string(),
+        Some("Test App QA")
+    );
+    assert_eq!(
+        plist["CFBundleShortVersionString"].as_string(),
+        Some("2.0.0")
+    );
+    assert_eq!(plist["CFBundleVersion"].as_string(), Some("2.0.0"));
+    assert_eq!(code_directory(&app.join("test-app")).0, "com.example.qa");
+
+    // Extensions move under the new bundle ID, but frameworks keep theirs
+    let widget = app.join("PlugIns/Widget.appex");
+    assert_eq!(
+        info_plist(&widget)["CFBundleIdentifier"].as_string(),
+        Some("com.example.qa.widget")
+    );
+    assert_eq!(
+        code_directory(&widget.join("Widget")).0,
+        "com.example.qa.widget"
+    );
+    let framework = app.join("Frameworks/Example.framework");
+    assert_eq!(
+        code_directory(&framework.join("Example")).0,
+        "com.example.framework"
+    );
+}
+
+#[test]
+fn nested_code_is_signed_before_the_app() {
+    let (_dir, ipa) = ipa();
+    let output = resign_with(&args(&ipa), &runner()).unwrap();
+    let unpacked = unpack(&output);
+    let app = unpacked.path().join(APP);
+
+    let code_resources = fs::read(app.join("_CodeSignature/CodeResources")).unwrap();
+    let sealed = Value::from_reader_xml(&*code_resources)
+        .unwrap()
+        .into_dictionary()
+        .unwrap();
+    let files2 = sealed["files2"].as_dictionary().unwrap();
+    for bundle in ["Frameworks/Example.framework", "PlugIns/Widget.appex"] {
+        let executable = app.join(bundle).join(if bundle.ends_with("framework") {
+            "Example"
+        } else {
+            "Widget"
+        });
+        let cdhash = sign::cdhash(&fs::read(executable).unwrap())
+            .unwrap()
+            .unwrap();
+        let entry = files2[bundle].as_dictionary().unwrap();
+        assert_eq!(entry["cdhash"].as_data().unwrap(), cdhash);
+        assert!(app
+            .join(bundle)
+            .join("_CodeSignature/CodeResources")
+            .is_file());
+    }
+    // Loose dylibs get signed too, and sealed like any other file
+    let dylib = fs::read(app.join("Frameworks/libexample.dylib")).unwrap();
+    assert!(sign::cdhash(&dylib).unwrap().is_some());
+    assert_eq!(
+        files2["Frameworks/libexample.dylib"]
+            .as_dictionary()
+            .unwrap()["hash2"]
+            .as_data()
+            .unwrap(),
+        sha256(&dylib)
+    );
+
+    // The app's signature covers its CodeResources, and keeps its old entitlements
+    let binary = fs::read(app.join("test-app")).unwrap();
+    let cd = sign::find_blob(&binary, 0).unwrap().unwrap();
+    let hash_offset = read_u32_be(cd, 16) as usize;
+    assert_eq!(
+        cd[hash_offset - 96..hash_offset - 64],
+        sha256(&code_resources)
+    );
+    let entitlements = entitlements(&app.join("test-app"));
+    assert!(entitlements.contains_key("keychain-access-groups"));
+}
+
+#[test]
+fn identities_and_profiles_are_applied() {
+    let (dir, ipa) = ipa();
+    let args = ResignArgs {
+        profile: Some(fixture_path("profile", "test.mobileprovision")),
+        output: Some(dir.path().join("signed.ipa")),
+        ..with_identity(args(&ipa), dir.path())
+    };
+    let output = resign_with(&args, &runner()).unwrap();
+    assert_eq!(output, dir.path().join("signed.ipa"));
+    let unpacked = unpack(&output);
+    let app = unpacked.path().join(APP);
+
+    assert_eq!(
+        fs::read(app.join("embedded.mobileprovision")).unwrap(),
+        fs::read(fixture_path("profile", "test.mobileprovision")).unwrap()
+    );
+    let (identifier, flags) = code_directory(&app.join("test-app"));
+    assert_eq!(identifier, "com.example.test-app");
+    assert_eq!(flags, 0); // Not ad-hoc
+
+    let entitlements = entitlements(&app.join("test-app"));
+    assert_eq!(
+        entitlements["application-identifier"].as_string(),
+        Some("TEAM123456.com.example.test-app")
+    );
+    assert_eq!(entitlements["get-task-allow"].as_boolean(), Some(true));
+}
+
+#[test]
+fn development_builds_can_be_resigned_for_ad_hoc() {
+    // A development build from another team, with push
+    let (dir, ipa) = ipa_with(|app| {
+        let entitlements = b"<plist version=\"1.0\"><dict><key>application-identifier</key><string>OLDTEAM123.com.example.test-app</string><key>com.apple.developer.team-identifier</key><string>OLDTEAM123</string><key>aps-environment</key><string>development</string><key>get-task-allow</key><true/><key>keychain-access-groups</key><array><string>OLDTEAM123.com.example.test-app</string></array></dict></plist>";
+        let binary = sign::sign(
+            FAKE_BINARY,
+            &SigningOptions {
+                identifier: "com.example.test-app",
+                entitlements: Some(entitlements),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        fs::write(app.join("test-app"), binary).unwrap();
+    });
+    let args = ResignArgs {
+        profile: Some(fixture_path("profile", "ad-hoc.mobileprovision")),
+        ..with_identity(args(&ipa), dir.path())
+    };
+    let output = resign_with(&args, &runner()).unwrap();
+    let unpacked = unpack(&output);
+
+    let entitlements = entitlements(&unpacked.path().join(APP).join("test-app"));
+    assert_eq!(
+        entitlements["aps-environment"].as_string(),
+        Some("production")
+    );
+    assert_eq!(entitlements["get-task-allow"].as_boolean(), Some(false));
+    assert_eq!(
+        entitlements["com.apple.developer.team-identifier"].as_string(),
+        Some("TEAM123456")
+    );
+    let keychain_groups: Vec<_> = entitlements["keychain-access-groups"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|group| group.as_string().unwrap())
+        .collect();
+    assert_eq!(keychain_groups, ["TEAM123456.com.example.test-app"]);
+}
+
+#[test]
+fn extensions_get_entitlements_for_their_new_bundle_id() {
+    let (dir, ipa) = ipa();
+    let args = ResignArgs {
+        bundle_id: Some("com.example.qa".into()),
+        ..with_identity(args(&ipa), dir.path())
+    };
+    let output = resign_with(&args, &runner()).unwrap();
+    let unpacked = unpack(&output);
+
+    let widget = unpacked
+        .path()
+        .join(APP)
+        .join("PlugIns/Widget.appex/Widget");
+    let entitlements = entitlements(&widget);
+    assert_eq!(
+        entitlements["application-identifier"].as_string(),
+        Some("TEAM123456.com.example.qa.widget")
+    );
+    assert_eq!(
+        entitlements["com.apple.developer.team-identifier"].as_string(),
+        Some("TEAM123456")
+    );
+    // Everything else is kept
+    assert!(entitlements.contains_key("keychain-access-groups"));
+}
+
+#[test]
+fn extension_profiles_that_dont_fit_anymore_fail() {
+    let (dir, ipa) = ipa_with(|app| {
+        fs::copy(
+            fixture_path("profile", "test.mobileprovision"),
+            app.join("PlugIns/Widget.appex/embedded.mobileprovision"),
+        )
+        .unwrap();
+    });
+    let args = with_identity(args(&ipa), dir.path());
+    let runner = runner();
+    let err = resign_with(&args, &runner).unwrap_err();
+    assert!(matches!(err, Error::Signing(_)), "{err}");
+    assert!(err.to_string().contains("Widget.appex"), "{err}");
+    assert!(
+        err.to_string().contains("com.example.test-app.widget"),
+        "{err}"
+    );
+    assert!(runner.calls_to("zip").is_empty());
+}
+
+#[test]
+fn files_outside_the_payload_are_kept() {
+    let (dir, ipa) = ipa();
+    // Add a SwiftSupport folder next to the Payload, like App Store IPAs have
+    let unpacked = unpack(&ipa);
+    let swift_support = unpacked.path().join("SwiftSupport/iphoneos");
+    fs::create_dir_all(&swift_support).unwrap();
+    fs::write(swift_support.join("libswiftCore.dylib"), b"swift").unwrap();
+    let ipa = dir.path().join("Swift App.ipa");
+    let zip = ToolCall::new(
+        "zip",
+        &["-r", ipa.to_str().unwrap(), "Payload", "SwiftSupport"],
+    )
+    .current_dir(unpacked.path());
+    fake_zip(&zip).unwrap();
+
+    let runner = runner();
+    let output = resign_with(&args(&ipa), &runner).unwrap();
+    assert_eq!(
+        runner.calls_to("zip")[0].args[2..],
+        ["Payload", "SwiftSupport"]
+    );
+    let resigned = unpack(&output);
+    assert_eq!(
+        fs::read(
+            resigned
+                .path()
+                .join("SwiftSupport/iphoneos/libswiftCore.dylib")
+        )
+        .unwrap(),
+        b"swift"
+    );
+    assert!(resigned.path().join(APP).join("test-app").is_file());
+}
+
+#[test]
+fn entitlements_can_be_replaced() {
+    let (dir, ipa) = ipa();
+    let replacement = dir.path().join("new.entitlements");
+    fs::write(
+        &replacement,
+        "<plist version=\"1.0\"><dict><key>com.apple.developer.game-center</key><true/></dict></plist>",
+    )
+    .unwrap();
+    let args = ResignArgs {
+        entitlements: Some(replacement),
+        ..args(&ipa)
+    };
+    let output = resign_with(&args, &runner()).unwrap();
+    let unpacked = unpack(&output);
+
+    let entitlements = entitlements(&unpacked.path().join(APP).join("test-app"));
+    assert_eq!(
+        entitlements.keys().collect::<Vec<_>>(),
+        ["com.apple.developer.game-center"]
+    );
+}
+
+#[test]
+fn profiles_for_other_bundle_ids_fail() {
+    let (dir, ipa) = ipa();
+    let args = ResignArgs {
+        bundle_id: Some("com.example.qa".into()),
+        profile: Some(fixture_path("profile", "test.mobileprovision")),
+        ..with_identity(args(&ipa), dir.path())
+    };
+    let runner = runner();
+    let err = resign_with(&args, &runner).unwrap_err();
+    assert!(matches!(err, Error::Signing(_)), "{err}");
+    assert!(err.to_string().contains("com.example.qa"));
+    assert!(runner.calls_to("zip").is_empty());
+}
+
+#[test]
+fn profiles_need_an_identity() {
+    let (_dir, ipa) = ipa();
+    let args = ResignArgs {
+        profile: Some(fixture_path("profile", "test.mobileprovision")),
+        ..args(&ipa)
+    };
+    let runner = runner();
+    let err = resign_with(&args, &runner).unwrap_err();
+    assert!(err.to_string().contains("--identity"), "{err}");
+    assert!(runner.calls_to("unzip").is_empty());
+}
+
+#[test]
+fn archives_without_an_app_fail() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("Payload")).unwrap();
+    fs::write(dir.path().join("Payload/readme.txt"), "hi").unwrap();
+    let zip = ToolCall::new("zip", &["-r", "empty.ipa", "Payload"]).current_dir(dir.path());
+    fake_zip(&zip).unwrap();
+
+    let err = resign_with(&args(&dir.path().join("empty.ipa")), &runner()).unwrap_err();
+    assert!(matches!(err, Error::Packaging { .. }), "{err}");
+    assert_eq!(err.exit_code(), 5);
+}
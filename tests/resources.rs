@@ -0,0 +1,327 @@
+use cargo_ipa::{
+    error::Error,
+    sign::{
+        self,
+        resources::{self, CODE_RESOURCES_PATH},
+        SigningOptions,
+    },
+};
+use plist::{Dictionary, Value};
+use sha1::{Digest, Sha1};
+use std::{fs, path::Path};
+
+const ARM64: &[u8] = include_bytes!("fixtures/macho/arm64");
+
+fn write(root: &Path, path: &str, data: &[u8]) {
+    let path = root.join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, data).unwrap();
+}
+
+/// Makes a framework with a signed executable, returning that executable
+fn framework(root: &Path, path: &str) -> Vec<u8> {
+    let binary = sign::sign(
+        ARM64,
+        &SigningOptions {
+            identifier: "com.example.framework",
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    write(
+        root,
+        &format!("{path}/Info.plist"),
+        b"<plist version=\"1.0\"><dict><key>CFBundleExecutable</key><string>Framework</string></dict></plist>",
+    );
+    write(root, &format!("{path}/Framework"), &binary);
+    binary
+}
+
+fn code_resources(root: &Path, main_executable: &str, macos: bool) -> Dictionary {
+    let xml = resources::code_resources(root, Path::new(main_executable), macos).unwrap();
+    Value::from_reader_xml(&*xml)
+        .unwrap()
+        .into_dictionary()
+        .unwrap()
+}
+
+fn section<'a>(code_resources: &'a Dictionary, name: &str) -> &'a Dictionary {
+    code_resources.get(name).unwrap().as_dictionary().unwrap()
+}
+
+fn keys(dict: &Dictionary) -> Vec<&str> {
+    dict.keys().map(String::as_str).collect()
+}
+
+fn data(value: &Value, key: &str) -> Vec<u8> {
+    value
+        .as_dictionary()
+        .unwrap()
+        .get(key)
+        .unwrap()
+        .as_data()
+        .unwrap()
+        .to_vec()
+}
+
+#[test]
+fn ios_apps_follow_the_standard_rules() {
+    let app = tempfile::tempdir().unwrap();
+    let app = app.path();
+    write(app, "Info.plist", b"info");
+    write(app, "PkgInfo", b"APPL????");
+    write(app, "test-app", ARM64);
+    write(app, "icon.png", b"icon");
+    write(app, "assets/.DS_Store", b"finder junk");
+    write(app, "en.lproj/Localizable.strings", b"strings");
+    write(app, "en.lproj/locversion.plist", b"locversion");
+    write(app, "Base.lproj/Main.storyboardc", b"storyboard");
+    write(app, "_CodeSignature/CodeResources", b"old seal");
+
+    let code_resources = code_resources(app, "test-app", false);
+    assert_eq!(
+        keys(&code_resources),
+        ["files", "files2", "rules", "rules2"]
+    );
+
+    // Version 1 hashes Info.plist and PkgInfo, but not the main executable, its own
+    // signature, or locversion.plist
+    let files = section(&code_resources, "files");
+    assert_eq!(
+        keys(files),
+        [
+            "Base.lproj/Main.storyboardc",
+            "Info.plist",
+            "PkgInfo",
+            "assets/.DS_Store",
+            "en.lproj/Localizable.strings",
+            "icon.png"
+        ]
+    );
+    assert_eq!(
+        files["icon.png"].as_data().unwrap(),
+        Sha1::digest(b"icon").as_slice()
+    );
+    // Localisations are optional, except for Base
+    assert_eq!(
+        data(&files["en.lproj/Localizable.strings"], "hash"),
+        Sha1::digest(b"strings").as_slice()
+    );
+    let optional = &files["en.lproj/Localizable.strings"]
+        .as_dictionary()
+        .unwrap()["optional"];
+    assert_eq!(optional.as_boolean(), Some(true));
+    assert!(files["Base.lproj/Main.storyboardc"].as_data().is_some());
+
+    // Version 2 leaves out Info.plist (it's sealed by the executable) and .DS_Store
+    let files2 = section(&code_resources, "files2");
+    assert_eq!(
+        keys(files2),
+        [
+            "Base.lproj/Main.storyboardc",
+            "en.lproj/Localizable.strings",
+            "icon.png"
+        ]
+    );
+    assert_eq!(
+        data(&files2["icon.png"], "hash"),
+        Sha1::digest(b"icon").as_slice()
+    );
+    assert_eq!(
+        data(&files2["icon.png"], "hash2"),
+        sign::blob::sha256(b"icon")
+    );
+    let localised = files2["en.lproj/Localizable.strings"]
+        .as_dictionary()
+        .unwrap();
+    assert_eq!(localised["optional"].as_boolean(), Some(true));
+
+    // The rules are written out too
+    let rules2 = section(&code_resources, "rules2");
+    assert_eq!(rules2["^.*"].as_boolean(), Some(true));
+    let info_plist = rules2["^Info\\.plist$"].as_dictionary().unwrap();
+    assert_eq!(info_plist["omit"].as_boolean(), Some(true));
+    assert_eq!(info_plist["weight"].as_real(), Some(20.0));
+}
+
+#[test]
+fn nested_code_is_sealed_by_cdhash() {
+    let app = tempfile::tempdir().unwrap();
+    let app = app.path();
+    write(app, "test-app", ARM64);
+    let framework = framework(app, "Frameworks/Example.framework");
+    write(app, "Frameworks/Example.framework/data.json", b"{}");
+
+    let code_resources = code_resources(app, "test-app", false);
+    let cdhash = sign::cdhash(&framework).unwrap().unwrap();
+
+    let files2 = section(&code_resources, "files2");
+    assert_eq!(keys(files2), ["Frameworks/Example.framework"]);
+    let nested = files2["Frameworks/Example.framework"]
+        .as_dictionary()
+        .unwrap();
+    assert_eq!(nested["cdhash"].as_data().unwrap(), cdhash);
+    let hex: String = cdhash.iter().map(|byte| format!("{byte:02x}")).collect();
+    assert_eq!(
+        nested["requirement"].as_string().unwrap(),
+        format!("cdhash H\"{hex}\"")
+    );
+
+    // Version 1 doesn't understand nested code, so it gets the framework's files instead
+    let files = section(&code_resources, "files");
+    assert_eq!(
+        keys(files),
+        [
+            "Frameworks/Example.framework/Framework",
+            "Frameworks/Example.framework/Info.plist",
+            "Frameworks/Example.framework/data.json"
+        ]
+    );
+}
+
+#[test]
+fn unsigned_nested_code_fails() {
+    let app = tempfile::tempdir().unwrap();
+    let app = app.path();
+    write(app, "test-app", ARM64);
+    framework(app, "Frameworks/Example.framework");
+    write(app, "Frameworks/Example.framework/Framework", ARM64);
+
+    let result = resources::code_resources(app, Path::new("test-app"), false);
+    let Err(Error::Signing(message)) = result else {
+        panic!("sealing unsigned nested code succeeded")
+    };
+    assert!(message.contains("Frameworks/Example.framework"));
+}
+
+#[test]
+fn macos_apps_seal_their_contents_folder() {
+    let app = tempfile::tempdir().unwrap();
+    let contents = app.path().join("Contents");
+    write(&contents, "Info.plist", b"info");
+    write(&contents, "MacOS/test-app", ARM64);
+    write(&contents, "Resources/icon.icns", b"icon");
+    write(
+        &contents,
+        "Resources/en.lproj/Localizable.strings",
+        b"strings",
+    );
+    let helper = sign::sign(
+        ARM64,
+        &SigningOptions {
+            identifier: "com.example.helper",
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    write(&contents, "MacOS/helper", &helper);
+
+    let code_resources = code_resources(&contents, "MacOS/test-app", true);
+
+    // Version 1 only covers resources
+    let files = section(&code_resources, "files");
+    assert_eq!(
+        keys(files),
+        [
+            "Resources/en.lproj/Localizable.strings",
+            "Resources/icon.icns"
+        ]
+    );
+
+    // Binaries in MacOS are nested code
+    let files2 = section(&code_resources, "files2");
+    assert_eq!(
+        keys(files2),
+        [
+            "MacOS/helper",
+            "Resources/en.lproj/Localizable.strings",
+            "Resources/icon.icns"
+        ]
+    );
+    assert_eq!(
+        files2["MacOS/helper"].as_dictionary().unwrap()["cdhash"]
+            .as_data()
+            .unwrap(),
+        sign::cdhash(&helper).unwrap().unwrap()
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_are_sealed_by_their_target() {
+    let app = tempfile::tempdir().unwrap();
+    let app = app.path();
+    write(app, "test-app", ARM64);
+    write(app, "icon.png", b"icon");
+    std::os::unix::fs::symlink("icon.png", app.join("icon@2x.png")).unwrap();
+
+    let code_resources = code_resources(app, "test-app", false);
+    let files2 = section(&code_resources, "files2");
+    let symlink = files2["icon@2x.png"].as_dictionary().unwrap();
+    assert_eq!(symlink["symlink"].as_string(), Some("icon.png"));
+    assert!(!section(&code_resources, "files").contains_key("icon@2x.png"));
+}
+
+#[test]
+fn sealing_writes_code_resources() {
+    let app = tempfile::tempdir().unwrap();
+    let app = app.path();
+    write(app, "test-app", ARM64);
+    write(app, "icon.png", b"icon");
+
+    let sealed = resources::seal(app, Path::new("test-app"), false).unwrap();
+    assert_eq!(fs::read(app.join(CODE_RESOURCES_PATH)).unwrap(), sealed);
+    // Sealing again ignores the last seal
+    assert_eq!(
+        resources::seal(app, Path::new("test-app"), false).unwrap(),
+        sealed
+    );
+}
+
+#[test]
+fn code_resources_are_bound_to_the_executable() {
+    let signed = sign::sign(
+        ARM64,
+        &SigningOptions {
+            identifier: "com.example.test-app",
+            code_resources: Some(b"code resources"),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let command = sign::macho::MachO::parse(&signed)
+        .unwrap()
+        .code_signature
+        .unwrap();
+    let signature = &signed[command.dataoff as usize..];
+    let cd = &signature[sign::macho::read_u32_be(signature, 16) as usize..];
+    let hash_offset = sign::macho::read_u32_be(cd, 16) as usize;
+    assert_eq!(
+        cd[hash_offset - 3 * 32..hash_offset - 2 * 32],
+        sign::blob::sha256(b"code resources")
+    );
+}
+
+#[test]
+fn cdhashes_are_read_from_signatures() {
+    assert_eq!(sign::cdhash(ARM64).unwrap(), None);
+    let signed = sign::sign(
+        ARM64,
+        &SigningOptions {
+            identifier: "com.example.test-app",
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let command = sign::macho::MachO::parse(&signed)
+        .unwrap()
+        .code_signature
+        .unwrap();
+    let signature = &signed[command.dataoff as usize..];
+    let offset = sign::macho::read_u32_be(signature, 16) as usize;
+    let length = sign::macho::read_u32_be(signature, offset + 4) as usize;
+    assert_eq!(
+        sign::cdhash(&signed).unwrap().unwrap(),
+        sign::blob::sha256(&signature[offset..offset + length])[..20]
+    );
+}
@@ -0,0 +1,469 @@
+mod common;
+
+use cargo_ipa::{
+    build::{build_with, BuildArgs},
+    context::{Architecture, Ctx, Platform},
+    error::Error,
+    run::{run_with, RunArgs},
+    runner::{FakeRunner, ToolCall, ToolOutput},
+};
+use common::*;
+use std::{env, fs, io, path::Path};
+use tempfile::TempDir;
+
+const IPHONE_15: &str = "6C3B5E1A-2F4D-4E8B-9A1C-3D5E7F9B1A2C";
+const IPAD_AIR: &str = "A1B2C3D4-E5F6-4789-8ABC-DEF012345678";
+const IPHONE_14: &str = "0F1E2D3C-4B5A-4697-8877-665544332211";
+const WATCH: &str = "99887766-5544-4332-8110-FFEEDDCCBBAA";
+
+/// `simctl list devices available --json`'s output, with `booted` booted
+fn simulators(booted: Option<&str>) -> String {
+    let simulator = |udid: &str, name: &str| {
+        let state = if booted == Some(udid) {
+            "Booted"
+        } else {
+            "Shutdown"
+        };
+        format!(
+            r#"{{"udid": "{udid}", "name": "{name}", "state": "{state}", "isAvailable": true, "deviceTypeIdentifier": "com.apple.CoreSimulator.SimDeviceType.{}"}}"#,
+            name.replace(' ', "-")
+        )
+    };
+    format!(
+        r#"{{"devices": {{
+            "com.apple.CoreSimulator.SimRuntime.iOS-16-4": [{}],
+            "com.apple.CoreSimulator.SimRuntime.iOS-17-2": [{}, {}],
+            "com.apple.CoreSimulator.SimRuntime.watchOS-10-2": [{}]
+        }}}}"#,
+        simulator(IPHONE_14, "iPhone 14"),
+        simulator(IPAD_AIR, "iPad Air"),
+        simulator(IPHONE_15, "iPhone 15"),
+        simulator(WATCH, "Apple Watch Series 9"),
+    )
+}
+
+/// A fake `xcrun`, whose simctl lists `simulators`, and whose apps exit with `exit_code`
+fn fake_xcrun(simulators: String, exit_code: i32) -> impl Fn(&ToolCall) -> io::Result<ToolOutput> {
+    move |call| {
+        let args: Vec<_> = call.args.iter().map(String::as_str).collect();
+        match args.as_slice() {
+            ["simctl", "list", ..] => Ok(ToolOutput::success(&simulators)),
+            ["simctl", "boot", _] => Ok(ToolOutput::success("")),
+            ["simctl", "install", _, app] => {
+                assert!(Path::new(app).join("Info.plist").exists());
+                Ok(ToolOutput::success(""))
+            }
+            ["simctl", "launch", ..] => {
+                let bundle_id = args.iter().find(|arg| arg.starts_with("com.example.")).unwrap();
+                Ok(ToolOutput {
+                    success: exit_code == 0,
+                    code: Some(exit_code),
+                    stdout: format!("{bundle_id}: 1234\nHello from the simulator\n"),
+                    stderr: String::new(),
+                })
+            }
+            _ => Ok(ToolOutput::success("/Applications/Xcode.app/Contents/Developer/Platforms/iPhoneOS.platform/Developer/SDKs/iPhoneOS.sdk\n")),
+        }
+    }
+}
+
+/// The target of the IPA `cargo ipa run` runs by default, which this machine's Simulator would
+/// run natively
+fn simulator_target() -> &'static str {
+    match env::consts::ARCH {
+        "aarch64" => "aarch64-apple-ios-sim",
+        _ => "x86_64-apple-ios",
+    }
+}
+
+/// Builds a Simulator IPA for this machine's architecture, with the simulators `xcrun` lists
+fn built_ipa(
+    xcrun: impl Fn(&ToolCall) -> io::Result<ToolOutput> + 'static,
+) -> (TempDir, Ctx, FakeRunner) {
+    let project = project(CARGO_TOML);
+    let runner = fake_toolchain(project.path())
+        .with_tool("unzip", fake_unzip)
+        .with_tool("xcrun", xcrun);
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+    let args = BuildArgs {
+        example: None,
+        release: false,
+        name: None,
+        platform: Some(Platform::iOS),
+        architecture: Some(match env::consts::ARCH {
+            "aarch64" => Architecture::aarch64,
+            _ => Architecture::x86_64,
+        }),
+        strip: false,
+        archive: false,
+        export_method: None,
+        simulator: true,
+    };
+    build_with(&mut ctx, &args, &runner).unwrap();
+    (project, ctx, runner)
+}
+
+fn args() -> RunArgs {
+    RunArgs {
+        app: None,
+        name: None,
+        platform: None,
+        device: None,
+        envs: Vec::new(),
+        log: false,
+        subsystem: None,
+        wait_for_debugger: false,
+        lldb: false,
+        app_args: Vec::new(),
+    }
+}
+
+fn simctl_calls(runner: &FakeRunner, command: &str) -> Vec<ToolCall> {
+    runner
+        .calls_to("xcrun")
+        .into_iter()
+        .filter(|call| call.args[..2] == ["simctl", command])
+        .collect()
+}
+
+#[test]
+fn apps_run_on_the_newest_iphone() {
+    let (project, ctx, runner) = built_ipa(fake_xcrun(simulators(None), 0));
+    let mut args = args();
+    args.envs = vec![("RUST_LOG".into(), "debug".into())];
+    args.app_args = vec!["--level".into(), "3".into()];
+
+    assert_eq!(run_with(&args, Some(&ctx), &runner).unwrap(), 0);
+    // The watchOS simulator, and older iPhones, weren't considered
+    let boot = simctl_calls(&runner, "boot");
+    assert_eq!(boot.len(), 1);
+    assert_eq!(boot[0].args[2], IPHONE_15);
+    // The app in the IPA was installed, then cleaned up
+    let install = simctl_calls(&runner, "install");
+    assert_eq!(install[0].args[2], IPHONE_15);
+    let target = simulator_target();
+    assert!(install[0].args[3].ends_with(&format!("Payload/Test App.{target}.app")));
+    assert!(!project
+        .path()
+        .join(format!("target/cargo-ipa/.Test App.{target}.run"))
+        .exists());
+
+    // It was launched by its bundle ID, with its arguments & environment, showing its output
+    let launch = &simctl_calls(&runner, "launch")[0];
+    assert_eq!(
+        launch.args[2..],
+        [
+            "--console-pty",
+            "--terminate-running-process",
+            IPHONE_15,
+            "com.example.test-app",
+            "--level",
+            "3"
+        ]
+    );
+    assert_eq!(
+        launch.envs,
+        [("SIMCTL_CHILD_RUST_LOG".to_string(), "debug".to_string())]
+    );
+    assert!(launch.echo);
+}
+
+#[test]
+fn exit_codes_are_passed_on() {
+    let (_project, ctx, runner) = built_ipa(fake_xcrun(simulators(None), 3));
+    assert_eq!(run_with(&args(), Some(&ctx), &runner).unwrap(), 3);
+}
+
+#[test]
+fn booted_simulators_are_used() {
+    let (_project, ctx, runner) = built_ipa(fake_xcrun(simulators(Some(IPHONE_14)), 0));
+
+    run_with(&args(), Some(&ctx), &runner).unwrap();
+    assert!(simctl_calls(&runner, "boot").is_empty());
+    assert_eq!(simctl_calls(&runner, "install")[0].args[2], IPHONE_14);
+}
+
+#[test]
+fn simulators_can_be_picked() {
+    let (_project, ctx, runner) = built_ipa(fake_xcrun(simulators(None), 0));
+    let mut args = args();
+
+    args.device = Some("iPad Air".into());
+    run_with(&args, Some(&ctx), &runner).unwrap();
+    args.device = Some(IPHONE_14.into());
+    run_with(&args, Some(&ctx), &runner).unwrap();
+    let installs: Vec<_> = simctl_calls(&runner, "install")
+        .into_iter()
+        .map(|call| call.args[2].clone())
+        .collect();
+    assert_eq!(installs, [IPAD_AIR, IPHONE_14]);
+
+    // Only iOS simulators can run iOS apps
+    for device in ["iPhone 99", WATCH] {
+        args.device = Some(device.into());
+        let err = run_with(&args, Some(&ctx), &runner).unwrap_err();
+        assert!(matches!(err, Error::Device(_)));
+        assert!(err.to_string().contains("no simulator called"), "{err}");
+    }
+}
+
+#[test]
+fn launch_failures_are_explained() {
+    let (_project, ctx, runner) = built_ipa(|call| {
+        let launch = ToolOutput {
+            success: false,
+            code: Some(4),
+            stdout: String::new(),
+            stderr: "An error was encountered processing the command (domain=FBSOpenApplicationServiceErrorDomain, code=4):\nThe request to open \"com.example.test-app\" failed.\n".into(),
+        };
+        match call.args.get(1).map(String::as_str) {
+            Some("launch") => Ok(launch),
+            _ => fake_xcrun(simulators(None), 0)(call),
+        }
+    });
+
+    let err = run_with(&args(), Some(&ctx), &runner).unwrap_err();
+    assert!(matches!(err, Error::Device(_)));
+    assert_eq!(err.exit_code(), 9);
+    assert!(err.to_string().contains("couldn't launch the app"), "{err}");
+    assert!(err
+        .to_string()
+        .contains("FBSOpenApplicationServiceErrorDomain"));
+}
+
+#[test]
+fn apps_that_fail_after_launching_pass_on_their_exit_code() {
+    // Whatever the app prints, it was launched, since simctl printed its PID
+    let (_project, ctx, runner) = built_ipa(|call| match call.args.get(1).map(String::as_str) {
+        Some("launch") => Ok(ToolOutput {
+            success: false,
+            code: Some(4),
+            stdout: "com.example.test-app: 1234\n".into(),
+            stderr: "An error was encountered processing the command\n".into(),
+        }),
+        _ => fake_xcrun(simulators(None), 0)(call),
+    });
+    assert_eq!(run_with(&args(), Some(&ctx), &runner).unwrap(), 4);
+}
+
+#[test]
+fn the_default_ipa_is_for_this_machines_simulator() {
+    let project = project(CARGO_TOML);
+    let ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+
+    let err = run_with(&args(), Some(&ctx), &FakeRunner::new()).unwrap_err();
+    assert!(matches!(err, Error::Config(_)));
+    let ipa = format!("Test App.{}.ipa", simulator_target());
+    assert!(err.to_string().contains(&ipa), "{err}");
+}
+
+#[test]
+fn simulators_have_to_be_installed() {
+    let (_project, ctx, runner) = built_ipa(fake_xcrun(r#"{"devices": {}}"#.into(), 0));
+
+    let err = run_with(&args(), Some(&ctx), &runner).unwrap_err();
+    assert!(matches!(err, Error::ToolchainMissing { .. }));
+    assert_eq!(err.exit_code(), 3);
+}
+
+#[test]
+fn apps_can_be_run_without_unpacking() {
+    let dir = tempfile::tempdir().unwrap();
+    let app = dir.path().join("Other.app");
+    fs::create_dir(&app).unwrap();
+    let mut info_plist = plist::Dictionary::new();
+    info_plist.insert("CFBundleIdentifier".into(), "com.example.other".into());
+    plist::Value::from(info_plist)
+        .to_file_xml(app.join("Info.plist"))
+        .unwrap();
+    let runner = FakeRunner::new().with_tool("xcrun", fake_xcrun(simulators(None), 0));
+    let mut args = args();
+    args.app = Some(app.clone());
+
+    run_with(&args, None, &runner).unwrap();
+    assert!(runner.calls_to("unzip").is_empty());
+    assert_eq!(
+        simctl_calls(&runner, "install")[0].args[3],
+        app.to_str().unwrap()
+    );
+    assert_eq!(
+        simctl_calls(&runner, "launch")[0].args[5],
+        "com.example.other"
+    );
+}
+
+#[test]
+fn simulator_logs_and_debugging() {
+    let (_project, ctx, runner) = built_ipa(fake_xcrun(simulators(None), 0));
+    let mut args = args();
+    args.log = true;
+    args.wait_for_debugger = true;
+
+    run_with(&args, Some(&ctx), &runner).unwrap();
+    // The simulator's log is streamed while the app runs
+    let log = &simctl_calls(&runner, "spawn")[0];
+    assert_eq!(
+        log.args[2..],
+        [
+            IPHONE_15,
+            "log",
+            "stream",
+            "--style",
+            "compact",
+            "--level",
+            "debug",
+            "--predicate",
+            "subsystem == \"com.example.test-app\""
+        ]
+    );
+    let launch = &simctl_calls(&runner, "launch")[0];
+    assert_eq!(launch.args[4..6], ["--wait-for-debugger", IPHONE_15]);
+
+    // lldb can't debug apps on the Simulator
+    args.wait_for_debugger = false;
+    args.lldb = true;
+    let err = run_with(&args, Some(&ctx), &runner).unwrap_err();
+    assert!(matches!(err, Error::Config(_)));
+}
+
+const RUSTC_VERSION: &str = "rustc 1.80.0 (051478957 2024-07-21)
+binary: rustc
+commit-hash: 051478957371ee0084a7c0913941d2a8c4757bb9
+commit-date: 2024-07-21
+host: aarch64-apple-darwin
+release: 1.80.0
+LLVM version: 18.1.7
+";
+const SYSROOT: &str = "/Users/me/.rustup/toolchains/stable-aarch64-apple-darwin";
+
+/// Builds a macOS app, whose binary exits with `exit_code`. Returns the binary's path too.
+fn built_app(exit_code: i32) -> (TempDir, Ctx, FakeRunner, String) {
+    let project = project(CARGO_TOML);
+    let binary = project
+        .path()
+        .join(format!(
+            "target/cargo-ipa/Test App.{}-apple-darwin.app/Contents/MacOS/test-app",
+            env::consts::ARCH
+        ))
+        .to_str()
+        .unwrap()
+        .to_string();
+    let runner = fake_toolchain(project.path())
+        .with_output("log", "")
+        .with_output("sh", "")
+        .with_output("lldb", "")
+        .with_tool("rustc", |call| match call.args[0].as_str() {
+            "--print" => Ok(ToolOutput::success(&format!("{SYSROOT}\n"))),
+            _ => Ok(ToolOutput::success(RUSTC_VERSION)),
+        })
+        .with_tool(&binary, move |_| {
+            Ok(ToolOutput {
+                success: exit_code == 0,
+                code: Some(exit_code),
+                stdout: "Hello from macOS\n".into(),
+                stderr: String::new(),
+            })
+        });
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+    let args = BuildArgs {
+        example: None,
+        release: false,
+        name: None,
+        platform: Some(Platform::macOS),
+        architecture: None,
+        strip: false,
+        archive: false,
+        export_method: None,
+        simulator: false,
+    };
+    build_with(&mut ctx, &args, &runner).unwrap();
+    (project, ctx, runner, binary)
+}
+
+#[test]
+fn macos_apps_run_inside_their_bundle() {
+    let (_project, ctx, runner, binary) = built_app(5);
+    let mut args = args();
+    args.platform = Some(Platform::macOS);
+    args.envs = vec![("RUST_LOG".into(), "debug".into())];
+    args.app_args = vec!["--level".into(), "3".into()];
+    args.log = true;
+    args.subsystem = Some("com.example.logging".into());
+
+    assert_eq!(run_with(&args, Some(&ctx), &runner).unwrap(), 5);
+    let app = &runner.calls_to(&binary)[0];
+    assert_eq!(app.args, ["--level", "3"]);
+    assert_eq!(app.envs, [("RUST_LOG".to_string(), "debug".to_string())]);
+    assert!(app.echo);
+    let log = &runner.calls_to("log")[0];
+    assert_eq!(
+        log.args.last().unwrap(),
+        "subsystem == \"com.example.logging\""
+    );
+}
+
+#[test]
+fn macos_apps_are_recognised() {
+    let (_project, ctx, runner, binary) = built_app(0);
+    let mut args = args();
+    args.app = Some(
+        ctx.cargo_ipa_dir
+            .join(format!("Test App.{}-apple-darwin.app", env::consts::ARCH)),
+    );
+
+    assert_eq!(run_with(&args, None, &runner).unwrap(), 0);
+    assert_eq!(runner.calls_to(&binary).len(), 1);
+    assert!(runner.calls_to("xcrun").is_empty());
+}
+
+#[test]
+fn macos_apps_can_wait_for_debuggers() {
+    let (_project, ctx, runner, binary) = built_app(0);
+    let mut args = args();
+    args.platform = Some(Platform::macOS);
+    args.wait_for_debugger = true;
+    args.app_args = vec!["--level".into(), "3".into()];
+
+    run_with(&args, Some(&ctx), &runner).unwrap();
+    // The app's started by a shell that stops itself, then becomes the app
+    let sh = &runner.calls_to("sh")[0];
+    assert_eq!(sh.args[0], "-c");
+    assert!(sh.args[1].contains("kill -STOP $$"));
+    assert_eq!(sh.args[2..], [binary.as_str(), "--level", "3"]);
+}
+
+#[test]
+fn macos_apps_can_be_opened_in_lldb() {
+    let (_project, ctx, runner, binary) = built_app(0);
+    let mut args = args();
+    args.platform = Some(Platform::macOS);
+    args.lldb = true;
+    args.envs = vec![("RUST_LOG".into(), "debug".into())];
+    args.app_args = vec!["--level".into(), "3".into()];
+
+    run_with(&args, Some(&ctx), &runner).unwrap();
+    let lldb = &runner.calls_to("lldb")[0];
+    assert!(lldb.interactive);
+    let source_map = format!(
+        "settings set target.source-map /rustc/051478957371ee0084a7c0913941d2a8c4757bb9 \"{SYSROOT}/lib/rustlib/src/rust\""
+    );
+    let lookup = format!("command script import \"{SYSROOT}/lib/rustlib/etc/lldb_lookup.py\"");
+    let commands = format!("command source -s 0 \"{SYSROOT}/lib/rustlib/etc/lldb_commands\"");
+    assert_eq!(
+        lldb.args,
+        [
+            "-O",
+            source_map.as_str(),
+            "-O",
+            &lookup,
+            "-O",
+            &commands,
+            "-O",
+            "settings append target.env-vars \"RUST_LOG=debug\"",
+            "--",
+            &binary,
+            "--level",
+            "3"
+        ]
+    );
+}
@@ -0,0 +1,543 @@
+use cargo_ipa::{
+    error::Error,
+    sign::{
+        self,
+        identity::Identity,
+        macho::{self, MachO},
+        SigningOptions,
+    },
+};
+use cms::{content_info::ContentInfo, signed_data::SignedData};
+use rsa::{
+    pkcs1v15::{Signature, VerifyingKey},
+    pkcs8::DecodePublicKey,
+    signature::Verifier,
+    RsaPublicKey,
+};
+use sha2::Sha256;
+use std::{
+    env, fs,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+use x509_cert::{
+    der::{
+        asn1::{OctetString, UtcTime},
+        oid::ObjectIdentifier,
+        pem::LineEnding,
+        Decode, Encode, EncodePem,
+    },
+    Certificate,
+};
+
+const INFO_PLIST: &[u8] =
+    b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<plist version=\"1.0\"><dict/></plist>\n";
+
+fn fixture(name: &str) -> Vec<u8> {
+    fs::read(fixture_path(name)).unwrap()
+}
+
+fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/macho")
+        .join(name)
+}
+
+fn options() -> SigningOptions<'static> {
+    SigningOptions {
+        identifier: "com.example.test-app",
+        info_plist: Some(INFO_PLIST),
+        ..Default::default()
+    }
+}
+
+/// The password for the test identities
+const PASSWORD: &str = "cargo-ipa";
+
+fn identity_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/identity")
+        .join(name)
+}
+
+/// Loads the test identity, with its whole certificate chain
+fn identity() -> Identity {
+    Identity::load(
+        &identity_path("identity.p12"),
+        PASSWORD,
+        &[identity_path("intermediate.pem"), identity_path("root.cer")],
+    )
+    .unwrap()
+}
+
+/// A fixed signing time, so signatures made with an identity don't change between runs
+fn signing_time() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+}
+
+/// Signs a fixture and compares it to the known-good signed version.
+/// Run with `CARGO_IPA_BLESS=1` to update the known-good versions, then run
+/// `tests/fixtures/macho/codesign.sh` on a Mac to check them with `codesign`.
+fn check_golden(name: &str) {
+    check_golden_with(name, name, &options());
+}
+
+fn check_golden_with(name: &str, golden: &str, options: &SigningOptions) {
+    let signed = sign::sign(&fixture(name), options).unwrap();
+    let golden_path = fixture_path(&format!("{golden}.signed"));
+    if env::var_os("CARGO_IPA_BLESS").is_some() {
+        fs::write(&golden_path, &signed).unwrap();
+    }
+    assert!(
+        signed == fs::read(&golden_path).unwrap(),
+        "signing `{name}` didn't match `{}`",
+        golden_path.display()
+    );
+}
+
+/// Finds the signature in a signed thin binary
+fn signature(signed: &[u8]) -> &[u8] {
+    let command = MachO::parse(signed).unwrap().code_signature.unwrap();
+    &signed[command.dataoff as usize..(command.dataoff + command.datasize) as usize]
+}
+
+fn be_u32(data: &[u8], offset: usize) -> u32 {
+    macho::read_u32_be(data, offset)
+}
+
+#[test]
+fn thin_arm64_matches_golden() {
+    check_golden("arm64");
+}
+
+#[test]
+fn thin_x86_64_matches_golden() {
+    check_golden("x86_64");
+}
+
+#[test]
+fn universal_matches_golden() {
+    check_golden("universal");
+}
+
+/// Compares each golden's CodeDirectory with what Apple's `codesign -d` saw in it. The dumps
+/// are made by `tests/fixtures/macho/codesign.sh`, which needs a Mac; CI runs it on macOS.
+#[test]
+fn goldens_match_codesign() {
+    let hex = |bytes: &[u8]| -> String { bytes.iter().map(|b| format!("{b:02x}")).collect() };
+    let goldens = [
+        ("arm64", "arm64"),
+        ("arm64.identity", "arm64"),
+        ("arm64-linker-signed", "arm64"),
+        ("x86_64", "x86_64"),
+        ("universal", "arm64"),
+        ("universal", "x86_64"),
+    ];
+    for (golden, arch) in goldens {
+        let dump_path = fixture_path(&format!("{golden}.signed.{arch}.codesign.txt"));
+        let Ok(dump) = fs::read_to_string(&dump_path) else {
+            continue;
+        };
+        let value = |key: &str| {
+            dump.lines()
+                .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+                .unwrap_or_else(|| panic!("`{}` doesn't have a {key}", dump_path.display()))
+        };
+
+        let signed = fixture(&format!("{golden}.signed"));
+        let cputype = if arch == "arm64" {
+            0x0100000c
+        } else {
+            0x01000007
+        };
+        let slice = match macho::parse_fat(&signed).unwrap() {
+            Some(arches) => {
+                let arch = arches.iter().find(|a| a.cputype == cputype).unwrap();
+                &signed[arch.offset as usize..(arch.offset + arch.size) as usize]
+            }
+            None => &signed[..],
+        };
+        let signature = signature(slice);
+        let cd = &signature[be_u32(signature, 16) as usize..];
+        let cd = &cd[..be_u32(cd, 4) as usize];
+
+        assert_eq!(value("Identifier"), "com.example.test-app", "{golden}");
+        assert_eq!(
+            value("CDHash"),
+            hex(&sign::blob::sha256(cd)[..20]),
+            "{golden} ({arch})"
+        );
+        let special_slots = be_u32(cd, 24) as isize;
+        let code_slots = be_u32(cd, 28) as isize;
+        assert!(
+            value("CodeDirectory v").contains(&format!("hashes={code_slots}+{special_slots}")),
+            "{golden} ({arch})"
+        );
+        // Every hash slot, from the highest special slot (like `-5`) to the last page
+        let hash_offset = be_u32(cd, 16) as isize;
+        let slots: Vec<(isize, &str)> = dump
+            .lines()
+            .filter_map(|line| {
+                let (slot, hash) = line.trim().split_once('=')?;
+                Some((slot.parse().ok()?, hash))
+            })
+            .collect();
+        assert_eq!(slots.len() as isize, special_slots + code_slots, "{golden}");
+        for (slot, hash) in slots {
+            let start = (hash_offset + slot * 32) as usize;
+            assert_eq!(
+                hash,
+                hex(&cd[start..start + 32]),
+                "{golden} ({arch}), slot {slot}"
+            );
+        }
+    }
+}
+
+#[test]
+fn linker_signature_is_replaced() {
+    check_golden("arm64-linker-signed");
+
+    // Replacing a signature in place shouldn't add another load command
+    let original = MachO::parse(&fixture("arm64-linker-signed")).unwrap();
+    let signed = sign::sign(&fixture("arm64-linker-signed"), &options()).unwrap();
+    let signed_macho = MachO::parse(&signed).unwrap();
+    assert_eq!(signed_macho.ncmds, original.ncmds);
+    assert_eq!(
+        signed_macho.code_signature.unwrap().dataoff,
+        original.code_signature.unwrap().dataoff
+    );
+}
+
+#[test]
+fn signature_is_added_to_linkedit() {
+    let original = MachO::parse(&fixture("arm64")).unwrap();
+    let signed = sign::sign(&fixture("arm64"), &options()).unwrap();
+    let macho = MachO::parse(&signed).unwrap();
+    assert_eq!(macho.ncmds, original.ncmds + 1);
+
+    let command = macho.code_signature.unwrap();
+    assert_eq!(command.dataoff % 16, 0);
+    assert_eq!(
+        command.dataoff as usize + command.datasize as usize,
+        signed.len()
+    );
+    let linkedit = macho.segment("__LINKEDIT").unwrap();
+    assert_eq!(linkedit.fileoff + linkedit.filesize, signed.len() as u64);
+    assert_eq!(linkedit.vmsize % 0x4000, 0);
+    assert!(linkedit.vmsize >= linkedit.filesize);
+}
+
+#[test]
+fn signature_has_the_expected_blobs() {
+    let signed = sign::sign(&fixture("arm64"), &options()).unwrap();
+    let signature = signature(&signed);
+
+    assert_eq!(be_u32(signature, 0), 0xfade0cc0);
+    assert_eq!(be_u32(signature, 8), 3);
+    let slots: Vec<u32> = (0..3).map(|i| be_u32(signature, 12 + i * 8)).collect();
+    assert_eq!(slots, [0, 2, 0x10000]);
+
+    // The CodeDirectory
+    let cd = &signature[be_u32(signature, 16) as usize..];
+    assert_eq!(be_u32(cd, 0), 0xfade0c02);
+    assert_eq!(be_u32(cd, 8), 0x20400);
+    assert_eq!(be_u32(cd, 12), 0x2); // Ad-hoc
+    assert_eq!(be_u32(cd, 24), 3); // Info.plist, requirements, and CodeResources
+    let ident_offset = be_u32(cd, 20) as usize;
+    assert_eq!(
+        &cd[ident_offset..ident_offset + 21],
+        b"com.example.test-app\0"
+    );
+    assert_eq!(cd[36..40], [32, 2, 0, 12]); // SHA-256, 4KiB pages
+
+    // Every page of the code is hashed
+    let code_limit = be_u32(cd, 32) as usize;
+    let hash_offset = be_u32(cd, 16) as usize;
+    assert_eq!(be_u32(cd, 28) as usize, code_limit.div_ceil(4096));
+    for (i, page) in signed[..code_limit].chunks(4096).enumerate() {
+        let hash = &cd[hash_offset + i * 32..hash_offset + (i + 1) * 32];
+        assert_eq!(hash, sign::blob::sha256(page));
+    }
+
+    // Info.plist is slot -1, and there's no CodeResources yet
+    assert_eq!(
+        &cd[hash_offset - 32..hash_offset],
+        sign::blob::sha256(INFO_PLIST)
+    );
+    assert_eq!(&cd[hash_offset - 96..hash_offset - 64], [0; 32]);
+
+    // Ad-hoc signatures have no requirements, and an empty CMS blob
+    let requirements = &signature[be_u32(signature, 24) as usize..];
+    assert_eq!(
+        requirements[..12],
+        [0xfa, 0xde, 0x0c, 0x01, 0, 0, 0, 12, 0, 0, 0, 0]
+    );
+    let cms = &signature[be_u32(signature, 32) as usize..];
+    assert_eq!(cms[..8], [0xfa, 0xde, 0x0b, 0x01, 0, 0, 0, 8]);
+}
+
+#[test]
+fn entitlements_are_embedded() {
+    let entitlements =
+        b"<plist version=\"1.0\"><dict><key>get-task-allow</key><true/></dict></plist>";
+    let options = SigningOptions {
+        entitlements: Some(entitlements),
+        ..options()
+    };
+    let signed = sign::sign(&fixture("arm64"), &options).unwrap();
+    let signature = signature(&signed);
+
+    assert_eq!(be_u32(signature, 8), 5);
+    let slots: Vec<u32> = (0..5).map(|i| be_u32(signature, 12 + i * 8)).collect();
+    assert_eq!(slots, [0, 2, 5, 7, 0x10000]);
+    let blob = &signature[be_u32(signature, 32) as usize..];
+    assert_eq!(be_u32(blob, 0), 0xfade7171);
+    assert_eq!(be_u32(blob, 4) as usize, entitlements.len() + 8);
+    let blob = &blob[..entitlements.len() + 8];
+    assert_eq!(&blob[8..], entitlements);
+
+    // The DER version: [APPLICATION 16] { version 1, [16] { { "get-task-allow", TRUE } } }
+    let der_blob = &signature[be_u32(signature, 40) as usize..];
+    let mut der = vec![
+        0x70, 0x1a, 0x02, 0x01, 0x01, 0xb0, 0x15, 0x30, 0x13, 0x0c, 0x0e,
+    ];
+    der.extend_from_slice(b"get-task-allow");
+    der.extend_from_slice(&[0x01, 0x01, 0xff]);
+    assert_eq!(be_u32(der_blob, 0), 0xfade7172);
+    assert_eq!(be_u32(der_blob, 4) as usize, der.len() + 8);
+    let der_blob = &der_blob[..der.len() + 8];
+    assert_eq!(der_blob[8..], der);
+
+    // Both are sealed, in special slots 5 & 7
+    let cd = &signature[be_u32(signature, 16) as usize..];
+    let hash_offset = be_u32(cd, 16) as usize;
+    assert_eq!(be_u32(cd, 24), 7);
+    assert_eq!(
+        &cd[hash_offset - 5 * 32..hash_offset - 4 * 32],
+        sign::blob::sha256(blob)
+    );
+    assert_eq!(
+        &cd[hash_offset - 7 * 32..hash_offset - 6 * 32],
+        sign::blob::sha256(der_blob)
+    );
+}
+
+#[test]
+fn resigning_gives_the_same_binary() {
+    let signed = sign::sign(&fixture("universal"), &options()).unwrap();
+    let resigned = sign::sign(&signed, &options()).unwrap();
+    assert!(signed == resigned);
+}
+
+#[test]
+fn binaries_without_headerpad_fail() {
+    let result = sign::sign(&fixture("arm64-no-headerpad"), &options());
+    let Err(err) = result else {
+        panic!("signing a binary without headerpad succeeded")
+    };
+    assert!(matches!(err, Error::Signing(_)));
+    assert_eq!(err.exit_code(), 7);
+    assert!(err.to_string().contains("headerpad"));
+}
+
+#[test]
+fn truncated_load_commands_fail() {
+    // A segment command too short to have its section count
+    let mut binary = fixture("arm64");
+    binary[36..40].copy_from_slice(&16u32.to_le_bytes());
+    let Err(err) = MachO::parse(&binary) else {
+        panic!("parsing a truncated segment command succeeded")
+    };
+    assert!(matches!(err, Error::Signing(_)));
+    assert!(err.to_string().contains("invalid segment"), "{err}");
+
+    // A code signature command too short to say where the signature is
+    let mut binary = fixture("arm64.signed");
+    let command = MachO::parse(&binary)
+        .unwrap()
+        .code_signature
+        .unwrap()
+        .command_offset;
+    binary[command + 4..command + 8].copy_from_slice(&8u32.to_le_bytes());
+    let Err(err) = MachO::parse(&binary) else {
+        panic!("parsing a truncated code signature command succeeded")
+    };
+    assert!(err.to_string().contains("code signature command"), "{err}");
+}
+
+#[test]
+fn non_macho_files_fail() {
+    let result = sign::sign(b"#!/bin/sh\necho hi\n", &options());
+    assert!(matches!(result, Err(Error::Signing(_))));
+}
+
+#[test]
+fn identities_load_from_p12_files() {
+    let identity = identity();
+    assert_eq!(identity.chain.len(), 3);
+    assert_eq!(identity.team_id().as_deref(), Some("TEAM123456"));
+    assert_eq!(
+        identity.common_name().as_deref(),
+        Some("Apple Distribution: Test Team (TEAM123456)")
+    );
+
+    // Older .p12 files use different encryption
+    let legacy = fs::read(identity_path("identity-legacy.p12")).unwrap();
+    let legacy = Identity::from_pkcs12(&legacy, PASSWORD).unwrap();
+    assert_eq!(legacy.chain, identity.chain[..1]);
+}
+
+#[test]
+fn wrong_passwords_fail() {
+    let no_chain: &[PathBuf] = &[];
+    let result = Identity::load(&identity_path("identity.p12"), "hunter2", no_chain);
+    let Err(err) = result else {
+        panic!("loading an identity with the wrong password succeeded")
+    };
+    assert!(matches!(err, Error::Signing(_)));
+    assert_eq!(err.exit_code(), 7);
+    assert!(err.to_string().contains("password"));
+}
+
+#[test]
+fn pem_files_add_every_certificate_to_the_chain() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = Certificate::from_der(&fs::read(identity_path("root.cer")).unwrap()).unwrap();
+    let pem = fs::read_to_string(identity_path("intermediate.pem")).unwrap()
+        + &root.to_pem(LineEnding::LF).unwrap();
+    let bundle = dir.path().join("chain.pem");
+    fs::write(&bundle, pem).unwrap();
+
+    let loaded = Identity::load(&identity_path("identity.p12"), PASSWORD, &[&bundle]).unwrap();
+    assert!(loaded.chain == identity().chain);
+
+    // PEM files without any certificates fail, rather than adding nothing
+    let empty = dir.path().join("empty.pem");
+    fs::write(
+        &empty,
+        "-----BEGIN CERTIFICATE-----\n-----END CERTIFICATE-----\n",
+    )
+    .unwrap();
+    let result = Identity::load(&identity_path("identity.p12"), PASSWORD, &[&empty]);
+    let Err(err) = result else {
+        panic!("loading an empty certificate chain succeeded")
+    };
+    assert!(matches!(err, Error::Signing(_)));
+    assert!(err.to_string().contains("empty.pem"), "{err}");
+}
+
+#[test]
+fn p12_files_need_a_certificate() {
+    let no_chain: &[PathBuf] = &[];
+    let result = Identity::load(&identity_path("key-only.p12"), PASSWORD, no_chain);
+    let Err(err) = result else {
+        panic!("loading an identity without a certificate succeeded")
+    };
+    assert!(matches!(err, Error::Signing(_)));
+    assert!(err.to_string().contains("certificate"), "{err}");
+}
+
+#[test]
+fn identity_signature_matches_golden() {
+    let identity = identity();
+    let options = SigningOptions {
+        identity: Some(&identity),
+        signing_time: Some(signing_time()),
+        ..options()
+    };
+    check_golden_with("arm64", "arm64.identity", &options);
+}
+
+#[test]
+fn identity_signatures_have_a_designated_requirement() {
+    let identity = identity();
+    let options = SigningOptions {
+        identity: Some(&identity),
+        signing_time: Some(signing_time()),
+        ..options()
+    };
+    let signed = sign::sign(&fixture("arm64"), &options).unwrap();
+    let signature = signature(&signed);
+
+    // `identifier "com.example.test-app" and anchor apple generic and
+    // certificate leaf[subject.OU] = "TEAM123456"`, compiled like codesign does
+    let mut expected = Vec::new();
+    let mut put = |words: &[u32], data: &[u8]| {
+        for word in words {
+            expected.extend_from_slice(&word.to_be_bytes());
+        }
+        expected.extend_from_slice(data);
+    };
+    put(&[0xfade0c01, 116, 1, 3, 20], b"");
+    put(&[0xfade0c00, 96, 1, 6, 6, 2, 20], b"com.example.test-app");
+    put(&[15, 11, 0, 10], b"subject.OU\0\0");
+    put(&[1, 10], b"TEAM123456\0\0");
+    let requirements = &signature[be_u32(signature, 24) as usize..];
+    assert_eq!(requirements[..expected.len()], expected);
+
+    // The CodeDirectory covers it, in special slot 2
+    let cd = &signature[be_u32(signature, 16) as usize..];
+    let hash_offset = be_u32(cd, 16) as usize;
+    assert_eq!(
+        &cd[hash_offset - 64..hash_offset - 32],
+        sign::blob::sha256(&expected)
+    );
+}
+
+#[test]
+fn identity_signature_is_valid_cms() {
+    let identity = identity();
+    let options = SigningOptions {
+        identity: Some(&identity),
+        signing_time: Some(signing_time()),
+        ..options()
+    };
+    let signed = sign::sign(&fixture("arm64"), &options).unwrap();
+    let signature = signature(&signed);
+
+    // The CodeDirectory isn't ad-hoc, and has the team ID
+    let cd_offset = be_u32(signature, 16) as usize;
+    let cd = &signature[cd_offset..cd_offset + be_u32(signature, cd_offset + 4) as usize];
+    assert_eq!(be_u32(cd, 12), 0);
+    let team_offset = be_u32(cd, 48) as usize;
+    assert_eq!(&cd[team_offset..team_offset + 11], b"TEAM123456\0");
+
+    let wrapper = &signature[be_u32(signature, 32) as usize..];
+    assert_eq!(be_u32(wrapper, 0), 0xfade0b01);
+    let cms = &wrapper[8..be_u32(wrapper, 4) as usize];
+    let signed_data: SignedData = ContentInfo::from_der(cms)
+        .unwrap()
+        .content
+        .decode_as()
+        .unwrap();
+
+    // The CodeDirectory is signed, but not included
+    assert!(signed_data.encap_content_info.econtent.is_none());
+    assert_eq!(signed_data.certificates.unwrap().0.len(), 3);
+
+    let signer = signed_data.signer_infos.0.get(0).unwrap();
+    let attributes = signer.signed_attrs.as_ref().unwrap();
+    let attribute = |oid: &str| {
+        let oid = ObjectIdentifier::new_unwrap(oid);
+        let attribute = attributes.iter().find(|attr| attr.oid == oid).unwrap();
+        attribute.values.get(0).unwrap().to_der().unwrap()
+    };
+    let digest = OctetString::from_der(&attribute("1.2.840.113549.1.9.4")).unwrap();
+    assert_eq!(digest.as_bytes(), sign::blob::sha256(cd));
+    let time = UtcTime::from_der(&attribute("1.2.840.113549.1.9.5")).unwrap();
+    assert_eq!(time.to_system_time(), signing_time());
+
+    // The signature checks out with the certificate's public key
+    let public_key = identity
+        .certificate()
+        .tbs_certificate
+        .subject_public_key_info
+        .to_der()
+        .unwrap();
+    let public_key = RsaPublicKey::from_public_key_der(&public_key).unwrap();
+    let rsa_signature = Signature::try_from(signer.signature.as_bytes()).unwrap();
+    VerifyingKey::<Sha256>::new(public_key)
+        .verify(&attributes.to_der().unwrap(), &rsa_signature)
+        .unwrap();
+}
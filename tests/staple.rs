@@ -0,0 +1,91 @@
+mod common;
+
+use cargo_ipa::{
+    build::{build_with, BuildArgs},
+    connect::ticket,
+    context::{Architecture, Ctx, Platform},
+    error::Error,
+    sign::verify::{verify, SealedFiles},
+    staple::staple_app,
+    verify::{verify_with, VerifyArgs},
+};
+use common::{notary::*, *};
+use std::{fs, path::Path, time::SystemTime};
+use tempfile::TempDir;
+
+const APP: &str = "target/cargo-ipa/Test App.aarch64-apple-darwin.app";
+
+fn built_app() -> TempDir {
+    let project = project(CARGO_TOML);
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+    let args = BuildArgs {
+        example: None,
+        release: false,
+        name: None,
+        platform: Some(Platform::macOS),
+        architecture: Some(Architecture::aarch64),
+        strip: false,
+        archive: false,
+        export_method: None,
+        simulator: false,
+    };
+    build_with(&mut ctx, &args, &runner).unwrap();
+    project
+}
+
+/// The name of the ticket record for an app
+fn record_name(app: &Path) -> String {
+    let binary = fs::read(app.join("Contents/MacOS/test-app")).unwrap();
+    let slices = verify(&binary, &SealedFiles::default(), SystemTime::now()).unwrap();
+    let cdhash = slices[0].cdhash.as_ref().unwrap();
+    ticket::record_name(slices[0].cdhash_type.unwrap(), cdhash)
+}
+
+#[test]
+fn tickets_are_stapled_into_the_contents_folder() {
+    let project = built_app();
+    let app = project.path().join(APP);
+    let notary = fake_notary(NotaryMode::Accept);
+    let record_name = record_name(&app);
+    // Looked up by the SHA-256 CDHash, truncated to 20 bytes
+    assert!(record_name.starts_with("2/2/"), "{record_name}");
+    assert_eq!(record_name.len(), 4 + 40);
+    notary.publish_ticket(&record_name, b"a ticket");
+
+    let path = staple_app(&app, &notary.ticket_url()).unwrap();
+    assert_eq!(path, app.join("Contents/CodeResources"));
+    assert_eq!(fs::read(&path).unwrap(), b"a ticket");
+
+    // The ticket isn't part of the seal, so the app's still valid
+    let args = VerifyArgs {
+        path: app,
+        json: false,
+        notarization: false,
+    };
+    let report = verify_with(&args, &fake_toolchain(project.path())).unwrap();
+    assert_eq!(report.problems(), Vec::<String>::new());
+}
+
+#[test]
+fn apps_without_tickets_fail() {
+    let project = built_app();
+    let notary = fake_notary(NotaryMode::Accept);
+    let err = staple_app(&project.path().join(APP), &notary.ticket_url()).unwrap_err();
+    assert!(matches!(err, Error::Service(_)));
+    assert!(
+        err.to_string().contains("There's no notarization ticket"),
+        "{err}"
+    );
+}
+
+#[test]
+fn only_macos_apps_are_stapled() {
+    let dir = tempfile::tempdir().unwrap();
+    let app = dir.path().join("Test App.app");
+    fs::create_dir(&app).unwrap();
+    fs::write(app.join("Info.plist"), "").unwrap();
+    let err = staple_app(&app, "http://127.0.0.1:1/").unwrap_err();
+    assert!(matches!(err, Error::Config(_)));
+    assert!(err.to_string().contains("isn't a macOS app"), "{err}");
+}
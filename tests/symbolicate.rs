@@ -0,0 +1,157 @@
+mod common;
+
+use cargo_ipa::{
+    context::Ctx,
+    error::Error,
+    symbolicate::{debug_info::demangle, symbolicate_with, SymbolicateArgs},
+};
+use common::*;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/symbolicate")
+        .join(name)
+}
+
+const CRASH_REPORT: &str = "Crashy-2026-10-18-101010.ips";
+
+fn args(search_paths: &[&str]) -> SymbolicateArgs {
+    SymbolicateArgs {
+        crash_report: fixture(CRASH_REPORT),
+        search_paths: search_paths.iter().map(|name| fixture(name)).collect(),
+        output: None,
+        name: None,
+    }
+}
+
+/// The frames in a symbolicated report's thread, without their addresses
+fn thread<'a>(report: &'a str, heading: &str) -> Vec<&'a str> {
+    report
+        .split(&format!("\n{heading}"))
+        .nth(1)
+        .unwrap()
+        .lines()
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .map(|line| line.split_once(" 0x").unwrap().1.split_once(' ').unwrap().1)
+        .collect()
+}
+
+fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        match entry.file_type().unwrap().is_dir() {
+            true => copy_dir(&entry.path(), &to.join(entry.file_name())),
+            false => drop(fs::copy(entry.path(), to.join(entry.file_name())).unwrap()),
+        }
+    }
+}
+
+#[test]
+fn app_frames_are_resolved_to_functions_and_lines() {
+    let report =
+        symbolicate_with(&args(&["Crashy.app.dSYM", "Engine.framework.dSYM"]), None).unwrap();
+
+    assert!(report.starts_with("Crashy 1.0 (1), com.example.crashy\n"));
+    assert!(report.contains("Exception: EXC_CRASH (SIGABRT)\n"));
+    // Inlined functions get their own lines. The app uses v0 mangling, and the framework uses
+    // legacy mangling, and neither have hashes once they're demangled.
+    assert_eq!(
+        thread(&report, "Thread 0 Crashed (com.apple.main-thread):"),
+        [
+            "__pthread_kill + 8",
+            "crashy::render::layout::measure (/Users/dev/crashy/src/crashy.rs:25) [inlined]",
+            "crashy::render::draw_frame + 12 (/Users/dev/crashy/src/crashy.rs:31)",
+            "main + 16 (/Users/dev/crashy/src/crashy.rs:37)",
+            "start + 2204",
+        ]
+    );
+    assert_eq!(
+        thread(&report, "Thread 1 (engine):"),
+        [
+            "engine::physics::step + 12 (/Users/dev/crashy/src/engine.rs:22)",
+            // No debug info was found for this framework
+            "???",
+        ]
+    );
+    assert!(
+        report.contains("\nBinary Images:\n0x0000000100784000 - 0x000000010078ffff crashy arm64 <")
+    );
+}
+
+#[test]
+fn binaries_without_dwarf_still_have_function_names() {
+    let report = symbolicate_with(&args(&["Engine"]), None).unwrap();
+    assert_eq!(
+        thread(&report, "Thread 1 (engine):"),
+        ["engine::physics::step + 12", "???"]
+    );
+    // The device's own guess is used for frames in images that weren't found
+    assert_eq!(
+        thread(&report, "Thread 0 Crashed (com.apple.main-thread):")[1],
+        "crashy::render::draw_frame + 12"
+    );
+}
+
+#[test]
+fn dsyms_are_found_in_the_projects_target_folder() {
+    let project = project(CARGO_TOML);
+    let ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+    // Like a dSYM kept from an earlier release build, next to a binary without DWARF
+    let release = ctx.target_dir.join("aarch64-apple-ios/release");
+    copy_dir(
+        &fixture("Engine.framework.dSYM"),
+        &release.join("Engine.framework.dSYM"),
+    );
+    fs::copy(fixture("Engine"), release.join("Engine")).unwrap();
+    fs::write(release.join("libengine.rlib"), "not a Mach-O").unwrap();
+
+    let report = symbolicate_with(&args(&[]), Some(&ctx)).unwrap();
+    // The dSYM was picked over the binary
+    assert_eq!(
+        thread(&report, "Thread 1 (engine):")[0],
+        "engine::physics::step + 12 (/Users/dev/crashy/src/engine.rs:22)"
+    );
+}
+
+#[test]
+fn somewhere_to_search_is_needed_outside_projects() {
+    let err = symbolicate_with(&args(&[]), None).unwrap_err();
+    assert!(matches!(err, Error::Config(_)));
+    assert!(err.to_string().contains("--search"), "{err}");
+}
+
+#[test]
+fn only_json_crash_reports_can_be_symbolicated() {
+    let dir = tempfile::tempdir().unwrap();
+    let old_report = dir.path().join("old.crash");
+    fs::write(
+        &old_report,
+        "Incident Identifier: 0E1D2C3B-4A59-4867-9586-A4B3C2D1E0F9\nHardware Model: iPhone13,2\n",
+    )
+    .unwrap();
+
+    let mut args = args(&["Crashy.app.dSYM"]);
+    args.crash_report = old_report;
+    let err = symbolicate_with(&args, None).unwrap_err();
+    assert!(matches!(err, Error::Io { .. }));
+    assert!(err.to_string().contains(".ips"), "{err}");
+}
+
+#[test]
+fn both_mangling_schemes_are_demangled() {
+    assert_eq!(
+        demangle("_RNvNtCs4DBgsroVcw6_6crashy6render10draw_frame"),
+        "crashy::render::draw_frame"
+    );
+    assert_eq!(
+        demangle("_ZN6engine7physics4step17h1174869d68992bb7E"),
+        "engine::physics::step"
+    );
+    assert_eq!(demangle("__pthread_kill"), "__pthread_kill");
+}
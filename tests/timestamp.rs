@@ -0,0 +1,112 @@
+mod common;
+
+use cargo_ipa::{
+    error::Error,
+    sign::{
+        self,
+        identity::Identity,
+        timestamp,
+        verify::{self, SealedFiles},
+        SigningOptions,
+    },
+};
+use common::*;
+use std::{net::TcpListener, path::PathBuf, time::SystemTime};
+use x509_cert::der::Decode;
+
+fn identity() -> Identity {
+    let fixtures = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/identity");
+    Identity::load(
+        &fixtures.join("identity.p12"),
+        "cargo-ipa",
+        &[fixtures.join("intermediate.pem"), fixtures.join("root.cer")],
+    )
+    .unwrap()
+}
+
+fn sign_with(identity: &Identity, hardened_runtime: bool, timestamp_url: Option<&str>) -> Vec<u8> {
+    let options = SigningOptions {
+        identifier: "com.example.test-app",
+        identity: Some(identity),
+        hardened_runtime,
+        timestamp_url,
+        ..Default::default()
+    };
+    sign::sign(FAKE_BINARY, &options).unwrap()
+}
+
+#[test]
+fn tokens_are_for_the_signature() {
+    let url = fake_tsa(TsaMode::Grant);
+    let token = timestamp::request(&url, b"a signature").unwrap();
+    let token = cms::content_info::ContentInfo::from_der(&token).unwrap();
+    let info = timestamp::tst_info(&token).unwrap();
+    assert_eq!(info.message_imprint, timestamp::imprint(b"a signature"));
+    let age = SystemTime::now()
+        .duration_since(info.time().unwrap())
+        .unwrap();
+    assert!(age.as_secs() < 60);
+}
+
+#[test]
+fn timestamps_are_added_to_signatures() {
+    let identity = identity();
+    let url = fake_tsa(TsaMode::Grant);
+    let signed = sign_with(&identity, true, Some(&url));
+
+    let reports = verify::verify(&signed, &SealedFiles::default(), SystemTime::now()).unwrap();
+    let report = &reports[0];
+    assert_eq!(report.problems, Vec::<String>::new());
+    assert!(report.hardened_runtime);
+    assert!(report.timestamp.is_some());
+
+    // Without either
+    let signed = sign_with(&identity, false, None);
+    let reports = verify::verify(&signed, &SealedFiles::default(), SystemTime::now()).unwrap();
+    assert!(!reports[0].hardened_runtime);
+    assert!(reports[0].timestamp.is_none());
+}
+
+#[test]
+fn ad_hoc_signatures_arent_timestamped() {
+    // There's no CMS signature to timestamp, so the server is never asked
+    let options = SigningOptions {
+        identifier: "com.example.test-app",
+        timestamp_url: Some("http://127.0.0.1:1/"),
+        ..Default::default()
+    };
+    let signed = sign::sign(FAKE_BINARY, &options).unwrap();
+    let reports = verify::verify(&signed, &SealedFiles::default(), SystemTime::now()).unwrap();
+    assert!(reports[0].timestamp.is_none());
+}
+
+#[test]
+fn refused_timestamps_fail() {
+    let url = fake_tsa(TsaMode::Reject);
+    let err = timestamp::request(&url, b"a signature").unwrap_err();
+    assert!(matches!(err, Error::Signing(_)));
+    assert!(err.to_string().contains("refused"), "{err}");
+}
+
+#[test]
+fn timestamps_for_something_else_fail() {
+    let url = fake_tsa(TsaMode::WrongImprint);
+    let err = timestamp::request(&url, b"a signature").unwrap_err();
+    assert!(
+        err.to_string().contains("timestamped something other"),
+        "{err}"
+    );
+}
+
+#[test]
+fn unreachable_servers_fail() {
+    // Nothing's listening on a port that was just freed
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let url = format!("http://127.0.0.1:{port}/");
+    let err = timestamp::request(&url, b"a signature").unwrap_err();
+    assert!(err.to_string().contains("Couldn't reach"), "{err}");
+}
@@ -0,0 +1,195 @@
+mod common;
+
+use cargo_ipa::{
+    build::{build_with, BuildArgs},
+    context::{Architecture, Ctx, Platform},
+    error::Error,
+    runner::FakeRunner,
+    upload::{upload_with, UploadArgs},
+};
+use common::{app_store::*, notary::*, *};
+use md5::{Digest, Md5};
+use std::fs;
+use tempfile::TempDir;
+
+const IPA: &str = "target/cargo-ipa/Test App.aarch64-apple-ios.ipa";
+
+/// Builds an iOS IPA, in a project with its App Store Connect settings in Cargo.toml
+fn built_ipa() -> (TempDir, Ctx, FakeRunner) {
+    let cargo_toml = format!(
+        "{CARGO_TOML}\n[package.metadata.cargo-ipa.app-store-connect]\napi-key = '{}'\napi-issuer = '{API_ISSUER}'\n",
+        api_key_path().display()
+    );
+    let project = project(&cargo_toml);
+    let runner = fake_toolchain(project.path()).with_tool("unzip", fake_unzip);
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+    let args = BuildArgs {
+        example: None,
+        release: true,
+        name: None,
+        platform: Some(Platform::iOS),
+        architecture: Some(Architecture::aarch64),
+        strip: false,
+        archive: false,
+        export_method: None,
+        simulator: false,
+    };
+    build_with(&mut ctx, &args, &runner).unwrap();
+    (project, ctx, runner)
+}
+
+fn args(app_store: &FakeAppStore) -> UploadArgs {
+    UploadArgs {
+        ipa: None,
+        name: None,
+        api_key: None,
+        api_key_id: None,
+        api_issuer: None,
+        app_id: None,
+        no_wait: false,
+        poll_interval: 0,
+        timeout: 60,
+        api_url: app_store.api_url(),
+    }
+}
+
+#[test]
+fn builds_are_uploaded_and_processed() {
+    let (project, ctx, runner) = built_ipa();
+    let app_store = fake_app_store(AppStoreMode::Complete);
+
+    let upload = upload_with(&args(&app_store), Some(&ctx), &runner).unwrap();
+    assert_eq!(upload.app_id, APP_ID);
+    assert_eq!(upload.state, "COMPLETE");
+    assert_eq!(
+        upload.warnings,
+        ["This app was built with an old SDK. (90725)"]
+    );
+
+    let record = app_store.record.lock().unwrap();
+    // The app was found by its bundle ID, and not the widget's
+    assert_eq!(record.lookups, ["com.example.test-app"]);
+    let build_upload = record.build_upload.as_ref().unwrap();
+    assert_eq!(
+        build_upload["attributes"]["cfBundleShortVersionString"],
+        "0.1.0"
+    );
+    assert_eq!(build_upload["attributes"]["cfBundleVersion"], "0.1.0");
+    assert_eq!(build_upload["attributes"]["platform"], "IOS");
+    assert_eq!(build_upload["relationships"]["app"]["data"]["id"], APP_ID);
+
+    // The IPA was uploaded in both chunks, and committed with its checksum
+    let ipa = fs::read(project.path().join(IPA)).unwrap();
+    let file = record.file.as_ref().unwrap();
+    assert_eq!(
+        file["attributes"]["fileName"],
+        "Test App.aarch64-apple-ios.ipa"
+    );
+    assert_eq!(file["attributes"]["fileSize"], ipa.len());
+    assert_eq!(record.parts.len(), 2);
+    assert_eq!(record.uploaded_file(), ipa);
+    let checksum = &record.checksums.as_ref().unwrap()["file"];
+    assert_eq!(checksum["algorithm"], "MD5");
+    assert_eq!(checksum["hash"], format!("{:x}", Md5::digest(&ipa)));
+    assert_eq!(record.polls, 2);
+
+    // The unpacked IPA was cleaned up
+    let out = project.path().join("target/cargo-ipa");
+    assert!(!out.join(".Test App.aarch64-apple-ios.upload").exists());
+}
+
+#[test]
+fn app_ids_skip_the_lookup() {
+    let (_project, ctx, runner) = built_ipa();
+    let app_store = fake_app_store(AppStoreMode::Complete);
+    let mut args = args(&app_store);
+    args.app_id = Some("42".into());
+    args.no_wait = true;
+
+    let upload = upload_with(&args, Some(&ctx), &runner).unwrap();
+    assert_eq!(upload.state, "PROCESSING");
+    let record = app_store.record.lock().unwrap();
+    assert!(record.lookups.is_empty());
+    assert_eq!(
+        record.build_upload.as_ref().unwrap()["relationships"]["app"]["data"]["id"],
+        "42"
+    );
+    assert_eq!(record.polls, 0);
+}
+
+#[test]
+fn ipas_can_be_uploaded_without_a_project() {
+    let (project, _ctx, runner) = built_ipa();
+    let app_store = fake_app_store(AppStoreMode::Complete);
+    let mut args = args(&app_store);
+    args.ipa = Some(project.path().join(IPA));
+    args.api_key = Some(api_key_path());
+    args.api_issuer = Some(API_ISSUER.into());
+
+    upload_with(&args, None, &runner).unwrap();
+    assert!(app_store.record.lock().unwrap().checksums.is_some());
+
+    // Without a project, there's nowhere to get the key from
+    args.api_key = None;
+    let err = upload_with(&args, None, &runner).unwrap_err();
+    assert!(matches!(err, Error::Config(_)));
+}
+
+#[test]
+fn failed_builds_list_the_errors() {
+    let (_project, ctx, runner) = built_ipa();
+    let app_store = fake_app_store(AppStoreMode::Failed);
+
+    let err = upload_with(&args(&app_store), Some(&ctx), &runner).unwrap_err();
+    assert!(matches!(err, Error::Service(_)));
+    let message = err.to_string();
+    assert!(
+        message.contains("couldn't process the build (FAILED)"),
+        "{message}"
+    );
+    assert!(
+        message.contains("must be higher than the previously uploaded version. (90062)"),
+        "{message}"
+    );
+}
+
+#[test]
+fn slow_processing_times_out() {
+    let (_project, ctx, runner) = built_ipa();
+    let app_store = fake_app_store(AppStoreMode::Stuck);
+    let mut args = args(&app_store);
+    args.timeout = 0;
+
+    let err = upload_with(&args, Some(&ctx), &runner).unwrap_err();
+    assert!(err.to_string().contains("is still processing"), "{err}");
+}
+
+#[test]
+fn unknown_apps_are_config_errors() {
+    let (_project, ctx, runner) = built_ipa();
+    let app_store = fake_app_store(AppStoreMode::Complete);
+    app_store
+        .record
+        .lock()
+        .unwrap()
+        .apps
+        .retain(|(_, bundle_id)| bundle_id != "com.example.test-app");
+
+    let err = upload_with(&args(&app_store), Some(&ctx), &runner).unwrap_err();
+    assert!(matches!(err, Error::Config(_)));
+    assert!(err.to_string().contains("`com.example.test-app`"), "{err}");
+    // Nothing was uploaded
+    assert!(app_store.record.lock().unwrap().build_upload.is_none());
+}
+
+#[test]
+fn only_ipas_can_be_uploaded() {
+    let (project, ctx, runner) = built_ipa();
+    let app_store = fake_app_store(AppStoreMode::Complete);
+    let mut args = args(&app_store);
+    args.ipa = Some(project.path().join("target/cargo-ipa/Test App.app"));
+
+    let err = upload_with(&args, Some(&ctx), &runner).unwrap_err();
+    assert!(matches!(err, Error::Config(_)));
+    assert!(err.to_string().contains("isn't an IPA"), "{err}");
+}
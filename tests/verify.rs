@@ -0,0 +1,319 @@
+mod common;
+
+use cargo_ipa::{
+    build::{build_with, BuildArgs},
+    context::{Architecture, Ctx, Platform},
+    resign::{resign_with, ResignArgs},
+    runner::{FakeRunner, ToolCall},
+    sign::macho::{read_u32_be, MachO},
+    verify::{verify_with, Report, VerifyArgs},
+};
+use common::*;
+use plist::{Dictionary, Value};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use tempfile::TempDir;
+
+const APP: &str = "Payload/Test App.app";
+
+fn fixture_path(folder: &str, name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(folder)
+        .join(name)
+}
+
+fn runner() -> FakeRunner {
+    FakeRunner::new()
+        .with_tool("zip", fake_zip)
+        .with_tool("unzip", fake_unzip)
+}
+
+fn write_info_plist(bundle: &Path, bundle_id: &str, executable: &str) {
+    let mut info_plist = Dictionary::new();
+    info_plist.insert("CFBundleIdentifier".into(), bundle_id.into());
+    info_plist.insert("CFBundleExecutable".into(), executable.into());
+    fs::create_dir_all(bundle).unwrap();
+    Value::from(info_plist)
+        .to_file_xml(bundle.join("Info.plist"))
+        .unwrap();
+}
+
+/// Makes an app with a resource, a framework, and a dylib, signs it with `cargo ipa resign`,
+/// and unpacks it again. Returns the .app folder.
+fn signed_app(with_identity: bool) -> (TempDir, PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    let app = dir.path().join(APP);
+    write_info_plist(&app, "com.example.test-app", "test-app");
+    fs::write(app.join("icon.png"), b"icon").unwrap();
+    fs::write(app.join("test-app"), FAKE_BINARY).unwrap();
+    let framework = app.join("Frameworks/Example.framework");
+    write_info_plist(&framework, "com.example.framework", "Example");
+    fs::write(framework.join("Example"), FAKE_BINARY).unwrap();
+    fs::write(app.join("Frameworks/libexample.dylib"), FAKE_BINARY).unwrap();
+    let zip = ToolCall::new("zip", &["-r", "unsigned.ipa", "Payload"]).current_dir(dir.path());
+    fake_zip(&zip).unwrap();
+    fs::remove_dir_all(dir.path().join("Payload")).unwrap();
+
+    let mut args = ResignArgs {
+        ipa: dir.path().join("unsigned.ipa"),
+        output: Some(dir.path().join("Test App.ipa")),
+        bundle_id: None,
+        display_name: None,
+        version: None,
+        identity: None,
+        password_file: None,
+        certificate_chain: Vec::new(),
+        profile: None,
+        entitlements: None,
+    };
+    if with_identity {
+        fs::write(dir.path().join("password.txt"), "cargo-ipa\n").unwrap();
+        args.identity = Some(fixture_path("identity", "identity.p12"));
+        args.password_file = Some(dir.path().join("password.txt"));
+        args.certificate_chain = vec![
+            fixture_path("identity", "intermediate.pem"),
+            fixture_path("identity", "root.cer"),
+        ];
+        args.profile = Some(fixture_path("profile", "test.mobileprovision"));
+    }
+    let ipa = resign_with(&args, &runner()).unwrap();
+    let unzip = ToolCall::new(
+        "unzip",
+        &[
+            "-q",
+            ipa.to_str().unwrap(),
+            "-d",
+            dir.path().to_str().unwrap(),
+        ],
+    );
+    fake_unzip(&unzip).unwrap();
+    let app = dir.path().join(APP);
+    (dir, app)
+}
+
+fn verify(path: &Path) -> Report {
+    let args = VerifyArgs {
+        path: path.to_owned(),
+        json: false,
+        notarization: false,
+    };
+    verify_with(&args, &runner()).unwrap()
+}
+
+fn assert_problem(report: &Report, expected: &str) {
+    let problems = report.problems();
+    assert!(
+        problems.iter().any(|problem| problem.contains(expected)),
+        "expected a problem containing {expected:?}, got {problems:#?}"
+    );
+    assert!(!report.valid);
+}
+
+#[test]
+fn signed_ipas_are_valid() {
+    let (dir, _) = signed_app(true);
+    let report = verify(&dir.path().join("Test App.ipa"));
+    assert_eq!(report.problems(), Vec::<String>::new());
+    assert!(report.valid);
+    // The unpacked copy is cleaned up
+    assert!(!dir.path().join(".Test App.verify").exists());
+
+    let paths: Vec<_> = report.code.iter().map(|code| code.path.clone()).collect();
+    assert_eq!(
+        paths,
+        [
+            PathBuf::from("Test App.app"),
+            PathBuf::from("Test App.app/Frameworks/Example.framework"),
+            PathBuf::from("Test App.app/Frameworks/libexample.dylib"),
+        ]
+    );
+
+    let app = &report.code[0];
+    assert_eq!(app.bundle_id.as_deref(), Some("com.example.test-app"));
+    let slice = &app.slices[0];
+    assert_eq!(slice.architecture, "arm64");
+    assert_eq!(slice.identifier.as_deref(), Some("com.example.test-app"));
+    assert!(!slice.ad_hoc);
+    assert_eq!(slice.cdhash.as_ref().map(String::len), Some(40));
+    assert!(slice.signing_time.is_some());
+    // The identity's certificate, the intermediate, and the root
+    assert_eq!(slice.certificates.len(), 3);
+    assert!(slice.warnings.is_empty());
+
+    let profile = app.profile.as_ref().unwrap();
+    assert_eq!(profile.name, "Test App Development");
+    assert!(!profile.expired);
+    let Some(Value::Dictionary(ref entitlements)) = app.entitlements else {
+        panic!("the app should have entitlements");
+    };
+    assert_eq!(
+        entitlements["application-identifier"].as_string(),
+        Some("TEAM123456.com.example.test-app")
+    );
+}
+
+#[test]
+fn ad_hoc_apps_are_valid() {
+    let (_dir, app) = signed_app(false);
+    let report = verify(&app);
+    assert!(report.valid, "{:#?}", report.problems());
+    let slice = &report.code[0].slices[0];
+    assert!(slice.ad_hoc);
+    assert!(slice.certificates.is_empty());
+}
+
+#[test]
+fn changed_resources_are_found() {
+    let (_dir, app) = signed_app(false);
+    fs::write(app.join("icon.png"), b"a different icon").unwrap();
+    fs::write(app.join("extra.txt"), b"extra").unwrap();
+    let report = verify(&app);
+    assert_problem(
+        &report,
+        "`icon.png` was changed after the bundle was signed",
+    );
+    assert_problem(&report, "`extra.txt` was added after the bundle was signed");
+}
+
+#[test]
+fn changed_code_is_found() {
+    let (_dir, app) = signed_app(false);
+    let binary = app.join("test-app");
+    let mut data = fs::read(&binary).unwrap();
+    // Somewhere in the first page, after the load commands
+    data[0x800] ^= 0xff;
+    fs::write(&binary, data).unwrap();
+    assert_problem(&verify(&app), "pages don't match");
+}
+
+/// Overwrites a byte in the primary CodeDirectory of a signed binary
+fn corrupt_code_directory(binary: &Path, offset: usize, value: u8) {
+    let mut data = fs::read(binary).unwrap();
+    let command = MachO::parse(&data).unwrap().code_signature.unwrap();
+    let signature = command.dataoff as usize;
+    let code_directory = signature + read_u32_be(&data[signature..], 16) as usize;
+    data[code_directory + offset] = value;
+    fs::write(binary, data).unwrap();
+}
+
+#[test]
+fn corrupted_code_directories_are_found() {
+    // The hash size is bigger than a SHA-256 hash
+    let (_dir, app) = signed_app(false);
+    corrupt_code_directory(&app.join("test-app"), 36, 0xff);
+    assert_problem(
+        &verify(&app),
+        "its hash size (255) doesn't fit its hash type (32 bytes)",
+    );
+
+    // Pages are 2^64 bytes
+    let (_dir, app) = signed_app(false);
+    corrupt_code_directory(&app.join("test-app"), 39, 64);
+    assert_problem(&verify(&app), "its page size (2^64 bytes) is too big");
+}
+
+#[test]
+fn changed_info_plists_are_found() {
+    let (_dir, app) = signed_app(false);
+    let info_plist = fs::read(app.join("Info.plist")).unwrap();
+    fs::write(app.join("Info.plist"), [&info_plist[..], b"\n"].concat()).unwrap();
+    assert_problem(
+        &verify(&app),
+        "Info.plist was changed after the binary was signed",
+    );
+}
+
+#[test]
+fn unsigned_nested_code_is_found() {
+    let (_dir, app) = signed_app(false);
+    fs::write(app.join("Frameworks/libexample.dylib"), FAKE_BINARY).unwrap();
+    let report = verify(&app);
+    assert_problem(
+        &report,
+        "libexample.dylib` (arm64): The binary isn't signed",
+    );
+}
+
+#[test]
+fn profiles_are_checked_against_the_bundle() {
+    let (_dir, app) = signed_app(true);
+    // The framework's bundle ID isn't covered by the profile
+    let framework = app.join("Frameworks/Example.framework");
+    fs::copy(
+        app.join("embedded.mobileprovision"),
+        framework.join("embedded.mobileprovision"),
+    )
+    .unwrap();
+    let report = verify(&app);
+    assert_problem(
+        &report,
+        "Example.framework`: The provisioning profile `Test App Development` is for the app ID `TEAM123456.com.example.test-app`, which doesn't match the bundle ID `com.example.framework`",
+    );
+}
+
+#[test]
+fn reports_serialise_to_json() {
+    let (_dir, app) = signed_app(true);
+    let report = verify(&app);
+    let json = serde_json::to_value(&report).unwrap();
+    assert_eq!(json["valid"], true);
+    let slice = &json["code"][0]["slices"][0];
+    assert_eq!(slice["identifier"], "com.example.test-app");
+    assert_eq!(slice["certificates"].as_array().unwrap().len(), 3);
+    assert!(slice.get("signing_certificate").is_none());
+    assert_eq!(
+        json["code"][0]["profile"]["application_identifier"],
+        "TEAM123456.com.example.test-app"
+    );
+    assert_eq!(
+        json["code"][0]["entitlements"]["get-task-allow"],
+        serde_json::Value::Bool(true)
+    );
+}
+
+#[test]
+fn ad_hoc_macos_apps_arent_ready_for_notarization() {
+    let project = project(CARGO_TOML);
+    let runner = fake_toolchain(project.path());
+    let mut ctx = Ctx::from_cargo_toml(project.path().join("Cargo.toml"), &None).unwrap();
+    let args = BuildArgs {
+        example: None,
+        release: false,
+        name: None,
+        platform: Some(Platform::macOS),
+        architecture: Some(Architecture::aarch64),
+        strip: false,
+        archive: false,
+        export_method: None,
+        simulator: false,
+    };
+    build_with(&mut ctx, &args, &runner).unwrap();
+
+    let args = VerifyArgs {
+        path: project
+            .path()
+            .join("target/cargo-ipa/Test App.aarch64-apple-darwin.app"),
+        json: false,
+        notarization: true,
+    };
+    let report = verify_with(&args, &runner).unwrap();
+    // The signature itself is fine
+    assert!(report.code[0].problems.is_empty());
+    assert_problem(&report, "isn't signed with a certificate");
+    assert_problem(&report, "doesn't have the hardened runtime turned on");
+}
+
+#[test]
+fn ios_apps_cant_be_notarized() {
+    let (_dir, app) = signed_app(true);
+    let args = VerifyArgs {
+        path: app,
+        json: false,
+        notarization: true,
+    };
+    let report = verify_with(&args, &runner()).unwrap();
+    assert_problem(&report, "Only macOS apps can be notarized");
+}